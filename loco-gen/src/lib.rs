@@ -299,6 +299,16 @@ pub enum Component {
     Deployment {
         kind: DeploymentKind,
     },
+    #[cfg(feature = "with-db")]
+    OAuth2 {
+        /// Name of the provider, eg. google, github or any OIDC provider
+        provider: String,
+    },
+    #[cfg(feature = "with-db")]
+    Policy {
+        /// Name of the resource guarded by the policy
+        name: String,
+    },
 }
 
 pub struct AppInfo {
@@ -392,6 +402,21 @@ pub fn generate(rrgen: &RRgen, component: Component, appinfo: &AppInfo) -> Resul
             let vars = json!({ "name": name });
             render_template(rrgen, Path::new("data"), &vars)?
         }
+        #[cfg(feature = "with-db")]
+        Component::OAuth2 { provider } => {
+            let kind = match provider.to_lowercase().as_str() {
+                "google" => "Google",
+                "github" => "GitHub",
+                _ => "Oidc",
+            };
+            let vars = json!({"provider": provider, "kind": kind, "pkg_name": appinfo.app_name});
+            render_template(rrgen, Path::new("oauth2"), &vars)?
+        }
+        #[cfg(feature = "with-db")]
+        Component::Policy { name } => {
+            let vars = json!({"name": name, "pkg_name": appinfo.app_name});
+            render_template(rrgen, Path::new("policy"), &vars)?
+        }
     };
 
     Ok(get_result)
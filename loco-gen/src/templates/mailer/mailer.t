@@ -7,6 +7,9 @@ injections:
 - into: "src/mailers/mod.rs"
   append: true
   content: "pub mod {{ module_name }};"
+- into: src/app.rs
+  before: "// mailer-previews-inject"
+  content: "        previews.register::<crate::mailers::{{module_name}}::{{struct_name}}>(ctx);"
 ---
 #![allow(non_upper_case_globals)]
 
@@ -41,3 +44,20 @@ impl {{struct_name}} {
         Ok(())
     }
 }
+
+impl MailerPreview for {{struct_name}} {
+    fn name() -> String {
+        "{{module_name}}".to_string()
+    }
+
+    fn previews(ctx: &AppContext) -> Vec<mailer::preview::Preview> {
+        vec![mailer::preview::Preview::new(
+            "welcome",
+            &welcome,
+            json!({
+              "message": "Hello from the preview",
+              "domain": ctx.config.server.full_url()
+            }),
+        )]
+    }
+}
@@ -0,0 +1,162 @@
+{% set provider = provider | snake_case -%}
+{% set env_prefix = provider | upper -%}
+to: "tests/requests/oauth2_{{provider}}.rs"
+skip_exists: true
+message: "Sign in with `{{provider}}` was added successfully. Enable the `auth_oauth2` feature of `loco-rs`, and set the `{{env_prefix}}_CLIENT_ID` and `{{env_prefix}}_CLIENT_SECRET` environment variables."
+injections:
+- into: src/controllers/auth.rs
+  append: true
+  skip_if: "async fn oauth2_callback"
+  content: |
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct OAuth2CallbackParams {
+        pub code: String,
+        pub state: String,
+    }
+
+    /// Redirects the user to the sign in page of the given OAuth2 provider
+    #[debug_handler]
+    async fn oauth2_authorize(
+        State(ctx): State<AppContext>,
+        Path(provider): Path<String>,
+    ) -> Result<Response> {
+        let authorization = loco_rs::auth::oauth2::OAuth2::from_context(&ctx)?
+            .authorize_url(&provider)
+            .await?;
+        Ok(authorization.into_response())
+    }
+
+    /// Signs in the user returning from the OAuth2 provider, creating the user
+    /// on first sign in
+    #[debug_handler]
+    async fn oauth2_callback(
+        State(ctx): State<AppContext>,
+        Path(provider): Path<String>,
+        axum::extract::Query(params): axum::extract::Query<OAuth2CallbackParams>,
+        headers: axum::http::HeaderMap,
+    ) -> Result<Response> {
+        let oauth2 = loco_rs::auth::oauth2::OAuth2::from_context(&ctx)?;
+        let oauth2_user = oauth2
+            .callback(&provider, &params.code, &params.state, &headers)
+            .await?;
+        let user = <users::Model as loco_rs::auth::oauth2::OAuth2Authenticable>::find_or_create_from_oauth2(
+            &ctx.db,
+            &oauth2_user,
+        )
+        .await?;
+
+        // the same as a password sign in, asking for the second factor when
+        // enabled
+        let response = login_response(&ctx, &user).await?;
+        Ok((oauth2.clear_state_cookie(&provider, &headers)?, response).into_response())
+    }
+- into: src/controllers/auth.rs
+  after: '\.prefix\("/?api/auth"\)'
+  skip_if: 'get\(oauth2_callback\)'
+  content: |2-
+            .add("/oauth2/{provider}", get(oauth2_authorize))
+            .add("/oauth2/{provider}/callback", get(oauth2_callback))
+- into: src/models/users.rs
+  append: true
+  skip_if: "impl loco_rs::auth::oauth2::OAuth2Authenticable"
+  content: |
+
+    #[async_trait]
+    impl loco_rs::auth::oauth2::OAuth2Authenticable for Model {
+        /// Finds the user by the email of the provider account, or creates the
+        /// user on first sign in
+        async fn find_or_create_from_oauth2(
+            db: &DatabaseConnection,
+            oauth2_user: &loco_rs::auth::oauth2::OAuth2User,
+        ) -> ModelResult<Self> {
+            // only trust emails verified by the provider, otherwise signing in
+            // with an unverified email would take over the account
+            let email = oauth2_user
+                .email
+                .as_deref()
+                .filter(|_| oauth2_user.email_verified)
+                .ok_or_else(|| ModelError::msg("a verified email is required to sign in"))?;
+
+            match Self::find_by_email(db, email).await {
+                Err(ModelError::EntityNotFound) => {}
+                user => return user,
+            }
+
+            let password_hash = hash::hash_password(&hash::random_string(32))
+                .map_err(|e| ModelError::Any(e.into()))?;
+            let user = users::ActiveModel {
+                email: ActiveValue::set(email.to_string()),
+                password: ActiveValue::set(password_hash),
+                name: ActiveValue::set(
+                    oauth2_user
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| email.to_string()),
+                ),
+                email_verified_at: ActiveValue::set(Some(Local::now().into())),
+                ..Default::default()
+            }
+            .insert(db)
+            .await?;
+
+            Ok(user)
+        }
+    }
+- into: tests/requests/mod.rs
+  append: true
+  content: "pub mod oauth2_{{provider}};"
+- into: config/development.yaml
+  after: "^auth:"
+  skip_if: "(?m)^  oauth2:"
+  content: |2-
+      # OAuth2 / OpenID Connect sign in providers
+      oauth2:
+        providers:
+- into: config/development.yaml
+  after: "^    providers:"
+  skip_if: "(?m)^      {{provider}}:"
+  content: |2-
+          {{provider}}:
+            kind: {{kind}}
+    {%- if kind == "Oidc" %}
+            issuer: https://{{provider}}.example.com
+    {%- endif %}
+            client_id: {{ "{{" }} get_env(name="{{env_prefix}}_CLIENT_ID", default="") {{ "}}" }}
+            client_secret: {{ "{{" }} get_env(name="{{env_prefix}}_CLIENT_SECRET", default="") {{ "}}" }}
+            redirect_url: http://localhost:5150/api/auth/oauth2/{{provider}}/callback
+- into: config/test.yaml
+  after: "^auth:"
+  skip_if: "(?m)^  oauth2:"
+  content: |2-
+      oauth2:
+        providers:
+- into: config/test.yaml
+  after: "^    providers:"
+  skip_if: "(?m)^      {{provider}}:"
+  content: |2-
+          {{provider}}:
+            kind: {{kind}}
+    {%- if kind == "Oidc" %}
+            issuer: https://{{provider}}.example.com
+    {%- endif %}
+            client_id: test
+            client_secret: test
+            redirect_url: http://localhost:5150/api/auth/oauth2/{{provider}}/callback
+---
+use loco_rs::testing::prelude::*;
+use {{pkg_name}}::app::App;
+use serial_test::serial;
+
+#[tokio::test]
+#[serial]
+async fn cannot_sign_in_with_{{provider}}_with_unknown_state() {
+    request::<App, _, _>(|request, _ctx| async move {
+        let response = request
+            .get("/api/auth/oauth2/{{provider}}/callback?code=code&state=unknown")
+            .await;
+
+        assert_eq!(response.status_code(), 401, "Unknown state should be rejected");
+    })
+    .await;
+}
@@ -0,0 +1,8 @@
+to: "src/policies/mod.rs"
+skip_exists: true
+injections:
+- into: src/lib.rs
+  append: true
+  skip_if: "pub mod policies;"
+  content: "pub mod policies;"
+---
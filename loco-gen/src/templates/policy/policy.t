@@ -0,0 +1,37 @@
+{% set file_name = name | snake_case -%}
+{% set module_name = file_name | pascal_case -%}
+{% set plural_snake = name | plural | snake_case -%}
+to: "src/policies/{{file_name}}.rs"
+skip_exists: true
+message: "A policy `{{module_name}}Policy` was added successfully. Guard handlers with `Authorize<{{module_name}}Policy>`."
+injections:
+- into: "src/policies/mod.rs"
+  append: true
+  content: "pub mod {{ file_name }};"
+---
+use loco_rs::{
+    auth::policy::{Action, Policy, Principal},
+    prelude::*,
+};
+
+use crate::models::{{plural_snake}};
+
+pub struct {{module_name}}Policy;
+
+#[async_trait]
+impl Policy for {{module_name}}Policy {
+    type User = auth::JWT;
+    type Resource = {{plural_snake}}::Model;
+
+    async fn can(
+        _ctx: &AppContext,
+        user: &Self::User,
+        action: &Action,
+        _item: Option<&Self::Resource>,
+    ) -> Result<bool> {
+        Ok(match action {
+            Action::Read => true,
+            _ => user.has_role("admin") || user.has_permission(&format!("{{plural_snake}}:{action}")),
+        })
+    }
+}
@@ -9,6 +9,7 @@ message: "Base template was added successfully."
 <head>
   <meta charset="utf-8">
   <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+  {% raw %}{{ csrf_meta() }}{% endraw %}
   <title>{% raw %}{% block title %}{% endblock title %}{% endraw %}</title>
   <script src="https://cdn.tailwindcss.com?plugins=forms,typography,aspect-ratio,line-clamp"></script>
   {% raw %}{% block head %}{% endraw %}
@@ -40,6 +41,10 @@ message: "Base template was added successfully."
         if (confirm("Are you sure you want to delete this item?")) {
             var xhr = new XMLHttpRequest();
             xhr.open("DELETE", delete_url, true);
+            var csrfToken = document.querySelector('meta[name="csrf-token"]');
+            if (csrfToken) {
+                xhr.setRequestHeader("x-csrf-token", csrfToken.content);
+            }
             xhr.onreadystatechange = function () {
                 if (xhr.readyState == 4 && xhr.status == 200) {
                     window.location.href = redirect_to;
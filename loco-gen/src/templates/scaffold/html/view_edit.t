@@ -17,6 +17,7 @@ Edit {{name}}: {% raw %}{{ item.id }}{% endraw %}
 {% raw %}{% block content %}{% endraw %}
 <div class="mb-10">
     <form action="/{{name | plural}}/{% raw %}{{ item.id }}{% endraw %}" method="post" class="flex-1 lg:max-w-2xl">
+        {% raw %}{{ csrf_field() }}{% endraw %}
     {% for column in columns -%}
             {{ render_form_field(fname=column.0, rust_type=column.1, ftype=column.2, edit_form=true)}}
         {% endfor -%}
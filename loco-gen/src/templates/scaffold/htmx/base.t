@@ -9,6 +9,7 @@ message: "Base template was added successfully."
 <head>
   <meta charset="utf-8">
   <meta name="viewport" content="width=device-width, initial-scale=1.0" />
+  {% raw %}{{ csrf_meta() }}{% endraw %}
   <title>{% raw %}{% block title %}{% endblock title %}{% endraw %}</title>
 
   <script src="https://unpkg.com/htmx.org@2.0.0/dist/htmx.min.js"></script>
@@ -18,7 +19,7 @@ message: "Base template was added successfully."
   {% raw %}{% endblock head %}{% endraw %}
 </head>
 
-<body class="min-h-screen bg-background font-sans antialiased">
+<body class="min-h-screen bg-background font-sans antialiased" {% raw %}{{ csrf_hx_headers() }}{% endraw %}>
     <div class="relative flex min-h-screen flex-col bg-background">
         <div class="themes-wrapper bg-background">
             <main>
@@ -77,6 +78,10 @@ message: "Base template was added successfully."
         if (confirm("Are you sure you want to delete this item?")) {
             var xhr = new XMLHttpRequest();
             xhr.open("DELETE", delete_url, true);
+            var csrfToken = document.querySelector('meta[name="csrf-token"]');
+            if (csrfToken) {
+                xhr.setRequestHeader("x-csrf-token", csrfToken.content);
+            }
             xhr.onreadystatechange = function () {
                 if (xhr.readyState == 4 && xhr.status == 200) {
                     window.location.href = redirect_to;
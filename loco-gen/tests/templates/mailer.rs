@@ -1,3 +1,4 @@
+use super::utils::APP_MAILER;
 use insta::assert_snapshot;
 use loco_gen::{collect_messages, generate, AppInfo, Component};
 use rrgen::RRgen;
@@ -17,6 +18,7 @@ fn can_generate() {
     let tree_fs = tree_fs::TreeBuilder::default()
         .drop(true)
         .add_empty("src/mailers/mod.rs")
+        .add("src/app.rs", APP_MAILER)
         .create()
         .unwrap();
 
@@ -45,6 +47,7 @@ fn can_generate() {
             mailer_path.join("reset_password.rs"),
         ),
         ("inject[mailer_mod_rs]", mailer_path.join("mod.rs")),
+        ("inject[app_rs]", tree_fs.root.join("src").join("app.rs")),
         (
             "generate[subject_t_file]",
             mailer_path
@@ -6,6 +6,10 @@ mod migration;
 #[cfg(feature = "with-db")]
 mod model;
 #[cfg(feature = "with-db")]
+mod oauth2;
+#[cfg(feature = "with-db")]
+mod policy;
+#[cfg(feature = "with-db")]
 mod scaffold;
 mod scheduler;
 mod task;
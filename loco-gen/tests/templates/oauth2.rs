@@ -0,0 +1,101 @@
+use insta::assert_snapshot;
+use loco_gen::{collect_messages, generate, AppInfo, Component};
+use rrgen::RRgen;
+use std::fs;
+
+macro_rules! configure_insta {
+    () => {
+        let mut settings = insta::Settings::clone_current();
+        settings.set_prepend_module_to_snapshot(false);
+        settings.set_snapshot_suffix("oauth2");
+        let _guard = settings.bind_to_scope();
+    };
+}
+
+const AUTH_CONTROLLER: &str = r#"use loco_rs::prelude::*;
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("/api/auth")
+        .add("/login", post(login))
+}
+"#;
+
+const CONFIG: &str = r"server:
+  port: 5150
+auth:
+  jwt:
+    secret: secret
+    expiration: 604800
+";
+
+fn generate_provider(rrgen: &RRgen, provider: &str) -> String {
+    let gen_result = generate(
+        rrgen,
+        Component::OAuth2 {
+            provider: provider.to_string(),
+        },
+        &AppInfo {
+            app_name: "tester".to_string(),
+        },
+    )
+    .expect("Failed to generate components");
+
+    collect_messages(&gen_result)
+}
+
+#[test]
+fn can_generate() {
+    configure_insta!();
+
+    let tree_fs = tree_fs::TreeBuilder::default()
+        .drop(true)
+        .add("src/controllers/auth.rs", AUTH_CONTROLLER)
+        .add("src/models/users.rs", "use loco_rs::prelude::*;\n")
+        .add_empty("tests/requests/mod.rs")
+        .add("config/development.yaml", CONFIG)
+        .add("config/test.yaml", CONFIG)
+        .create()
+        .expect("Failed to create tree_fs structure");
+
+    let rrgen = RRgen::with_working_dir(&tree_fs.root);
+
+    assert_eq!(
+        generate_provider(&rrgen, "google"),
+        "* Sign in with `google` was added successfully. Enable the `auth_oauth2` feature of \
+         `loco-rs`, and set the `GOOGLE_CLIENT_ID` and `GOOGLE_CLIENT_SECRET` environment \
+         variables.\n"
+    );
+    generate_provider(&rrgen, "keycloak");
+
+    assert_snapshot!(
+        "inject[controller_file]",
+        fs::read_to_string(tree_fs.root.join("src/controllers/auth.rs"))
+            .expect("Failed to read updated controller file: auth.rs")
+    );
+    assert_snapshot!(
+        "inject[model_file]",
+        fs::read_to_string(tree_fs.root.join("src/models/users.rs"))
+            .expect("Failed to read updated model file: users.rs")
+    );
+    assert_snapshot!(
+        "inject[development_config]",
+        fs::read_to_string(tree_fs.root.join("config/development.yaml"))
+            .expect("Failed to read updated config file: development.yaml")
+    );
+    assert_snapshot!(
+        "inject[test_config]",
+        fs::read_to_string(tree_fs.root.join("config/test.yaml"))
+            .expect("Failed to read updated config file: test.yaml")
+    );
+    assert_snapshot!(
+        "generate[tests_request_file]",
+        fs::read_to_string(tree_fs.root.join("tests/requests/oauth2_google.rs"))
+            .expect("Failed to read generated test file: oauth2_google.rs")
+    );
+    assert_snapshot!(
+        "inject[tests_request_mod]",
+        fs::read_to_string(tree_fs.root.join("tests/requests/mod.rs"))
+            .expect("Failed to read updated tests mod file: mod.rs")
+    );
+}
@@ -0,0 +1,64 @@
+use insta::assert_snapshot;
+use loco_gen::{collect_messages, generate, AppInfo, Component};
+use rrgen::RRgen;
+use std::fs;
+
+macro_rules! configure_insta {
+    () => {
+        let mut settings = insta::Settings::clone_current();
+        settings.set_prepend_module_to_snapshot(false);
+        settings.set_snapshot_suffix("policy");
+        let _guard = settings.bind_to_scope();
+    };
+}
+
+fn generate_policy(rrgen: &RRgen, name: &str) -> String {
+    let gen_result = generate(
+        rrgen,
+        Component::Policy {
+            name: name.to_string(),
+        },
+        &AppInfo {
+            app_name: "tester".to_string(),
+        },
+    )
+    .expect("Failed to generate components");
+
+    collect_messages(&gen_result)
+}
+
+#[test]
+fn can_generate() {
+    configure_insta!();
+
+    let tree_fs = tree_fs::TreeBuilder::default()
+        .drop(true)
+        .add("src/lib.rs", "pub mod app;\npub mod models;\n")
+        .create()
+        .expect("Failed to create tree_fs structure");
+
+    let rrgen = RRgen::with_working_dir(&tree_fs.root);
+
+    assert_eq!(
+        generate_policy(&rrgen, "note"),
+        "* A policy `NotePolicy` was added successfully. Guard handlers with `Authorize<NotePolicy>`.\n"
+    );
+    generate_policy(&rrgen, "comment");
+
+    let policies_path = tree_fs.root.join("src").join("policies");
+    assert_snapshot!(
+        "generate[policy_file]",
+        fs::read_to_string(policies_path.join("note.rs"))
+            .expect("Failed to read generated policy file: note.rs")
+    );
+    assert_snapshot!(
+        "inject[policies_mod_rs]",
+        fs::read_to_string(policies_path.join("mod.rs"))
+            .expect("Failed to read updated policies mod file: mod.rs")
+    );
+    assert_snapshot!(
+        "inject[lib_rs]",
+        fs::read_to_string(tree_fs.root.join("src").join("lib.rs"))
+            .expect("Failed to read updated lib file: lib.rs")
+    );
+}
@@ -37,6 +37,13 @@ impl Hooks for App {
     }
 ";
 
+pub const APP_MAILER: &str = r"
+impl Hooks for App {
+    fn register_mailer_previews(ctx: &AppContext, previews: &mut MailerPreviews) {
+        // mailer-previews-inject (do not remove)
+    }
+";
+
 pub const APP_WORKER: &str = r"
 async fn connect_workers(ctx: &AppContext, queue: &Queue) -> Result<()> {
     queue.register(DownloadWorker::build(ctx)).await?;
@@ -124,6 +124,10 @@ database:
 
 {%- if settings.auth %}
 
+# Cache configuration, used to store refresh tokens and revoked tokens
+cache:
+  kind: InMem
+
 # Authentication Configuration
 auth:
   # JWT authentication
@@ -132,4 +136,10 @@ auth:
     secret: {{20 | random_string }}
     # Token expiration time in seconds
     expiration: 604800 # 7 days
+    # Refresh tokens, exchanged at `/api/auth/refresh` for a new access token
+    refresh:
+      # Refresh token expiration time in seconds
+      expiration: 1209600 # 14 days
+    # Reject access tokens revoked on logout
+    revocation: true
 {%- endif %}
@@ -27,6 +27,10 @@ impl MigrationTrait for Migration {
                 ("email_verified_at", ColType::TimestampWithTimeZoneNull),
                 ("magic_link_token", ColType::StringNull),
                 ("magic_link_expiration", ColType::TimestampWithTimeZoneNull),
+                ("totp_secret", ColType::StringNull),
+                ("totp_enabled_at", ColType::TimestampWithTimeZoneNull),
+                ("totp_last_step", ColType::BigIntegerNull),
+                ("totp_recovery_codes", ColType::TextNull),
             ],
             &[],
         )
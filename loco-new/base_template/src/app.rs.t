@@ -16,6 +16,9 @@ use loco_rs::{
     db::{self, truncate_table},
     {%- endif %}
     environment::Environment,
+    {%- if settings.mailer %}
+    mailer::preview::MailerPreviews,
+    {%- endif %}
     task::Tasks,
     Result,
 };
@@ -35,6 +38,9 @@ use crate::{
     {%- if settings.background %}
     , workers::downloader::DownloadWorker
     {%- endif %}
+    {%- if settings.mailer %}
+    , mailers::auth::AuthMailer
+    {%- endif %}
 };
 
 pub struct App;
@@ -77,6 +83,10 @@ impl Hooks for App {
         {%- else %}
             .add_route(controllers::home::routes())
         {%- endif %}
+        {%- if settings.mailer %}
+            // mailer previews, answering only in development and test
+            .add_route(loco_rs::mailer::preview::routes::<Self>())
+        {%- endif %}
     }
 
     {%- if settings.background %}
@@ -95,6 +105,14 @@ impl Hooks for App {
         // tasks-inject (do not remove)
     }
 
+    {%- if settings.mailer %}
+
+    fn register_mailer_previews(ctx: &AppContext, previews: &mut MailerPreviews) {
+        previews.register::<AuthMailer>(ctx);
+        // mailer-previews-inject (do not remove)
+    }
+    {%- endif %}
+
     {%- if settings.db %}
 
     {%- if settings.auth %}
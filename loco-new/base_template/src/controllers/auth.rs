@@ -2,18 +2,34 @@ use crate::{
     mailers::auth::AuthMailer,
     models::{
         _entities::users,
-        users::{LoginParams, RegisterParams},
+        users::{LoginParams, RegisterParams, RECOVERY_CODES_COUNT, TOTP_ISSUER},
+    },
+    views::auth::{
+        CurrentResponse, LoginResponse, RecoveryCodesResponse, RefreshResponse,
+        TwoFactorChallengeResponse, TwoFactorSetupResponse,
     },
-    views::auth::{CurrentResponse, LoginResponse},
 };
 use axum::debug_handler;
-use loco_rs::prelude::*;
+use loco_rs::{
+    auth::{
+        tokens::Tokens,
+        totp::{self, Totp},
+    },
+    hash::{self, PasswordHashing},
+    prelude::*,
+};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::sync::OnceLock;
+use serde_json::Map;
+use std::{sync::OnceLock, time::Duration};
 
 pub static EMAIL_DOMAIN_RE: OnceLock<Regex> = OnceLock::new();
 
+/// How long a user has to enter their two-factor code after the login
+const TWO_FACTOR_CHALLENGE_EXPIRATION: Duration = Duration::from_secs(300);
+/// How many codes can be tried for a login
+const TWO_FACTOR_MAX_ATTEMPTS: u64 = 5;
+
 fn get_allow_email_domain_re() -> &'static Regex {
     EMAIL_DOMAIN_RE.get_or_init(|| {
         Regex::new(r"@example\.com$|@gmail\.com$").expect("Failed to compile regex")
@@ -36,6 +52,66 @@ pub struct MagicLinkParams {
     pub email: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RefreshParams {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LogoutParams {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorParams {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorVerifyParams {
+    pub two_factor_token: String,
+    pub code: String,
+}
+
+/// A login waiting for the second factor, kept in the cache.
+#[derive(Debug, Deserialize, Serialize)]
+struct TwoFactorChallenge {
+    pid: String,
+}
+
+fn two_factor_challenge_key(token: &str) -> String {
+    format!("2fa:{token}")
+}
+
+fn two_factor_attempts_key(token: &str) -> String {
+    format!("2fa:{token}:attempts")
+}
+
+/// Issues the tokens of a user who signed in, or a two-factor challenge when
+/// the user enabled two-factor authentication.
+async fn login_response(ctx: &AppContext, user: &users::Model) -> Result<Response> {
+    if user.has_two_factor() {
+        let token = hash::random_string(32);
+        let challenge = TwoFactorChallenge {
+            pid: user.pid.to_string(),
+        };
+        ctx.cache
+            .insert_with_expiry(
+                &two_factor_challenge_key(&token),
+                &challenge,
+                TWO_FACTOR_CHALLENGE_EXPIRATION,
+            )
+            .await?;
+        return format::json(TwoFactorChallengeResponse::new(token));
+    }
+
+    let tokens = Tokens::from_context(ctx)?
+        .issue(&user.pid.to_string(), Map::new())
+        .await?;
+
+    format::json(LoginResponse::new(user, &tokens))
+}
+
 /// Register function creates a new user with the given parameters and sends a
 /// welcome email to the user
 #[debug_handler]
@@ -43,7 +119,12 @@ async fn register(
     State(ctx): State<AppContext>,
     Json(params): Json<RegisterParams>,
 ) -> Result<Response> {
-    let res = users::Model::create_with_password(&ctx.db, &params).await;
+    let res = users::Model::create_with_password(
+        &ctx.db,
+        &PasswordHashing::from_config(&ctx.config),
+        &params,
+    )
+    .await;
 
     let user = match res {
         Ok(user) => user,
@@ -122,13 +203,18 @@ async fn reset(State(ctx): State<AppContext>, Json(params): Json<ResetParams>) -
         return format::json(());
     };
     user.into_active_model()
-        .reset_password(&ctx.db, &params.password)
+        .reset_password(
+            &ctx.db,
+            &PasswordHashing::from_config(&ctx.config),
+            &params.password,
+        )
         .await?;
 
     format::json(())
 }
 
-/// Creates a user login and returns a token
+/// Creates a user login and returns an access token and a refresh token, or
+/// a two-factor challenge when the user enabled two-factor authentication
 #[debug_handler]
 async fn login(State(ctx): State<AppContext>, Json(params): Json<LoginParams>) -> Result<Response> {
     let Ok(user) = users::Model::find_by_email(&ctx.db, &params.email).await else {
@@ -139,19 +225,49 @@ async fn login(State(ctx): State<AppContext>, Json(params): Json<LoginParams>) -
         return unauthorized("Invalid credentials!");
     };
 
-    let valid = user.verify_password(&params.password);
-
-    if !valid {
+    let Some(user) = user
+        .verify_password_and_rehash(
+            &ctx.db,
+            &PasswordHashing::from_config(&ctx.config),
+            &params.password,
+        )
+        .await?
+    else {
         return unauthorized("unauthorized!");
-    }
+    };
 
-    let jwt_secret = ctx.config.get_jwt_config()?;
+    login_response(&ctx, &user).await
+}
 
-    let token = user
-        .generate_jwt(&jwt_secret.secret, jwt_secret.expiration)
-        .or_else(|_| unauthorized("unauthorized!"))?;
+/// Exchanges a refresh token for a new access token and a new refresh token.
+/// A refresh token can be used once, reusing it revokes all the tokens
+/// refreshed from the same login.
+#[debug_handler]
+async fn refresh(
+    State(ctx): State<AppContext>,
+    Json(params): Json<RefreshParams>,
+) -> Result<Response> {
+    let tokens = Tokens::from_context(&ctx)?
+        .refresh(&params.refresh_token)
+        .await?;
 
-    format::json(LoginResponse::new(&user, &token))
+    format::json(RefreshResponse::new(tokens))
+}
+
+/// Revokes the current access token and the given refresh token.
+#[debug_handler]
+async fn logout(
+    auth: auth::JWT,
+    State(ctx): State<AppContext>,
+    Json(params): Json<LogoutParams>,
+) -> Result<Response> {
+    let tokens = Tokens::from_context(&ctx)?;
+    tokens.revoke(&auth.claims).await?;
+    if let Some(refresh_token) = params.refresh_token {
+        tokens.revoke_refresh_token(&refresh_token).await?;
+    }
+
+    format::empty_json()
 }
 
 #[debug_handler]
@@ -170,7 +286,7 @@ async fn current(auth: auth::JWT, State(ctx): State<AppContext>) -> Result<Respo
 ///
 /// 2. **Click the Magic Link**:
 ///    The user clicks the link (/magic-link/{token}), which validates the token and its expiration.
-///    If valid, the server generates a JWT and responds with a [`LoginResponse`].
+///    If valid, the server generates a JWT (and a refresh token) and responds with a [`LoginResponse`].
 ///    If invalid or expired, an unauthorized response is returned.
 ///
 /// This flow enhances security by avoiding traditional passwords and providing a seamless login experience.
@@ -213,13 +329,112 @@ async fn magic_link_verify(
 
     let user = user.into_active_model().clear_magic_link(&ctx.db).await?;
 
-    let jwt_secret = ctx.config.get_jwt_config()?;
+    login_response(&ctx, &user).await
+}
 
-    let token = user
-        .generate_jwt(&jwt_secret.secret, jwt_secret.expiration)
-        .or_else(|_| unauthorized("unauthorized!"))?;
+/// Completes a login of a user who enabled two-factor authentication, with a
+/// code of their authenticator app or a recovery code.
+#[debug_handler]
+async fn two_factor_verify(
+    State(ctx): State<AppContext>,
+    Json(params): Json<TwoFactorVerifyParams>,
+) -> Result<Response> {
+    let key = two_factor_challenge_key(&params.two_factor_token);
+    let Some(challenge) = ctx.cache.get::<TwoFactorChallenge>(&key).await? else {
+        return unauthorized("unauthorized!");
+    };
+
+    // attempts are counted before the code is checked, so that concurrent
+    // requests can't try more codes
+    let attempts = ctx
+        .cache
+        .increment(
+            &two_factor_attempts_key(&params.two_factor_token),
+            TWO_FACTOR_CHALLENGE_EXPIRATION,
+        )
+        .await?;
+    if attempts > TWO_FACTOR_MAX_ATTEMPTS {
+        ctx.cache.remove(&key).await?;
+        return unauthorized("unauthorized!");
+    }
+
+    let user = users::Model::find_by_pid(&ctx.db, &challenge.pid).await?;
+    if !user.verify_two_factor(&ctx.db, &params.code).await? {
+        if attempts == TWO_FACTOR_MAX_ATTEMPTS {
+            ctx.cache.remove(&key).await?;
+        }
+        return unauthorized("invalid code");
+    }
+    ctx.cache.remove(&key).await?;
+
+    let tokens = Tokens::from_context(&ctx)?
+        .issue(&user.pid.to_string(), Map::new())
+        .await?;
+
+    format::json(LoginResponse::new(&user, &tokens))
+}
+
+/// Starts enabling two-factor authentication, returning a new secret and its
+/// QR code to scan with an authenticator app.
+#[debug_handler]
+async fn two_factor_setup(auth: auth::JWT, State(ctx): State<AppContext>) -> Result<Response> {
+    let user = users::Model::find_by_pid(&ctx.db, &auth.claims.pid).await?;
+    if user.has_two_factor() {
+        return bad_request("two-factor authentication is already enabled");
+    }
+
+    let secret = Totp::generate_secret();
+    let totp = Totp::new(&secret, TOTP_ISSUER, &user.email)?;
+    user.into_active_model()
+        .set_totp_secret(&ctx.db, &secret)
+        .await?;
 
-    format::json(LoginResponse::new(&user, &token))
+    format::json(TwoFactorSetupResponse::new(&totp, totp.qr_svg()?))
+}
+
+/// Enables two-factor authentication once the user entered a code of their
+/// authenticator app, returning recovery codes to keep in a safe place.
+#[debug_handler]
+async fn two_factor_enable(
+    auth: auth::JWT,
+    State(ctx): State<AppContext>,
+    Json(params): Json<TwoFactorParams>,
+) -> Result<Response> {
+    let user = users::Model::find_by_pid(&ctx.db, &auth.claims.pid).await?;
+    if user.has_two_factor() {
+        return bad_request("two-factor authentication is already enabled");
+    }
+    let Some(step) = user
+        .totp()
+        .and_then(|totp| totp.verify_current(&params.code, None))
+    else {
+        return bad_request("invalid code");
+    };
+
+    let recovery_codes = totp::generate_recovery_codes(RECOVERY_CODES_COUNT);
+    user.into_active_model()
+        .enable_two_factor(&ctx.db, step, &recovery_codes)
+        .await?;
+
+    format::json(RecoveryCodesResponse::new(recovery_codes))
+}
+
+/// Disables two-factor authentication, with a code of the authenticator app
+/// or a recovery code.
+#[debug_handler]
+async fn two_factor_disable(
+    auth: auth::JWT,
+    State(ctx): State<AppContext>,
+    Json(params): Json<TwoFactorParams>,
+) -> Result<Response> {
+    let user = users::Model::find_by_pid(&ctx.db, &auth.claims.pid).await?;
+    if !user.has_two_factor() || !user.verify_two_factor(&ctx.db, &params.code).await? {
+        return bad_request("invalid code");
+    }
+
+    user.into_active_model().disable_two_factor(&ctx.db).await?;
+
+    format::empty_json()
 }
 
 pub fn routes() -> Routes {
@@ -228,9 +443,15 @@ pub fn routes() -> Routes {
         .add("/register", post(register))
         .add("/verify/{token}", get(verify))
         .add("/login", post(login))
+        .add("/refresh", post(refresh))
+        .add("/logout", post(logout))
         .add("/forgot", post(forgot))
         .add("/reset", post(reset))
         .add("/current", get(current))
         .add("/magic-link", post(magic_link))
         .add("/magic-link/{token}", get(magic_link_verify))
+        .add("/2fa/verify", post(two_factor_verify))
+        .add("/2fa/setup", post(two_factor_setup))
+        .add("/2fa/enable", post(two_factor_enable))
+        .add("/2fa/disable", post(two_factor_disable))
 }
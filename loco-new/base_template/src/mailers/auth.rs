@@ -88,3 +88,42 @@ impl AuthMailer {
         Ok(())
     }
 }
+
+impl MailerPreview for AuthMailer {
+    fn name() -> String {
+        "auth".to_string()
+    }
+
+    fn previews(ctx: &AppContext) -> Vec<mailer::preview::Preview> {
+        let domain = ctx.config.server.full_url();
+        vec![
+            mailer::preview::Preview::new(
+                "welcome",
+                &welcome,
+                json!({
+                  "name": "Jane Doe",
+                  "verifyToken": "preview-verify-token",
+                  "domain": domain
+                }),
+            ),
+            mailer::preview::Preview::new(
+                "forgot",
+                &forgot,
+                json!({
+                  "name": "Jane Doe",
+                  "resetToken": "preview-reset-token",
+                  "domain": domain
+                }),
+            ),
+            mailer::preview::Preview::new(
+                "magic_link",
+                &magic_link,
+                json!({
+                  "name": "Jane Doe",
+                  "token": "preview-magic-link-token",
+                  "host": domain
+                }),
+            ),
+        ]
+    }
+}
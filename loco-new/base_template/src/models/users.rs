@@ -1,6 +1,14 @@
 use async_trait::async_trait;
 use chrono::{offset::Local, Duration};
-use loco_rs::{auth::jwt, hash, prelude::*};
+use loco_rs::{
+    auth::{
+        jwt,
+        totp::{self, Totp},
+    },
+    hash::{self, PasswordHashing},
+    prelude::*,
+};
+use sea_orm::{sea_query::Expr, Condition};
 use serde::{Deserialize, Serialize};
 use serde_json::Map;
 use uuid::Uuid;
@@ -9,6 +17,9 @@ pub use super::_entities::users::{self, ActiveModel, Entity, Model};
 
 pub const MAGIC_LINK_LENGTH: i8 = 32;
 pub const MAGIC_LINK_EXPIRATION_MIN: i8 = 5;
+/// The issuer shown by authenticator apps
+pub const TOTP_ISSUER: &str = env!("CARGO_PKG_NAME");
+pub const RECOVERY_CODES_COUNT: usize = 8;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoginParams {
@@ -213,6 +224,34 @@ impl Model {
         hash::verify_password(password, &self.password)
     }
 
+    /// Verifies the provided plain password, and stores a new hash of it when
+    /// the current hash was made with another algorithm or other parameters
+    /// than the given ones (see `auth.password` in the configuration).
+    /// Returns `None` when the password does not match.
+    ///
+    /// # Errors
+    ///
+    /// when could not hash the password, or could not save the user
+    pub async fn verify_password_and_rehash(
+        self,
+        db: &DatabaseConnection,
+        password_hashing: &PasswordHashing,
+        password: &str,
+    ) -> ModelResult<Option<Self>> {
+        match password_hashing
+            .verify_and_maybe_rehash(password, &self.password)
+            .map_err(|e| ModelError::Any(e.into()))?
+        {
+            hash::Verification::Invalid => Ok(None),
+            hash::Verification::Valid => Ok(Some(self)),
+            hash::Verification::Rehashed(hashed) => {
+                let mut user = self.into_active_model();
+                user.password = ActiveValue::set(hashed);
+                Ok(Some(user.update(db).await?))
+            }
+        }
+    }
+
     /// Asynchronously creates a user with a password and saves it to the
     /// database.
     ///
@@ -221,6 +260,7 @@ impl Model {
     /// When could not save the user into the DB
     pub async fn create_with_password(
         db: &DatabaseConnection,
+        password_hashing: &PasswordHashing,
         params: &RegisterParams,
     ) -> ModelResult<Self> {
         let txn = db.begin().await?;
@@ -238,8 +278,9 @@ impl Model {
             return Err(ModelError::EntityAlreadyExists {});
         }
 
-        let password_hash =
-            hash::hash_password(&params.password).map_err(|e| ModelError::Any(e.into()))?;
+        let password_hash = password_hashing
+            .hash(&params.password)
+            .map_err(|e| ModelError::Any(e.into()))?;
         let user = users::ActiveModel {
             email: ActiveValue::set(params.email.to_string()),
             password: ActiveValue::set(password_hash),
@@ -264,6 +305,82 @@ impl Model {
             .generate_token(expiration, self.pid.to_string(), Map::new())
             .map_err(ModelError::from)
     }
+
+    /// Returns the TOTP generator of the user, once a secret was set up.
+    #[must_use]
+    pub fn totp(&self) -> Option<Totp> {
+        self.totp_secret
+            .as_deref()
+            .and_then(|secret| Totp::new(secret, TOTP_ISSUER, &self.email).ok())
+    }
+
+    /// Returns whether the user enabled two-factor authentication.
+    #[must_use]
+    pub fn has_two_factor(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    /// Verifies a code of the authenticator app, or a recovery code, and
+    /// records its use so that it can't be used again.
+    ///
+    /// # Errors
+    ///
+    /// when has DB query error
+    pub async fn verify_two_factor(
+        &self,
+        db: &DatabaseConnection,
+        code: &str,
+    ) -> ModelResult<bool> {
+        let Some(totp) = self.totp() else {
+            return Ok(false);
+        };
+
+        let last_step = self
+            .totp_last_step
+            .and_then(|step| u64::try_from(step).ok());
+        if let Some(step) = totp.verify_current(code, last_step) {
+            let step = i64::try_from(step).map_err(|e| ModelError::Any(e.into()))?;
+            // only one of several concurrent logins can use the code
+            let result = users::Entity::update_many()
+                .col_expr(users::Column::TotpLastStep, Expr::value(step))
+                .filter(users::Column::Id.eq(self.id))
+                .filter(
+                    Condition::any()
+                        .add(users::Column::TotpLastStep.is_null())
+                        .add(users::Column::TotpLastStep.lt(step)),
+                )
+                .exec(db)
+                .await?;
+            return Ok(result.rows_affected == 1);
+        }
+
+        // recovery codes are longer than the codes of authenticator apps
+        if code.trim().len() <= 8 {
+            return Ok(false);
+        }
+        let mut hashes: Vec<String> = self
+            .totp_recovery_codes
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .map(ToString::to_string)
+            .collect();
+        let Some(index) = totp::verify_recovery_code(code, &hashes) else {
+            return Ok(false);
+        };
+        hashes.remove(index);
+        // only one of several concurrent logins can use the recovery code
+        let result = users::Entity::update_many()
+            .col_expr(
+                users::Column::TotpRecoveryCodes,
+                Expr::value(hashes.join("\n")),
+            )
+            .filter(users::Column::Id.eq(self.id))
+            .filter(users::Column::TotpRecoveryCodes.eq(self.totp_recovery_codes.clone()))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
 }
 
 impl ActiveModel {
@@ -329,10 +446,14 @@ impl ActiveModel {
     pub async fn reset_password(
         mut self,
         db: &DatabaseConnection,
+        password_hashing: &PasswordHashing,
         password: &str,
     ) -> ModelResult<Model> {
-        self.password =
-            ActiveValue::set(hash::hash_password(password).map_err(|e| ModelError::Any(e.into()))?);
+        self.password = ActiveValue::set(
+            password_hashing
+                .hash(password)
+                .map_err(|e| ModelError::Any(e.into()))?,
+        );
         self.reset_token = ActiveValue::Set(None);
         self.reset_sent_at = ActiveValue::Set(None);
         self.update(db).await.map_err(ModelError::from)
@@ -366,4 +487,56 @@ impl ActiveModel {
         self.magic_link_expiration = ActiveValue::set(None);
         self.update(db).await.map_err(ModelError::from)
     }
+
+    /// Sets a new TOTP secret, which is enabled once the user confirms it with
+    /// a code of their authenticator app (see [`ActiveModel::enable_two_factor`]).
+    ///
+    /// # Errors
+    ///
+    /// when has DB query error
+    pub async fn set_totp_secret(
+        mut self,
+        db: &DatabaseConnection,
+        secret: &str,
+    ) -> ModelResult<Model> {
+        self.totp_secret = ActiveValue::set(Some(secret.to_string()));
+        self.totp_enabled_at = ActiveValue::set(None);
+        self.totp_last_step = ActiveValue::set(None);
+        self.totp_recovery_codes = ActiveValue::set(None);
+        self.update(db).await.map_err(ModelError::from)
+    }
+
+    /// Enables two-factor authentication, with the time step of the code
+    /// which confirmed the secret, and the hashes of the given recovery codes.
+    ///
+    /// # Errors
+    ///
+    /// when has DB query error or could not hash the recovery codes
+    pub async fn enable_two_factor(
+        mut self,
+        db: &DatabaseConnection,
+        step: u64,
+        recovery_codes: &[String],
+    ) -> ModelResult<Model> {
+        let hashes =
+            totp::hash_recovery_codes(recovery_codes).map_err(|e| ModelError::Any(e.into()))?;
+        self.totp_enabled_at = ActiveValue::set(Some(Local::now().into()));
+        self.totp_last_step = ActiveValue::set(i64::try_from(step).ok());
+        self.totp_recovery_codes = ActiveValue::set(Some(hashes.join("\n")));
+        self.update(db).await.map_err(ModelError::from)
+    }
+
+    /// Disables two-factor authentication, removing the secret and the
+    /// recovery codes.
+    ///
+    /// # Errors
+    ///
+    /// when has DB query error
+    pub async fn disable_two_factor(mut self, db: &DatabaseConnection) -> ModelResult<Model> {
+        self.totp_secret = ActiveValue::set(None);
+        self.totp_enabled_at = ActiveValue::set(None);
+        self.totp_last_step = ActiveValue::set(None);
+        self.totp_recovery_codes = ActiveValue::set(None);
+        self.update(db).await.map_err(ModelError::from)
+    }
 }
@@ -1,3 +1,4 @@
+use loco_rs::auth::{tokens::TokenPair, totp::Totp};
 use serde::{Deserialize, Serialize};
 
 use crate::models::_entities::users;
@@ -5,6 +6,8 @@ use crate::models::_entities::users;
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
     pub pid: String,
     pub name: String,
     pub is_verified: bool,
@@ -12,9 +15,10 @@ pub struct LoginResponse {
 
 impl LoginResponse {
     #[must_use]
-    pub fn new(user: &users::Model, token: &String) -> Self {
+    pub fn new(user: &users::Model, tokens: &TokenPair) -> Self {
         Self {
-            token: token.to_string(),
+            token: tokens.access_token.clone(),
+            refresh_token: tokens.refresh_token.clone(),
             pid: user.pid.to_string(),
             name: user.name.clone(),
             is_verified: user.email_verified_at.is_some(),
@@ -22,6 +26,22 @@ impl LoginResponse {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RefreshResponse {
+    pub token: String,
+    pub refresh_token: Option<String>,
+}
+
+impl RefreshResponse {
+    #[must_use]
+    pub fn new(tokens: TokenPair) -> Self {
+        Self {
+            token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CurrentResponse {
     pub pid: String,
@@ -39,3 +59,48 @@ impl CurrentResponse {
         }
     }
 }
+
+/// Returned on login instead of the tokens when the user enabled two-factor
+/// authentication. The token is exchanged at `/api/auth/2fa/verify` along
+/// with a code.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorChallengeResponse {
+    pub two_factor_token: String,
+}
+
+impl TwoFactorChallengeResponse {
+    #[must_use]
+    pub fn new(two_factor_token: String) -> Self {
+        Self { two_factor_token }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    pub uri: String,
+    pub qr_svg: String,
+}
+
+impl TwoFactorSetupResponse {
+    #[must_use]
+    pub fn new(totp: &Totp, qr_svg: String) -> Self {
+        Self {
+            secret: totp.secret(),
+            uri: totp.provisioning_uri(),
+            qr_svg,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+impl RecoveryCodesResponse {
+    #[must_use]
+    pub fn new(recovery_codes: Vec<String>) -> Self {
+        Self { recovery_codes }
+    }
+}
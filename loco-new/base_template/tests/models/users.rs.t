@@ -1,6 +1,6 @@
 use chrono::{offset::Local, Duration};
 use insta::assert_debug_snapshot;
-use loco_rs::testing::prelude::*;
+use loco_rs::{hash::PasswordHashing, testing::prelude::*};
 use {{settings.module_name}}::{
     app::App,
     models::users::{self, Model, RegisterParams},
@@ -48,7 +48,12 @@ async fn can_create_with_password() {
         name: "framework".to_string(),
     };
 
-    let res = Model::create_with_password(&boot.app_context.db, &params).await;
+    let res = Model::create_with_password(
+        &boot.app_context.db,
+        &PasswordHashing::from_config(&boot.app_context.config),
+        &params,
+    )
+    .await;
 
     insta::with_settings!({
         filters => cleanup_user_model()
@@ -66,6 +71,7 @@ async fn handle_create_with_password_with_duplicate() {
 
     let new_user = Model::create_with_password(
         &boot.app_context.db,
+        &PasswordHashing::from_config(&boot.app_context.config),
         &RegisterParams {
             email: "user1@example.com".to_string(),
             password: "1234".to_string(),
@@ -215,7 +221,11 @@ async fn can_reset_password() {
     let result = user
         .clone()
         .into_active_model()
-        .reset_password(&boot.app_context.db, "new-password")
+        .reset_password(
+            &boot.app_context.db,
+            &PasswordHashing::from_config(&boot.app_context.config),
+            "new-password",
+        )
         .await;
 
     assert!(result.is_ok(), "Failed to reset password");
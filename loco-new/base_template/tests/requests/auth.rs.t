@@ -1,5 +1,6 @@
 use insta::{assert_debug_snapshot, with_settings};
-use loco_rs::testing::prelude::*;
+use loco_rs::{auth::totp::Totp, testing::prelude::*, TestServer};
+use std::time::{SystemTime, UNIX_EPOCH};
 use {{settings.module_name}}::{app::App, models::users};
 use rstest::rstest;
 use serial_test::serial;
@@ -284,6 +285,131 @@ async fn can_get_current_user() {
     .await;
 }
 
+#[tokio::test]
+#[serial]
+async fn can_refresh_token() {
+    configure_insta!();
+
+    request::<App, _, _>(|request, ctx| async move {
+        let user = prepare_data::init_user_login(&request, &ctx).await;
+        let payload = serde_json::json!({
+            "refresh_token": user.refresh_token,
+        });
+
+        let response = request.post("/api/auth/refresh").json(&payload).await;
+        assert_eq!(response.status_code(), 200, "Refresh request should succeed");
+
+        // a refresh token can only be used once
+        let response = request.post("/api/auth/refresh").json(&payload).await;
+        assert_eq!(response.status_code(), 401, "Reused refresh token should be rejected");
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn can_logout() {
+    configure_insta!();
+
+    request::<App, _, _>(|request, ctx| async move {
+        let user = prepare_data::init_user_login(&request, &ctx).await;
+
+        let (auth_key, auth_value) = prepare_data::auth_header(&user.token);
+        let response = request
+            .post("/api/auth/logout")
+            .add_header(auth_key.clone(), auth_value.clone())
+            .json(&serde_json::json!({
+                "refresh_token": user.refresh_token,
+            }))
+            .await;
+        assert_eq!(response.status_code(), 200, "Logout request should succeed");
+
+        let response = request
+            .get("/api/auth/current")
+            .add_header(auth_key, auth_value)
+            .await;
+        assert_eq!(response.status_code(), 401, "Revoked token should be rejected");
+    })
+    .await;
+}
+
+async fn two_factor_login(request: &TestServer, code: &str) -> u16 {
+    let response = request
+        .post("/api/auth/login")
+        .json(&serde_json::json!({
+            "email": "test@loco.com",
+            "password": "1234"
+        }))
+        .await;
+    let challenge: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+    assert!(
+        challenge.get("token").is_none(),
+        "Login should require a code"
+    );
+
+    let response = request
+        .post("/api/auth/2fa/verify")
+        .json(&serde_json::json!({
+            "two_factor_token": challenge["two_factor_token"],
+            "code": code,
+        }))
+        .await;
+    response.status_code().as_u16()
+}
+
+#[tokio::test]
+#[serial]
+async fn can_login_with_two_factor() {
+    configure_insta!();
+
+    request::<App, _, _>(|request, ctx| async move {
+        let user = prepare_data::init_user_login(&request, &ctx).await;
+        let (auth_key, auth_value) = prepare_data::auth_header(&user.token);
+
+        let response = request
+            .post("/api/auth/2fa/setup")
+            .add_header(auth_key.clone(), auth_value.clone())
+            .await;
+        assert_eq!(response.status_code(), 200, "Setup request should succeed");
+        let setup: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+        assert!(setup["qr_svg"].as_str().unwrap().starts_with("<svg"));
+        let totp = Totp::new(setup["secret"].as_str().unwrap(), "test", "test").unwrap();
+
+        let code = totp.generate_current();
+        let response = request
+            .post("/api/auth/2fa/enable")
+            .add_header(auth_key, auth_value)
+            .json(&serde_json::json!({ "code": code }))
+            .await;
+        assert_eq!(response.status_code(), 200, "Enable request should succeed");
+        let enabled: serde_json::Value = serde_json::from_str(&response.text()).unwrap();
+        let recovery_codes = enabled["recovery_codes"].as_array().unwrap();
+
+        assert_eq!(
+            two_factor_login(&request, &code).await,
+            401,
+            "A code can only be used once"
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(
+            two_factor_login(&request, &totp.generate(now + 30)).await,
+            200
+        );
+
+        let recovery_code = recovery_codes[0].as_str().unwrap();
+        assert_eq!(two_factor_login(&request, recovery_code).await, 200);
+        assert_eq!(
+            two_factor_login(&request, recovery_code).await,
+            401,
+            "A recovery code can only be used once"
+        );
+    })
+    .await;
+}
+
 #[tokio::test]
 #[serial]
 async fn can_auth_with_magic_link() {
@@ -8,6 +8,7 @@ const USER_PASSWORD: &str = "1234";
 pub struct LoggedInUser {
     pub user: users::Model,
     pub token: String,
+    pub refresh_token: Option<String>,
 }
 
 pub async fn init_user_login(request: &TestServer, ctx: &AppContext) -> LoggedInUser {
@@ -47,6 +48,7 @@ pub async fn init_user_login(request: &TestServer, ctx: &AppContext) -> LoggedIn
             .await
             .unwrap(),
         token: login_response.token,
+        refresh_token: login_response.refresh_token,
     }
 }
 
@@ -33,7 +33,8 @@ fn test_config_file_with_auth(
     let content = assertion::yaml::load(generator.path(config_file));
     assertion::yaml::assert_path_key_count(&content, &["auth"], 1);
 
-    assertion::yaml::assert_path_key_count(&content, &["auth", "jwt"], 2);
+    assertion::yaml::assert_path_key_count(&content, &["auth", "jwt"], 4);
+    assertion::yaml::assert_path_key_count(&content, &["auth", "jwt", "refresh"], 1);
 }
 
 #[test]
@@ -23,7 +23,7 @@ use crate::{
         AppRoutes,
     },
     environment::Environment,
-    mailer::EmailSender,
+    mailer::{preview::MailerPreviews, EmailSender},
     storage::Storage,
     task::Tasks,
     Result,
@@ -422,6 +422,14 @@ pub trait Hooks: Send {
     /// Registers custom tasks with the provided [`Tasks`] object.
     fn register_tasks(tasks: &mut Tasks);
 
+    /// Registers the mailer previews, see [`crate::mailer::preview`].
+    fn register_mailer_previews(_ctx: &AppContext, _previews: &mut MailerPreviews) {}
+
+    /// Registers the mailboxes processing the received emails, see
+    /// [`crate::mailer::inbound`].
+    #[cfg(feature = "mailer_inbound")]
+    fn register_mailboxes(_ctx: &AppContext, _mailboxes: &mut crate::mailer::inbound::Mailboxes) {}
+
     /// Truncates the database as required. Users should implement this
     /// function. The truncate controlled from the [`crate::config::Database`]
     /// by changing dangerously_truncate to true (default false).
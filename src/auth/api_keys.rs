@@ -0,0 +1,499 @@
+//! # API Keys
+//!
+//! Named, scoped and expiring API keys. A user can hold any number of keys,
+//! each one revoked on its own.
+//!
+//! A key looks like `lo_<prefix>_<secret>`. Only the prefix and a hash of the
+//! secret are stored, so a key is shown once, when it is created. The prefix
+//! identifies the key in listings and when revoking it.
+//!
+//! Keys are kept in the `loco_api_keys` table, which is created on first use
+//! when missing. Use the `ApiToken` extractor to authenticate requests, and
+//! `cargo loco api-key` to create, list and revoke keys.
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::{Alias, ColumnDef, Expr, Index, Order, Query, Table},
+    ConnectionTrait, DatabaseConnection, QueryResult,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::policy::Principal;
+use crate::{app::AppContext, db::LazySchema, hash, Error, Result};
+
+const TABLE: &str = "loco_api_keys";
+const KEY_PREFIX: &str = "lo_";
+const PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+
+/// The last used time of a key is written at most once in this many seconds.
+const LAST_USED_RESOLUTION: i64 = 60;
+
+/// An API key, without its secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Identifies the key, the part after `lo_` in the key
+    pub prefix: String,
+    /// The key owner, as found by `Authenticable::find_by_claims_key`
+    pub owner: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Returns `true` when the key is neither revoked nor expired.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.map_or(true, |at| at > Utc::now())
+    }
+
+    /// Returns `true` when the key grants the given scope. Scopes support the
+    /// same wildcards as [`Principal::has_permission`].
+    #[must_use]
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.has_permission(scope)
+    }
+
+    fn from_row(row: &QueryResult) -> Result<Self> {
+        let timestamp = |column: &str| -> Result<Option<DateTime<Utc>>> {
+            Ok(row
+                .try_get::<Option<i64>>("", column)?
+                .and_then(|ts| DateTime::from_timestamp(ts, 0)))
+        };
+        Ok(Self {
+            prefix: row.try_get("", "prefix")?,
+            owner: row.try_get("", "owner")?,
+            name: row.try_get("", "name")?,
+            scopes: row
+                .try_get::<String>("", "scopes")?
+                .split_whitespace()
+                .map(ToString::to_string)
+                .collect(),
+            created_at: timestamp("created_at")?.unwrap_or_default(),
+            expires_at: timestamp("expires_at")?,
+            last_used_at: timestamp("last_used_at")?,
+            revoked_at: timestamp("revoked_at")?,
+        })
+    }
+}
+
+impl Principal for ApiKey {
+    fn roles(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn permissions(&self) -> Vec<String> {
+        self.scopes.clone()
+    }
+}
+
+/// A newly created key, along with the only copy of its token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewApiKey {
+    pub key: ApiKey,
+    /// The full key to hand out, `lo_<prefix>_<secret>`
+    pub token: String,
+}
+
+/// Returns `true` when the given token has the format of an API key created
+/// by [`ApiKeys`].
+#[must_use]
+pub fn is_api_key(token: &str) -> bool {
+    split_token(token).is_some()
+}
+
+fn split_token(token: &str) -> Option<(&str, &str)> {
+    let (prefix, secret) = token.strip_prefix(KEY_PREFIX)?.split_once('_')?;
+    let valid = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|b| b.is_ascii_alphanumeric())
+    };
+    (valid(prefix, PREFIX_LENGTH) && valid(secret, SECRET_LENGTH)).then_some((prefix, secret))
+}
+
+fn secret_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn invalid_api_key() -> Error {
+    Error::Unauthorized("api key is not valid".to_string())
+}
+
+/// Creates, authenticates and revokes API keys.
+pub struct ApiKeys {
+    db: DatabaseConnection,
+    schema: LazySchema,
+}
+
+impl ApiKeys {
+    /// Creates a new instance keeping keys in the given database.
+    #[must_use]
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self {
+            db,
+            schema: LazySchema::default(),
+        }
+    }
+
+    /// Returns the instance kept in the shared store, creating it on first
+    /// use.
+    #[must_use]
+    pub fn from_context(ctx: &AppContext) -> Arc<Self> {
+        if let Some(keys) = ctx.shared_store.get::<Arc<Self>>() {
+            return keys;
+        }
+        let keys = Arc::new(Self::new(ctx.db.clone()));
+        ctx.shared_store.insert(keys.clone());
+        keys
+    }
+
+    fn table() -> Alias {
+        Alias::new(TABLE)
+    }
+
+    async fn create_table(&self) -> Result<()> {
+        self.schema
+            .ensure(&self.db, |backend| {
+                let table = Table::create()
+                    .table(Self::table())
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("prefix"))
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("key_hash")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("owner")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("name")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("scopes")).text().not_null())
+                    .col(
+                        ColumnDef::new(Alias::new("created_at"))
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Alias::new("expires_at"))
+                            .big_integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Alias::new("last_used_at"))
+                            .big_integer()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Alias::new("revoked_at"))
+                            .big_integer()
+                            .null(),
+                    )
+                    .to_owned();
+                let index = Index::create()
+                    .name(format!("idx_{TABLE}_owner"))
+                    .table(Self::table())
+                    .col(Alias::new("owner"))
+                    .if_not_exists()
+                    .to_owned();
+                vec![backend.build(&table), backend.build(&index)]
+            })
+            .await
+    }
+
+    /// Creates a key for the given owner. The returned token is not stored
+    /// and cannot be recovered later.
+    ///
+    /// # Errors
+    ///
+    /// When the key could not be stored.
+    pub async fn create(
+        &self,
+        owner: &str,
+        name: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<NewApiKey> {
+        self.create_table().await?;
+
+        let prefix = hash::random_string(PREFIX_LENGTH);
+        let secret = hash::random_string(SECRET_LENGTH);
+        let now = Utc::now();
+        let statement = Query::insert()
+            .into_table(Self::table())
+            .columns([
+                Alias::new("prefix"),
+                Alias::new("key_hash"),
+                Alias::new("owner"),
+                Alias::new("name"),
+                Alias::new("scopes"),
+                Alias::new("created_at"),
+                Alias::new("expires_at"),
+            ])
+            .values_panic([
+                prefix.as_str().into(),
+                secret_hash(&secret).into(),
+                owner.into(),
+                name.into(),
+                scopes.join(" ").into(),
+                now.timestamp().into(),
+                expires_at.map(|at| at.timestamp()).into(),
+            ])
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+
+        Ok(NewApiKey {
+            token: format!("{KEY_PREFIX}{prefix}_{secret}"),
+            key: ApiKey {
+                prefix,
+                owner: owner.to_string(),
+                name: name.to_string(),
+                scopes: scopes.to_vec(),
+                created_at: DateTime::from_timestamp(now.timestamp(), 0).unwrap_or(now),
+                expires_at,
+                last_used_at: None,
+                revoked_at: None,
+            },
+        })
+    }
+
+    /// Returns the key with the given prefix, active or not.
+    ///
+    /// # Errors
+    ///
+    /// When the keys could not be read.
+    pub async fn find(&self, prefix: &str) -> Result<Option<ApiKey>> {
+        self.create_table().await?;
+
+        let statement = Query::select()
+            .expr(Expr::cust("*"))
+            .from(Self::table())
+            .and_where(Expr::col(Alias::new("prefix")).eq(prefix))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db
+            .query_one(backend.build(&statement))
+            .await?
+            .map(|row| ApiKey::from_row(&row))
+            .transpose()
+    }
+
+    /// Returns the keys of the given owner, or all the keys, newest first.
+    ///
+    /// # Errors
+    ///
+    /// When the keys could not be read.
+    pub async fn list(&self, owner: Option<&str>) -> Result<Vec<ApiKey>> {
+        self.create_table().await?;
+
+        let mut statement = Query::select();
+        statement
+            .expr(Expr::cust("*"))
+            .from(Self::table())
+            .order_by(Alias::new("created_at"), Order::Desc);
+        if let Some(owner) = owner {
+            statement.and_where(Expr::col(Alias::new("owner")).eq(owner));
+        }
+        let backend = self.db.get_database_backend();
+        self.db
+            .query_all(backend.build(&statement))
+            .await?
+            .iter()
+            .map(ApiKey::from_row)
+            .collect()
+    }
+
+    /// Authenticates the given token, returning its key and recording when
+    /// it was last used.
+    ///
+    /// # Errors
+    ///
+    /// When the token is unknown, revoked or expired, or when the keys could
+    /// not be read.
+    pub async fn authenticate(&self, token: &str) -> Result<ApiKey> {
+        self.create_table().await?;
+
+        let (prefix, secret) = split_token(token).ok_or_else(invalid_api_key)?;
+        let statement = Query::select()
+            .expr(Expr::cust("*"))
+            .from(Self::table())
+            .and_where(Expr::col(Alias::new("prefix")).eq(prefix))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        let row = self
+            .db
+            .query_one(backend.build(&statement))
+            .await?
+            .ok_or_else(invalid_api_key)?;
+        let key_hash = row.try_get::<String>("", "key_hash")?;
+        if !hash::constant_time_eq(&key_hash, &secret_hash(secret)) {
+            return Err(invalid_api_key());
+        }
+
+        let mut key = ApiKey::from_row(&row)?;
+        if key.revoked_at.is_some() {
+            return Err(Error::Unauthorized("api key was revoked".to_string()));
+        }
+        if !key.is_active() {
+            return Err(Error::Unauthorized("api key has expired".to_string()));
+        }
+
+        let now = Utc::now();
+        if key
+            .last_used_at
+            .map_or(true, |at| (now - at).num_seconds() >= LAST_USED_RESOLUTION)
+        {
+            let statement = Query::update()
+                .table(Self::table())
+                .value(Alias::new("last_used_at"), now.timestamp())
+                .and_where(Expr::col(Alias::new("prefix")).eq(prefix))
+                .to_owned();
+            self.db.execute(backend.build(&statement)).await?;
+            key.last_used_at = DateTime::from_timestamp(now.timestamp(), 0);
+        }
+        Ok(key)
+    }
+
+    /// Revokes the key with the given prefix. Returns `false` when there is
+    /// no such active key.
+    ///
+    /// # Errors
+    ///
+    /// When the key could not be updated.
+    pub async fn revoke(&self, prefix: &str) -> Result<bool> {
+        self.create_table().await?;
+
+        let statement = Query::update()
+            .table(Self::table())
+            .value(Alias::new("revoked_at"), Utc::now().timestamp())
+            .and_where(Expr::col(Alias::new("prefix")).eq(prefix))
+            .and_where(Expr::col(Alias::new("revoked_at")).is_null())
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        let result = self.db.execute(backend.build(&statement)).await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::tests_cfg;
+
+    async fn keys() -> ApiKeys {
+        ApiKeys::new(tests_cfg::db::memory_db().await)
+    }
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(ToString::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn can_create_and_authenticate() {
+        let keys = keys().await;
+        let new = keys
+            .create(
+                "user-1",
+                "ci",
+                &scopes(&["notes:read", "notes:write"]),
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(is_api_key(&new.token));
+        assert!(new.token.starts_with(&format!("lo_{}_", new.key.prefix)));
+
+        let key = keys.authenticate(&new.token).await.unwrap();
+        assert_eq!(key.owner, "user-1");
+        assert_eq!(key.name, "ci");
+        assert!(key.has_scope("notes:write"));
+        assert!(!key.has_scope("users:read"));
+        assert!(key.last_used_at.is_some());
+        assert_eq!(
+            keys.find(&new.key.prefix)
+                .await
+                .unwrap()
+                .unwrap()
+                .last_used_at,
+            key.last_used_at
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_tampered_keys() {
+        let keys = keys().await;
+        let new = keys.create("user-1", "ci", &[], None).await.unwrap();
+
+        let mut tampered = new.token.clone();
+        tampered.pop();
+        tampered.push(if new.token.ends_with('a') { 'b' } else { 'a' });
+
+        for token in [tampered.as_str(), "lo-legacy-key", "lo_abc_def", ""] {
+            assert!(matches!(
+                keys.authenticate(token).await,
+                Err(Error::Unauthorized(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_and_revoked_keys() {
+        let keys = keys().await;
+        let expired = keys
+            .create(
+                "user-1",
+                "old",
+                &[],
+                Some(Utc::now() - Duration::seconds(1)),
+            )
+            .await
+            .unwrap();
+        assert!(!expired.key.is_active());
+        assert!(keys.authenticate(&expired.token).await.is_err());
+
+        let revoked = keys.create("user-1", "ci", &[], None).await.unwrap();
+        assert!(keys.revoke(&revoked.key.prefix).await.unwrap());
+        assert!(!keys.revoke(&revoked.key.prefix).await.unwrap());
+        assert!(keys.authenticate(&revoked.token).await.is_err());
+        assert!(!keys
+            .find(&revoked.key.prefix)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_active());
+    }
+
+    #[tokio::test]
+    async fn can_list_keys_by_owner() {
+        let keys = keys().await;
+        keys.create("user-1", "a", &[], None).await.unwrap();
+        keys.create("user-1", "b", &[], None).await.unwrap();
+        keys.create("user-2", "c", &[], None).await.unwrap();
+
+        assert_eq!(keys.list(Some("user-1")).await.unwrap().len(), 2);
+        assert_eq!(keys.list(Some("user-3")).await.unwrap().len(), 0);
+        assert_eq!(keys.list(None).await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn supports_wildcard_scopes() {
+        let key = ApiKey {
+            prefix: "abcdefgh".to_string(),
+            owner: "user-1".to_string(),
+            name: "admin".to_string(),
+            scopes: scopes(&["notes:*"]),
+            created_at: Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        assert!(key.has_scope("notes:write"));
+        assert!(!key.has_scope("users:write"));
+    }
+}
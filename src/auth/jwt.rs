@@ -2,13 +2,32 @@
 //!
 //! This module provides functionality for working with JSON Web Tokens (JWTs)
 //! and password hashing.
+//!
+//! Tokens are signed either with a shared secret (HMAC), or with asymmetric
+//! [`Key`]s (RSA, ECDSA or `EdDSA`) so other services can verify them using
+//! the public keys published by [`JWT::jwks`].
+use std::{fmt, str::FromStr, sync::Arc};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use jsonwebtoken::{
-    decode, encode, errors::Result as JWTResult, get_current_timestamp, Algorithm, DecodingKey,
-    EncodingKey, Header, TokenData, Validation,
+    decode, decode_header, encode,
+    errors::{ErrorKind, Result as JWTResult},
+    get_current_timestamp,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
+use crate::{app::AppContext, config::JWT as JWTConfig, Error};
+
 /// Represents the default JWT algorithm used by the [`JWT`] struct.
 const JWT_ALGORITHM: Algorithm = Algorithm::HS512;
 
@@ -18,10 +37,185 @@ const JWT_ALGORITHM: Algorithm = Algorithm::HS512;
 pub struct UserClaims {
     pub pid: String,
     exp: u64,
+    /// Unique token identifier, used to revoke a single token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Issued at timestamp, used to revoke all the tokens of a user issued
+    /// before a given time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
     #[serde(default, flatten)]
     pub claims: Map<String, Value>,
 }
 
+impl UserClaims {
+    /// Returns the token expiration timestamp.
+    #[must_use]
+    pub fn exp(&self) -> u64 {
+        self.exp
+    }
+}
+
+#[derive(Clone)]
+enum Material {
+    Secret(String),
+    Pem {
+        encoding: Option<EncodingKey>,
+        decoding: DecodingKey,
+        jwk: Box<Jwk>,
+    },
+}
+
+/// A key used to sign and verify tokens.
+///
+/// # Example
+/// ```rust,ignore
+/// use jsonwebtoken::Algorithm;
+/// use loco_rs::auth::jwt::{Key, JWT};
+///
+/// let key = Key::from_pem(
+///     "2024-06",
+///     Algorithm::ES256,
+///     Some(&std::fs::read("config/keys/2024-06.pem")?),
+///     &std::fs::read("config/keys/2024-06.pub.pem")?,
+/// )?;
+/// let jwt = JWT::with_keys(vec![key]);
+/// ```
+#[derive(Clone)]
+pub struct Key {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    material: Material,
+    active_from: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("active_from", &self.active_from)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Key {
+    /// Creates an HMAC key from a base64 encoded secret, without a key id.
+    #[must_use]
+    pub fn secret(secret: &str) -> Self {
+        Self {
+            kid: None,
+            algorithm: JWT_ALGORITHM,
+            material: Material::Secret(secret.to_string()),
+            active_from: None,
+            expires_at: None,
+        }
+    }
+
+    /// Creates an asymmetric key from PEM encoded keys. Without a private key,
+    /// the key only verifies tokens.
+    ///
+    /// # Errors
+    ///
+    /// When the algorithm is not asymmetric, or the keys are not valid PEM
+    /// keys for the algorithm.
+    pub fn from_pem(
+        kid: &str,
+        algorithm: Algorithm,
+        private_key: Option<&[u8]>,
+        public_key: &[u8],
+    ) -> JWTResult<Self> {
+        let (encoding, decoding) = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => (
+                private_key.map(EncodingKey::from_rsa_pem).transpose()?,
+                DecodingKey::from_rsa_pem(public_key)?,
+            ),
+            Algorithm::ES256 | Algorithm::ES384 => (
+                private_key.map(EncodingKey::from_ec_pem).transpose()?,
+                DecodingKey::from_ec_pem(public_key)?,
+            ),
+            Algorithm::EdDSA => (
+                private_key.map(EncodingKey::from_ed_pem).transpose()?,
+                DecodingKey::from_ed_pem(public_key)?,
+            ),
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                return Err(ErrorKind::InvalidAlgorithm.into())
+            }
+        };
+
+        Ok(Self {
+            kid: Some(kid.to_string()),
+            algorithm,
+            material: Material::Pem {
+                encoding,
+                decoding,
+                jwk: Box::new(public_jwk(kid, algorithm, public_key)?),
+            },
+            active_from: None,
+            expires_at: None,
+        })
+    }
+
+    /// Sets the timestamp from which the key signs new tokens.
+    #[must_use]
+    pub fn active_from(mut self, timestamp: u64) -> Self {
+        self.active_from = Some(timestamp);
+        self
+    }
+
+    /// Sets the timestamp after which the key no longer verifies tokens.
+    #[must_use]
+    pub fn expires_at(mut self, timestamp: u64) -> Self {
+        self.expires_at = Some(timestamp);
+        self
+    }
+
+    /// Returns the key id.
+    #[must_use]
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn can_sign(&self, now: u64) -> bool {
+        let has_private_key = match &self.material {
+            Material::Secret(_) => true,
+            Material::Pem { encoding, .. } => encoding.is_some(),
+        };
+        has_private_key
+            && !self.is_expired(now)
+            && self
+                .active_from
+                .map_or(true, |active_from| active_from <= now)
+    }
+
+    fn encoding_key(&self) -> JWTResult<EncodingKey> {
+        match &self.material {
+            Material::Secret(secret) => EncodingKey::from_base64_secret(secret),
+            Material::Pem { encoding, .. } => encoding
+                .clone()
+                .ok_or_else(|| ErrorKind::InvalidKeyFormat.into()),
+        }
+    }
+
+    fn decoding_key(&self) -> JWTResult<DecodingKey> {
+        match &self.material {
+            Material::Secret(secret) => DecodingKey::from_base64_secret(secret),
+            Material::Pem { decoding, .. } => Ok(decoding.clone()),
+        }
+    }
+}
+
 /// Represents the JWT configuration and operations.
 ///
 /// # Example
@@ -30,35 +224,145 @@ pub struct UserClaims {
 ///
 /// auth::jwt::JWT::new("PqRwLF2rhHe8J22oBeHy");
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct JWT {
-    secret: String,
-    algorithm: Algorithm,
+    keys: Vec<Key>,
 }
 
 impl JWT {
     /// Creates a new [`JWT`] instance with the specified secret key.
     #[must_use]
     pub fn new(secret: &str) -> Self {
-        Self {
-            secret: secret.to_string(),
-            algorithm: JWT_ALGORITHM,
+        Self::with_keys(vec![Key::secret(secret)])
+    }
+
+    /// Creates a new [`JWT`] instance with the given keys.
+    ///
+    /// Tokens are signed with the signing key that was activated last, and
+    /// verified with the key matching their `kid` header.
+    #[must_use]
+    pub fn with_keys(keys: Vec<Key>) -> Self {
+        Self { keys }
+    }
+
+    /// Creates a new [`JWT`] instance from the JWT configuration, loading the
+    /// configured keys. The secret, when set, is kept to verify tokens issued
+    /// before moving to asymmetric keys.
+    ///
+    /// # Errors
+    ///
+    /// When there is neither a secret nor keys, or a key could not be loaded.
+    pub fn from_config(config: &JWTConfig) -> crate::Result<Self> {
+        let mut keys = vec![];
+        if !config.secret.is_empty() {
+            keys.push(Key::secret(&config.secret));
+        }
+
+        for key_config in &config.keys {
+            let key_err = |err: &dyn fmt::Display| {
+                Error::string(&format!(
+                    "could not load JWT key `{}`: {err}",
+                    key_config.kid
+                ))
+            };
+            let algorithm = Algorithm::from_str(&key_config.algorithm).map_err(|e| key_err(&e))?;
+            let private_key = key_config
+                .private_key
+                .as_deref()
+                .map(read_pem)
+                .transpose()
+                .map_err(|e| key_err(&e))?;
+            let public_key = read_pem(&key_config.public_key).map_err(|e| key_err(&e))?;
+
+            let mut key = Key::from_pem(
+                &key_config.kid,
+                algorithm,
+                private_key.as_deref(),
+                &public_key,
+            )
+            .map_err(|e| key_err(&e))?;
+            if let Some(active_from) = key_config.active_from {
+                key = key.active_from(u64::try_from(active_from.timestamp()).unwrap_or_default());
+            }
+            if let Some(expires_at) = key_config.expires_at {
+                key = key.expires_at(u64::try_from(expires_at.timestamp()).unwrap_or_default());
+            }
+            keys.push(key);
+        }
+
+        if keys.is_empty() {
+            return Err(Error::string("JWT config requires a secret or keys"));
         }
+        Ok(Self::with_keys(keys))
     }
 
-    /// Override the default  JWT algorithm to be used.
+    /// Returns the application [`JWT`], loading the configured keys once and
+    /// keeping them in the shared store.
+    ///
+    /// # Errors
+    ///
+    /// When JWT is not configured, or the keys could not be loaded.
+    pub fn from_context(ctx: &AppContext) -> crate::Result<Arc<Self>> {
+        if let Some(jwt) = ctx.shared_store.get::<Arc<Self>>() {
+            return Ok(jwt);
+        }
+        let jwt = Arc::new(Self::from_config(ctx.config.get_jwt_config()?)?);
+        ctx.shared_store.insert(jwt.clone());
+        Ok(jwt)
+    }
+
+    /// Override the default  JWT algorithm to be used with the secret key.
     #[must_use]
     pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
-        self.algorithm = algorithm;
+        for key in &mut self.keys {
+            if matches!(key.material, Material::Secret(_)) {
+                key.algorithm = algorithm;
+            }
+        }
         self
     }
 
+    /// Returns the key used to sign new tokens: the key activated last among
+    /// the keys that can sign now.
+    fn signing_key(&self, now: u64) -> Option<&Key> {
+        self.keys
+            .iter()
+            .filter(|key| key.can_sign(now))
+            .max_by_key(|key| key.active_from)
+    }
+
+    /// Returns the key matching the token `kid` header.
+    fn verifying_key(&self, kid: Option<&str>, now: u64) -> Option<&Key> {
+        self.keys
+            .iter()
+            .find(|key| key.kid.as_deref() == kid && !key.is_expired(now))
+    }
+
+    /// Returns the public keys that verify tokens, to be published at a JWKS
+    /// endpoint. Keys that are not active yet are included, so they can be
+    /// fetched before they sign tokens.
+    #[must_use]
+    pub fn jwks(&self) -> JwkSet {
+        let now = get_current_timestamp();
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter(|key| !key.is_expired(now))
+                .filter_map(|key| match &key.material {
+                    Material::Pem { jwk, .. } => Some(jwk.as_ref().clone()),
+                    Material::Secret(_) => None,
+                })
+                .collect(),
+        }
+    }
+
     /// Generates a new JWT with specified claims and an expiration time.
     ///
     /// # Errors
     ///
     /// returns [`JWTResult`] error when could not generate JWT token. can be an
-    /// invalid secret.
+    /// invalid secret, or no key that can sign tokens.
     ///
     /// # Example
     /// ```rust
@@ -73,15 +377,21 @@ impl JWT {
         pid: String,
         claims: Map<String, Value>,
     ) -> JWTResult<String> {
-        let exp = get_current_timestamp().saturating_add(expiration);
+        let iat = get_current_timestamp();
+        let exp = iat.saturating_add(expiration);
+        let key = self.signing_key(iat).ok_or(ErrorKind::InvalidKeyFormat)?;
 
-        let claims = UserClaims { pid, exp, claims };
+        let claims = UserClaims {
+            pid,
+            exp,
+            jti: Some(uuid::Uuid::new_v4().to_string()),
+            iat: Some(iat),
+            claims,
+        };
 
-        let token = encode(
-            &Header::new(self.algorithm),
-            &claims,
-            &EncodingKey::from_base64_secret(&self.secret)?,
-        )?;
+        let mut header = Header::new(key.algorithm);
+        header.kid.clone_from(&key.kid);
+        let token = encode(&header, &claims, &key.encoding_key()?)?;
 
         Ok(token)
     }
@@ -92,7 +402,8 @@ impl JWT {
     /// # Errors
     ///
     /// returns [`JWTResult`] error when could not convert the given token to
-    /// [`UserClaims`], if the `secret` is invalid or token is expired.
+    /// [`UserClaims`], if the `secret` is invalid, no key matches the token
+    /// `kid` or token is expired.
     ///
     /// # Example
     /// ```rust
@@ -100,15 +411,122 @@ impl JWT {
     /// auth::jwt::JWT::new("PqRwLF2rhHe8J22oBeHy").validate("JWT-TOKEN");
     /// ```
     pub fn validate(&self, token: &str) -> JWTResult<TokenData<UserClaims>> {
-        let mut validate = Validation::new(self.algorithm);
+        let header = decode_header(token)?;
+        let key = self
+            .verifying_key(header.kid.as_deref(), get_current_timestamp())
+            .ok_or(ErrorKind::InvalidSignature)?;
+
+        let mut validate = Validation::new(key.algorithm);
         validate.leeway = 0;
 
-        decode::<UserClaims>(
-            token,
-            &DecodingKey::from_base64_secret(&self.secret)?,
-            &validate,
+        decode::<UserClaims>(token, &key.decoding_key()?, &validate)
+    }
+}
+
+/// Reads a PEM key given inline, or from a file.
+fn read_pem(value: &str) -> std::io::Result<Vec<u8>> {
+    if value.trim_start().starts_with("-----BEGIN") {
+        Ok(value.as_bytes().to_vec())
+    } else {
+        std::fs::read(value)
+    }
+}
+
+/// Builds the public JWK of a PEM encoded `SubjectPublicKeyInfo`.
+fn public_jwk(kid: &str, algorithm: Algorithm, public_key: &[u8]) -> JWTResult<Jwk> {
+    let invalid = || ErrorKind::InvalidKeyFormat;
+    let pem = std::str::from_utf8(public_key).map_err(|_| invalid())?;
+    let der = STANDARD
+        .decode(
+            pem.lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect::<String>(),
         )
+        .map_err(|_| invalid())?;
+
+    // SubjectPublicKeyInfo ::= SEQUENCE { algorithm, subjectPublicKey BIT STRING }
+    let (spki, _) = der_read(&der, 0x30).ok_or_else(invalid)?;
+    let (_, rest) = der_read(spki, 0x30).ok_or_else(invalid)?;
+    let (bits, _) = der_read(rest, 0x03).ok_or_else(invalid)?;
+    // skip the unused bits count
+    let key = bits.get(1..).ok_or_else(invalid)?;
+
+    let algorithm_parameters = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => {
+            // uncompressed point: 0x04 || x || y
+            let point = key.strip_prefix(&[0x04]).ok_or_else(invalid)?;
+            let (x, y) = point.split_at(point.len() / 2);
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: if algorithm == Algorithm::ES256 {
+                    EllipticCurve::P256
+                } else {
+                    EllipticCurve::P384
+                },
+                x: URL_SAFE_NO_PAD.encode(x),
+                y: URL_SAFE_NO_PAD.encode(y),
+            })
+        }
+        Algorithm::EdDSA => AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(key),
+        }),
+        _ => {
+            // RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+            let (rsa, _) = der_read(key, 0x30).ok_or_else(invalid)?;
+            let (n, rest) = der_read(rsa, 0x02).ok_or_else(invalid)?;
+            let (e, _) = der_read(rest, 0x02).ok_or_else(invalid)?;
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(strip_leading_zeros(n)),
+                e: URL_SAFE_NO_PAD.encode(strip_leading_zeros(e)),
+            })
+        }
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::from_str(&format!("{algorithm:?}"))?),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: algorithm_parameters,
+    })
+}
+
+/// Reads a DER element with the expected tag, returning its content and the
+/// remaining input.
+fn der_read(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&found, rest) = input.split_first()?;
+    if found != tag {
+        return None;
     }
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count > std::mem::size_of::<usize>() || rest.len() < count {
+            return None;
+        }
+        let (len_bytes, remaining) = rest.split_at(count);
+        rest = remaining;
+        len_bytes
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | usize::from(*byte))
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (content, rest) = rest.split_at(len);
+    Some((content, rest))
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
 }
 
 #[cfg(test)]
@@ -146,7 +564,9 @@ mod tests {
 
         std::thread::sleep(std::time::Duration::from_secs(3));
         with_settings!({filters => vec![
-            (r"exp: (\d+),", "exp: EXP,")
+            (r"exp: (\d+),", "exp: EXP,"),
+            (r#"jti: Some\(\s*"[0-9a-f-]+",?\s*\),"#, "jti: Some(JTI),"),
+            (r"iat: Some\(\s*\d+,?\s*\),", "iat: Some(IAT),")
         ]}, {
             assert_debug_snapshot!(test_name, jwt.validate(&token));
         });
@@ -169,6 +589,8 @@ mod tests {
         let input_user_claims = UserClaims {
             pid: "pid".to_string(),
             exp: 60,
+            jti: None,
+            iat: None,
             claims: claims.clone(),
         };
 
@@ -215,6 +637,8 @@ mod tests {
         let expected_user_claims = UserClaims {
             pid: "pid".to_string(),
             exp: 60,
+            jti: None,
+            iat: None,
             claims,
         };
 
@@ -223,4 +647,161 @@ mod tests {
             serde_json::from_str(&input_json).unwrap()
         );
     }
+
+    fn pem_key(kid: &str, algorithm: Algorithm, name: &str) -> Key {
+        let dir = crate::tests_cfg::auth::jwt_fixtures_dir();
+        Key::from_pem(
+            kid,
+            algorithm,
+            Some(&std::fs::read(dir.join(format!("{name}_private.pem"))).unwrap()),
+            &std::fs::read(dir.join(format!("{name}_public.pem"))).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(Algorithm::RS256, "rsa")]
+    #[case(Algorithm::PS384, "rsa")]
+    #[case(Algorithm::ES256, "ec")]
+    #[case(Algorithm::EdDSA, "ed")]
+    fn can_sign_with_asymmetric_keys(#[case] algorithm: Algorithm, #[case] name: &str) {
+        let jwt = JWT::with_keys(vec![pem_key("key-1", algorithm, name)]);
+        let token = jwt
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.alg, algorithm);
+        assert_eq!(header.kid.as_deref(), Some("key-1"));
+        assert_eq!(jwt.validate(&token).unwrap().claims.pid, "pid");
+
+        // verify the token with the published key only, as another service would
+        let jwks = jwt.jwks();
+        let jwk = jwks.find("key-1").unwrap();
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = 0;
+        let claims =
+            decode::<UserClaims>(&token, &DecodingKey::from_jwk(jwk).unwrap(), &validation)
+                .unwrap()
+                .claims;
+        assert_eq!(claims.pid, "pid");
+    }
+
+    #[test]
+    fn can_rotate_keys() {
+        let now = get_current_timestamp();
+        let old = pem_key("old", Algorithm::RS256, "rsa");
+        let old_token = JWT::with_keys(vec![old.clone()])
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+
+        // a scheduled key is published but does not sign yet
+        let jwt = JWT::with_keys(vec![
+            old.clone(),
+            pem_key("new", Algorithm::ES256, "ec").active_from(now + 3600),
+        ]);
+        let token = jwt
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("old"));
+        let kids: Vec<_> = jwt
+            .jwks()
+            .keys
+            .into_iter()
+            .filter_map(|jwk| jwk.common.key_id)
+            .collect();
+        assert_eq!(kids, vec!["old", "new"]);
+
+        // once active, the new key signs and the old key still verifies
+        let jwt = JWT::with_keys(vec![
+            old.clone(),
+            pem_key("new", Algorithm::ES256, "ec").active_from(now),
+        ]);
+        let token = jwt
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("new"));
+        assert!(jwt.validate(&old_token).is_ok());
+
+        // an expired key is not published and no longer verifies
+        let jwt = JWT::with_keys(vec![
+            old.expires_at(now),
+            pem_key("new", Algorithm::ES256, "ec").active_from(now),
+        ]);
+        assert!(jwt.validate(&old_token).is_err());
+        assert!(jwt.jwks().find("old").is_none());
+    }
+
+    #[test]
+    fn cannot_sign_with_verify_only_key() {
+        let dir = crate::tests_cfg::auth::jwt_fixtures_dir();
+        let key = Key::from_pem(
+            "key-1",
+            Algorithm::EdDSA,
+            None,
+            &std::fs::read(dir.join("ed_public.pem")).unwrap(),
+        )
+        .unwrap();
+
+        let jwt = JWT::with_keys(vec![key]);
+        assert!(jwt
+            .generate_token(60, "pid".to_string(), Map::new())
+            .is_err());
+        assert!(jwt.jwks().find("key-1").is_some());
+    }
+
+    #[test]
+    fn cannot_validate_token_with_unknown_kid() {
+        let token = JWT::with_keys(vec![pem_key("key-1", Algorithm::RS256, "rsa")])
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+
+        let jwt = JWT::with_keys(vec![pem_key("key-2", Algorithm::RS256, "rsa")]);
+        assert!(jwt.validate(&token).is_err());
+        assert!(JWT::new("PqRwLF2rhHe8J22oBeHy").validate(&token).is_err());
+    }
+
+    #[test]
+    fn can_load_keys_from_config() {
+        let dir = crate::tests_cfg::auth::jwt_fixtures_dir();
+        let config: JWTConfig = serde_yaml::from_str(&format!(
+            r"
+expiration: 60
+secret: PqRwLF2rhHe8J22oBeHy
+keys:
+  - kid: rsa
+    algorithm: RS256
+    private_key: {}
+    public_key: |
+{}
+    active_from: 2024-01-01T00:00:00Z
+",
+            dir.join("rsa_private.pem").display(),
+            std::fs::read_to_string(dir.join("rsa_public.pem"))
+                .unwrap()
+                .lines()
+                .map(|line| format!("      {line}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+        .unwrap();
+
+        let secret_token = JWT::new("PqRwLF2rhHe8J22oBeHy")
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+        let jwt = JWT::from_config(&config).unwrap();
+        let token = jwt
+            .generate_token(60, "pid".to_string(), Map::new())
+            .unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("rsa"));
+        assert!(jwt.validate(&token).is_ok());
+        assert!(jwt.validate(&secret_token).is_ok());
+
+        let config = JWTConfig {
+            secret: String::new(),
+            keys: vec![],
+            ..config
+        };
+        assert!(JWT::from_config(&config).is_err());
+    }
 }
@@ -1,2 +1,12 @@
+#[cfg(feature = "with-db")]
+pub mod api_keys;
 #[cfg(feature = "auth_jwt")]
 pub mod jwt;
+#[cfg(feature = "auth_oauth2")]
+pub mod oauth2;
+#[cfg(feature = "auth_oidc")]
+pub mod oidc;
+pub mod policy;
+#[cfg(feature = "auth_jwt")]
+pub mod tokens;
+pub mod totp;
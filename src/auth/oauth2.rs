@@ -0,0 +1,809 @@
+//! # OAuth2 and OpenID Connect Sign In
+//!
+//! Implements the authorization code flow with PKCE for the providers
+//! configured in `auth.oauth2.providers`:
+//!
+//! 1. [`OAuth2::authorize_url`] returns the provider sign in URL the user is
+//!    redirected to. The `state`, PKCE verifier and OIDC `nonce` are kept in
+//!    the [`TokenStore`] until the user returns, and the `state` is also set
+//!    in a signed cookie binding the sign in to the browser.
+//! 2. The provider redirects back to the callback URL with a `code` and the
+//!    `state`. [`OAuth2::callback`] checks the `state` matches the cookie,
+//!    exchanges the code for the provider tokens and returns the signed in
+//!    [`OAuth2User`].
+//! 3. The application finds or creates its own user with
+//!    [`OAuth2Authenticable::find_or_create_from_oauth2`], signs it in, and
+//!    clears the cookie with [`OAuth2::clear_state_cookie`].
+//!
+//! OpenID Connect providers (Google and any provider supporting discovery)
+//! are trusted through their validated `id_token`. GitHub, which is plain
+//! OAuth2, is trusted through its user API.
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Duration};
+
+#[cfg(feature = "with-db")]
+use async_trait::async_trait;
+use axum::{
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, Key, SameSite, SignedCookieJar};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::Algorithm;
+use reqwest::{header, Url};
+#[cfg(feature = "with-db")]
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::sync::OnceCell;
+
+use super::{
+    oidc::Validator,
+    tokens::{store_from_context, TokenStore},
+};
+#[cfg(feature = "with-db")]
+use crate::model::{Authenticable, ModelResult};
+use crate::{
+    app::AppContext,
+    config::{OAuth2 as OAuth2Config, OAuth2Provider, OAuth2ProviderKind, OIDC as OIDCConfig},
+    controller::format,
+    hash, Error, Result,
+};
+
+/// How long a user has to sign in with the provider.
+const STATE_TTL: Duration = Duration::from_secs(600);
+const STATE_LENGTH: usize = 32;
+const CODE_VERIFIER_LENGTH: usize = 64;
+/// The cookie holding the `state` of the sign in in progress.
+const STATE_COOKIE: &str = "loco_oauth2_state";
+
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const GITHUB_USER_URL: &str = "https://api.github.com/user";
+const GITHUB_EMAILS_URL: &str = "https://api.github.com/user/emails";
+
+/// The user signed in with a provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2User {
+    /// The provider name, as configured
+    pub provider: String,
+    /// The user id at the provider
+    pub subject: String,
+    pub email: Option<String>,
+    /// Whether the provider verified the user email
+    pub email_verified: bool,
+    pub name: Option<String>,
+    /// All the claims of the `id_token`, or the provider user API response
+    pub claims: Map<String, Value>,
+}
+
+/// Finds or creates the application user signing in with a provider.
+#[cfg(feature = "with-db")]
+#[async_trait]
+pub trait OAuth2Authenticable: Authenticable {
+    /// Returns the user signing in, creating it on first sign in.
+    async fn find_or_create_from_oauth2(
+        db: &DatabaseConnection,
+        user: &OAuth2User,
+    ) -> ModelResult<Self>;
+}
+
+/// The state kept between the redirect to the provider and the callback.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+}
+
+fn state_key(state: &str) -> String {
+    format!("auth:oauth2:state:{state}")
+}
+
+/// The redirect to the provider sign in page. Responding with it redirects
+/// the user, and sets the cookie holding the `state`.
+pub struct Authorization {
+    /// The provider sign in URL
+    pub url: String,
+    cookie: Cookie<'static>,
+    key: Key,
+}
+
+impl std::fmt::Debug for Authorization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authorization")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl IntoResponse for Authorization {
+    fn into_response(self) -> Response {
+        let jar = SignedCookieJar::new(self.key).add(self.cookie);
+        match format::redirect(&self.url) {
+            Ok(redirect) => (jar, redirect).into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// The subset of the OpenID Connect discovery document in use.
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    id_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+struct Endpoints {
+    authorize: String,
+    token: String,
+    /// Validates the `id_token` of OpenID Connect providers
+    id_token: Option<Validator>,
+}
+
+struct Provider {
+    name: String,
+    config: OAuth2Provider,
+    endpoints: OnceCell<Endpoints>,
+    /// Signs the state cookie, derived from the client secret
+    key: Key,
+}
+
+impl Provider {
+    /// The state cookie, sent back on the callback URL only.
+    fn state_cookie(&self, state: String) -> Cookie<'static> {
+        let url = Url::parse(&self.config.redirect_url).ok();
+        Cookie::build((STATE_COOKIE, state))
+            .path(url.as_ref().map_or("/", Url::path).to_string())
+            .secure(url.as_ref().is_some_and(|url| url.scheme() == "https"))
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::try_from(STATE_TTL).unwrap_or(time::Duration::MAX))
+            .build()
+    }
+
+    fn is_oidc(&self) -> bool {
+        !matches!(self.config.kind, OAuth2ProviderKind::GitHub)
+    }
+
+    fn scopes(&self) -> String {
+        if !self.config.scopes.is_empty() {
+            return self.config.scopes.join(" ");
+        }
+        match self.config.kind {
+            OAuth2ProviderKind::GitHub => "read:user user:email".to_string(),
+            OAuth2ProviderKind::Google | OAuth2ProviderKind::Oidc { .. } => {
+                "openid email profile".to_string()
+            }
+        }
+    }
+
+    async fn endpoints(&self, client: &reqwest::Client) -> Result<&Endpoints> {
+        self.endpoints
+            .get_or_try_init(|| async {
+                let issuer = match &self.config.kind {
+                    OAuth2ProviderKind::GitHub => {
+                        return Ok(Endpoints {
+                            authorize: GITHUB_AUTHORIZE_URL.to_string(),
+                            token: GITHUB_TOKEN_URL.to_string(),
+                            id_token: None,
+                        })
+                    }
+                    OAuth2ProviderKind::Google => GOOGLE_ISSUER,
+                    OAuth2ProviderKind::Oidc { issuer } => issuer.as_str(),
+                };
+
+                let discovery: Discovery = client
+                    .get(format!(
+                        "{}/.well-known/openid-configuration",
+                        issuer.trim_end_matches('/')
+                    ))
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .map_err(Error::wrap)?
+                    .json()
+                    .await
+                    .map_err(Error::wrap)?;
+
+                let algorithms = if discovery.id_token_signing_alg_values_supported.is_empty() {
+                    vec!["RS256".to_string()]
+                } else {
+                    discovery.id_token_signing_alg_values_supported
+                };
+                let validator = Validator::new(&OIDCConfig {
+                    location: None,
+                    issuer: discovery.issuer,
+                    jwks_url: discovery.jwks_uri,
+                    audience: vec![self.config.client_id.clone()],
+                    pid_claim: "sub".to_string(),
+                    // `none`, HMAC and unsupported algorithms are not accepted
+                    algorithms: algorithms
+                        .into_iter()
+                        .filter(|alg| {
+                            Algorithm::from_str(alg).is_ok_and(|alg| {
+                                !matches!(
+                                    alg,
+                                    Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+                                )
+                            })
+                        })
+                        .collect(),
+                    jwks_cache_ttl: 3600,
+                    leeway: 60,
+                })?;
+
+                Ok(Endpoints {
+                    authorize: discovery.authorization_endpoint,
+                    token: discovery.token_endpoint,
+                    id_token: Some(validator),
+                })
+            })
+            .await
+    }
+}
+
+/// Signs users in with the configured OAuth2 / OpenID Connect providers.
+pub struct OAuth2 {
+    providers: BTreeMap<String, Provider>,
+    store: Arc<dyn TokenStore>,
+    client: reqwest::Client,
+}
+
+impl OAuth2 {
+    /// Creates a new instance for the given providers, keeping the pending
+    /// sign ins in the given store.
+    #[must_use]
+    pub fn new(config: &OAuth2Config, store: Arc<dyn TokenStore>) -> Self {
+        let providers = config
+            .providers
+            .iter()
+            .map(|(name, config)| {
+                (
+                    name.clone(),
+                    Provider {
+                        name: name.clone(),
+                        config: config.clone(),
+                        endpoints: OnceCell::new(),
+                        key: Key::from(&Sha512::digest(format!(
+                            "loco-oauth2-state:{}",
+                            config.client_secret
+                        ))),
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            providers,
+            store,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Returns the application instance, kept in the shared store so the
+    /// provider discovery and keys are cached across requests.
+    ///
+    /// # Errors
+    ///
+    /// When OAuth2 is not configured.
+    pub fn from_context(ctx: &AppContext) -> Result<Arc<Self>> {
+        if let Some(oauth2) = ctx.shared_store.get::<Arc<Self>>() {
+            return Ok(oauth2);
+        }
+        let config = ctx
+            .config
+            .auth
+            .as_ref()
+            .and_then(|auth| auth.oauth2.as_ref())
+            .ok_or_else(|| Error::string("OAuth2 not configured"))?;
+        let oauth2 = Arc::new(Self::new(config, store_from_context(ctx)));
+        ctx.shared_store.insert(oauth2.clone());
+        Ok(oauth2)
+    }
+
+    fn provider(&self, name: &str) -> Result<&Provider> {
+        self.providers.get(name).ok_or_else(|| Error::NotFound)
+    }
+
+    /// Returns the redirect to the provider sign in page. The response must
+    /// be sent as is, as it sets the cookie [`OAuth2::callback`] requires.
+    ///
+    /// # Errors
+    ///
+    /// When the provider is not configured, its discovery document could not
+    /// be fetched, or the sign in state could not be stored.
+    pub async fn authorize_url(&self, provider: &str) -> Result<Authorization> {
+        let provider = self.provider(provider)?;
+        let endpoints = provider.endpoints(&self.client).await?;
+
+        let state = hash::random_string(STATE_LENGTH);
+        let pending = PendingAuthorization {
+            provider: provider.name.clone(),
+            code_verifier: hash::random_string(CODE_VERIFIER_LENGTH),
+            nonce: hash::random_string(STATE_LENGTH),
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&pending.code_verifier));
+
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", provider.config.client_id.as_str()),
+            ("redirect_uri", provider.config.redirect_url.as_str()),
+            ("state", state.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        let scopes = provider.scopes();
+        params.push(("scope", scopes.as_str()));
+        if provider.is_oidc() {
+            params.push(("nonce", pending.nonce.as_str()));
+        }
+        let url = Url::parse_with_params(&endpoints.authorize, &params).map_err(Error::wrap)?;
+
+        self.store
+            .insert(
+                &state_key(&state),
+                &serde_json::to_string(&pending)?,
+                STATE_TTL,
+            )
+            .await?;
+        Ok(Authorization {
+            url: url.to_string(),
+            cookie: provider.state_cookie(state),
+            key: provider.key.clone(),
+        })
+    }
+
+    /// Completes the sign in when the provider redirects back with the given
+    /// `code` and `state`. A state can be used once, and only by the browser
+    /// which started the sign in, whose request `headers` hold the state
+    /// cookie.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unauthorized`] when the state is unknown, expired or
+    /// does not match the cookie, the code is rejected by the provider, or
+    /// the `id_token` is not valid.
+    pub async fn callback(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        headers: &HeaderMap,
+    ) -> Result<OAuth2User> {
+        let provider = self.provider(provider)?;
+
+        let cookie = SignedCookieJar::from_headers(headers, provider.key.clone()).get(STATE_COOKIE);
+        if !cookie.is_some_and(|cookie| cookie.value() == state) {
+            return Err(Error::Unauthorized("OAuth2 state is not valid".to_string()));
+        }
+
+        let key = state_key(state);
+        let pending: PendingAuthorization = match self.store.get(&key).await? {
+            Some(pending) => serde_json::from_str(&pending)?,
+            None => return Err(Error::Unauthorized("OAuth2 state is not valid".to_string())),
+        };
+        self.store.remove(&key).await?;
+        if pending.provider != provider.name {
+            return Err(Error::Unauthorized("OAuth2 state is not valid".to_string()));
+        }
+
+        let endpoints = provider.endpoints(&self.client).await?;
+        let tokens: TokenResponse = self
+            .client
+            .post(&endpoints.token)
+            .header(header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", provider.config.redirect_url.as_str()),
+                ("client_id", provider.config.client_id.as_str()),
+                ("client_secret", provider.config.client_secret.as_str()),
+                ("code_verifier", pending.code_verifier.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(Error::wrap)?
+            .json()
+            .await
+            .map_err(Error::wrap)?;
+        if let Some(error) = tokens.error {
+            return Err(Error::Unauthorized(format!(
+                "OAuth2 code exchange failed: {error} {}",
+                tokens.error_description.unwrap_or_default()
+            )));
+        }
+
+        match &endpoints.id_token {
+            Some(validator) => {
+                let id_token = tokens.id_token.ok_or_else(|| {
+                    Error::Unauthorized("OAuth2 provider returned no id_token".to_string())
+                })?;
+                let claims = validator.validate(&id_token).await?;
+                if claims.claims.get("nonce").and_then(Value::as_str) != Some(&pending.nonce) {
+                    return Err(Error::Unauthorized(
+                        "id_token nonce is not valid".to_string(),
+                    ));
+                }
+
+                Ok(OAuth2User {
+                    provider: provider.name.clone(),
+                    subject: claims.pid,
+                    email: string_claim(&claims.claims, "email"),
+                    email_verified: claims
+                        .claims
+                        .get("email_verified")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false),
+                    name: string_claim(&claims.claims, "name"),
+                    claims: claims.claims,
+                })
+            }
+            None => {
+                let access_token = tokens.access_token.ok_or_else(|| {
+                    Error::Unauthorized("OAuth2 provider returned no access_token".to_string())
+                })?;
+                self.github_user(provider, &access_token).await
+            }
+        }
+    }
+
+    /// Returns the cookie jar removing the `state` cookie of a completed
+    /// sign in, to respond with along with the sign in response.
+    ///
+    /// # Errors
+    ///
+    /// When the provider is not configured.
+    pub fn clear_state_cookie(
+        &self,
+        provider: &str,
+        headers: &HeaderMap,
+    ) -> Result<SignedCookieJar> {
+        let provider = self.provider(provider)?;
+        Ok(SignedCookieJar::from_headers(headers, provider.key.clone())
+            .remove(provider.state_cookie(String::new())))
+    }
+
+    async fn github_user(&self, provider: &Provider, access_token: &str) -> Result<OAuth2User> {
+        let get = |url: &'static str| {
+            self.client
+                .get(url)
+                .bearer_auth(access_token)
+                .header(header::USER_AGENT, "loco")
+                .header(header::ACCEPT, "application/vnd.github+json")
+                .send()
+        };
+
+        let claims: Map<String, Value> = get(GITHUB_USER_URL)
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(Error::wrap)?
+            .json()
+            .await
+            .map_err(Error::wrap)?;
+
+        // the profile email is public and not always verified, the primary
+        // email is
+        #[derive(Deserialize)]
+        struct Email {
+            email: String,
+            primary: bool,
+            verified: bool,
+        }
+        let emails: Vec<Email> = get(GITHUB_EMAILS_URL)
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(Error::wrap)?
+            .json()
+            .await
+            .map_err(Error::wrap)?;
+        let primary = emails.into_iter().find(|email| email.primary);
+
+        Ok(OAuth2User {
+            provider: provider.name.clone(),
+            subject: match claims.get("id") {
+                Some(Value::Number(id)) => id.to_string(),
+                _ => return Err(Error::string("GitHub user has no id")),
+            },
+            email_verified: primary.as_ref().is_some_and(|email| email.verified),
+            email: primary.map(|email| email.email),
+            name: string_claim(&claims, "name").or_else(|| string_claim(&claims, "login")),
+            claims,
+        })
+    }
+}
+
+fn string_claim(claims: &Map<String, Value>, name: &str) -> Option<String> {
+    claims
+        .get(name)
+        .and_then(Value::as_str)
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use axum::{
+        extract::{Form, State},
+        routing::{get, post},
+        Json, Router,
+    };
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        auth::jwt::{Key, JWT},
+        tests_cfg::auth::{jwt_fixture as fixture, MemStore},
+    };
+
+    /// The authorization requests received by the mock provider, by code.
+    #[derive(Clone, Default)]
+    struct Provider {
+        issuer: String,
+        /// code -> (`code_challenge`, nonce)
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    async fn token(
+        State(provider): State<Provider>,
+        Form(params): Form<HashMap<String, String>>,
+    ) -> Json<Value> {
+        let Some((challenge, nonce)) = provider.codes.lock().unwrap().remove(&params["code"])
+        else {
+            return Json(json!({ "error": "invalid_grant" }));
+        };
+        if URL_SAFE_NO_PAD.encode(Sha256::digest(&params["code_verifier"])) != challenge {
+            return Json(json!({ "error": "invalid_grant", "error_description": "PKCE" }));
+        }
+
+        let now = get_current_timestamp();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("provider-key".to_string());
+        let id_token = encode(
+            &header,
+            &json!({
+                "iss": provider.issuer,
+                "aud": params["client_id"],
+                "sub": "provider-user-1",
+                "email": "user@example.com",
+                "email_verified": true,
+                "name": "Loco User",
+                "nonce": nonce,
+                "iat": now,
+                "exp": now + 60,
+            }),
+            &EncodingKey::from_rsa_pem(&fixture("rsa_private.pem")).unwrap(),
+        )
+        .unwrap();
+        Json(json!({ "access_token": "access", "token_type": "Bearer", "id_token": id_token }))
+    }
+
+    /// Starts a mock OpenID Connect provider
+    async fn provider() -> Provider {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let provider = Provider {
+            issuer: issuer.clone(),
+            ..Default::default()
+        };
+
+        let key = Key::from_pem(
+            "provider-key",
+            Algorithm::RS256,
+            None,
+            &fixture("rsa_public.pem"),
+        )
+        .unwrap();
+        let jwks = JWT::with_keys(vec![key]).jwks();
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks.json"),
+            "id_token_signing_alg_values_supported": ["RS256", "ES512", "HS256", "none"],
+        });
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(discovery) }),
+            )
+            .route("/jwks.json", get(move || async move { Json(jwks) }))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        provider
+    }
+
+    fn oauth2(issuer: &str) -> OAuth2 {
+        let config: OAuth2Config = serde_json::from_value(json!({
+            "providers": {
+                "sso": {
+                    "kind": "Oidc",
+                    "issuer": issuer,
+                    "client_id": "loco-app",
+                    "client_secret": "secret",
+                    "redirect_url": "http://localhost:5150/api/auth/oauth2/sso/callback",
+                }
+            }
+        }))
+        .unwrap();
+        OAuth2::new(&config, Arc::new(MemStore::default()))
+    }
+
+    /// Returns the cookie header the browser sends back on the callback.
+    fn cookies(authorization: Authorization) -> HeaderMap {
+        let response = authorization.into_response();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly"));
+        assert!(set_cookie.contains("SameSite=Lax"));
+        assert!(set_cookie.contains("Path=/api/auth/oauth2/sso/callback"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            set_cookie.split(';').next().unwrap().parse().unwrap(),
+        );
+        headers
+    }
+
+    /// Follows the authorize URL as the provider would, returning the code and
+    /// state of the redirect back to the app.
+    fn sign_in(provider: &Provider, url: &str) -> (String, String) {
+        let url = Url::parse(url).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], "loco-app");
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(params["scope"], "openid email profile");
+
+        let code = hash::random_string(16);
+        provider.codes.lock().unwrap().insert(
+            code.clone(),
+            (params["code_challenge"].clone(), params["nonce"].clone()),
+        );
+        (code, params["state"].clone())
+    }
+
+    #[tokio::test]
+    async fn can_sign_in_with_oidc_provider() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+
+        let authorization = oauth2.authorize_url("sso").await.unwrap();
+        let url = authorization.url.clone();
+        assert!(url.starts_with(&format!("{}/authorize?", provider.issuer)));
+        let (code, state) = sign_in(&provider, &url);
+
+        let user = oauth2
+            .callback("sso", &code, &state, &cookies(authorization))
+            .await
+            .unwrap();
+        assert_eq!(user.provider, "sso");
+        assert_eq!(user.subject, "provider-user-1");
+        assert_eq!(user.email.as_deref(), Some("user@example.com"));
+        assert!(user.email_verified);
+        assert_eq!(user.name.as_deref(), Some("Loco User"));
+    }
+
+    #[tokio::test]
+    async fn can_clear_state_cookie() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+        let headers = cookies(oauth2.authorize_url("sso").await.unwrap());
+
+        let response = oauth2
+            .clear_state_cookie("sso", &headers)
+            .unwrap()
+            .into_response();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with(&format!("{STATE_COOKIE}=;")));
+        assert!(set_cookie.contains("Max-Age=0"));
+        assert!(set_cookie.contains("Path=/api/auth/oauth2/sso/callback"));
+    }
+
+    #[tokio::test]
+    async fn cannot_reuse_state() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+
+        let authorization = oauth2.authorize_url("sso").await.unwrap();
+        let url = authorization.url.clone();
+        let headers = cookies(authorization);
+        let (code, state) = sign_in(&provider, &url);
+        assert!(oauth2
+            .callback("sso", &code, &state, &headers)
+            .await
+            .is_ok());
+
+        let (code, _) = sign_in(&provider, &url);
+        let err = oauth2
+            .callback("sso", &code, &state, &headers)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn cannot_sign_in_from_another_browser() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+
+        // the attacker's sign in, completed in the victim's browser
+        let authorization = oauth2.authorize_url("sso").await.unwrap();
+        let (code, state) = sign_in(&provider, &authorization.url);
+        let err = oauth2
+            .callback("sso", &code, &state, &HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+
+        // the victim's own sign in cookie doesn't match
+        let victim = cookies(oauth2.authorize_url("sso").await.unwrap());
+        let err = oauth2
+            .callback("sso", &code, &state, &victim)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+
+        // nor does a forged cookie
+        let mut forged = HeaderMap::new();
+        forged.insert(
+            header::COOKIE,
+            format!("{STATE_COOKIE}={state}").parse().unwrap(),
+        );
+        let err = oauth2
+            .callback("sso", &code, &state, &forged)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn cannot_sign_in_with_unknown_state_or_provider() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+
+        let authorization = oauth2.authorize_url("sso").await.unwrap();
+        let (code, _) = sign_in(&provider, &authorization.url);
+        let err = oauth2
+            .callback("sso", &code, "forged", &cookies(authorization))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+
+        assert!(matches!(
+            oauth2.authorize_url("unknown").await.unwrap_err(),
+            Error::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn cannot_sign_in_with_rejected_code() {
+        let provider = provider().await;
+        let oauth2 = oauth2(&provider.issuer);
+
+        let authorization = oauth2.authorize_url("sso").await.unwrap();
+        let (_, state) = sign_in(&provider, &authorization.url);
+        let err = oauth2
+            .callback("sso", "unknown", &state, &cookies(authorization))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+    }
+}
@@ -0,0 +1,393 @@
+//! # External OIDC Tokens
+//!
+//! Validates the tokens issued by an external OIDC provider, such as an SSO
+//! provider, using the keys published at the provider JWKS URL.
+//!
+//! The keys are fetched on first use and cached for `jwks_cache_ttl`. A token
+//! signed with an unknown key triggers a refetch, so provider key rotations
+//! are picked up without waiting for the cache to expire.
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+use tokio::sync::RwLock;
+
+use super::jwt::UserClaims;
+use crate::{app::AppContext, config::OIDC as OIDCConfig, Error, Result};
+
+/// The minimum time between two fetches triggered by unknown keys, so tokens
+/// signed with bogus keys can not flood the provider.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct CachedKeys {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Validates tokens issued by an external OIDC provider.
+pub struct Validator {
+    config: OIDCConfig,
+    algorithms: Vec<Algorithm>,
+    client: reqwest::Client,
+    cache: RwLock<Option<CachedKeys>>,
+}
+
+impl Validator {
+    /// Creates a new validator for the given provider.
+    ///
+    /// # Errors
+    ///
+    /// When an algorithm is not valid.
+    pub fn new(config: &OIDCConfig) -> Result<Self> {
+        let algorithms = config
+            .algorithms
+            .iter()
+            .map(|algorithm| {
+                Algorithm::from_str(algorithm)
+                    .map_err(|_| Error::string(&format!("invalid OIDC algorithm `{algorithm}`")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            config: config.clone(),
+            algorithms,
+            client: reqwest::Client::new(),
+            cache: RwLock::new(None),
+        })
+    }
+
+    /// Returns the application validator, kept in the shared store so the
+    /// fetched keys are cached across requests.
+    ///
+    /// # Errors
+    ///
+    /// When OIDC is not configured, or the configuration is not valid.
+    pub fn from_context(ctx: &AppContext) -> Result<Arc<Self>> {
+        if let Some(validator) = ctx.shared_store.get::<Arc<Self>>() {
+            return Ok(validator);
+        }
+        let config = ctx
+            .config
+            .auth
+            .as_ref()
+            .and_then(|auth| auth.oidc.as_ref())
+            .ok_or_else(|| Error::string("OIDC not configured"))?;
+        let validator = Arc::new(Self::new(config)?);
+        ctx.shared_store.insert(validator.clone());
+        Ok(validator)
+    }
+
+    /// Validates the token signature, `exp`, `nbf`, `iss` and `aud` claims,
+    /// and maps the configured claim to the `pid` of the returned claims.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unauthorized`] when the token is not valid, or an
+    /// error when the provider keys could not be fetched.
+    pub async fn validate(&self, token: &str) -> Result<UserClaims> {
+        let header = decode_header(token).map_err(unauthorized)?;
+        if !self.algorithms.contains(&header.alg) {
+            return Err(Error::Unauthorized(format!(
+                "token algorithm {:?} is not accepted",
+                header.alg
+            )));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| Error::Unauthorized("token has no kid".to_string()))?;
+
+        let key = match self.find_key(&kid, false).await? {
+            Some(key) => key,
+            None => self
+                .find_key(&kid, true)
+                .await?
+                .ok_or_else(|| Error::Unauthorized(format!("unknown token key `{kid}`")))?,
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.config.issuer]);
+        if self.config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audience);
+        }
+
+        let mut claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(unauthorized)?
+            .claims;
+        let pid = match claims.get(&self.config.pid_claim) {
+            Some(Value::String(pid)) => pid.clone(),
+            Some(pid @ Value::Number(_)) => pid.to_string(),
+            _ => {
+                return Err(Error::Unauthorized(format!(
+                    "token has no `{}` claim",
+                    self.config.pid_claim
+                )))
+            }
+        };
+        claims.insert("pid".to_string(), Value::String(pid));
+
+        serde_json::from_value(Value::Object(claims)).map_err(unauthorized)
+    }
+
+    /// Finds the key with the given id in the cached keys, fetching the keys
+    /// when the cache expired. With `refetch`, fetches the keys again unless
+    /// they were fetched recently.
+    async fn find_key(&self, kid: &str, refetch: bool) -> Result<Option<DecodingKey>> {
+        let ttl = Duration::from_secs(self.config.jwks_cache_ttl);
+        {
+            let cache = self.cache.read().await;
+            if let Some(cached) = cache.as_ref() {
+                let age = cached.fetched_at.elapsed();
+                let fresh = if refetch {
+                    age < MIN_REFETCH_INTERVAL
+                } else {
+                    age < ttl
+                };
+                if fresh {
+                    return find(&cached.keys, kid);
+                }
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // another request may have fetched the keys while waiting for the lock
+        if let Some(cached) = cache.as_ref() {
+            if cached.fetched_at.elapsed() < MIN_REFETCH_INTERVAL {
+                return find(&cached.keys, kid);
+            }
+        }
+
+        let keys = self.fetch().await?;
+        let key = find(&keys, kid);
+        *cache = Some(CachedKeys {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        key
+    }
+
+    async fn fetch(&self) -> Result<JwkSet> {
+        tracing::debug!(url = self.config.jwks_url, "fetching OIDC provider keys");
+        self.client
+            .get(&self.config.jwks_url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(Error::wrap)?
+            .json::<JwkSet>()
+            .await
+            .map_err(Error::wrap)
+    }
+}
+
+fn find(keys: &JwkSet, kid: &str) -> Result<Option<DecodingKey>> {
+    keys.find(kid)
+        .map(|jwk| DecodingKey::from_jwk(jwk).map_err(unauthorized))
+        .transpose()
+}
+
+fn unauthorized(err: impl std::fmt::Display) -> Error {
+    Error::Unauthorized(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{routing::get, Json, Router};
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        auth::jwt::{Key, JWT},
+        tests_cfg::auth::jwt_fixture as fixture,
+    };
+
+    /// Serves the fixture RSA public key as `provider-key` and the fixture EC
+    /// public key as `provider-ec-key`, and counts the requests.
+    async fn jwks_server() -> (String, Arc<AtomicUsize>) {
+        let keys = vec![
+            Key::from_pem(
+                "provider-key",
+                Algorithm::RS256,
+                None,
+                &fixture("rsa_public.pem"),
+            )
+            .unwrap(),
+            Key::from_pem(
+                "provider-ec-key",
+                Algorithm::ES256,
+                None,
+                &fixture("ec_public.pem"),
+            )
+            .unwrap(),
+        ];
+        let jwks = JWT::with_keys(keys).jwks();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        let app = Router::new().route(
+            "/jwks.json",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let jwks = jwks.clone();
+                async move { Json(jwks) }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{addr}/jwks.json"), requests)
+    }
+
+    fn validator(jwks_url: String) -> Validator {
+        Validator::new(&OIDCConfig {
+            location: None,
+            issuer: "https://sso.example.com/".to_string(),
+            jwks_url,
+            audience: vec!["my-api".to_string()],
+            pid_claim: "sub".to_string(),
+            algorithms: vec!["RS256".to_string(), "ES256".to_string()],
+            jwks_cache_ttl: 3600,
+            leeway: 0,
+        })
+        .unwrap()
+    }
+
+    fn token(kid: &str, claims: &Value) -> String {
+        let now = get_current_timestamp();
+        let mut payload = json!({
+            "sub": "user-1",
+            "iss": "https://sso.example.com/",
+            "aud": "my-api",
+            "iat": now,
+            "exp": now + 60,
+            "email": "user@example.com",
+        });
+        payload
+            .as_object_mut()
+            .unwrap()
+            .extend(claims.as_object().unwrap().clone());
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        encode(
+            &header,
+            &payload,
+            &EncodingKey::from_rsa_pem(&fixture("rsa_private.pem")).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn can_validate_provider_token() {
+        let (url, _) = jwks_server().await;
+        let validator = validator(url);
+
+        let claims = validator
+            .validate(&token("provider-key", &json!({})))
+            .await
+            .unwrap();
+        assert_eq!(claims.pid, "user-1");
+        assert_eq!(claims.claims["email"], "user@example.com");
+        assert_eq!(claims.claims["sub"], "user-1");
+    }
+
+    #[tokio::test]
+    async fn cannot_validate_invalid_provider_token() {
+        let (url, _) = jwks_server().await;
+        let validator = validator(url);
+        let now = get_current_timestamp();
+
+        for claims in [
+            json!({ "iss": "https://other.example.com/" }),
+            json!({ "aud": "other-api" }),
+            json!({ "nbf": now + 600 }),
+            json!({ "exp": now - 600 }),
+        ] {
+            let err = validator
+                .validate(&token("provider-key", &claims))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::Unauthorized(_)), "{claims}: {err}");
+        }
+
+        let err = validator
+            .validate(&token("unknown-key", &json!({})))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(_)));
+
+        // signed with a key that is not the provider key
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("provider-key".to_string());
+        let forged = encode(
+            &header,
+            &json!({ "sub": "user-1", "exp": now + 60 }),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(validator.validate(&forged).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn can_validate_tokens_of_mixed_key_families() {
+        let (url, _) = jwks_server().await;
+        let validator = validator(url);
+        let now = get_current_timestamp();
+        let claims = json!({
+            "sub": "user-2",
+            "iss": "https://sso.example.com/",
+            "aud": "my-api",
+            "exp": now + 60,
+        });
+        let ec_key = EncodingKey::from_ec_pem(&fixture("ec_private.pem")).unwrap();
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("provider-ec-key".to_string());
+        let ec_token = encode(&header, &claims, &ec_key).unwrap();
+        assert_eq!(validator.validate(&ec_token).await.unwrap().pid, "user-2");
+        assert_eq!(
+            validator
+                .validate(&token("provider-key", &json!({})))
+                .await
+                .unwrap()
+                .pid,
+            "user-1"
+        );
+
+        // the algorithm of the header must match the family of the key
+        header.kid = Some("provider-key".to_string());
+        let mismatched = encode(&header, &claims, &ec_key).unwrap();
+        assert!(matches!(
+            validator.validate(&mismatched).await.unwrap_err(),
+            Error::Unauthorized(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn can_cache_provider_keys() {
+        let (url, requests) = jwks_server().await;
+        let validator = validator(url);
+
+        let valid = token("provider-key", &json!({}));
+        validator.validate(&valid).await.unwrap();
+        validator.validate(&valid).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // unknown keys refetch at most once per interval
+        let unknown = token("unknown-key", &json!({}));
+        assert!(validator.validate(&unknown).await.is_err());
+        assert!(validator.validate(&unknown).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
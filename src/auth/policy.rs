@@ -0,0 +1,566 @@
+//! # Authorization Policies
+//!
+//! A [`Policy`] decides what a user can do with a kind of resource. Handlers
+//! enforce it with the [`Authorize`] extractor, which rejects the request with
+//! `403 Forbidden` before the handler runs, and can check a loaded resource
+//! afterwards. Whole routes are guarded with the [`Guard`] layer.
+//!
+//! ```rust,ignore
+//! use loco_rs::{auth::policy::*, prelude::*};
+//!
+//! pub struct NotePolicy;
+//!
+//! #[async_trait]
+//! impl Policy for NotePolicy {
+//!     type User = auth::JWT;
+//!     type Resource = notes::Model;
+//!
+//!     async fn can(
+//!         _ctx: &AppContext,
+//!         user: &Self::User,
+//!         action: &Action,
+//!         note: Option<&Self::Resource>,
+//!     ) -> Result<bool> {
+//!         Ok(match (action, note) {
+//!             (Action::Read, _) => true,
+//!             // checked again once the note is loaded
+//!             (Action::Update | Action::Delete, None) => true,
+//!             (Action::Update | Action::Delete, Some(note)) => note.owner_pid == user.claims.pid,
+//!             _ => user.has_role("editor"),
+//!         })
+//!     }
+//! }
+//!
+//! async fn update(
+//!     auth: Authorize<NotePolicy>,
+//!     Path(id): Path<i32>,
+//!     State(ctx): State<AppContext>,
+//! ) -> Result<Response> {
+//!     let note = load_item(&ctx, id).await?;
+//!     auth.authorize(&Action::Update, &note).await?;
+//!     // ...
+//! }
+//! ```
+use std::{
+    convert::Infallible,
+    fmt,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+#[cfg(feature = "auth_jwt")]
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::{app::AppContext, controller::ErrorDetail, Error, Result};
+
+/// What a user wants to do with a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    Read,
+    Create,
+    Update,
+    Delete,
+    /// Any other action, such as `publish`
+    Custom(String),
+}
+
+impl Action {
+    /// Returns the action of a request with the given method: `GET` reads,
+    /// `POST` creates, `PUT` and `PATCH` update, and `DELETE` deletes.
+    #[must_use]
+    pub fn from_method(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS => Self::Read,
+            Method::POST => Self::Create,
+            Method::PUT | Method::PATCH => Self::Update,
+            Method::DELETE => Self::Delete,
+            _ => Self::Custom(method.as_str().to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => f.write_str("read"),
+            Self::Create => f.write_str("create"),
+            Self::Update => f.write_str("update"),
+            Self::Delete => f.write_str("delete"),
+            Self::Custom(action) => f.write_str(action),
+        }
+    }
+}
+
+/// The roles and permissions of a user, read from token claims or from the
+/// database.
+pub trait Principal {
+    /// Returns the roles of the user, such as `admin`.
+    fn roles(&self) -> Vec<String>;
+
+    /// Returns the permissions of the user, such as `notes:update`.
+    fn permissions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns whether the user has the given role.
+    fn has_role(&self, role: &str) -> bool {
+        self.roles().iter().any(|r| r == role)
+    }
+
+    /// Returns whether the user has the given permission. A `notes:*`
+    /// permission grants any `notes:` permission, and `*` grants all of them.
+    fn has_permission(&self, permission: &str) -> bool {
+        self.permissions().iter().any(|granted| {
+            granted == permission
+                || granted.strip_suffix('*').is_some_and(|prefix| {
+                    (prefix.is_empty() || prefix.ends_with(':')) && permission.starts_with(prefix)
+                })
+        })
+    }
+}
+
+/// Reads a list of strings from a claim which is either an array, or a space
+/// separated string (as the OAuth2 `scope` claim).
+#[cfg(feature = "auth_jwt")]
+fn claim_values(claims: &serde_json::Map<String, Value>, names: &[&str]) -> Vec<String> {
+    names
+        .iter()
+        .filter_map(|name| claims.get(*name))
+        .flat_map(|value| match value {
+            Value::Array(values) => values
+                .iter()
+                .filter_map(|value| value.as_str().map(ToString::to_string))
+                .collect(),
+            Value::String(value) => value.split_whitespace().map(ToString::to_string).collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Roles are read from the `roles` and `role` claims, and permissions from
+/// the `permissions` and `scope` claims.
+#[cfg(feature = "auth_jwt")]
+impl Principal for super::jwt::UserClaims {
+    fn roles(&self) -> Vec<String> {
+        claim_values(&self.claims, &["roles", "role"])
+    }
+
+    fn permissions(&self) -> Vec<String> {
+        claim_values(&self.claims, &["permissions", "scope"])
+    }
+}
+
+#[cfg(all(feature = "auth_jwt", feature = "with-db"))]
+impl Principal for crate::controller::extractor::auth::JWT {
+    fn roles(&self) -> Vec<String> {
+        self.claims.roles()
+    }
+
+    fn permissions(&self) -> Vec<String> {
+        self.claims.permissions()
+    }
+}
+
+/// Roles and permissions are read from the user model.
+#[cfg(all(feature = "auth_jwt", feature = "with-db"))]
+impl<T> Principal for crate::controller::extractor::auth::JWTWithUser<T>
+where
+    T: crate::model::Authenticable + Principal,
+{
+    fn roles(&self) -> Vec<String> {
+        self.user.roles()
+    }
+
+    fn permissions(&self) -> Vec<String> {
+        self.user.permissions()
+    }
+}
+
+/// Decides what users can do with a kind of resource.
+#[async_trait]
+pub trait Policy: Send + Sync + 'static {
+    /// The authenticated user, extracted from the request, such as
+    /// `auth::JWT` or `auth::JWTWithUser<users::Model>`.
+    type User: FromRequestParts<AppContext, Rejection = Error> + Send + Sync;
+
+    /// The resource guarded by the policy.
+    type Resource: Send + Sync;
+
+    /// Returns whether the user can perform the action. The resource is
+    /// `None` when checking a request before the resource is loaded (and for
+    /// actions on the whole collection, such as listing or creating).
+    ///
+    /// # Errors
+    ///
+    /// When the permissions could not be loaded.
+    async fn can(
+        ctx: &AppContext,
+        user: &Self::User,
+        action: &Action,
+        resource: Option<&Self::Resource>,
+    ) -> Result<bool>;
+}
+
+fn forbidden() -> Error {
+    Error::CustomError(
+        StatusCode::FORBIDDEN,
+        ErrorDetail::new("forbidden", "you are not allowed to perform this action"),
+    )
+}
+
+/// Extracts the user of the policy, and checks the action of the request
+/// method (see [`Action::from_method`]) before the resource is loaded.
+async fn check<P: Policy>(ctx: &AppContext, parts: &mut Parts) -> Result<P::User> {
+    let user = P::User::from_request_parts(parts, ctx).await?;
+    let action = Action::from_method(&parts.method);
+    if P::can(ctx, &user, &action, None).await? {
+        Ok(user)
+    } else {
+        Err(forbidden())
+    }
+}
+
+/// An extractor authorizing the request with the policy `P`.
+///
+/// The request is rejected when the user can't perform the action of the
+/// request method. Once the resource is loaded, call [`Authorize::authorize`]
+/// to check the resource itself.
+pub struct Authorize<P: Policy> {
+    pub user: P::User,
+    ctx: AppContext,
+    policy: PhantomData<P>,
+}
+
+impl<P: Policy> Authorize<P> {
+    /// Returns whether the user can perform the action on the resource.
+    ///
+    /// # Errors
+    ///
+    /// When the policy fails.
+    pub async fn can(&self, action: &Action, resource: &P::Resource) -> Result<bool> {
+        P::can(&self.ctx, &self.user, action, Some(resource)).await
+    }
+
+    /// Checks that the user can perform the action on the resource.
+    ///
+    /// # Errors
+    ///
+    /// A `403 Forbidden` error when the user can't perform the action, or
+    /// when the policy fails.
+    pub async fn authorize(&self, action: &Action, resource: &P::Resource) -> Result<()> {
+        if self.can(action, resource).await? {
+            Ok(())
+        } else {
+            Err(forbidden())
+        }
+    }
+}
+
+impl<P: Policy> FromRequestParts<AppContext> for Authorize<P> {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, ctx: &AppContext) -> Result<Self> {
+        Ok(Self {
+            user: check::<P>(ctx, parts).await?,
+            ctx: ctx.clone(),
+            policy: PhantomData,
+        })
+    }
+}
+
+/// A layer guarding routes with the policy `P`, for [`Routes::layer`].
+///
+/// Requests are checked as with the [`Authorize`] extractor, without a
+/// resource.
+///
+/// ```rust,ignore
+/// fn routes(ctx: &AppContext) -> AppRoutes {
+///     AppRoutes::with_default_routes()
+///         .add_route(controllers::admin::routes().layer(Guard::<AdminPolicy>::new(ctx)))
+/// }
+/// ```
+///
+/// [`Routes::layer`]: crate::controller::Routes::layer
+pub struct Guard<P> {
+    ctx: AppContext,
+    policy: PhantomData<fn() -> P>,
+}
+
+impl<P: Policy> Guard<P> {
+    #[must_use]
+    pub fn new(ctx: &AppContext) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            policy: PhantomData,
+        }
+    }
+}
+
+impl<P> Clone for Guard<P> {
+    fn clone(&self) -> Self {
+        Self {
+            ctx: self.ctx.clone(),
+            policy: PhantomData,
+        }
+    }
+}
+
+impl<S, P> Layer<S> for Guard<P> {
+    type Service = GuardService<S, P>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardService {
+            inner,
+            guard: self.clone(),
+        }
+    }
+}
+
+/// The service of the [`Guard`] layer.
+pub struct GuardService<S, P> {
+    inner: S,
+    guard: Guard<P>,
+}
+
+impl<S: Clone, P> Clone for GuardService<S, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            guard: self.guard.clone(),
+        }
+    }
+}
+
+impl<S, P> Service<Request> for GuardService<S, P>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    P: Policy,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let ctx = self.guard.ctx.clone();
+        // the ready service is the one polled above
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            match check::<P>(&ctx, &mut parts).await {
+                Ok(_) => inner.call(Request::from_parts(parts, body)).await,
+                Err(err) => Ok(err.into_response()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::tests_cfg;
+
+    /// A user whose roles are sent in the `x-roles` header.
+    struct HeaderUser(Vec<String>);
+
+    impl Principal for HeaderUser {
+        fn roles(&self) -> Vec<String> {
+            self.0.clone()
+        }
+    }
+
+    impl FromRequestParts<AppContext> for HeaderUser {
+        type Rejection = Error;
+
+        async fn from_request_parts(parts: &mut Parts, _ctx: &AppContext) -> Result<Self> {
+            let roles = parts
+                .headers
+                .get("x-roles")
+                .ok_or_else(|| Error::Unauthorized("no user".to_string()))?;
+            Ok(Self(
+                roles
+                    .to_str()
+                    .unwrap()
+                    .split(',')
+                    .map(ToString::to_string)
+                    .collect(),
+            ))
+        }
+    }
+
+    struct Note {
+        owner: String,
+    }
+
+    struct NotePolicy;
+
+    #[async_trait]
+    impl Policy for NotePolicy {
+        type User = HeaderUser;
+        type Resource = Note;
+
+        async fn can(
+            _ctx: &AppContext,
+            user: &Self::User,
+            action: &Action,
+            note: Option<&Self::Resource>,
+        ) -> Result<bool> {
+            Ok(match (action, note) {
+                (Action::Read, _) => true,
+                (Action::Delete, Some(note)) => user.has_role(&note.owner),
+                _ => user.has_role("editor"),
+            })
+        }
+    }
+
+    async fn delete_note(auth: Authorize<NotePolicy>) -> Result<()> {
+        auth.authorize(
+            &Action::Delete,
+            &Note {
+                owner: "alice".to_string(),
+            },
+        )
+        .await
+    }
+
+    async fn status(app: Router, method: Method, roles: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(method).uri("/");
+        if let Some(roles) = roles {
+            request = request.header("x-roles", roles);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn can_authorize_with_extractor() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let app = Router::new()
+            .route(
+                "/",
+                get(|_: Authorize<NotePolicy>| async {}).delete(delete_note),
+            )
+            .with_state(ctx);
+
+        assert_eq!(
+            status(app.clone(), Method::GET, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(app.clone(), Method::GET, Some("reader")).await,
+            StatusCode::OK
+        );
+        // checked before the note is loaded
+        assert_eq!(
+            status(app.clone(), Method::DELETE, Some("alice")).await,
+            StatusCode::FORBIDDEN
+        );
+        // checked once the note is loaded
+        assert_eq!(
+            status(app.clone(), Method::DELETE, Some("editor,bob")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(app, Method::DELETE, Some("editor,alice")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn can_guard_routes() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let routes = crate::controller::Routes::new()
+            .add("/", get(|| async {}).post(|| async {}))
+            .layer(Guard::<NotePolicy>::new(&ctx));
+        let app = Router::new()
+            .route("/", routes.handlers[0].method.clone())
+            .with_state(ctx);
+
+        assert_eq!(
+            status(app.clone(), Method::GET, Some("reader")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(app.clone(), Method::POST, Some("reader")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(app, Method::POST, Some("editor")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn can_match_permissions() {
+        struct User(Vec<&'static str>);
+        impl Principal for User {
+            fn roles(&self) -> Vec<String> {
+                Vec::new()
+            }
+
+            fn permissions(&self) -> Vec<String> {
+                self.0.iter().map(ToString::to_string).collect()
+            }
+        }
+
+        let user = User(vec!["notes:*", "users:read"]);
+        assert!(user.has_permission("notes:update"));
+        assert!(user.has_permission("users:read"));
+        assert!(!user.has_permission("users:update"));
+        assert!(!user.has_permission("notesx:update"));
+        assert!(User(vec!["*"]).has_permission("users:update"));
+    }
+
+    #[cfg(feature = "auth_jwt")]
+    #[test]
+    fn can_read_claim_values() {
+        let claims = json!({
+            "roles": ["admin", "editor"],
+            "role": "owner",
+            "scope": "notes:read notes:update",
+        });
+        let claims = claims.as_object().unwrap();
+
+        assert_eq!(
+            claim_values(claims, &["roles", "role"]),
+            vec!["admin", "editor", "owner"]
+        );
+        assert_eq!(
+            claim_values(claims, &["permissions", "scope"]),
+            vec!["notes:read", "notes:update"]
+        );
+    }
+
+    #[test]
+    fn can_map_methods_to_actions() {
+        assert_eq!(Action::from_method(&Method::GET), Action::Read);
+        assert_eq!(Action::from_method(&Method::POST), Action::Create);
+        assert_eq!(Action::from_method(&Method::PATCH), Action::Update);
+        assert_eq!(Action::from_method(&Method::DELETE), Action::Delete);
+        assert_eq!(
+            Action::from_method(&Method::TRACE),
+            Action::Custom("trace".to_string())
+        );
+        assert_eq!(Action::Custom("publish".to_string()).to_string(), "publish");
+    }
+}
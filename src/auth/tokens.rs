@@ -0,0 +1,593 @@
+//! # Refresh Tokens and Revocation
+//!
+//! Issues short lived access tokens alongside rotating refresh tokens, and
+//! keeps a revocation list of access tokens.
+//!
+//! * A refresh token can be used once. Each use returns a new access token and
+//!   a new refresh token of the same family.
+//! * Presenting a refresh token that was already used means it was leaked, so
+//!   the whole family is revoked and the caller must log in again.
+//! * Access tokens can be revoked one by one (by their `jti` claim), or all the
+//!   tokens of a user at once, which logs the user out everywhere. Access
+//!   tokens carry the revocation generation they were issued in as the `gen`
+//!   claim, tokens of an older generation are revoked.
+//!
+//! State is kept in a [`TokenStore`], which is the application [`Cache`] by
+//! default. To keep it elsewhere (for example, in a database table), insert
+//! an `Arc<dyn TokenStore>` into the shared store in the `after_context` hook.
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::jwt::{UserClaims, JWT};
+use crate::{app::AppContext, cache::Cache, config::JWT as JWTConfig, hash, Error, Result};
+
+const FAMILY_ID_LENGTH: usize = 24;
+const SECRET_LENGTH: usize = 48;
+const GENERATION_CLAIM: &str = "gen";
+
+/// Key-value store keeping refresh token families and the revocation list.
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Returns the value of the given key.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be read.
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    /// Inserts a value which expires after the given duration.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    async fn insert(&self, key: &str, value: &str, expiry: Duration) -> Result<()>;
+
+    /// Replaces the value of the given key with a value which expires after
+    /// the given duration, only when the current value is `expected`. Returns
+    /// `true` when the value was replaced.
+    ///
+    /// The comparison and the replacement must be atomic, so that only one of
+    /// several concurrent calls succeeds.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        expiry: Duration,
+    ) -> Result<bool>;
+
+    /// Removes the given key.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    async fn remove(&self, key: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl TokenStore for Cache {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.driver.get(key).await?)
+    }
+
+    async fn insert(&self, key: &str, value: &str, expiry: Duration) -> Result<()> {
+        Ok(self.driver.insert_with_expiry(key, value, expiry).await?)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        expiry: Duration,
+    ) -> Result<bool> {
+        Ok(self
+            .driver
+            .compare_and_swap(key, expected, value, expiry)
+            .await?)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        Ok(self.driver.remove(key).await?)
+    }
+}
+
+/// Returns the [`TokenStore`] found in the shared store, or the application
+/// cache.
+#[must_use]
+pub fn store_from_context(ctx: &AppContext) -> Arc<dyn TokenStore> {
+    ctx.shared_store
+        .get::<Arc<dyn TokenStore>>()
+        .unwrap_or_else(|| ctx.cache.clone())
+}
+
+/// An access token, and a refresh token when refresh tokens are configured.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+/// A refresh token family. Only the hash of the latest refresh token is kept.
+#[derive(Debug, Serialize, Deserialize)]
+struct Family {
+    pid: String,
+    token_hash: String,
+    claims: Map<String, Value>,
+    generation: u64,
+}
+
+fn family_key(family: &str) -> String {
+    format!("auth:refresh:{family}")
+}
+
+fn revoked_token_key(jti: &str) -> String {
+    format!("auth:revoked:jti:{jti}")
+}
+
+fn generation_key(pid: &str) -> String {
+    format!("auth:generation:pid:{pid}")
+}
+
+fn current_timestamp_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+fn token_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn invalid_refresh_token() -> Error {
+    Error::Unauthorized("refresh token is not valid".to_string())
+}
+
+/// Issues, refreshes and revokes tokens.
+pub struct Tokens {
+    jwt: Arc<JWT>,
+    config: JWTConfig,
+    store: Arc<dyn TokenStore>,
+}
+
+impl Tokens {
+    /// Creates a new instance with the given JWT configuration and store.
+    ///
+    /// # Errors
+    ///
+    /// When the JWT keys could not be loaded.
+    pub fn new(config: &JWTConfig, store: Arc<dyn TokenStore>) -> Result<Self> {
+        Ok(Self {
+            jwt: Arc::new(JWT::from_config(config)?),
+            config: config.clone(),
+            store,
+        })
+    }
+
+    /// Creates a new instance from the application JWT configuration, using
+    /// the [`TokenStore`] found in the shared store, or the application cache.
+    ///
+    /// # Errors
+    ///
+    /// When JWT is not configured, or the JWT keys could not be loaded.
+    pub fn from_context(ctx: &AppContext) -> Result<Self> {
+        Ok(Self {
+            jwt: JWT::from_context(ctx)?,
+            config: ctx.config.get_jwt_config()?.clone(),
+            store: store_from_context(ctx),
+        })
+    }
+
+    /// Issues an access token for the given user, and a new refresh token
+    /// family when refresh tokens are configured.
+    ///
+    /// # Errors
+    ///
+    /// When the access token could not be generated, or the refresh token
+    /// could not be stored.
+    pub async fn issue(&self, pid: &str, claims: Map<String, Value>) -> Result<TokenPair> {
+        let generation = self.generation(pid).await?.unwrap_or_default();
+        let access_token = self.access_token(pid, claims.clone(), generation)?;
+        let refresh_token = match &self.config.refresh {
+            Some(_) => {
+                let family_id = hash::random_string(FAMILY_ID_LENGTH);
+                let mut family = Family {
+                    pid: pid.to_string(),
+                    token_hash: String::new(),
+                    claims,
+                    generation,
+                };
+                let secret = Self::rotate(&mut family);
+                self.store
+                    .insert(
+                        &family_key(&family_id),
+                        &serde_json::to_string(&family)?,
+                        self.refresh_expiration(),
+                    )
+                    .await?;
+                Some(format!("{family_id}.{secret}"))
+            }
+            None => None,
+        };
+
+        Ok(TokenPair {
+            access_token,
+            refresh_token,
+        })
+    }
+
+    /// Exchanges a refresh token for a new access token and a new refresh
+    /// token. The given refresh token can not be used again.
+    ///
+    /// When a refresh token is used twice, the whole family is revoked.
+    ///
+    /// # Errors
+    ///
+    /// When refresh tokens are not configured, or the refresh token is not
+    /// valid, expired, revoked or reused.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair> {
+        if self.config.refresh.is_none() {
+            return Err(Error::string("refresh tokens are not configured"));
+        }
+
+        let (family_id, secret) = refresh_token
+            .split_once('.')
+            .ok_or_else(invalid_refresh_token)?;
+        let key = family_key(family_id);
+        let stored = self
+            .store
+            .get(&key)
+            .await?
+            .ok_or_else(invalid_refresh_token)?;
+        let mut family: Family = serde_json::from_str(&stored)?;
+
+        if self
+            .generation(&family.pid)
+            .await?
+            .is_some_and(|generation| family.generation < generation)
+        {
+            self.store.remove(&key).await?;
+            return Err(invalid_refresh_token());
+        }
+
+        if !hash::constant_time_eq(&family.token_hash, &token_hash(secret)) {
+            return self.revoke_reused_family(&key, &family.pid).await;
+        }
+
+        let access_token =
+            self.access_token(&family.pid, family.claims.clone(), family.generation)?;
+        let secret = Self::rotate(&mut family);
+        // only one of several concurrent uses of the same token can rotate it
+        if !self
+            .store
+            .compare_and_swap(
+                &key,
+                &stored,
+                &serde_json::to_string(&family)?,
+                self.refresh_expiration(),
+            )
+            .await?
+        {
+            return self.revoke_reused_family(&key, &family.pid).await;
+        }
+
+        Ok(TokenPair {
+            access_token,
+            refresh_token: Some(format!("{family_id}.{secret}")),
+        })
+    }
+
+    /// Revokes the family of the given refresh token.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    pub async fn revoke_refresh_token(&self, refresh_token: &str) -> Result<()> {
+        match refresh_token.split_once('.') {
+            Some((family_id, _)) => self.store.remove(&family_key(family_id)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Revokes the given access token until it expires.
+    ///
+    /// # Errors
+    ///
+    /// When the token has no `jti` claim, or the store could not be written.
+    pub async fn revoke(&self, claims: &UserClaims) -> Result<()> {
+        let jti = claims
+            .jti
+            .as_ref()
+            .ok_or_else(|| Error::string("token has no jti claim"))?;
+        let ttl = claims.exp().saturating_sub(get_current_timestamp()).max(1);
+        self.store
+            .insert(&revoked_token_key(jti), "1", Duration::from_secs(ttl))
+            .await
+    }
+
+    /// Revokes all the access and refresh tokens issued to the given user so
+    /// far, which logs the user out everywhere.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    pub async fn revoke_all(&self, pid: &str) -> Result<()> {
+        let ttl = self
+            .config
+            .refresh
+            .as_ref()
+            .map_or(0, |refresh| refresh.expiration)
+            .max(self.config.expiration)
+            .max(1);
+        // generations grow even after the previous one expired, so tokens of
+        // an expired generation are never valid again
+        let generation = current_timestamp_millis().max(
+            self.generation(pid)
+                .await?
+                .unwrap_or_default()
+                .saturating_add(1),
+        );
+        self.store
+            .insert(
+                &generation_key(pid),
+                &generation.to_string(),
+                Duration::from_secs(ttl),
+            )
+            .await
+    }
+
+    /// Returns `true` when the access token with the given claims was revoked.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be read.
+    pub async fn is_revoked(&self, claims: &UserClaims) -> Result<bool> {
+        if let Some(jti) = &claims.jti {
+            if self.store.get(&revoked_token_key(jti)).await?.is_some() {
+                return Ok(true);
+            }
+        }
+
+        let Some(generation) = self.generation(&claims.pid).await? else {
+            return Ok(false);
+        };
+        Ok(
+            match claims.claims.get(GENERATION_CLAIM).and_then(Value::as_u64) {
+                Some(token_generation) => token_generation < generation,
+                // tokens issued elsewhere are dated by `iat`, and revoked when they
+                // can not be dated
+                None => claims
+                    .iat
+                    .map_or(true, |iat| iat.saturating_mul(1000) <= generation),
+            },
+        )
+    }
+
+    fn access_token(
+        &self,
+        pid: &str,
+        mut claims: Map<String, Value>,
+        generation: u64,
+    ) -> Result<String> {
+        claims.insert(GENERATION_CLAIM.to_string(), generation.into());
+        self.jwt
+            .generate_token(self.config.expiration, pid.to_string(), claims)
+            .map_err(|err| Error::Any(Box::new(err)))
+    }
+
+    fn refresh_expiration(&self) -> Duration {
+        Duration::from_secs(
+            self.config
+                .refresh
+                .as_ref()
+                .map_or(self.config.expiration, |refresh| refresh.expiration),
+        )
+    }
+
+    /// Sets a new refresh token on the family, and returns its secret.
+    fn rotate(family: &mut Family) -> String {
+        let secret = hash::random_string(SECRET_LENGTH);
+        family.token_hash = token_hash(&secret);
+        secret
+    }
+
+    async fn revoke_reused_family(&self, key: &str, pid: &str) -> Result<TokenPair> {
+        tracing::warn!(
+            pid,
+            "refresh token reuse detected, revoking the token family"
+        );
+        self.store.remove(key).await?;
+        Err(invalid_refresh_token())
+    }
+
+    /// Returns the revocation generation of the given user, if any of the user
+    /// tokens were revoked.
+    async fn generation(&self, pid: &str) -> Result<Option<u64>> {
+        Ok(self
+            .store
+            .get(&generation_key(pid))
+            .await?
+            .and_then(|generation| generation.parse().ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::JWTRefresh, tests_cfg::auth::MemStore};
+
+    fn tokens(refresh: bool) -> Tokens {
+        Tokens::new(
+            &JWTConfig {
+                location: None,
+                secret: "PqRwLF2rhHe8J22oBeHy".to_string(),
+                expiration: 60,
+                keys: vec![],
+                refresh: refresh.then_some(JWTRefresh { expiration: 600 }),
+                revocation: true,
+            },
+            Arc::new(MemStore::default()),
+        )
+        .unwrap()
+    }
+
+    fn claims(tokens: &Tokens, access_token: &str) -> UserClaims {
+        tokens.jwt.validate(access_token).unwrap().claims
+    }
+
+    #[tokio::test]
+    async fn can_issue_tokens() {
+        let pair = tokens(false).issue("pid", Map::new()).await.unwrap();
+        assert!(pair.refresh_token.is_none());
+
+        let tokens = tokens(true);
+        let pair = tokens.issue("pid", Map::new()).await.unwrap();
+        assert!(pair.refresh_token.is_some());
+
+        let claims = claims(&tokens, &pair.access_token);
+        assert_eq!(claims.pid, "pid");
+        assert!(claims.jti.is_some());
+        assert!(claims.iat.is_some());
+    }
+
+    #[tokio::test]
+    async fn can_rotate_refresh_tokens() {
+        let tokens = tokens(true);
+        let mut custom = Map::new();
+        custom.insert("role".to_string(), "admin".into());
+        let first = tokens.issue("pid", custom).await.unwrap();
+
+        let second = tokens
+            .refresh(first.refresh_token.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert_eq!(
+            claims(&tokens, &second.access_token).claims.get("role"),
+            Some(&Value::from("admin"))
+        );
+
+        let third = tokens
+            .refresh(second.refresh_token.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert!(third.refresh_token.is_some());
+    }
+
+    #[tokio::test]
+    async fn can_detect_refresh_token_reuse() {
+        let tokens = tokens(true);
+        let first = tokens.issue("pid", Map::new()).await.unwrap();
+        let second = tokens
+            .refresh(first.refresh_token.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // the first token is reused, the whole family is revoked
+        assert!(tokens
+            .refresh(first.refresh_token.as_ref().unwrap())
+            .await
+            .is_err());
+        assert!(tokens
+            .refresh(second.refresh_token.as_ref().unwrap())
+            .await
+            .is_err());
+
+        assert!(tokens.refresh("invalid").await.is_err());
+        assert!(tokens.refresh("invalid.token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn can_rotate_refresh_tokens_once() {
+        let tokens = tokens(true);
+        let pair = tokens.issue("pid", Map::new()).await.unwrap();
+        let refresh_token = pair.refresh_token.as_ref().unwrap();
+
+        let (first, second) =
+            tokio::join!(tokens.refresh(refresh_token), tokens.refresh(refresh_token));
+        assert!(first.is_ok() != second.is_ok());
+
+        // the concurrent use revoked the family
+        let rotated = first.or(second).unwrap();
+        assert!(tokens
+            .refresh(rotated.refresh_token.as_ref().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn can_revoke_access_token() {
+        let tokens = tokens(false);
+        let first = claims(
+            &tokens,
+            &tokens.issue("pid", Map::new()).await.unwrap().access_token,
+        );
+        let second = claims(
+            &tokens,
+            &tokens.issue("pid", Map::new()).await.unwrap().access_token,
+        );
+
+        tokens.revoke(&first).await.unwrap();
+        assert!(tokens.is_revoked(&first).await.unwrap());
+        assert!(!tokens.is_revoked(&second).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn can_revoke_all_user_tokens() {
+        let tokens = tokens(true);
+        let pair = tokens.issue("pid", Map::new()).await.unwrap();
+        let other = tokens.issue("other-pid", Map::new()).await.unwrap();
+
+        tokens.revoke_all("pid").await.unwrap();
+        assert!(tokens
+            .is_revoked(&claims(&tokens, &pair.access_token))
+            .await
+            .unwrap());
+        assert!(tokens
+            .refresh(pair.refresh_token.as_ref().unwrap())
+            .await
+            .is_err());
+
+        assert!(!tokens
+            .is_revoked(&claims(&tokens, &other.access_token))
+            .await
+            .unwrap());
+        assert!(tokens
+            .refresh(other.refresh_token.as_ref().unwrap())
+            .await
+            .is_ok());
+
+        // tokens issued right after the revocation are valid
+        let pair = tokens.issue("pid", Map::new()).await.unwrap();
+        assert!(!tokens
+            .is_revoked(&claims(&tokens, &pair.access_token))
+            .await
+            .unwrap());
+        let pair = tokens
+            .refresh(pair.refresh_token.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert!(!tokens
+            .is_revoked(&claims(&tokens, &pair.access_token))
+            .await
+            .unwrap());
+
+        tokens.revoke_all("pid").await.unwrap();
+        assert!(tokens
+            .is_revoked(&claims(&tokens, &pair.access_token))
+            .await
+            .unwrap());
+    }
+}
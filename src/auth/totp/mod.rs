@@ -0,0 +1,486 @@
+//! # TOTP Two-Factor Authentication
+//!
+//! Time-based one-time passwords ([RFC 6238]), as generated by authenticator
+//! apps, and recovery codes for users who lost their device.
+//!
+//! Enrolling a user:
+//!
+//! ```rust
+//! use loco_rs::auth::totp::Totp;
+//!
+//! let secret = Totp::generate_secret();
+//! let totp = Totp::new(&secret, "MyApp", "user@example.com").unwrap();
+//! // shown to the user, to scan with an authenticator app
+//! let uri = totp.provisioning_uri();
+//! let svg = totp.qr_svg().unwrap();
+//! // the first code confirms the enrollment
+//! let code = totp.generate_current();
+//! assert!(totp.verify_current(&code, None).is_some());
+//! ```
+//!
+//! [`Totp::verify`] returns the time step of the accepted code. Store it, and
+//! pass it back on the next verification, so that a code can't be used twice.
+//!
+//! [RFC 6238]: https://datatracker.ietf.org/doc/html/rfc6238
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::{distr::Alphanumeric, rng, Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::{hash, Error, Result};
+
+/// The length of generated secrets, in bytes (as recommended by RFC 4226).
+const SECRET_LENGTH: usize = 20;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The hash algorithm of the codes. Most authenticator apps only support
+/// [`Algorithm::Sha1`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        }
+    }
+
+    fn sign(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        fn sign<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+            let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(key)
+                .expect("HMAC accepts keys of any size");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        match self {
+            Self::Sha1 => sign::<Hmac<Sha1>>(key, message),
+            Self::Sha256 => sign::<Hmac<Sha256>>(key, message),
+            Self::Sha512 => sign::<Hmac<Sha512>>(key, message),
+        }
+    }
+}
+
+/// Generates and verifies the codes of one user.
+#[derive(Debug, Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    issuer: String,
+    account: String,
+    algorithm: Algorithm,
+    digits: u32,
+    period: u64,
+    skew: u64,
+}
+
+impl Totp {
+    /// Creates a generator from a base32 encoded secret, with 6 digit codes
+    /// valid for 30 seconds, accepting the codes of one step before and after
+    /// the current one to allow for clock drift.
+    ///
+    /// The issuer (the application name) and the account (such as the user
+    /// email) are shown by authenticator apps.
+    ///
+    /// # Errors
+    ///
+    /// When the secret is not valid base32.
+    pub fn new(secret: &str, issuer: &str, account: &str) -> Result<Self> {
+        let secret = base32_decode(secret)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| Error::string("invalid TOTP secret"))?;
+        Ok(Self {
+            secret,
+            issuer: issuer.to_string(),
+            account: account.to_string(),
+            algorithm: Algorithm::default(),
+            digits: 6,
+            period: 30,
+            skew: 1,
+        })
+    }
+
+    /// Returns a new random base32 encoded secret.
+    #[must_use]
+    pub fn generate_secret() -> String {
+        let mut secret = [0u8; SECRET_LENGTH];
+        rng().fill_bytes(&mut secret);
+        base32_encode(&secret)
+    }
+
+    /// Sets the hash algorithm of the codes.
+    #[must_use]
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the number of digits of the codes, between 6 and 8.
+    #[must_use]
+    pub fn with_digits(mut self, digits: u32) -> Self {
+        self.digits = digits.clamp(6, 8);
+        self
+    }
+
+    /// Sets how long a code is valid, in seconds.
+    #[must_use]
+    pub fn with_period(mut self, period: u64) -> Self {
+        self.period = period.max(1);
+        self
+    }
+
+    /// Sets how many steps before and after the current one are accepted.
+    #[must_use]
+    pub fn with_skew(mut self, skew: u64) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    /// Returns the base32 encoded secret.
+    #[must_use]
+    pub fn secret(&self) -> String {
+        base32_encode(&self.secret)
+    }
+
+    /// Returns the time step of the given unix timestamp.
+    #[must_use]
+    pub const fn step(&self, time: u64) -> u64 {
+        time / self.period
+    }
+
+    /// Returns the code of the given time step.
+    #[must_use]
+    pub fn generate_at_step(&self, step: u64) -> String {
+        let hash = self.algorithm.sign(&self.secret, &step.to_be_bytes());
+        // dynamic truncation, as in RFC 4226
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = binary % 10u32.pow(self.digits);
+        format!("{code:0width$}", width = self.digits as usize)
+    }
+
+    /// Returns the code at the given unix timestamp.
+    #[must_use]
+    pub fn generate(&self, time: u64) -> String {
+        self.generate_at_step(self.step(time))
+    }
+
+    /// Returns the current code.
+    #[must_use]
+    pub fn generate_current(&self) -> String {
+        self.generate(now())
+    }
+
+    /// Verifies a code at the given unix timestamp, returning the time step
+    /// of the code when it is valid.
+    ///
+    /// Codes of the steps up to `last_step`, the step returned by the last
+    /// successful verification, are rejected so that a code can't be
+    /// replayed.
+    #[must_use]
+    pub fn verify(&self, code: &str, time: u64, last_step: Option<u64>) -> Option<u64> {
+        let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+        if code.len() != self.digits as usize {
+            return None;
+        }
+
+        let current = self.step(time);
+        (current.saturating_sub(self.skew)..=current.saturating_add(self.skew))
+            .filter(|step| last_step.map_or(true, |last| *step > last))
+            .find(|step| hash::constant_time_eq(&self.generate_at_step(*step), &code))
+    }
+
+    /// Verifies a code at the current time. See [`Totp::verify`].
+    #[must_use]
+    pub fn verify_current(&self, code: &str, last_step: Option<u64>) -> Option<u64> {
+        self.verify(code, now(), last_step)
+    }
+
+    /// Returns the `otpauth://` URI to enroll the user in an authenticator
+    /// app.
+    #[must_use]
+    pub fn provisioning_uri(&self) -> String {
+        let issuer = percent_encode(&self.issuer);
+        format!(
+            "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm={}&digits={}&period={}",
+            percent_encode(&self.account),
+            self.secret(),
+            self.algorithm.name(),
+            self.digits,
+            self.period
+        )
+    }
+
+    /// Returns the provisioning URI as an SVG QR code.
+    ///
+    /// # Errors
+    ///
+    /// When the URI is too long for a QR code.
+    pub fn qr_svg(&self) -> Result<String> {
+        let code = qrcode::QrCode::with_error_correction_level(
+            self.provisioning_uri(),
+            qrcode::EcLevel::M,
+        )
+        .map_err(|err| Error::string(&err.to_string()))?;
+        let svg = code
+            .render::<qrcode::render::svg::Color<'_>>()
+            .min_dimensions(200, 200)
+            .build();
+        // drop the XML declaration, so that the SVG can be embedded in HTML
+        Ok(svg
+            .find("<svg")
+            .map_or_else(|| svg.clone(), |start| svg[start..].to_string()))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Percent-encodes all the characters of a URI component, but the unreserved
+/// ones.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Encodes bytes as unpadded base32 (RFC 4648).
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(char::from(
+                BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize],
+            ));
+        }
+    }
+    if bits > 0 {
+        encoded.push(char::from(
+            BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize],
+        ));
+    }
+    encoded
+}
+
+/// Decodes base32, ignoring case, spaces and padding.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded.bytes().filter(|c| !matches!(c, b' ' | b'-' | b'=')) {
+        let c = c.to_ascii_uppercase();
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)?;
+        buffer = (buffer << 5) | u32::try_from(value).ok()?;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push(((buffer >> bits) & 0xff) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Returns new random recovery codes, formatted as `xxxxx-xxxxx`.
+///
+/// Show them to the user once, and store their hashes (see
+/// [`hash_recovery_codes`]).
+#[must_use]
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let code: String = rng()
+                .sample_iter(&Alphanumeric)
+                .filter(u8::is_ascii_alphanumeric)
+                .take(10)
+                .map(|c| char::from(c.to_ascii_lowercase()))
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Normalizes a recovery code as typed by a user.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Hashes recovery codes with [`hash::hash_password`], for storage.
+///
+/// # Errors
+///
+/// When a code could not be hashed.
+pub fn hash_recovery_codes(codes: &[String]) -> Result<Vec<String>> {
+    codes
+        .iter()
+        .map(|code| hash::hash_password(&normalize_recovery_code(code)))
+        .collect()
+}
+
+/// Returns the index of the hash matching the given recovery code. Remove it
+/// from the stored hashes, as a recovery code can be used once.
+#[must_use]
+pub fn verify_recovery_code(code: &str, hashes: &[String]) -> Option<usize> {
+    let code = normalize_recovery_code(code);
+    if code.is_empty() {
+        return None;
+    }
+    hashes
+        .iter()
+        .position(|hashed| hash::verify_password(&code, hashed))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// The RFC 6238 test secrets, of the size of each algorithm.
+    fn rfc_totp(algorithm: Algorithm) -> Totp {
+        let secret: &[u8] = match algorithm {
+            Algorithm::Sha1 => b"12345678901234567890",
+            Algorithm::Sha256 => b"12345678901234567890123456789012",
+            Algorithm::Sha512 => {
+                b"1234567890123456789012345678901234567890123456789012345678901234"
+            }
+        };
+        Totp::new(&base32_encode(secret), "Loco", "user@example.com")
+            .unwrap()
+            .with_algorithm(algorithm)
+            .with_digits(8)
+    }
+
+    #[rstest]
+    #[case(Algorithm::Sha1, 59, "94287082")]
+    #[case(Algorithm::Sha256, 59, "46119246")]
+    #[case(Algorithm::Sha512, 59, "90693936")]
+    #[case(Algorithm::Sha1, 1_111_111_109, "07081804")]
+    #[case(Algorithm::Sha256, 1_111_111_109, "68084774")]
+    #[case(Algorithm::Sha512, 1_111_111_109, "25091201")]
+    #[case(Algorithm::Sha1, 20_000_000_000, "65353130")]
+    #[case(Algorithm::Sha256, 20_000_000_000, "77737706")]
+    #[case(Algorithm::Sha512, 20_000_000_000, "47863826")]
+    fn can_generate_rfc_6238_codes(
+        #[case] algorithm: Algorithm,
+        #[case] time: u64,
+        #[case] code: &str,
+    ) {
+        assert_eq!(rfc_totp(algorithm).generate(time), code);
+    }
+
+    #[test]
+    fn can_verify_with_drift_window() {
+        let totp = rfc_totp(Algorithm::Sha1).with_digits(6);
+        let code = totp.generate(1_000_000);
+
+        assert_eq!(totp.verify(&code, 1_000_000, None), Some(33_333));
+        assert_eq!(totp.verify(&code, 1_000_000 + 30, None), Some(33_333));
+        assert_eq!(totp.verify(&code, 1_000_000 - 30, None), Some(33_333));
+        assert_eq!(totp.verify(&code, 1_000_000 + 60, None), None);
+        assert_eq!(
+            totp.verify(&code[..3], 1_000_000, None),
+            None,
+            "partial codes are rejected"
+        );
+        assert_eq!(
+            totp.verify(&format!("{} {}", &code[..3], &code[3..]), 1_000_000, None),
+            Some(33_333)
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_codes() {
+        let totp = rfc_totp(Algorithm::Sha1).with_digits(6);
+        let code = totp.generate(1_000_000);
+
+        let step = totp.verify(&code, 1_000_000, None);
+        assert_eq!(totp.verify(&code, 1_000_000, step), None);
+        // an older code can't be used after a newer one either
+        let previous = totp.generate(1_000_000 - 30);
+        assert_eq!(totp.verify(&previous, 1_000_000, step), None);
+    }
+
+    #[test]
+    fn can_build_provisioning_uri() {
+        let totp = Totp::new("JBSWY3DPEHPK3PXP", "Loco App", "user@example.com").unwrap();
+
+        assert_eq!(
+            totp.provisioning_uri(),
+            "otpauth://totp/Loco%20App:user%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Loco%20App&algorithm=SHA1&digits=6&period=30"
+        );
+        assert!(totp.qr_svg().unwrap().starts_with("<svg"));
+    }
+
+    #[test]
+    fn can_generate_secrets() {
+        let secret = Totp::generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_ne!(secret, Totp::generate_secret());
+
+        let totp = Totp::new(&secret.to_lowercase(), "Loco", "user").unwrap();
+        assert_eq!(totp.secret(), secret);
+        assert!(Totp::new("not base32!", "Loco", "user").is_err());
+        assert!(Totp::new("", "Loco", "user").is_err());
+    }
+
+    #[rstest]
+    #[case(b"", "")]
+    #[case(b"f", "MY")]
+    #[case(b"fo", "MZXQ")]
+    #[case(b"foo", "MZXW6")]
+    #[case(b"foob", "MZXW6YQ")]
+    #[case(b"fooba", "MZXW6YTB")]
+    #[case(b"foobar", "MZXW6YTBOI")]
+    fn can_encode_base32(#[case] data: &[u8], #[case] encoded: &str) {
+        assert_eq!(base32_encode(data), encoded);
+        assert_eq!(base32_decode(encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn can_use_recovery_codes() {
+        let codes = generate_recovery_codes(3);
+        assert_eq!(codes.len(), 3);
+        assert!(codes.iter().all(|code| code.len() == 11));
+
+        let hashes = hash_recovery_codes(&codes).unwrap();
+        assert_eq!(verify_recovery_code(&codes[1], &hashes), Some(1));
+        assert_eq!(
+            verify_recovery_code(&codes[2].to_uppercase().replace('-', " "), &hashes),
+            Some(2)
+        );
+        assert_eq!(verify_recovery_code("aaaaa-bbbbb", &hashes), None);
+        assert_eq!(verify_recovery_code("", &hashes), None);
+    }
+}
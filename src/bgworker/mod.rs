@@ -3,6 +3,7 @@ use std::{
     io::Write,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -94,7 +95,6 @@ impl Queue {
     /// # Errors
     ///
     /// This function will return an error if fails
-    #[allow(unused_variables)]
     pub async fn enqueue<A: Serialize + Send + Sync>(
         &self,
         class: String,
@@ -102,11 +102,29 @@ impl Queue {
         args: A,
         tags: Option<Vec<String>>,
     ) -> Result<()> {
-        tracing::debug!(worker = class, queue = ?queue, tags = ?tags, "Enqueuing background job");
+        self.enqueue_at(class, queue, args, tags, chrono::Utc::now())
+            .await
+    }
+
+    /// Add a job to the queue, which is not run before `run_at`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if fails
+    #[allow(unused_variables)]
+    pub async fn enqueue_at<A: Serialize + Send + Sync>(
+        &self,
+        class: String,
+        queue: Option<String>,
+        args: A,
+        tags: Option<Vec<String>>,
+        run_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        tracing::debug!(worker = class, queue = ?queue, tags = ?tags, run_at = %run_at, "Enqueuing background job");
         match self {
             #[cfg(feature = "bg_redis")]
             Self::Redis(pool, _, _, _) => {
-                redis::enqueue(pool, class, queue, args, tags).await?;
+                redis::enqueue_at(pool, class, queue, args, tags, run_at).await?;
             }
             #[cfg(feature = "bg_pg")]
             Self::Postgres(pool, _, _, _) => {
@@ -114,7 +132,7 @@ impl Queue {
                     pool,
                     &class,
                     serde_json::to_value(args)?,
-                    chrono::Utc::now(),
+                    run_at,
                     None,
                     tags,
                 )
@@ -127,7 +145,7 @@ impl Queue {
                     pool,
                     &class,
                     serde_json::to_value(args)?,
-                    chrono::Utc::now(),
+                    run_at,
                     None,
                     tags,
                 )
@@ -648,6 +666,47 @@ pub trait BackgroundWorker<A: Send + Sync + serde::Serialize + 'static>: Send +
         Ok(())
     }
 
+    /// Performs the job after `delay`. The queue providers keep the job
+    /// until it is due, so it survives a restart, while the other modes wait
+    /// in the process.
+    async fn perform_in(ctx: &AppContext, args: A, delay: Duration) -> crate::Result<()>
+    where
+        Self: Sized,
+    {
+        match &ctx.config.workers.mode {
+            WorkerMode::BackgroundQueue => {
+                if let Some(p) = &ctx.queue_provider {
+                    let tags = Self::tags();
+                    let tags_option = if tags.is_empty() { None } else { Some(tags) };
+                    let run_at = chrono::Utc::now()
+                        + chrono::Duration::from_std(delay)
+                            .map_err(|err| Error::string(&err.to_string()))?;
+                    p.enqueue_at(Self::class_name(), Self::queue(), args, tags_option, run_at)
+                        .await?;
+                } else {
+                    tracing::error!(
+                        "perform_in: background queue is selected, but queue was not populated \
+                         in context"
+                    );
+                }
+            }
+            WorkerMode::ForegroundBlocking => {
+                tokio::time::sleep(delay).await;
+                Self::build(ctx).perform(args).await?;
+            }
+            WorkerMode::BackgroundAsync => {
+                let dx = ctx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Err(err) = Self::build(&dx).perform(args).await {
+                        tracing::error!(err = err.to_string(), "worker failed to perform job");
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
     async fn perform(&self, args: A) -> crate::Result<()>;
 }
 
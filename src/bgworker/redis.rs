@@ -267,6 +267,22 @@ pub async fn enqueue(
     queue: Option<String>,
     args: impl serde::Serialize + Send,
     tags: Option<Vec<String>>,
+) -> Result<()> {
+    enqueue_at(client, class, queue, args, tags, Utc::now()).await
+}
+
+/// Add a task which is not run before `run_at`
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_at(
+    client: &RedisPool,
+    class: String,
+    queue: Option<String>,
+    args: impl serde::Serialize + Send,
+    tags: Option<Vec<String>>,
+    run_at: DateTime<Utc>,
 ) -> Result<()> {
     let mut conn = get_connection(client).await?;
     let queue_name = queue.unwrap_or_else(|| "default".to_string());
@@ -281,6 +297,7 @@ pub async fn enqueue(
     // Create job
     let mut job = Job::new(job_id.clone(), class, args_json);
     job.tags = tags;
+    job.run_at = run_at;
 
     // Serialize job for Redis storage
     let job_json = job.to_json()?;
@@ -328,6 +345,12 @@ async fn dequeue(
                             .is_some_and(|job_tags| job_tags.iter().any(|tag| tags.contains(tag)))
                     };
 
+                    if job.run_at > Utc::now() {
+                        // Not due yet, put the job back in the queue
+                        let _: () = conn.rpush(&queue_key, json).await?;
+                        continue;
+                    }
+
                     if !should_process {
                         // Put the job back in the queue
                         let _: () = conn.rpush(&queue_key, json).await?;
@@ -1090,6 +1113,32 @@ mod tests {
         assert!(job_opt.is_some());
     }
 
+    #[tokio::test]
+    async fn test_cannot_dequeue_delayed_jobs_redis() {
+        let (client, _container) = setup_redis().await;
+
+        let args = serde_json::json!({"user_id": 42});
+        let run_at = Utc::now() + chrono::Duration::milliseconds(500);
+        enqueue_at(
+            &client,
+            "PasswordReset".to_string(),
+            None,
+            args,
+            None,
+            run_at,
+        )
+        .await
+        .expect("enqueue");
+
+        let queues = vec!["default".to_string()];
+        let job_opt = dequeue(&client, &queues, &[]).await.expect("dequeue");
+        assert!(job_opt.is_none());
+
+        sleep(Duration::from_millis(600)).await;
+        let job_opt = dequeue(&client, &queues, &[]).await.expect("dequeue");
+        assert!(job_opt.is_some());
+    }
+
     #[tokio::test]
     async fn test_can_clear_redis() {
         // Setup Redis directly with testcontainer
@@ -27,7 +27,7 @@ use crate::{
     mailer::{EmailSender, MailerWorker},
     prelude::BackgroundWorker,
     scheduler::{self, Scheduler},
-    storage::{self, Storage},
+    storage,
     task::{self, Tasks},
     Result,
 };
@@ -98,6 +98,11 @@ pub async fn start<H: Hooks>(
         });
     }
 
+    #[cfg(feature = "mailer_inbound")]
+    if boot.router.is_some() {
+        start_inbound_smtp(&boot.app_context).await?;
+    }
+
     if !no_banner {
         print_banner(&boot, &server_config);
     }
@@ -371,6 +376,7 @@ pub async fn create_context<H: Hooks>(
              for production. disable with `logger.pretty_backtrace` in your config yaml)"
         );
     }
+
     #[cfg(feature = "with-db")]
     let db = db::connect(&config.database).await?;
 
@@ -386,14 +392,40 @@ pub async fn create_context<H: Hooks>(
         #[cfg(feature = "with-db")]
         db,
         queue_provider,
-        storage: Storage::single(storage::drivers::null::new()).into(),
+        storage: storage::create_storage_provider(&config)?,
         cache: cache::create_cache_provider(&config).await?,
         config,
         mailer,
         shared_store: Arc::new(crate::app::SharedStore::default()),
     };
+    crate::mailer::bulk::register_throttle(&ctx);
 
-    H::after_context(ctx).await
+    let ctx = H::after_context(ctx).await?;
+    #[cfg(feature = "auth_jwt")]
+    check_token_store(&ctx)?;
+    Ok(ctx)
+}
+
+/// Rejects refresh tokens and revocation without a store keeping them, rather
+/// than failing on the first sign in.
+#[cfg(feature = "auth_jwt")]
+fn check_token_store(ctx: &AppContext) -> Result<()> {
+    let Some(jwt) = ctx.config.auth.as_ref().and_then(|auth| auth.jwt.as_ref()) else {
+        return Ok(());
+    };
+    if (jwt.refresh.is_some() || jwt.revocation)
+        && matches!(ctx.config.cache, config::CacheConfig::Null)
+        && !ctx
+            .shared_store
+            .contains::<Arc<dyn crate::auth::tokens::TokenStore>>()
+    {
+        return Err(Error::string(
+            "`auth.jwt.refresh` and `auth.jwt.revocation` need a cache supporting compare and \
+             swap: configure an `InMem` or `Redis` cache, or register a custom \
+             `auth::tokens::TokenStore` in the shared store",
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(feature = "with-db")]
@@ -438,6 +470,10 @@ pub async fn create_app<H: Hooks>(
 /// When could not create the application
 pub async fn run_app<H: Hooks>(mode: &StartMode, app_context: AppContext) -> Result<BootResult> {
     H::before_run(&app_context).await?;
+    #[cfg(feature = "mailer_inbound")]
+    app_context.shared_store.insert(Arc::new(
+        crate::mailer::inbound::Mailboxes::from_hooks::<H>(&app_context),
+    ));
     let initializers = H::initializers(&app_context).await?;
 
     info!(
@@ -491,6 +527,30 @@ pub async fn run_app<H: Hooks>(mode: &StartMode, app_context: AppContext) -> Res
     }
 }
 
+/// Starts the inbound SMTP listener when `mailer.inbound.smtp` is configured.
+#[cfg(feature = "mailer_inbound")]
+async fn start_inbound_smtp(app_context: &AppContext) -> Result<()> {
+    let Some(smtp) = app_context
+        .config
+        .mailer
+        .as_ref()
+        .and_then(|mailer| mailer.inbound.as_ref())
+        .and_then(|inbound| inbound.smtp.as_ref())
+    else {
+        return Ok(());
+    };
+    let listener = crate::mailer::inbound::smtp::SmtpListener::bind(app_context, smtp).await?;
+    tokio::spawn(async move {
+        if let Err(err) = listener.run().await {
+            error!(
+                err = err.to_string(),
+                "error while running the inbound SMTP listener"
+            );
+        }
+    });
+    Ok(())
+}
+
 /// Sets up the application's routes based on the provided initializers and hooks.
 async fn setup_routes<H: Hooks>(
     app_context: &AppContext,
@@ -511,6 +571,13 @@ async fn register_workers<H: Hooks>(app_context: &AppContext) -> Result<()> {
     if app_context.config.workers.mode == WorkerMode::BackgroundQueue {
         if let Some(queue) = &app_context.queue_provider {
             queue.register(MailerWorker::build(app_context)).await?;
+            queue
+                .register(crate::mailer::bulk::BulkMailerWorker::build(app_context))
+                .await?;
+            #[cfg(feature = "mailer_inbound")]
+            queue
+                .register(crate::mailer::inbound::InboundWorker::build(app_context))
+                .await?;
             H::connect_workers(app_context, queue).await?;
         } else {
             return Err(Error::QueueProviderMissing);
@@ -577,13 +644,96 @@ pub fn list_middlewares<H: Hooks>(ctx: &AppContext) -> Vec<MiddlewareInfo> {
 /// Initializes an [`EmailSender`] based on the mailer configuration settings
 /// ([`config::Mailer`]).
 fn create_mailer(config: &config::Mailer) -> Result<Option<EmailSender>> {
+    check_mailer_features(config)?;
+    let sender = create_mailer_transport(config)?;
+    #[cfg(feature = "mailer_dkim")]
+    if let Some(dkim) = config.dkim.as_ref() {
+        return sender.map(|sender| sender.dkim(dkim)).transpose();
+    }
+    Ok(sender)
+}
+
+/// Rejects the mailer settings of the features loco is built without, rather
+/// than ignoring them.
+fn check_mailer_features(config: &config::Mailer) -> Result<()> {
+    let settings = [
+        (
+            "http",
+            "mailer_http",
+            config.http.is_some(),
+            cfg!(feature = "mailer_http"),
+        ),
+        (
+            "inbound",
+            "mailer_inbound",
+            config.inbound.is_some(),
+            cfg!(feature = "mailer_inbound"),
+        ),
+        (
+            "tracking",
+            "with-db",
+            config.tracking.is_some(),
+            cfg!(feature = "with-db"),
+        ),
+        (
+            "dkim",
+            "mailer_dkim",
+            config.dkim.is_some(),
+            cfg!(feature = "mailer_dkim"),
+        ),
+    ];
+    for (setting, feature, configured, enabled) in settings {
+        if configured && !enabled {
+            return Err(Error::Message(format!(
+                "`mailer.{setting}` is configured, but loco-rs is built without the \
+                 `{feature}` feature"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Initializes the [`EmailSender`] of the configured transport.
+fn create_mailer_transport(config: &config::Mailer) -> Result<Option<EmailSender>> {
     if config.stub {
         return Ok(Some(EmailSender::stub()));
     }
+    if let Some(catcher) = config.catcher.as_ref() {
+        return Ok(Some(EmailSender::catcher(catcher)));
+    }
     if let Some(smtp) = config.smtp.as_ref() {
         if smtp.enable {
             return Ok(Some(EmailSender::smtp(smtp)?));
         }
     }
+    #[cfg(feature = "mailer_http")]
+    if let Some(http) = config.http.as_ref() {
+        return Ok(Some(EmailSender::http(http)?));
+    }
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "auth_jwt")]
+    #[tokio::test]
+    async fn rejects_refresh_tokens_without_cache() {
+        let mut ctx = crate::tests_cfg::app::get_app_context().await;
+        ctx.config.auth = Some(
+            serde_json::from_value(serde_json::json!({
+                "jwt": {
+                    "secret": "PqRwLF2rhHe8J22oBeHy",
+                    "expiration": 900,
+                    "refresh": { "expiration": 1_209_600 },
+                },
+            }))
+            .unwrap(),
+        );
+        ctx.config.cache = crate::config::CacheConfig::Null;
+        assert!(super::check_token_store(&ctx).is_err());
+
+        ctx.shared_store
+            .insert::<std::sync::Arc<dyn crate::auth::tokens::TokenStore>>(ctx.cache.clone());
+        assert!(super::check_token_store(&ctx).is_ok());
+    }
+}
@@ -7,10 +7,14 @@ use std::{
 };
 
 use async_trait::async_trait;
-use moka::{sync::Cache, Expiry};
+use moka::{
+    ops::compute::{CompResult, Op},
+    sync::Cache,
+    Expiry,
+};
 
 use super::CacheDriver;
-use crate::cache::CacheResult;
+use crate::cache::{CacheError, CacheResult};
 use crate::config::InMemCacheConfig;
 
 /// Creates a new instance of the in-memory cache driver, with a default Loco
@@ -106,6 +110,60 @@ impl CacheDriver for Inmem {
         Ok(())
     }
 
+    /// Replaces the value of a key, only when the current value is
+    /// `expected`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CacheError` if there is an error during the operation.
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        duration: Duration,
+    ) -> CacheResult<bool> {
+        let result = self
+            .cache
+            .entry_by_ref(key)
+            .and_compute_with(|entry| match entry {
+                Some(entry) if entry.value().1 == expected => {
+                    Op::Put((Expiration::AfterDuration(duration), value.to_string()))
+                }
+                _ => Op::Nop,
+            });
+        Ok(matches!(result, CompResult::ReplacedWith(_)))
+    }
+
+    /// Increments the counter of a key, and returns the new count.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CacheError` if the value of the key is not a counter.
+    async fn increment(&self, key: &str, duration: Duration) -> CacheResult<u64> {
+        let mut count = Ok(1);
+        self.cache
+            .entry_by_ref(key)
+            .and_compute_with(|entry| match entry {
+                Some(entry) => {
+                    let (expiration, value) = entry.into_value();
+                    match value.parse::<u64>() {
+                        Ok(value) => {
+                            let value = value.saturating_add(1);
+                            count = Ok(value);
+                            Op::Put((expiration, value.to_string()))
+                        }
+                        Err(err) => {
+                            count = Err(CacheError::Any(Box::new(err)));
+                            Op::Nop
+                        }
+                    }
+                }
+                None => Op::Put((Expiration::AfterDuration(duration), "1".to_string())),
+            });
+        count
+    }
+
     /// Removes a key-value pair from the cache.
     ///
     /// # Errors
@@ -188,6 +246,38 @@ mod tests {
         assert_eq!(mem.get::<String>("not-found").await.unwrap(), None);
     }
 
+    #[tokio::test]
+    async fn can_compare_and_swap() {
+        let config = create_test_config();
+        let mem = new(&config).driver;
+        let ttl = Duration::from_secs(60);
+        assert!(!mem.compare_and_swap("key", "", "loco", ttl).await.unwrap());
+        assert!(mem.insert("key", "loco").await.is_ok());
+
+        assert!(mem
+            .compare_and_swap("key", "loco", "rs", ttl)
+            .await
+            .unwrap());
+        assert!(!mem
+            .compare_and_swap("key", "loco", "rs", ttl)
+            .await
+            .unwrap());
+        assert_eq!(mem.get("key").await.unwrap(), Some("rs".to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_increment() {
+        let config = create_test_config();
+        let mem = new(&config);
+        let ttl = Duration::from_secs(60);
+        assert_eq!(mem.increment("key", ttl).await.unwrap(), 1);
+        assert_eq!(mem.increment("key", ttl).await.unwrap(), 2);
+        assert_eq!(mem.get::<u64>("key").await.unwrap(), Some(2));
+
+        assert!(mem.insert("other", "loco").await.is_ok());
+        assert!(mem.increment("other", ttl).await.is_err());
+    }
+
     #[tokio::test]
     async fn can_remove_key() {
         let config = create_test_config();
@@ -5,7 +5,7 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 
-use super::CacheResult;
+use super::{CacheError, CacheResult};
 
 #[cfg(feature = "cache_inmem")]
 pub mod inmem;
@@ -54,6 +54,46 @@ pub trait CacheDriver: Sync + Send {
         duration: Duration,
     ) -> CacheResult<()>;
 
+    /// Replaces the value of a key with a value that expires after the
+    /// specified duration, only when the current value is `expected`.
+    /// Returns `true` when the value was replaced.
+    ///
+    /// The comparison and the replacement are atomic, so only one of several
+    /// concurrent calls expecting the same value succeeds.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`super::CacheError`] if there is an error during the
+    /// operation, or the driver does not support it.
+    async fn compare_and_swap(
+        &self,
+        _key: &str,
+        _expected: &str,
+        _value: &str,
+        _duration: Duration,
+    ) -> CacheResult<bool> {
+        Err(CacheError::Any(
+            "compare and swap is not supported by this cache driver".into(),
+        ))
+    }
+
+    /// Increments the counter of a key, and returns the new count. A missing
+    /// counter starts at 1 and expires after the specified duration.
+    ///
+    /// The increment is atomic, so concurrent calls all get a different
+    /// count.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`super::CacheError`] if there is an error during the
+    /// operation, the driver does not support it, or the value of the key is
+    /// not a counter.
+    async fn increment(&self, _key: &str, _duration: Duration) -> CacheResult<u64> {
+        Err(CacheError::Any(
+            "increment is not supported by this cache driver".into(),
+        ))
+    }
+
     /// Removes a key-value pair from the cache.
     ///
     /// # Errors
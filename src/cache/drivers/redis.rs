@@ -15,6 +15,16 @@ use super::CacheDriver;
 use crate::cache::CacheResult;
 use crate::config::RedisCacheConfig;
 
+/// Sets `KEYS[1]` to `ARGV[2]` with an expiry of `ARGV[3]` seconds when its
+/// value is `ARGV[1]`.
+const COMPARE_AND_SWAP: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+    return 1
+end
+return 0
+";
+
 /// Creates a new instance of the Redis cache driver with a default configuration.
 ///
 /// # Returns
@@ -52,6 +62,16 @@ impl Redis {
     }
 }
 
+/// Increments `KEYS[1]`, setting an expiry of `ARGV[1]` seconds on a new
+/// counter.
+const INCREMENT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+return count
+";
+
 #[async_trait]
 impl CacheDriver for Redis {
     /// Checks if a key exists in the cache.
@@ -106,6 +126,48 @@ impl CacheDriver for Redis {
         Ok(())
     }
 
+    /// Replaces the value of a key, only when the current value is
+    /// `expected`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CacheError` if there is an error during the operation.
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        duration: Duration,
+    ) -> CacheResult<bool> {
+        let mut conn = self.pool.get().await?;
+        let swapped: i64 = cmd("EVAL")
+            .arg(COMPARE_AND_SWAP)
+            .arg(1)
+            .arg(key)
+            .arg(expected)
+            .arg(value)
+            .arg(duration.as_secs().max(1))
+            .query_async(&mut *conn)
+            .await?;
+        Ok(swapped == 1)
+    }
+
+    /// Increments the counter of a key, and returns the new count.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CacheError` if there is an error during the operation.
+    async fn increment(&self, key: &str, duration: Duration) -> CacheResult<u64> {
+        let mut conn = self.pool.get().await?;
+        Ok(cmd("EVAL")
+            .arg(INCREMENT)
+            .arg(1)
+            .arg(key)
+            .arg(duration.as_secs().max(1))
+            .query_async(&mut *conn)
+            .await?)
+    }
+
     /// Removes a key-value pair from the cache.
     ///
     /// # Errors
@@ -201,6 +263,46 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_compare_and_swap() {
+        let (redis, _container) = setup_redis_driver().await;
+        let ttl = Duration::from_secs(60);
+
+        redis
+            .insert("test_key", "test_value")
+            .await
+            .expect("Failed to insert key");
+
+        assert!(redis
+            .compare_and_swap("test_key", "test_value", "new_value", ttl)
+            .await
+            .expect("Failed to swap key"));
+        assert!(!redis
+            .compare_and_swap("test_key", "test_value", "other_value", ttl)
+            .await
+            .expect("Failed to swap key"));
+        assert_eq!(
+            redis.get("test_key").await.expect("Failed to get value"),
+            Some("new_value".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_increment() {
+        let (redis, _container) = setup_redis_driver().await;
+        let ttl = Duration::from_secs(60);
+
+        for count in 1..=3 {
+            assert_eq!(
+                redis
+                    .increment("test_key", ttl)
+                    .await
+                    .expect("Failed to increment key"),
+                count
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_remove_key() {
         let (redis, _container) = setup_redis_driver().await;
@@ -359,6 +359,31 @@ impl Cache {
         }
     }
 
+    /// Increments the counter of a key, and returns the new count. A missing
+    /// counter starts at 1 and expires after the given duration. Concurrent
+    /// calls all get a different count.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use loco_rs::cache::{self, CacheResult};
+    /// use loco_rs::config::InMemCacheConfig;
+    ///
+    /// pub async fn count_attempts() -> CacheResult<u64> {
+    ///     let config = InMemCacheConfig { max_capacity: 100 };
+    ///     let cache = cache::Cache::new(cache::drivers::inmem::new(&config).driver);
+    ///     cache.increment("attempts", Duration::from_secs(300)).await
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// A [`CacheResult`] with the new count, or an error when the driver does
+    /// not support counters or the value of the key is not a counter.
+    pub async fn increment(&self, key: &str, duration: Duration) -> CacheResult<u64> {
+        self.driver.increment(key, duration).await
+    }
+
     /// Removes a key-value pair from the cache.
     ///
     /// # Example
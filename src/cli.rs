@@ -42,7 +42,9 @@ use crate::{
     },
     config::Config,
     environment::{resolve_from_env, Environment, DEFAULT_ENVIRONMENT},
-    logger, task, Error,
+    logger,
+    mailer::preview::MailerPreviews,
+    storage, task, Error,
 };
 
 #[derive(Parser)]
@@ -97,6 +99,12 @@ enum Commands {
         #[command(subcommand)]
         command: DbCommands,
     },
+    #[cfg(feature = "with-db")]
+    /// Manage API keys
+    ApiKey {
+        #[command(subcommand)]
+        command: ApiKeyCommands,
+    },
     /// Describe all application endpoints
     Routes {},
     /// Describe all application middlewares
@@ -120,6 +128,16 @@ enum Commands {
         #[command(subcommand)]
         command: JobsCommands,
     },
+    /// Storage maintenance.
+    Storage {
+        #[command(subcommand)]
+        command: StorageCommands,
+    },
+    /// Mailer tools.
+    Mailer {
+        #[command(subcommand)]
+        command: MailerCommands,
+    },
     /// Run the scheduler
     Scheduler {
         /// Run a specific job by its name.
@@ -333,6 +351,20 @@ After running the migration, follow these steps to complete the process:
         #[clap(value_enum)]
         kind: DeploymentKind,
     },
+    /// Generate sign in with an OAuth2 / OpenID Connect provider
+    #[cfg(feature = "with-db")]
+    #[clap(name = "oauth2")]
+    OAuth2 {
+        /// Name of the provider, eg. google, github or any OpenID Connect
+        /// provider
+        provider: String,
+    },
+    /// Generate an authorization policy for a resource
+    #[cfg(feature = "with-db")]
+    Policy {
+        /// Name of the resource guarded by the policy
+        name: String,
+    },
 
     /// Override templates and allows you to take control of them. You can
     /// always go back when deleting the local template.
@@ -428,6 +460,10 @@ impl ComponentArg {
             Self::Mailer { name } => Ok(loco_gen::Component::Mailer { name }),
             Self::Data { name } => Ok(loco_gen::Component::Data { name }),
             Self::Deployment { kind } => Ok(kind.to_generator_component(config)),
+            #[cfg(feature = "with-db")]
+            Self::OAuth2 { provider } => Ok(loco_gen::Component::OAuth2 { provider }),
+            #[cfg(feature = "with-db")]
+            Self::Policy { name } => Ok(loco_gen::Component::Policy { name }),
             Self::Override {
                 template_path: _,
                 info: _,
@@ -557,6 +593,70 @@ impl DeploymentKind {
     }
 }
 
+#[derive(Subcommand)]
+enum StorageCommands {
+    /// Reconciles the secondary stores of the mirror and backup strategies.
+    /// By default, replays the failed operations recorded in the strategy
+    /// journal.
+    Sync {
+        /// Report the paths that are missing from, or extra in, the
+        /// secondaries instead of replaying the journal.
+        #[arg(long, action)]
+        diff: bool,
+        /// Repair the paths reported by `--diff`.
+        #[arg(long, action, requires = "diff")]
+        repair: bool,
+        /// Only compare paths under this prefix.
+        #[arg(long, default_value = "")]
+        prefix: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MailerCommands {
+    /// Renders a mailer preview without sending anything. Lists the
+    /// registered previews when no name is given.
+    Preview {
+        /// The preview name, as `<mailer>/<preview>`
+        name: Option<String>,
+        /// Only print the HTML, for example to save it to a file.
+        #[arg(long, action)]
+        html: bool,
+    },
+}
+
+#[cfg(feature = "with-db")]
+#[derive(Subcommand)]
+enum ApiKeyCommands {
+    /// Creates an API key and prints it. The key cannot be shown again.
+    Create {
+        /// The key owner, the value `Authenticable::find_by_claims_key` looks
+        /// up (the user pid by default).
+        #[arg(long)]
+        owner: String,
+        /// A name to tell the key apart from the other keys of the owner.
+        #[arg(long)]
+        name: String,
+        /// The scopes the key grants, such as `notes:read,notes:write`.
+        #[arg(long, use_value_delimiter = true)]
+        scopes: Vec<String>,
+        /// Expire the key after this many days.
+        #[arg(long)]
+        expires_in: Option<u32>,
+    },
+    /// Lists API keys, without their secrets.
+    List {
+        /// Only list the keys of this owner.
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// Revokes an API key.
+    Revoke {
+        /// The key prefix, as printed by `list`.
+        prefix: String,
+    },
+}
+
 #[cfg(any(feature = "bg_redis", feature = "bg_pg", feature = "bg_sqlt"))]
 #[derive(Subcommand)]
 enum JobsCommands {
@@ -722,6 +822,10 @@ pub async fn main<H: Hooks, M: MigratorTrait>() -> crate::Result<()> {
                 run_db::<H, M>(&app_context, command.into()).await?;
             }
         }
+        Commands::ApiKey { command } => {
+            let app_context = create_context::<H>(&environment, app_context.config).await?;
+            handle_api_key_command(command, &app_context).await?;
+        }
         #[cfg(any(feature = "bg_redis", feature = "bg_pg", feature = "bg_sqlt"))]
         Commands::Jobs { command } => {
             handle_job_command::<H>(command, &environment, app_context.config).await?;
@@ -730,6 +834,13 @@ pub async fn main<H: Hooks, M: MigratorTrait>() -> crate::Result<()> {
             let app_context = create_context::<H>(&environment, app_context.config).await?;
             show_list_endpoints::<H>(&app_context);
         }
+        Commands::Storage { command } => {
+            let app_context = create_context::<H>(&environment, app_context.config).await?;
+            handle_storage_command(command, &app_context).await?;
+        }
+        Commands::Mailer { command } => {
+            handle_mailer_command::<H>(command, &app_context)?;
+        }
         Commands::Middleware { show_config } => {
             let app_context = create_context::<H>(&environment, app_context.config).await?;
             let middlewares = list_middlewares::<H>(&app_context);
@@ -892,6 +1003,12 @@ pub async fn main<H: Hooks>() -> crate::Result<()> {
         Commands::Jobs { command } => {
             handle_job_command::<H>(command, &environment, config).await?
         }
+        Commands::Storage { command } => {
+            handle_storage_command(command, &app_context).await?;
+        }
+        Commands::Mailer { command } => {
+            handle_mailer_command::<H>(command, &app_context)?;
+        }
         Commands::Scheduler {
             name,
             config_path,
@@ -1217,6 +1334,147 @@ async fn handle_job_command<H: Hooks>(
     }
 }
 
+#[cfg(feature = "with-db")]
+async fn handle_api_key_command(
+    command: ApiKeyCommands,
+    app_context: &AppContext,
+) -> crate::Result<()> {
+    let keys = crate::auth::api_keys::ApiKeys::from_context(app_context);
+    match command {
+        ApiKeyCommands::Create {
+            owner,
+            name,
+            scopes,
+            expires_in,
+        } => {
+            let expires_at =
+                expires_in.map(|days| chrono::Utc::now() + chrono::Duration::days(i64::from(days)));
+            let new = keys.create(&owner, &name, &scopes, expires_at).await?;
+            println!("{}", new.token.bold());
+            println!("store this key now, it cannot be shown again");
+        }
+        ApiKeyCommands::List { owner } => {
+            let format = |at: Option<chrono::DateTime<chrono::Utc>>| {
+                at.map_or_else(
+                    || "-".to_string(),
+                    |at| at.format("%Y-%m-%d %H:%M").to_string(),
+                )
+            };
+            for key in keys.list(owner.as_deref()).await? {
+                println!(
+                    "{} {:<16} {:<24} {:<10} expires: {} last used: {} scopes: {}",
+                    key.prefix.bold(),
+                    key.owner,
+                    key.name,
+                    if key.is_active() {
+                        "active".green()
+                    } else {
+                        "inactive".dimmed()
+                    },
+                    format(key.expires_at),
+                    format(key.last_used_at),
+                    key.scopes.join(" ")
+                );
+            }
+        }
+        ApiKeyCommands::Revoke { prefix } => {
+            if !keys.revoke(&prefix).await? {
+                return Err(Error::string(&format!("no active api key `{prefix}`")));
+            }
+            println!("revoked api key `{prefix}`");
+        }
+    }
+    Ok(())
+}
+
+async fn handle_storage_command(
+    command: StorageCommands,
+    app_context: &AppContext,
+) -> crate::Result<()> {
+    match command {
+        StorageCommands::Sync {
+            diff: false,
+            prefix: _,
+            repair: _,
+        } => {
+            let report = storage::sync::replay(&app_context.storage).await?;
+            for (entry, err) in &report.failed {
+                println!(
+                    "{} {} {:?}: {err}",
+                    "failed".red(),
+                    entry.store,
+                    entry.operation
+                );
+            }
+            println!(
+                "replayed {} journal entries, {} failed",
+                report.replayed.len(),
+                report.failed.len()
+            );
+        }
+        StorageCommands::Sync {
+            diff: true,
+            repair,
+            prefix,
+        } => {
+            let diffs = storage::sync::diff(&app_context.storage, &prefix).await?;
+            for diff in &diffs {
+                println!("{}", diff.store.bold());
+                for path in &diff.missing {
+                    println!("  {} {}", "missing".yellow(), path.display());
+                }
+                for path in &diff.extra {
+                    println!("  {} {}", "extra".yellow(), path.display());
+                }
+            }
+
+            if repair {
+                let errors = storage::sync::repair(&app_context.storage, &diffs).await?;
+                for (store, paths) in &errors {
+                    for (path, err) in paths {
+                        println!("{} {store} {}: {err}", "failed".red(), path.display());
+                    }
+                }
+                if !errors.is_empty() {
+                    return Err(Error::string("storage repair failed"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_mailer_command<H: Hooks>(
+    command: MailerCommands,
+    app_context: &AppContext,
+) -> crate::Result<()> {
+    match command {
+        MailerCommands::Preview { name: None, .. } => {
+            for name in MailerPreviews::from_hooks::<H>(app_context).names() {
+                println!("{name}");
+            }
+        }
+        MailerCommands::Preview {
+            name: Some(name),
+            html,
+        } => {
+            let previews = MailerPreviews::from_hooks::<H>(app_context);
+            let content = previews
+                .get(&name)
+                .ok_or_else(|| Error::Message(format!("mailer preview `{name}` not found")))?
+                .render()?;
+            if html {
+                println!("{}", content.html);
+            } else {
+                println!("{} {}", "subject:".bold(), content.subject.trim());
+                println!("\n{}\n{}", "text:".bold(), content.text);
+                println!("\n{}\n{}", "html:".bold(), content.html);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(debug_assertions)]
 fn handle_generate_command<H: Hooks>(
     component: ComponentArg,
@@ -28,6 +28,7 @@ use std::{
     sync::OnceLock,
 };
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tracing::info;
@@ -51,6 +52,7 @@ pub struct Config {
     pub database: Database,
     #[serde(default)]
     pub cache: CacheConfig,
+    pub storage: Option<StorageConfig>,
     pub queue: Option<QueueConfig>,
     pub auth: Option<Auth>,
     #[serde(default)]
@@ -273,6 +275,117 @@ pub struct RedisCacheConfig {
     pub max_size: u32,
 }
 
+/// Storage configuration
+///
+/// Declares the named stores and the strategy used by `AppContext.storage`.
+/// When the section is missing, storage is left to the `after_context` hook.
+///
+/// Example (development):
+/// ```yaml
+/// # config/development.yaml
+/// storage:
+///   stores:
+///     local:
+///       kind: Local
+///       prefix: storage
+///     backup:
+///       kind: S3
+///       bucket: my-app-backup
+///       region: us-east-1
+///       key_id: {{ get_env(name="AWS_ACCESS_KEY_ID") }}
+///       secret_key: {{ get_env(name="AWS_SECRET_ACCESS_KEY") }}
+///   strategy:
+///     kind: Backup
+///     primary: local
+///     secondaries:
+///       - backup
+///     failure_mode: AllowBackupFailure
+///     journal: tmp/storage-journal
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StorageConfig {
+    /// Stores by name.
+    pub stores: BTreeMap<String, StoreConfig>,
+    /// The strategy applied on the stores. When not set, the single strategy
+    /// is used on the only store.
+    pub strategy: Option<StorageStrategyConfig>,
+}
+
+/// A named store
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoreConfig {
+    #[serde(flatten)]
+    pub driver: StoreDriverConfig,
+    /// Deduplicate the store content by its hash.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Encrypt the store content with the given keyring.
+    #[cfg(feature = "storage_encryption")]
+    pub encryption: Option<crate::storage::drivers::encrypted::KeyringConfig>,
+}
+
+/// Store driver configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum StoreDriverConfig {
+    /// Local filesystem, paths are relative to `prefix` when given.
+    Local { prefix: Option<PathBuf> },
+    /// In-memory store, useful for testing.
+    Mem,
+    /// Null store, every operation fails.
+    Null,
+    /// AWS S3, credentials are loaded from the environment when not given.
+    #[cfg(feature = "storage_aws_s3")]
+    S3 {
+        bucket: String,
+        region: String,
+        key_id: Option<String>,
+        secret_key: Option<String>,
+        token: Option<String>,
+    },
+    /// Google Cloud Storage
+    #[cfg(feature = "storage_gcp")]
+    Gcs {
+        bucket: String,
+        credential_path: String,
+    },
+    /// Azure Blob Storage
+    #[cfg(feature = "storage_azure")]
+    Azure {
+        container: String,
+        account_name: String,
+        access_key: String,
+        endpoint: String,
+    },
+}
+
+/// Storage strategy configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum StorageStrategyConfig {
+    /// All operations are applied on a single store.
+    Single { store: String },
+    /// Operations are mirrored to the secondaries, downloads fall back to the
+    /// secondaries.
+    Mirror {
+        primary: String,
+        #[serde(default)]
+        secondaries: Vec<String>,
+        failure_mode: crate::storage::strategies::mirror::FailureMode,
+        /// Folder of the journal recording failed secondary operations.
+        journal: Option<PathBuf>,
+    },
+    /// Operations are backed up to the secondaries.
+    Backup {
+        primary: String,
+        #[serde(default)]
+        secondaries: Vec<String>,
+        failure_mode: crate::storage::strategies::backup::FailureMode,
+        /// Folder of the journal recording failed secondary operations.
+        journal: Option<PathBuf>,
+    },
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "kind")]
 pub enum QueueConfig {
@@ -398,6 +511,136 @@ fn num_workers() -> u32 {
 pub struct Auth {
     /// JWT authentication config
     pub jwt: Option<JWT>,
+    /// Password hashing algorithm and parameters, Argon2id with its default
+    /// parameters when not set
+    pub password: Option<crate::hash::PasswordHashing>,
+    /// External OIDC provider config. When set, the JWT extractors accept the
+    /// tokens issued by the provider instead of the tokens issued by the app.
+    #[cfg(feature = "auth_oidc")]
+    pub oidc: Option<OIDC>,
+    /// OAuth2 / OpenID Connect sign in providers
+    #[cfg(feature = "auth_oauth2")]
+    pub oauth2: Option<OAuth2>,
+}
+
+/// OAuth2 / OpenID Connect sign in configuration.
+///
+/// Example:
+/// ```yaml
+/// auth:
+///   oauth2:
+///     providers:
+///       google:
+///         kind: Google
+///         client_id: {{ get_env(name="GOOGLE_CLIENT_ID") }}
+///         client_secret: {{ get_env(name="GOOGLE_CLIENT_SECRET") }}
+///         redirect_url: http://localhost:5150/api/auth/oauth2/google/callback
+///       sso:
+///         kind: Oidc
+///         issuer: https://sso.example.com
+///         client_id: my-app
+///         client_secret: {{ get_env(name="SSO_CLIENT_SECRET") }}
+///         redirect_url: http://localhost:5150/api/auth/oauth2/sso/callback
+/// ```
+#[cfg(feature = "auth_oauth2")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuth2 {
+    /// The sign in providers, by name
+    pub providers: BTreeMap<String, OAuth2Provider>,
+}
+
+/// An OAuth2 / OpenID Connect provider.
+#[cfg(feature = "auth_oauth2")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuth2Provider {
+    #[serde(flatten)]
+    pub kind: OAuth2ProviderKind,
+    /// The client id registered with the provider
+    pub client_id: String,
+    /// The client secret registered with the provider
+    pub client_secret: String,
+    /// The callback URL registered with the provider
+    pub redirect_url: String,
+    /// The requested scopes. Defaults to the scopes needed to read the user
+    /// email and name.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// The kind of an OAuth2 provider.
+#[cfg(feature = "auth_oauth2")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind")]
+pub enum OAuth2ProviderKind {
+    /// Sign in with Google
+    Google,
+    /// Sign in with GitHub
+    GitHub,
+    /// Any OpenID Connect provider, configured by discovery
+    Oidc {
+        /// The provider issuer URL, serving
+        /// `/.well-known/openid-configuration`
+        issuer: String,
+    },
+}
+
+/// External OIDC provider configuration.
+///
+/// Example:
+/// ```yaml
+/// auth:
+///   oidc:
+///     issuer: https://sso.example.com/
+///     jwks_url: https://sso.example.com/.well-known/jwks.json
+///     audience:
+///       - my-api
+///     pid_claim: sub
+/// ```
+#[cfg(feature = "auth_oidc")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OIDC {
+    /// The location where tokens are expected to be found during
+    /// authentication.
+    pub location: Option<JWTLocation>,
+    /// The expected `iss` claim
+    pub issuer: String,
+    /// The URL of the provider JSON Web Key Set
+    pub jwks_url: String,
+    /// The accepted `aud` claims. The audience is not checked when empty.
+    #[serde(default)]
+    pub audience: Vec<String>,
+    /// The claim mapped to the `pid` of the user claims
+    #[serde(default = "oidc_pid_claim")]
+    pub pid_claim: String,
+    /// The accepted signing algorithms
+    #[serde(default = "oidc_algorithms")]
+    pub algorithms: Vec<String>,
+    /// How long the fetched keys are cached, in seconds
+    #[serde(default = "oidc_jwks_cache_ttl")]
+    pub jwks_cache_ttl: u64,
+    /// The accepted clock skew when checking `exp` and `nbf`, in seconds
+    #[serde(default = "oidc_leeway")]
+    pub leeway: u64,
+}
+
+#[cfg(feature = "auth_oidc")]
+fn oidc_pid_claim() -> String {
+    "sub".to_string()
+}
+
+#[cfg(feature = "auth_oidc")]
+fn oidc_algorithms() -> Vec<String> {
+    vec!["RS256".to_string()]
+}
+
+#[cfg(feature = "auth_oidc")]
+fn oidc_jwks_cache_ttl() -> u64 {
+    3600
+}
+
+#[cfg(feature = "auth_oidc")]
+fn oidc_leeway() -> u64 {
+    60
 }
 
 /// JWT configuration structure.
@@ -406,10 +649,81 @@ pub struct JWT {
     /// The location where JWT tokens are expected to be found during
     /// authentication.
     pub location: Option<JWTLocation>,
-    /// The secret key For JWT token
+    /// The secret key For JWT token. Not required when `keys` are set.
+    #[serde(default)]
     pub secret: String,
     /// The expiration time for authentication tokens
     pub expiration: u64,
+    /// Asymmetric keys used to sign and verify tokens. Tokens are signed with
+    /// the most recently activated key, and verified with the key matching
+    /// their `kid` header.
+    ///
+    /// Example:
+    /// ```yaml
+    /// auth:
+    ///   jwt:
+    ///     expiration: 900
+    ///     keys:
+    ///       - kid: "2024-01"
+    ///         algorithm: RS256
+    ///         private_key: config/keys/2024-01.pem
+    ///         public_key: config/keys/2024-01.pub.pem
+    ///         expires_at: 2024-07-01T00:00:00Z
+    ///       - kid: "2024-06"
+    ///         algorithm: ES256
+    ///         private_key: {{ get_env(name="JWT_PRIVATE_KEY") }}
+    ///         public_key: config/keys/2024-06.pub.pem
+    ///         active_from: 2024-06-01T00:00:00Z
+    /// ```
+    #[serde(default)]
+    pub keys: Vec<JWTKey>,
+    /// Refresh tokens configuration. When set, refresh tokens are issued
+    /// alongside access tokens and rotated on each use.
+    ///
+    /// Example:
+    /// ```yaml
+    /// auth:
+    ///   jwt:
+    ///     secret: PqRwLF2rhHe8J22oBeHy
+    ///     expiration: 900 # 15 minutes
+    ///     refresh:
+    ///       expiration: 1209600 # 14 days
+    ///     revocation: true
+    /// ```
+    pub refresh: Option<JWTRefresh>,
+    /// Check every access token against the revocation list, so tokens can be
+    /// revoked before they expire. Requires a cache (or a custom
+    /// `auth::tokens::TokenStore`).
+    #[serde(default)]
+    pub revocation: bool,
+}
+
+/// An asymmetric JWT key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JWTKey {
+    /// The key id, set as the `kid` header of the signed tokens
+    pub kid: String,
+    /// The signing algorithm: `RS256`, `RS384`, `RS512`, `PS256`, `PS384`,
+    /// `PS512`, `ES256`, `ES384` or `EdDSA`
+    pub algorithm: String,
+    /// The PEM encoded private key, or a path to a PEM file. Keys without a
+    /// private key only verify tokens.
+    pub private_key: Option<String>,
+    /// The PEM encoded public key, or a path to a PEM file
+    pub public_key: String,
+    /// The time from which the key signs new tokens. Publish a key before it
+    /// becomes active so other services can fetch it ahead of time.
+    pub active_from: Option<DateTime<Utc>>,
+    /// The time after which the key no longer verifies tokens
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Refresh tokens configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JWTRefresh {
+    /// The expiration time in seconds of a refresh token. Each rotation issues
+    /// a new refresh token with a renewed expiration.
+    pub expiration: u64,
 }
 
 /// Defines the authentication mechanism for middleware.
@@ -519,10 +833,32 @@ pub enum WorkerMode {
 ///     port: 1025
 ///     secure: false
 /// ```
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Mailer {
     pub smtp: Option<SmtpMailer>,
 
+    /// Send emails through an HTTP API instead of SMTP, requires the
+    /// `mailer_http` feature
+    pub http: Option<HttpMailer>,
+
+    /// Keep the emails on disk instead of sending them, to browse them in
+    /// development
+    pub catcher: Option<CatcherMailer>,
+
+    /// Receive emails into the application, requires the `mailer_inbound`
+    /// feature
+    pub inbound: Option<InboundMailer>,
+
+    /// Record deliveries, and stop sending to bounced addresses, requires the
+    /// `with-db` feature
+    pub tracking: Option<MailTracking>,
+
+    /// Sign the outgoing emails with DKIM, requires the `mailer_dkim` feature
+    pub dkim: Option<DkimMailer>,
+
+    /// Chunking and rate limiting of the bulk emails
+    pub bulk: Option<BulkMailer>,
+
     #[serde(default)]
     pub stub: bool,
 }
@@ -557,6 +893,253 @@ pub struct SmtpMailer {
     pub hello_name: Option<String>,
 }
 
+/// HTTP API mailer configuration. `endpoint` overrides the provider API base
+/// URL, for example to use the EU region of a provider.
+///
+/// Example:
+/// ```yaml
+/// mailer:
+///   http:
+///     provider: postmark
+///     server_token: {{ get_env(name="POSTMARK_TOKEN") }}
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum HttpMailer {
+    /// [SendGrid](https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send)
+    Sendgrid {
+        api_key: String,
+        endpoint: Option<String>,
+    },
+    /// [Postmark](https://postmarkapp.com/developer/api/email-api)
+    Postmark {
+        server_token: String,
+        /// Defaults to the `outbound` stream
+        message_stream: Option<String>,
+        endpoint: Option<String>,
+    },
+    /// [Mailgun](https://documentation.mailgun.com/docs/mailgun/api-reference/send/mailgun/messages)
+    Mailgun {
+        api_key: String,
+        /// Sending domain, such as `mg.example.com`
+        domain: String,
+        endpoint: Option<String>,
+    },
+    /// [Amazon SES](https://docs.aws.amazon.com/ses/latest/APIReference-V2/API_SendEmail.html)
+    Ses {
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+        /// For temporary credentials
+        session_token: Option<String>,
+        endpoint: Option<String>,
+    },
+    /// POSTs the email as JSON to the given URL, for example to a relay
+    /// service of your own
+    Webhook {
+        url: String,
+        /// Headers sent with each request, such as `Authorization`
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+/// Mail catcher configuration. See [`crate::mailer::catcher`].
+///
+/// Example (development):
+/// ```yaml
+/// mailer:
+///   catcher:
+///     path: tmp/mails
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CatcherMailer {
+    /// Directory of the caught emails
+    #[serde(default = "default_catcher_path")]
+    pub path: PathBuf,
+}
+
+fn default_catcher_path() -> PathBuf {
+    PathBuf::from("tmp/mails")
+}
+
+/// Inbound email configuration. See [`crate::mailer::inbound`].
+///
+/// Example:
+/// ```yaml
+/// mailer:
+///   inbound:
+///     webhook:
+///       token: {{ get_env(name="INBOUND_MAIL_TOKEN") }}
+///     smtp:
+///       port: 2525
+///       domains:
+///         - reply.example.com
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InboundMailer {
+    /// Accept raw MIME messages posted to the inbound route
+    pub webhook: Option<InboundWebhook>,
+    /// Listen for emails over SMTP along with the server
+    pub smtp: Option<InboundSmtp>,
+}
+
+/// Inbound email webhook configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InboundWebhook {
+    /// Token the provider sends as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+/// Inbound SMTP listener configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InboundSmtp {
+    /// Interface to listen on
+    #[serde(default = "default_inbound_smtp_binding")]
+    pub binding: String,
+    /// Port to listen on
+    #[serde(default = "default_inbound_smtp_port")]
+    pub port: u16,
+    /// Name sent in the SMTP greeting, the binding by default
+    pub hostname: Option<String>,
+    /// Largest accepted message, in bytes
+    #[serde(default = "default_inbound_smtp_max_size")]
+    pub max_size: usize,
+    /// Accepted recipient domains, any domain when empty
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Milliseconds a client may stay idle between commands before it is
+    /// disconnected
+    #[serde(default = "default_inbound_smtp_idle_timeout")]
+    pub idle_timeout: u64,
+    /// Milliseconds a client may take to send each line of a message
+    #[serde(default = "default_inbound_smtp_read_timeout")]
+    pub read_timeout: u64,
+    /// Most clients connected at once, the others are turned away with a
+    /// `421` reply
+    #[serde(default = "default_inbound_smtp_max_sessions")]
+    pub max_sessions: usize,
+}
+
+fn default_inbound_smtp_binding() -> String {
+    "0.0.0.0".to_string()
+}
+
+const fn default_inbound_smtp_port() -> u16 {
+    2525
+}
+
+const fn default_inbound_smtp_max_size() -> usize {
+    25 * 1024 * 1024
+}
+
+const fn default_inbound_smtp_idle_timeout() -> u64 {
+    300_000
+}
+
+const fn default_inbound_smtp_read_timeout() -> u64 {
+    60_000
+}
+
+const fn default_inbound_smtp_max_sessions() -> usize {
+    100
+}
+
+/// Email delivery tracking configuration. See
+/// [`crate::mailer::tracking`].
+///
+/// Example:
+/// ```yaml
+/// mailer:
+///   tracking:
+///     retries: 3
+///     webhook_token: {{ get_env(name="BOUNCES_TOKEN") }}
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MailTracking {
+    /// How many times transient failures are retried
+    #[serde(default = "default_tracking_retries")]
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds, doubled on each retry
+    #[serde(default = "default_tracking_backoff")]
+    pub backoff: u64,
+    /// Token the bounce notifications are sent with, as
+    /// `Authorization: Bearer <token>`
+    pub webhook_token: Option<String>,
+}
+
+const fn default_tracking_retries() -> u32 {
+    3
+}
+
+const fn default_tracking_backoff() -> u64 {
+    1000
+}
+
+/// Bulk email configuration. See [`crate::mailer::bulk`].
+///
+/// Example:
+/// ```yaml
+/// mailer:
+///   bulk:
+///     chunk_size: 100
+///     rate_limit: 14
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BulkMailer {
+    /// How many emails a job sends
+    #[serde(default = "default_bulk_chunk_size")]
+    pub chunk_size: usize,
+    /// Maximum emails sent per second by each process, unlimited when unset
+    pub rate_limit: Option<u32>,
+}
+
+impl Default for BulkMailer {
+    fn default() -> Self {
+        Self {
+            chunk_size: default_bulk_chunk_size(),
+            rate_limit: None,
+        }
+    }
+}
+
+const fn default_bulk_chunk_size() -> usize {
+    100
+}
+
+/// DKIM signing configuration. The public key is published in DNS as a
+/// `TXT` record of `<selector>._domainkey.<domain>`.
+///
+/// Example:
+/// ```yaml
+/// mailer:
+///   dkim:
+///     selector: loco
+///     domain: example.com
+///     private_key_path: config/dkim.pem
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DkimMailer {
+    /// Name of the key in DNS
+    pub selector: String,
+    /// Domain the emails are signed for, usually the domain of `From`
+    pub domain: String,
+    /// Path to the private key: a PKCS#1 PEM file for `rsa`, or the base64
+    /// encoded 32 bytes key for `ed25519`
+    pub private_key_path: PathBuf,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+}
+
+/// DKIM signing algorithm
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
 /// Authentication details for the mailer
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MailerAuth {
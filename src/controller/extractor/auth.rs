@@ -19,11 +19,11 @@
 //!     format::json(TestResponse{ pid: auth.claims.pid})
 //! }
 //! ```
-use std::collections::HashMap;
+use std::{collections::HashMap, marker::PhantomData};
 
 use axum::{
     extract::{FromRef, FromRequestParts, Query},
-    http::{request::Parts, HeaderMap},
+    http::{request::Parts, HeaderMap, StatusCode},
 };
 use axum_extra::extract::cookie;
 use serde::{Deserialize, Serialize};
@@ -32,7 +32,8 @@ use tracing;
 use crate::{
     app::AppContext,
     auth,
-    config::JWT as JWTConfig,
+    config::{JWTLocation, JWT as JWTConfig},
+    controller::ErrorDetail,
     errors::Error,
     model::{Authenticable, ModelError},
     Result as LocoResult,
@@ -68,35 +69,21 @@ where
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Error> {
         let ctx: AppContext = AppContext::from_ref(state);
 
-        let token = extract_token(get_jwt_from_config(&ctx)?, parts)?;
-
-        let jwt_secret = ctx.config.get_jwt_config()?;
-
-        match auth::jwt::JWT::new(&jwt_secret.secret).validate(&token) {
-            Ok(claims) => {
-                let user = T::find_by_claims_key(&ctx.db, &claims.claims.pid)
-                    .await
-                    .map_err(|e| match e {
-                        ModelError::EntityNotFound => Error::Unauthorized("not found".to_string()),
-                        ModelError::DbErr(db_err) => {
-                            tracing::error!("Database error during authentication: {}", db_err);
-                            Error::InternalServerError
-                        }
-                        _ => {
-                            tracing::error!("Authentication error: {}", e);
-                            Error::Unauthorized("could not authorize".to_string())
-                        }
-                    })?;
-                Ok(Self {
-                    claims: claims.claims,
-                    user,
-                })
-            }
-            Err(err) => {
-                tracing::error!("JWT validation error: {}", err);
-                Err(Error::Unauthorized("token is not valid".to_string()))
-            }
-        }
+        let claims = authenticate(&ctx, parts).await?;
+        let user = T::find_by_claims_key(&ctx.db, &claims.pid)
+            .await
+            .map_err(|e| match e {
+                ModelError::EntityNotFound => Error::Unauthorized("not found".to_string()),
+                ModelError::DbErr(db_err) => {
+                    tracing::error!("Database error during authentication: {}", db_err);
+                    Error::InternalServerError
+                }
+                _ => {
+                    tracing::error!("Authentication error: {}", e);
+                    Error::Unauthorized("could not authorize".to_string())
+                }
+            })?;
+        Ok(Self { claims, user })
     }
 }
 
@@ -116,12 +103,16 @@ where
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Error> {
-        extract_jwt_from_request_parts(parts, state)
+        authenticate_request_parts(parts, state).await
     }
 }
 
 /// extract a [JWT] token from request parts, using a non-mutable reference to the [Parts]
 ///
+/// Only the signature and the expiration of the token are checked: revoked
+/// tokens and OIDC tokens are not. Use [`authenticate_request_parts`] to
+/// check them too.
+///
 /// # Errors
 /// Return an error when JWT token not configured or when the token is not valid
 pub fn extract_jwt_from_request_parts<S>(parts: &Parts, state: &S) -> Result<JWT, Error>
@@ -131,14 +122,87 @@ where
 {
     let ctx: AppContext = AppContext::from_ref(state); // change to ctx
 
-    let token = extract_token(get_jwt_from_config(&ctx)?, parts)?;
+    let jwt_config = get_jwt_from_config(&ctx)?;
+    let token = extract_token(jwt_config.location.as_ref(), parts)?;
+    Ok(JWT {
+        claims: decode_token(&ctx, &token)?,
+    })
+}
+
+/// authenticate the request parts as the [JWT] extractor does: with the
+/// external OIDC provider when configured, otherwise with the JWT
+/// configuration, checking the revocation list when revocation is enabled
+///
+/// # Errors
+/// Return an error when auth is not configured or when the token is not
+/// valid or revoked
+pub async fn authenticate_request_parts<S>(parts: &Parts, state: &S) -> Result<JWT, Error>
+where
+    AppContext: FromRef<S>,
+    S: Send + Sync,
+{
+    let ctx: AppContext = AppContext::from_ref(state);
+
+    Ok(JWT {
+        claims: authenticate(&ctx, parts).await?,
+    })
+}
+
+/// extract the token from the request and validate it, with the external OIDC
+/// provider when configured, otherwise with the JWT configuration
+///
+/// # Errors
+/// Return an error when auth is not configured or when the token is not valid
+async fn authenticate(ctx: &AppContext, parts: &Parts) -> LocoResult<auth::jwt::UserClaims> {
+    #[cfg(feature = "auth_oidc")]
+    if let Some(oidc_config) = ctx.config.auth.as_ref().and_then(|auth| auth.oidc.as_ref()) {
+        let token = extract_token(oidc_config.location.as_ref(), parts)?;
+        return match auth::oidc::Validator::from_context(ctx)?
+            .validate(&token)
+            .await
+        {
+            Ok(claims) => Ok(claims),
+            Err(Error::Unauthorized(err)) => {
+                tracing::error!("OIDC token validation error: {}", err);
+                Err(Error::Unauthorized("token is not valid".to_string()))
+            }
+            Err(err) => Err(err),
+        };
+    }
+
+    let jwt_config = get_jwt_from_config(ctx)?;
+    let token = extract_token(jwt_config.location.as_ref(), parts)?;
+    validate_token(ctx, jwt_config, &token).await
+}
 
-    let jwt_secret = ctx.config.get_jwt_config()?;
+/// validate the given token, and check it against the revocation list when
+/// revocation is enabled
+///
+/// # Errors
+/// Return an error when the token is not valid or revoked
+async fn validate_token(
+    ctx: &AppContext,
+    jwt_config: &JWTConfig,
+    token: &str,
+) -> LocoResult<auth::jwt::UserClaims> {
+    let claims = decode_token(ctx, token)?;
+    if jwt_config.revocation
+        && auth::tokens::Tokens::from_context(ctx)?
+            .is_revoked(&claims)
+            .await?
+    {
+        return Err(Error::Unauthorized("token is revoked".to_string()));
+    }
+    Ok(claims)
+}
 
-    match auth::jwt::JWT::new(&jwt_secret.secret).validate(&token) {
-        Ok(claims) => Ok(JWT {
-            claims: claims.claims,
-        }),
+/// validate the signature and the expiration of the given token
+///
+/// # Errors
+/// Return an error when the token is not valid
+fn decode_token(ctx: &AppContext, token: &str) -> LocoResult<auth::jwt::UserClaims> {
+    match auth::jwt::JWT::from_context(ctx)?.validate(token) {
+        Ok(token) => Ok(token.claims),
         Err(err) => {
             tracing::error!("JWT validation error: {}", err);
             Err(Error::Unauthorized("token is not valid".to_string()))
@@ -160,13 +224,9 @@ fn get_jwt_from_config(ctx: &AppContext) -> LocoResult<&JWTConfig> {
         .ok_or_else(|| Error::string("JWT token not configured"))
 }
 /// extract token from the configured jwt location settings
-fn extract_token(jwt_config: &JWTConfig, parts: &Parts) -> LocoResult<String> {
+fn extract_token(location: Option<&JWTLocation>, parts: &Parts) -> LocoResult<String> {
     #[allow(clippy::match_wildcard_for_single_variants)]
-    match jwt_config
-        .location
-        .as_ref()
-        .unwrap_or(&crate::config::JWTLocation::Bearer)
-    {
+    match location.unwrap_or(&JWTLocation::Bearer) {
         crate::config::JWTLocation::Query { name } => extract_token_from_query(name, parts),
         crate::config::JWTLocation::Cookie { name } => extract_token_from_cookie(name, parts),
         crate::config::JWTLocation::Bearer => extract_token_from_header(&parts.headers)
@@ -223,19 +283,61 @@ pub fn extract_token_from_query(name: &str, parts: &Parts) -> LocoResult<String>
 // API Token Auth / Extractor
 //
 // ---------------------------------------
+/// A scope an [`ApiToken`] must grant, such as `notes:write`.
+///
+/// ```
+/// use loco_rs::controller::extractor::auth::Scope;
+///
+/// pub struct WriteNotes;
+///
+/// impl Scope for WriteNotes {
+///     const NAME: Option<&'static str> = Some("notes:write");
+/// }
+/// ```
+pub trait Scope: Send + Sync {
+    /// The required scope, or `None` to accept any key.
+    const NAME: Option<&'static str>;
+}
+
+impl Scope for () {
+    const NAME: Option<&'static str> = None;
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 // Represents the data structure for the API token.
-pub struct ApiToken<T: Authenticable> {
+//
+// Keys created with `auth::api_keys::ApiKeys` are checked for the `S` scope,
+// and set `key`. Other keys are looked up with `Authenticable::find_by_api_key`,
+// and are only accepted when no scope is required.
+pub struct ApiToken<T: Authenticable, S: Scope = ()> {
     pub user: T,
+    pub key: Option<auth::api_keys::ApiKey>,
+    #[serde(skip)]
+    scope: PhantomData<S>,
+}
+
+fn map_api_key_user_error(e: ModelError) -> Error {
+    match e {
+        ModelError::EntityNotFound => Error::Unauthorized("not found".to_string()),
+        ModelError::DbErr(db_err) => {
+            tracing::error!("Database error during API key authentication: {}", db_err);
+            Error::InternalServerError
+        }
+        _ => {
+            tracing::error!("API key authentication error: {}", e);
+            Error::Unauthorized("could not authorize".to_string())
+        }
+    }
 }
 
 // Implementing the `FromRequestParts` trait for `ApiToken` to enable extracting
 // it from the request.
-impl<S, T> FromRequestParts<S> for ApiToken<T>
+impl<S, T, P> FromRequestParts<S> for ApiToken<T, P>
 where
     AppContext: FromRef<S>,
     S: Send + Sync,
     T: Authenticable,
+    P: Scope,
 {
     type Rejection = Error;
 
@@ -247,22 +349,47 @@ where
         // Convert the state reference to the application context.
         let state: AppContext = AppContext::from_ref(state);
 
+        if auth::api_keys::is_api_key(&api_key) {
+            let key = auth::api_keys::ApiKeys::from_context(&state)
+                .authenticate(&api_key)
+                .await?;
+            if let Some(scope) = P::NAME {
+                if !key.has_scope(scope) {
+                    return Err(Error::CustomError(
+                        StatusCode::FORBIDDEN,
+                        ErrorDetail::new(
+                            "forbidden".to_string(),
+                            format!("api key is missing the `{scope}` scope"),
+                        ),
+                    ));
+                }
+            }
+            let user = T::find_by_claims_key(&state.db, &key.owner)
+                .await
+                .map_err(map_api_key_user_error)?;
+            return Ok(Self {
+                user,
+                key: Some(key),
+                scope: PhantomData,
+            });
+        }
+
+        if P::NAME.is_some() {
+            return Err(Error::Unauthorized(
+                "api key does not grant any scope".to_string(),
+            ));
+        }
+
         // Retrieve user information based on the API key from the database.
         let user = T::find_by_api_key(&state.db, &api_key)
             .await
-            .map_err(|e| match e {
-                ModelError::EntityNotFound => Error::Unauthorized("not found".to_string()),
-                ModelError::DbErr(db_err) => {
-                    tracing::error!("Database error during API key authentication: {}", db_err);
-                    Error::InternalServerError
-                }
-                _ => {
-                    tracing::error!("API key authentication error: {}", e);
-                    Error::Unauthorized("could not authorize".to_string())
-                }
-            })?;
+            .map_err(map_api_key_user_error)?;
 
-        Ok(Self { user })
+        Ok(Self {
+            user,
+            key: None,
+            scope: PhantomData,
+        })
     }
 }
 
@@ -289,6 +416,9 @@ mod tests {
             location,
             secret: String::new(),
             expiration: 1,
+            keys: vec![],
+            refresh: None,
+            revocation: false,
         };
 
         let request = axum::http::Request::builder()
@@ -301,7 +431,10 @@ mod tests {
             .body(())
             .unwrap();
         let (parts, ()) = request.into_parts();
-        assert_debug_snapshot!(test_name, extract_token(&jwt_config, &parts));
+        assert_debug_snapshot!(
+            test_name,
+            extract_token(jwt_config.location.as_ref(), &parts)
+        );
 
         // expected error
         let request = axum::http::Request::builder()
@@ -309,6 +442,6 @@ mod tests {
             .body(())
             .unwrap();
         let (parts, ()) = request.into_parts();
-        assert!(extract_token(&jwt_config, &parts).is_err());
+        assert!(extract_token(jwt_config.location.as_ref(), &parts).is_err());
     }
 }
@@ -0,0 +1,25 @@
+//! This module contains the route publishing the public keys that verify the
+//! tokens issued by the application, as a JSON Web Key Set. Other services
+//! use it to verify tokens without sharing a secret.
+//!
+//! The route is not part of the default routes, add it to the application
+//! routes to publish the keys:
+//!
+//! ```rust,ignore
+//! AppRoutes::with_default_routes().add_route(loco_rs::controller::jwks::routes())
+//! ```
+
+use axum::{extract::State, response::Response, routing::get};
+
+use super::{format, routes::Routes};
+use crate::{app::AppContext, auth::jwt::JWT, Result};
+
+/// Returns the public keys of the configured JWT keys
+async fn jwks(State(ctx): State<AppContext>) -> Result<Response> {
+    format::json(JWT::from_context(&ctx)?.jwks())
+}
+
+/// Defines and returns the JWKS route.
+pub fn routes() -> Routes {
+    Routes::new().add("/.well-known/jwks.json", get(jwks))
+}
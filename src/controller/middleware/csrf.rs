@@ -0,0 +1,625 @@
+//! CSRF Protection Middleware
+//!
+//! This middleware protects HTML forms and HTMX requests against cross-site
+//! request forgery, using the signed double-submit cookie pattern: a token is
+//! kept in a cookie, and every unsafe request (anything but `GET`, `HEAD`,
+//! `OPTIONS` and `TRACE`) must send the same token, either in the
+//! `x-csrf-token` header or in the `csrf_token` field of a url-encoded or
+//! multipart form. Another site can make the browser send the cookie, but
+//! can't read it to send the token along.
+//!
+//! Tokens are a random nonce signed with the configured secret, along with
+//! the id of the session when the session middleware keeps sessions in a
+//! store. A token planted by a sibling subdomain, or over plain HTTP, is
+//! rejected, as it can't be signed without the secret, nor taken from
+//! another session.
+//!
+//! Templates add the token with the `csrf_field()`, `csrf_meta()` and
+//! `csrf_hx_headers()` Tera functions, and handlers can read it with the
+//! [`CsrfToken`] extractor.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router as AXRouter,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{
+    app::AppContext,
+    controller::{middleware::MiddlewareLayer, ErrorDetail},
+    hash, Error, Result,
+};
+
+/// The header carrying the token.
+pub const HEADER_NAME: &str = "x-csrf-token";
+/// The form field carrying the token.
+pub const FIELD_NAME: &str = "csrf_token";
+const NONCE_LENGTH: usize = 32;
+/// Larger url-encoded forms must send the token in the header, as must
+/// multipart forms with the token field after this many bytes.
+const MAX_FORM_SIZE: usize = 2 * 1024 * 1024;
+/// The `Content-Disposition` parameter of the multipart token field.
+const MULTIPART_FIELD: &[u8] = b"; name=\"csrf_token\"";
+
+tokio::task_local! {
+    static CURRENT_TOKEN: CsrfToken;
+}
+
+/// CSRF middleware configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Csrf {
+    #[serde(default)]
+    pub enable: bool,
+    /// The secret signing the tokens, at least 32 bytes long
+    #[serde(default, skip_serializing)]
+    pub secret: String,
+    /// The name of the cookie keeping the token
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    /// Only send the cookie over HTTPS
+    #[serde(default = "default_true")]
+    pub secure: bool,
+    /// Paths which are not checked, such as webhooks or token authenticated
+    /// APIs. A trailing `*` matches any path with the given prefix.
+    #[serde(default)]
+    pub exempt: Vec<String>,
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        serde_json::from_value(json!({})).unwrap()
+    }
+}
+
+fn default_cookie_name() -> String {
+    "loco_csrf".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Csrf {
+    fn mac(&self, session_id: &str, nonce: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(session_id.as_bytes());
+        mac.update(b"!");
+        mac.update(nonce.as_bytes());
+        mac
+    }
+
+    /// Returns a new token of the given session.
+    fn issue(&self, session_id: &str) -> String {
+        let nonce = hash::random_string(NONCE_LENGTH);
+        let signature = hex::encode(self.mac(session_id, &nonce).finalize().into_bytes());
+        format!("{nonce}.{signature}")
+    }
+
+    /// Returns `true` when the token was issued for the given session.
+    fn verify(&self, session_id: &str, token: &str) -> bool {
+        token.split_once('.').is_some_and(|(nonce, signature)| {
+            nonce.len() == NONCE_LENGTH
+                && hex::decode(signature).is_ok_and(|signature| {
+                    self.mac(session_id, nonce).verify_slice(&signature).is_ok()
+                })
+        })
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == pattern,
+            })
+    }
+}
+
+impl MiddlewareLayer for Csrf {
+    /// Returns the name of the middleware
+    fn name(&self) -> &'static str {
+        "csrf"
+    }
+
+    /// Returns whether the middleware is enabled or not
+    fn is_enabled(&self) -> bool {
+        self.enable
+    }
+
+    fn config(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
+
+    /// Applies the CSRF middleware to the application router.
+    ///
+    /// # Errors
+    ///
+    /// When the secret is shorter than 32 bytes.
+    fn apply(&self, app: AXRouter<AppContext>) -> Result<AXRouter<AppContext>> {
+        if self.secret.len() < 32 {
+            return Err(Error::string(
+                "the csrf secret must be at least 32 bytes long",
+            ));
+        }
+        Ok(app.layer(axum::middleware::from_fn_with_state(
+            Arc::new(self.clone()),
+            csrf_middleware,
+        )))
+    }
+}
+
+/// The CSRF token of the current request, to render in forms.
+#[derive(Debug, Clone)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Returns the token value.
+    #[must_use]
+    pub fn get(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Returns the token of the request being handled, when the CSRF
+    /// middleware is enabled.
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        CURRENT_TOKEN.try_with(Clone::clone).ok()
+    }
+}
+
+#[cfg(test)]
+impl CsrfToken {
+    /// Runs the given future as if the CSRF middleware had set this token.
+    pub(crate) async fn scope<F: std::future::Future>(token: &str, f: F) -> F::Output {
+        CURRENT_TOKEN.scope(Self(token.to_string()), f).await
+    }
+}
+
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .ok_or_else(|| Error::string("the csrf middleware is not enabled"))
+    }
+}
+
+/// Returns the id of the session the token is bound to, empty without a
+/// stored session.
+#[cfg_attr(not(feature = "session"), allow(unused_variables))]
+fn session_id(request: &Request) -> String {
+    #[cfg(feature = "session")]
+    if let Some(id) = request
+        .extensions()
+        .get::<crate::session::Session>()
+        .and_then(crate::session::Session::id)
+    {
+        return id;
+    }
+    String::new()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the value of the multipart part whose headers contain the given
+/// position, once the part is buffered.
+fn multipart_value(buffered: &[u8], field: usize) -> Option<String> {
+    let start = field + find(&buffered[field..], b"\r\n\r\n")? + 4;
+    let end = start + find(&buffered[start..], b"\r\n")?;
+    String::from_utf8(buffered[start..end].to_vec()).ok()
+}
+
+/// Reads a multipart body until the token field, which is usually the first
+/// one. The buffered start of the body is put back in front of the rest.
+async fn multipart_token(body: Body) -> Result<(Body, Option<String>)> {
+    let mut stream = body.into_data_stream();
+    let mut buffered = Vec::new();
+    let mut searched = 0;
+    let mut field = None;
+    let mut token = None;
+    while buffered.len() < MAX_FORM_SIZE {
+        let Some(chunk) = stream.next().await else {
+            break;
+        };
+        buffered.extend_from_slice(&chunk.map_err(|err| Error::BadRequest(err.to_string()))?);
+        if field.is_none() {
+            field = find(&buffered[searched..], MULTIPART_FIELD).map(|at| searched + at);
+            searched = buffered.len().saturating_sub(MULTIPART_FIELD.len());
+        }
+        if let Some(field) = field {
+            token = multipart_value(&buffered, field);
+            if token.is_some() {
+                break;
+            }
+        }
+    }
+    let start = futures_util::stream::once(async { Ok(Bytes::from(buffered)) });
+    Ok((Body::from_stream(start.chain(stream)), token))
+}
+
+/// Returns the token sent in the header, or in the url-encoded or multipart
+/// form body. The body is read and put back into the request.
+async fn submitted_token(request: Request) -> Result<(Request, Option<String>)> {
+    if let Some(token) = request
+        .headers()
+        .get(HEADER_NAME)
+        .and_then(|value| value.to_str().ok())
+    {
+        let token = token.to_string();
+        return Ok((request, Some(token)));
+    }
+
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("multipart/form-data") {
+        let (parts, body) = request.into_parts();
+        let (body, token) = multipart_token(body).await?;
+        return Ok((Request::from_parts(parts, body), token));
+    }
+    if !content_type.starts_with("application/x-www-form-urlencoded") {
+        return Ok((request, None));
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_FORM_SIZE)
+        .await
+        .map_err(|err| Error::BadRequest(err.to_string()))?;
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+        .ok()
+        .and_then(|fields| {
+            fields
+                .into_iter()
+                .find_map(|(name, value)| (name == FIELD_NAME).then_some(value))
+        });
+    Ok((Request::from_parts(parts, Body::from(bytes)), token))
+}
+
+fn forbidden() -> Response {
+    Error::CustomError(
+        StatusCode::FORBIDDEN,
+        ErrorDetail::new("forbidden", "invalid CSRF token"),
+    )
+    .into_response()
+}
+
+async fn csrf_middleware(
+    State(config): State<Arc<Csrf>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // a token of another session, such as the one before signing in, is
+    // replaced
+    let session_id = session_id(&request);
+    let cookie_token = CookieJar::from_headers(request.headers())
+        .get(&config.cookie_name)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| config.verify(&session_id, token));
+
+    let is_safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    );
+    let mut request = if is_safe || config.is_exempt(request.uri().path()) {
+        request
+    } else {
+        let (request, submitted) = match submitted_token(request).await {
+            Ok(result) => result,
+            Err(err) => return err.into_response(),
+        };
+        match (&cookie_token, submitted) {
+            (Some(expected), Some(actual)) if hash::constant_time_eq(expected, &actual) => request,
+            _ => {
+                tracing::warn!(
+                    method = request.method().to_string(),
+                    uri = request.uri().to_string(),
+                    "rejected a request with a missing or invalid CSRF token"
+                );
+                return forbidden();
+            }
+        }
+    };
+
+    let is_new = cookie_token.is_none();
+    let token = CsrfToken(cookie_token.unwrap_or_else(|| config.issue(&session_id)));
+    request.extensions_mut().insert(token.clone());
+
+    let mut response = CURRENT_TOKEN.scope(token.clone(), next.run(request)).await;
+
+    if is_new {
+        let cookie = Cookie::build((config.cookie_name.clone(), token.0))
+            .path("/")
+            .http_only(true)
+            .secure(config.secure)
+            .same_site(SameSite::Lax)
+            .build();
+        if let Ok(value) = HeaderValue::from_str(&cookie.encoded().to_string()) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        routing::{get, post},
+        Router,
+    };
+    use rstest::rstest;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn router(config: Csrf) -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|token: CsrfToken| async move { token.get().to_string() }),
+            )
+            .route("/form", post(|body: String| async move { body }))
+            .route("/webhooks/stripe", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(config),
+                csrf_middleware,
+            ))
+    }
+
+    fn config() -> Csrf {
+        Csrf {
+            enable: true,
+            secret: "a".repeat(32),
+            exempt: vec!["/webhooks/*".to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// Returns the token and the cookie of a first visit.
+    async fn visit(app: &Router) -> (String, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let set_cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .expect("csrf cookie")
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(set_cookie.contains("HttpOnly"));
+        assert!(set_cookie.contains("SameSite=Lax"));
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), cookie)
+    }
+
+    #[tokio::test]
+    async fn keeps_the_token_of_the_cookie() {
+        let app = router(config());
+        let (token, cookie) = visit(&app).await;
+        assert!(config().verify("", &token));
+        assert_eq!(cookie, format!("loco_csrf={token}"));
+
+        let response = app
+            .oneshot(
+                Request::get("/")
+                    .header(header::COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, token.as_bytes());
+    }
+
+    #[rstest]
+    #[case::header(true, None, StatusCode::OK)]
+    #[case::form(false, Some("csrf_token={token}&name=loco"), StatusCode::OK)]
+    #[case::missing(false, Some("name=loco"), StatusCode::FORBIDDEN)]
+    #[case::wrong(
+        false,
+        Some("csrf_token=aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"),
+        StatusCode::FORBIDDEN
+    )]
+    #[tokio::test]
+    async fn checks_unsafe_requests(
+        #[case] in_header: bool,
+        #[case] form: Option<&str>,
+        #[case] expected: StatusCode,
+    ) {
+        let app = router(config());
+        let (token, cookie) = visit(&app).await;
+
+        let mut request = Request::post("/form").header(header::COOKIE, cookie);
+        if in_header {
+            request = request.header(HEADER_NAME, &token);
+        }
+        let body = form.map_or_else(String::new, |form| form.replace("{token}", &token));
+        if form.is_some() {
+            request = request.header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        }
+        let response = app
+            .oneshot(request.body(Body::from(body.clone())).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected);
+
+        if expected == StatusCode::OK {
+            // the handler still gets the whole form
+            let received = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(received, body.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_tokens_of_multipart_forms() {
+        let app = router(config());
+        let (token, cookie) = visit(&app).await;
+
+        let file = "x".repeat(100_000);
+        let body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{token}\r\n\
+             --boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n{file}\r\n\
+             --boundary--\r\n"
+        );
+        let response = app
+            .oneshot(
+                Request::post("/form")
+                    .header(header::COOKIE, cookie)
+                    .header(
+                        header::CONTENT_TYPE,
+                        "multipart/form-data; boundary=boundary",
+                    )
+                    .body(Body::from(body.clone()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let received = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(received, body.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_tokens() {
+        let token = format!("{}.{}", "a".repeat(32), "0".repeat(64));
+        let response = router(config())
+            .oneshot(
+                Request::post("/form")
+                    .header(header::COOKIE, format!("loco_csrf={token}"))
+                    .header(HEADER_NAME, &token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[cfg(feature = "session")]
+    #[tokio::test]
+    async fn binds_tokens_to_the_session() {
+        let config = config();
+        let token = config.issue("first");
+        let app = router(config).layer(axum::middleware::from_fn(
+            |mut request: Request, next: Next| async move {
+                let id = request
+                    .headers()
+                    .get("x-session")
+                    .map(|value| value.to_str().unwrap().to_string());
+                request
+                    .extensions_mut()
+                    .insert(crate::session::Session::new(crate::session::State {
+                        id,
+                        ..Default::default()
+                    }));
+                next.run(request).await
+            },
+        ));
+
+        for (session, expected) in [("first", StatusCode::OK), ("second", StatusCode::FORBIDDEN)] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::post("/form")
+                        .header("x-session", session)
+                        .header(header::COOKIE, format!("loco_csrf={token}"))
+                        .header(HEADER_NAME, &token)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+
+    #[test]
+    fn requires_a_long_secret() {
+        let config = Csrf {
+            secret: "short".to_string(),
+            ..config()
+        };
+        assert!(config.apply(AXRouter::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_cookie() {
+        let app = router(config());
+        let (token, _) = visit(&app).await;
+
+        let response = app
+            .oneshot(
+                Request::post("/form")
+                    .header(HEADER_NAME, token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn skips_exempt_paths() {
+        let response = router(config())
+            .oneshot(
+                Request::post("/webhooks/stripe")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn matches_exempt_paths() {
+        let config = Csrf {
+            exempt: vec!["/api/*".to_string(), "/hooks".to_string()],
+            ..Default::default()
+        };
+        assert!(config.is_exempt("/api/notes"));
+        assert!(config.is_exempt("/hooks"));
+        assert!(!config.is_exempt("/hooks/1"));
+        assert!(!config.is_exempt("/notes"));
+    }
+}
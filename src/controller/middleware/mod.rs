@@ -9,6 +9,7 @@
 pub mod catch_panic;
 pub mod compression;
 pub mod cors;
+pub mod csrf;
 pub mod etag;
 pub mod fallback;
 pub mod format;
@@ -18,6 +19,8 @@ pub mod powered_by;
 pub mod remote_ip;
 pub mod request_id;
 pub mod secure_headers;
+#[cfg(feature = "session")]
+pub mod session;
 #[cfg(feature = "embedded_assets")]
 pub mod static_assets_embedded;
 #[cfg(feature = "embedded_assets")]
@@ -85,6 +88,8 @@ pub fn default_middleware_stack(ctx: &AppContext) -> Vec<Box<dyn MiddlewareLayer
             enable: false,
             ..Default::default()
         })),
+        // CSRF middleware with a default if none
+        Box::new(middlewares.csrf.clone().unwrap_or_default()),
         // Catch Panic middleware with a default if none
         Box::new(
             middlewares
@@ -155,6 +160,12 @@ pub fn default_middleware_stack(ctx: &AppContext) -> Vec<Box<dyn MiddlewareLayer
                 .clone()
                 .unwrap_or_else(|| request_id::RequestId { enable: true }),
         ),
+        // Session middleware with a default if none
+        #[cfg(feature = "session")]
+        Box::new(session::new(
+            &middlewares.session.clone().unwrap_or_default(),
+            ctx,
+        )),
         // Fallback middleware with a default if none
         Box::new(
             middlewares
@@ -194,6 +205,9 @@ pub struct Config {
     /// CORS configuration
     pub cors: Option<cors::Cors>,
 
+    /// CSRF protection for forms and HTMX requests
+    pub csrf: Option<csrf::Csrf>,
+
     /// Serving static assets
     #[serde(rename = "static")]
     pub static_assets: Option<static_assets::StaticAssets>,
@@ -209,4 +223,8 @@ pub struct Config {
 
     /// Request ID
     pub request_id: Option<request_id::RequestId>,
+
+    /// Server-side sessions
+    #[cfg(feature = "session")]
+    pub session: Option<session::Config>,
 }
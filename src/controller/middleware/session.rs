@@ -0,0 +1,685 @@
+//! Session Middleware
+//!
+//! This middleware loads the [`Session`] of every request from a signed or
+//! encrypted cookie, makes it available to handlers through the [`Session`]
+//! extractor, and saves it once the response is ready.
+//!
+//! The session data is kept either in the cookie itself (the `cookie` store),
+//! or server-side in the application cache (`cache`) or a database table
+//! (`db`), in which case the cookie only holds a random session id.
+//!
+//! Sessions expire after `idle_timeout` seconds without requests, and after
+//! `absolute_timeout` seconds since they were created, whichever comes first.
+//! An expired session is replaced by a new, empty one.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Request, State as AxumState},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Router as AXRouter,
+};
+use axum_extra::extract::cookie::{self, Cookie, Key, PrivateCookieJar, SignedCookieJar};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[cfg(feature = "with-db")]
+use crate::session::db::DbStore;
+use crate::{
+    app::AppContext,
+    controller::middleware::MiddlewareLayer,
+    hash,
+    session::{Record, Session, SessionStore, State},
+    Error, Result,
+};
+
+/// How long server-side sessions are kept when no timeout is configured.
+const DEFAULT_STORE_TTL: u64 = 14 * 24 * 60 * 60;
+const SESSION_ID_LENGTH: usize = 32;
+/// Browsers drop cookies larger than this.
+const MAX_COOKIE_SIZE: usize = 4096;
+
+/// Where the session data is kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Store {
+    /// In the session cookie. Sessions can't be revoked server-side, and are
+    /// limited to 4KB.
+    #[default]
+    Cookie,
+    /// In the application cache.
+    Cache,
+    /// In a database table.
+    #[cfg(feature = "with-db")]
+    Db,
+}
+
+/// The `SameSite` attribute of the session cookie.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    #[default]
+    Lax,
+    None,
+}
+
+impl From<SameSite> for cookie::SameSite {
+    fn from(value: SameSite) -> Self {
+        match value {
+            SameSite::Strict => Self::Strict,
+            SameSite::Lax => Self::Lax,
+            SameSite::None => Self::None,
+        }
+    }
+}
+
+/// Session middleware configuration
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub enable: bool,
+    /// Where the session data is kept
+    #[serde(default)]
+    pub store: Store,
+    /// The table of the `db` store
+    #[serde(default = "default_table")]
+    pub table: String,
+    /// The secret signing or encrypting the cookie, at least 32 bytes long
+    #[serde(default, skip_serializing)]
+    pub secret: String,
+    /// Encrypt the cookie instead of only signing it, so that its content
+    /// can't be read by the client
+    #[serde(default)]
+    pub encrypt: bool,
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    /// Only send the cookie over HTTPS
+    #[serde(default = "default_true")]
+    pub secure: bool,
+    #[serde(default)]
+    pub same_site: SameSite,
+    /// The domain of the cookie, defaults to the host of the request
+    pub domain: Option<String>,
+    /// Expire sessions after this many seconds without requests
+    pub idle_timeout: Option<u64>,
+    /// Expire sessions this many seconds after they were created
+    pub absolute_timeout: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        serde_json::from_value(json!({})).unwrap()
+    }
+}
+
+fn default_table() -> String {
+    "loco_sessions".to_string()
+}
+
+fn default_cookie_name() -> String {
+    "loco_session".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// [`Middleware`] struct responsible for loading and saving sessions.
+pub struct Middleware {
+    config: Config,
+    store: Option<Arc<dyn SessionStore>>,
+}
+
+/// Creates a new instance of [`Middleware`], with the store selected in
+/// the [`Config`].
+#[must_use]
+pub fn new(config: &Config, ctx: &AppContext) -> Middleware {
+    let store: Option<Arc<dyn SessionStore>> = match config.store {
+        Store::Cookie => None,
+        Store::Cache => Some(ctx.cache.clone()),
+        #[cfg(feature = "with-db")]
+        Store::Db => Some(Arc::new(DbStore::new(ctx.db.clone(), &config.table))),
+    };
+
+    Middleware {
+        config: config.clone(),
+        store,
+    }
+}
+
+impl MiddlewareLayer for Middleware {
+    /// Returns the name of the middleware
+    fn name(&self) -> &'static str {
+        "session"
+    }
+
+    /// Returns whether the middleware is enabled or not
+    fn is_enabled(&self) -> bool {
+        self.config.enable
+    }
+
+    fn config(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(&self.config)
+    }
+
+    /// Applies the session middleware to the application router.
+    ///
+    /// # Errors
+    ///
+    /// When the secret is shorter than 32 bytes.
+    fn apply(&self, app: AXRouter<AppContext>) -> Result<AXRouter<AppContext>> {
+        Ok(app.layer(axum::middleware::from_fn_with_state(
+            self.sessions()?,
+            session_middleware,
+        )))
+    }
+}
+
+impl Middleware {
+    fn sessions(&self) -> Result<Arc<Sessions>> {
+        if self.config.secret.len() < 32 {
+            return Err(Error::string(
+                "the session secret must be at least 32 bytes long",
+            ));
+        }
+
+        Ok(Arc::new(Sessions {
+            key: Key::derive_from(self.config.secret.as_bytes()),
+            config: self.config.clone(),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+/// What to do with the session cookie once the response is ready.
+enum CookieUpdate {
+    Keep,
+    Set(Cookie<'static>),
+    Remove,
+}
+
+struct Sessions {
+    config: Config,
+    key: Key,
+    store: Option<Arc<dyn SessionStore>>,
+}
+
+impl Sessions {
+    fn read_cookie(&self, headers: &HeaderMap) -> Option<String> {
+        let name = self.config.cookie_name.as_str();
+        let cookie = if self.config.encrypt {
+            PrivateCookieJar::from_headers(headers, self.key.clone()).get(name)
+        } else {
+            SignedCookieJar::from_headers(headers, self.key.clone()).get(name)
+        };
+        cookie.map(|cookie| cookie.value().to_string())
+    }
+
+    fn is_expired(&self, record: &Record, now: i64) -> bool {
+        let elapsed = |since: i64, timeout: u64| now.saturating_sub(since) >= seconds(timeout);
+        self.config
+            .idle_timeout
+            .is_some_and(|timeout| elapsed(record.last_seen, timeout))
+            || self
+                .config
+                .absolute_timeout
+                .is_some_and(|timeout| elapsed(record.created_at, timeout))
+    }
+
+    /// Returns for how long the session is valid, when it expires.
+    fn ttl(&self, record: &Record, now: i64) -> Option<u64> {
+        let remaining = self.config.absolute_timeout.map(|timeout| {
+            let expires_at = record.created_at.saturating_add(seconds(timeout));
+            u64::try_from(expires_at.saturating_sub(now)).unwrap_or_default()
+        });
+        match (self.config.idle_timeout, remaining) {
+            (Some(idle), Some(remaining)) => Some(idle.min(remaining)),
+            (idle, remaining) => idle.or(remaining),
+        }
+    }
+
+    async fn load(&self, cookie: Option<String>, now: i64) -> Result<State> {
+        let Some(value) = cookie else {
+            return Ok(State {
+                record: Record::new(now),
+                ..Default::default()
+            });
+        };
+
+        let (id, data) = match &self.store {
+            Some(store) => {
+                let data = store.load(&value).await?;
+                (Some(value), data)
+            }
+            None => (None, Some(value)),
+        };
+        let record = data
+            .and_then(|data| serde_json::from_str::<Record>(&data).ok())
+            .filter(|record| !self.is_expired(record, now));
+
+        let Some(mut record) = record else {
+            // an unknown or expired session is replaced by a new one
+            if let (Some(store), Some(id)) = (&self.store, &id) {
+                store.destroy(id).await?;
+            }
+            return Ok(State {
+                record: Record::new(now),
+                ..Default::default()
+            });
+        };
+
+        let flashes = std::mem::take(&mut record.flash);
+        Ok(State {
+            id,
+            record,
+            changed: !flashes.is_empty(),
+            flashes,
+            rotate: false,
+        })
+    }
+
+    async fn save(&self, state: State, had_cookie: bool, now: i64) -> Result<CookieUpdate> {
+        let State {
+            id,
+            mut record,
+            changed,
+            rotate,
+            ..
+        } = state;
+
+        if let (Some(store), Some(id)) = (&self.store, &id) {
+            if rotate || record.is_empty() {
+                store.destroy(id).await?;
+            }
+        }
+
+        if record.is_empty() {
+            return Ok(if had_cookie {
+                CookieUpdate::Remove
+            } else {
+                CookieUpdate::Keep
+            });
+        }
+        // sessions with an idle timeout are kept alive by every request
+        if !changed && self.config.idle_timeout.is_none() {
+            return Ok(CookieUpdate::Keep);
+        }
+
+        record.last_seen = now;
+        let ttl = self.ttl(&record, now);
+        let data = serde_json::to_string(&record)?;
+        let value = match &self.store {
+            Some(store) => {
+                let id = match id {
+                    Some(id) if !rotate => id,
+                    _ => hash::random_string(SESSION_ID_LENGTH),
+                };
+                let expiry = Duration::from_secs(ttl.unwrap_or(DEFAULT_STORE_TTL));
+                store.save(&id, &data, expiry).await?;
+                id
+            }
+            None => data,
+        };
+
+        Ok(CookieUpdate::Set(self.cookie(value, ttl)))
+    }
+
+    fn cookie(&self, value: String, ttl: Option<u64>) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.config.cookie_name.clone(), value))
+            .path("/")
+            .http_only(true)
+            .secure(self.config.secure)
+            .same_site(self.config.same_site.into());
+        if let Some(domain) = &self.config.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        if let Some(ttl) = ttl {
+            cookie = cookie.max_age(time::Duration::seconds(seconds(ttl)));
+        }
+        cookie.build()
+    }
+
+    fn update_cookie(&self, update: CookieUpdate, response: Response) -> Response {
+        let cookie = match update {
+            CookieUpdate::Keep => return response,
+            CookieUpdate::Set(cookie) => Some(cookie),
+            CookieUpdate::Remove => None,
+        };
+        let Some(cookie) = cookie else {
+            // browsers only remove the cookie matching its domain and path
+            let mut removal = self.cookie(String::new(), None);
+            removal.make_removal();
+            let mut response = response;
+            if let Ok(value) = removal.encoded().to_string().parse() {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+            return response;
+        };
+
+        let response = if self.config.encrypt {
+            let jar = PrivateCookieJar::new(self.key.clone()).add(cookie);
+            (jar, response).into_response()
+        } else {
+            let jar = SignedCookieJar::new(self.key.clone()).add(cookie);
+            (jar, response).into_response()
+        };
+
+        if self.store.is_none() {
+            let size = response
+                .headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(axum::http::HeaderValue::len)
+                .max()
+                .unwrap_or_default();
+            if size > MAX_COOKIE_SIZE {
+                tracing::warn!(
+                    size,
+                    "the session cookie is too large and may be dropped by browsers, use a \
+                     server-side session store"
+                );
+            }
+        }
+        response
+    }
+}
+
+fn seconds(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+async fn session_middleware(
+    AxumState(sessions): AxumState<Arc<Sessions>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    let cookie = sessions.read_cookie(request.headers());
+    let had_cookie = cookie.is_some();
+
+    let state = match sessions.load(cookie, now).await {
+        Ok(state) => state,
+        Err(err) => {
+            tracing::error!(error = err.to_string(), "could not load the session");
+            return err.into_response();
+        }
+    };
+    let session = Session::new(state);
+    request.extensions_mut().insert(session.clone());
+
+    let response = next.run(request).await;
+
+    match sessions.save(session.take_state(), had_cookie, now).await {
+        Ok(update) => sessions.update_cookie(update, response),
+        Err(err) => {
+            tracing::error!(error = err.to_string(), "could not save the session");
+            err.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{cache, tests_cfg};
+
+    const SECRET: &str = "a secret which is long enough to derive session keys from";
+
+    fn config(store: Store) -> Config {
+        Config {
+            enable: true,
+            store,
+            secret: SECRET.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn counter(session: Session) -> Result<String> {
+        let visits = session.get::<u32>("visits")?.unwrap_or_default() + 1;
+        session.insert("visits", &visits)?;
+        Ok(visits.to_string())
+    }
+
+    async fn login(session: Session) -> Result<()> {
+        session.rotate();
+        session.insert("pid", "user-pid")?;
+        session.flash("notice", "Signed in")
+    }
+
+    async fn flashes(session: Session) -> String {
+        serde_json::to_string(&session.flashes()).unwrap()
+    }
+
+    async fn logout(session: Session) {
+        session.destroy();
+    }
+
+    fn router(middleware: &Middleware) -> Router {
+        let app = Router::new()
+            .route("/", get(counter))
+            .route("/login", post(login))
+            .route("/flashes", get(flashes))
+            .route("/logout", post(logout));
+        app.layer(axum::middleware::from_fn_with_state(
+            middleware.sessions().unwrap(),
+            session_middleware,
+        ))
+    }
+
+    /// Sends a request with the given cookie, returning the body and the
+    /// `set-cookie` header.
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        cookie: Option<&str>,
+    ) -> (String, Option<String>) {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), set_cookie)
+    }
+
+    fn cookie_pair(set_cookie: &str) -> String {
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    async fn can_keep_state(middleware: &Middleware) {
+        let app = router(middleware);
+
+        let (body, set_cookie) = send(&app, Method::GET, "/", None).await;
+        assert_eq!(body, "1");
+        let set_cookie = set_cookie.expect("session cookie");
+        assert!(set_cookie.starts_with("loco_session="));
+        assert!(set_cookie.contains("HttpOnly"));
+        assert!(set_cookie.contains("Secure"));
+        assert!(set_cookie.contains("SameSite=Lax"));
+        let cookie = cookie_pair(&set_cookie);
+
+        let (body, set_cookie) = send(&app, Method::GET, "/", Some(&cookie)).await;
+        assert_eq!(body, "2");
+        let cookie = cookie_pair(&set_cookie.expect("session cookie"));
+
+        let (body, _) = send(&app, Method::GET, "/", Some(&cookie)).await;
+        assert_eq!(body, "3");
+    }
+
+    #[tokio::test]
+    async fn can_keep_state_in_cookie() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        can_keep_state(&new(&config(Store::Cookie), &ctx)).await;
+
+        let config = Config {
+            encrypt: true,
+            ..config(Store::Cookie)
+        };
+        can_keep_state(&new(&config, &ctx)).await;
+    }
+
+    #[tokio::test]
+    async fn can_keep_state_in_cache() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        can_keep_state(&new(&config(Store::Cache), &ctx)).await;
+    }
+
+    #[cfg(feature = "with-db")]
+    #[tokio::test]
+    async fn can_keep_state_in_db() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        ctx.db = tests_cfg::db::memory_db().await;
+
+        can_keep_state(&new(&config(Store::Db), &ctx)).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_cookies() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let app = router(&new(&config(Store::Cookie), &ctx));
+
+        let (_, set_cookie) = send(&app, Method::GET, "/", None).await;
+        let cookie = cookie_pair(&set_cookie.unwrap()).replace("visits", "visitz");
+
+        let (body, set_cookie) = send(&app, Method::GET, "/", Some(&cookie)).await;
+        assert_eq!(body, "1");
+        assert!(set_cookie.is_some());
+    }
+
+    #[tokio::test]
+    async fn rotates_session_id_and_destroys_session() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let cache: Arc<cache::Cache> = ctx.cache.clone();
+        let config = Config {
+            domain: Some("example.com".to_string()),
+            ..config(Store::Cache)
+        };
+        let middleware = new(&config, &ctx);
+        let app = router(&middleware);
+
+        let (_, set_cookie) = send(&app, Method::GET, "/", None).await;
+        let anonymous = cookie_pair(&set_cookie.unwrap());
+        let sessions = Sessions {
+            key: Key::derive_from(SECRET.as_bytes()),
+            config: middleware.config.clone(),
+            store: None,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, anonymous.parse().unwrap());
+        let anonymous_id = sessions.read_cookie(&headers).unwrap();
+
+        let (_, set_cookie) = send(&app, Method::POST, "/login", Some(&anonymous)).await;
+        let logged_in = cookie_pair(&set_cookie.unwrap());
+        assert_ne!(anonymous, logged_in);
+        assert!(cache
+            .driver
+            .get(&format!("session:{anonymous_id}"))
+            .await
+            .unwrap()
+            .is_none());
+
+        // the values are kept by the new session only
+        let (body, _) = send(&app, Method::GET, "/", Some(&anonymous)).await;
+        assert_eq!(body, "1");
+        let (body, _) = send(&app, Method::GET, "/", Some(&logged_in)).await;
+        assert_eq!(body, "2");
+
+        let (_, set_cookie) = send(&app, Method::POST, "/logout", Some(&logged_in)).await;
+        let set_cookie = set_cookie.expect("removal cookie");
+        assert!(set_cookie.starts_with("loco_session=;"));
+        assert!(set_cookie.contains("Max-Age=0"));
+        assert!(set_cookie.contains("Domain=example.com"));
+        assert!(set_cookie.contains("Path=/"));
+        let (body, _) = send(&app, Method::GET, "/", Some(&logged_in)).await;
+        assert_eq!(body, "1");
+    }
+
+    #[tokio::test]
+    async fn flashes_are_available_to_the_next_request_only() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let app = router(&new(&config(Store::Cache), &ctx));
+
+        let (_, set_cookie) = send(&app, Method::POST, "/login", None).await;
+        let cookie = cookie_pair(&set_cookie.unwrap());
+
+        let (body, _) = send(&app, Method::GET, "/flashes", Some(&cookie)).await;
+        assert_eq!(body, r#"{"notice":"Signed in"}"#);
+        let (body, _) = send(&app, Method::GET, "/flashes", Some(&cookie)).await;
+        assert_eq!(body, "{}");
+    }
+
+    #[tokio::test]
+    async fn does_not_set_cookie_for_empty_sessions() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let app = router(&new(&config(Store::Cookie), &ctx));
+
+        let (_, set_cookie) = send(&app, Method::GET, "/flashes", None).await;
+        assert!(set_cookie.is_none());
+    }
+
+    #[test]
+    fn expires_idle_and_old_sessions() {
+        let sessions = Sessions {
+            key: Key::derive_from(SECRET.as_bytes()),
+            config: Config {
+                idle_timeout: Some(60),
+                absolute_timeout: Some(3600),
+                ..config(Store::Cookie)
+            },
+            store: None,
+        };
+        let record = Record {
+            created_at: 1000,
+            last_seen: 4000,
+            ..Default::default()
+        };
+
+        assert!(!sessions.is_expired(&record, 4059));
+        assert_eq!(sessions.ttl(&record, 4059), Some(60));
+        assert!(sessions.is_expired(&record, 4060));
+        assert_eq!(sessions.ttl(&record, 4590), Some(10));
+        assert!(sessions.is_expired(
+            &Record {
+                last_seen: 4590,
+                ..record
+            },
+            4600
+        ));
+    }
+
+    #[test]
+    fn requires_long_secret() {
+        let middleware = Middleware {
+            config: Config {
+                secret: "short".to_string(),
+                ..config(Store::Cookie)
+            },
+            store: None,
+        };
+        assert!(middleware.apply(AXRouter::new()).is_err());
+    }
+}
@@ -84,6 +84,8 @@ pub mod extractor;
 pub mod format;
 #[cfg(feature = "with-db")]
 mod health;
+#[cfg(feature = "auth_jwt")]
+pub mod jwks;
 pub mod middleware;
 mod ping;
 mod routes;
@@ -44,6 +44,7 @@ impl TeraView {
                 .ok_or_else(|| Error::string("invalid blob"))?,
         )?;
         tera_builtins::filters::register_filters(&mut tera);
+        tera_builtins::functions::register_functions(&mut tera);
         Ok(tera)
     }
 
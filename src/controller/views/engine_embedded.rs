@@ -41,6 +41,7 @@ impl TeraView {
         Self::load_templates_into_tera(&mut tera)?;
 
         tera_builtins::filters::register_filters(&mut tera);
+        tera_builtins::functions::register_functions(&mut tera);
         let ctx = tera::Context::default();
 
         Ok(Self {
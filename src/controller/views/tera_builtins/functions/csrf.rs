@@ -0,0 +1,101 @@
+//! Tera functions rendering the CSRF token of the current request.
+//!
+//! They render nothing when the CSRF middleware is disabled, so templates can
+//! use them either way.
+use std::collections::HashMap;
+
+use serde_json::value::Value;
+use tera::{Function, Result};
+
+use crate::controller::middleware::csrf::{CsrfToken, FIELD_NAME, HEADER_NAME};
+
+fn render(f: impl FnOnce(&str) -> String) -> Value {
+    Value::String(CsrfToken::current().map_or_else(String::new, |token| f(token.get())))
+}
+
+/// `{{ csrf_token() }}`: the token itself.
+pub struct Token;
+
+impl Function for Token {
+    fn call(&self, _args: &HashMap<String, Value>) -> Result<Value> {
+        Ok(render(ToString::to_string))
+    }
+}
+
+/// `{{ csrf_field() }}`: a hidden form field with the token.
+pub struct Field;
+
+impl Function for Field {
+    fn call(&self, _args: &HashMap<String, Value>) -> Result<Value> {
+        Ok(render(|token| {
+            format!(r#"<input type="hidden" name="{FIELD_NAME}" value="{token}">"#)
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `{{ csrf_meta() }}`: a `csrf-token` meta tag with the token, for scripts
+/// sending the `x-csrf-token` header.
+pub struct Meta;
+
+impl Function for Meta {
+    fn call(&self, _args: &HashMap<String, Value>) -> Result<Value> {
+        Ok(render(|token| {
+            format!(r#"<meta name="csrf-token" content="{token}">"#)
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `<body {{ csrf_hx_headers() }}>`: an `hx-headers` attribute making HTMX
+/// send the token with every request.
+pub struct HxHeaders;
+
+impl Function for HxHeaders {
+    fn call(&self, _args: &HashMap<String, Value>) -> Result<Value> {
+        Ok(render(|token| {
+            format!(r#"hx-headers='{{"{HEADER_NAME}": "{token}"}}'"#)
+        }))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tera::{Context, Tera};
+
+    use super::*;
+    use crate::controller::views::tera_builtins::functions::register_functions;
+
+    fn render_template(template: &str) -> String {
+        let mut tera = Tera::default();
+        register_functions(&mut tera);
+        tera.add_raw_template("form.html", template).unwrap();
+        tera.render("form.html", &Context::new()).unwrap()
+    }
+
+    const TEMPLATE: &str = "{{ csrf_meta() }}|{{ csrf_field() }}|<body {{ csrf_hx_headers() }}>";
+
+    #[test]
+    fn renders_nothing_without_middleware() {
+        assert_eq!(render_template(TEMPLATE), "||<body >");
+    }
+
+    #[tokio::test]
+    async fn renders_the_current_token() {
+        let html = CsrfToken::scope("abc", async { render_template(TEMPLATE) }).await;
+        assert_eq!(
+            html,
+            r#"<meta name="csrf-token" content="abc">|<input type="hidden" name="csrf_token" value="abc">|<body hx-headers='{"x-csrf-token": "abc"}'>"#
+        );
+    }
+}
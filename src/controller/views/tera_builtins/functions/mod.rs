@@ -0,0 +1,8 @@
+pub mod csrf;
+
+pub fn register_functions(tera: &mut tera::Tera) {
+    tera.register_function("csrf_token", csrf::Token);
+    tera.register_function("csrf_field", csrf::Field);
+    tera.register_function("csrf_meta", csrf::Meta);
+    tera.register_function("csrf_hx_headers", csrf::HxHeaders);
+}
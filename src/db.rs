@@ -37,6 +37,35 @@ const IGNORED_TABLES: &[&str] = &[
     "sqlt_loco_queue_lock",
 ];
 
+/// Creates the tables of a built-in store on first use, so that applications
+/// don't need a migration for them. The statements run once per instance, and
+/// should use `IF NOT EXISTS`.
+#[derive(Debug, Default)]
+pub(crate) struct LazySchema(tokio::sync::OnceCell<()>);
+
+impl LazySchema {
+    /// Runs the statements built for the backend of the database, unless they
+    /// already ran.
+    ///
+    /// # Errors
+    ///
+    /// When a statement fails.
+    pub(crate) async fn ensure<F>(&self, db: &DatabaseConnection, statements: F) -> AppResult<()>
+    where
+        F: FnOnce(DatabaseBackend) -> Vec<Statement> + Send,
+    {
+        self.0
+            .get_or_try_init(|| async {
+                for statement in statements(db.get_database_backend()) {
+                    db.execute(statement).await?;
+                }
+                Ok::<_, Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
 fn re_extract_db_name() -> &'static Regex {
     EXTRACT_DB_NAME.get_or_init(|| {
         Regex::new(r"^.+://(?:.*?/)?([^/?#]+)(?:[?#]|$)").expect("Extract db regex is correct")
@@ -1,11 +1,238 @@
-use crate::{Error, Result};
+//! Password hashing and random strings.
+//!
+//! Passwords are hashed with Argon2id by default. The algorithm and its
+//! parameters are set with `auth.password` in the configuration (see
+//! [`PasswordHashing::from_config`]), and hashes made with any supported
+//! algorithm can be verified, so that users imported from another application
+//! can still log in. Use [`PasswordHashing::verify_and_maybe_rehash`] when
+//! logging users in, to upgrade their hashes when the configuration changes.
+use crate::{config::Config, Error, Result};
 use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
     Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
 };
 use rand::{distr::Alphanumeric, rng, Rng};
+use serde::{Deserialize, Serialize};
 
-/// Hashes a plain text password and returns the hashed result.
+/// The algorithm and parameters used to hash passwords.
+///
+/// Example:
+/// ```yaml
+/// auth:
+///   password:
+///     algorithm: argon2id
+///     memory: 19456 # in KiB
+///     iterations: 2
+///     parallelism: 1
+/// ```
+///
+/// `bcrypt` (with `cost`) and `scrypt` (with `log_n`, `r` and `p`) are
+/// available with the `password_bcrypt` and `password_scrypt` features.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum PasswordHashing {
+    Argon2id {
+        /// Memory size, in KiB
+        #[serde(default = "default_argon2_memory")]
+        memory: u32,
+        #[serde(default = "default_argon2_iterations")]
+        iterations: u32,
+        #[serde(default = "default_argon2_parallelism")]
+        parallelism: u32,
+    },
+    #[cfg(feature = "password_bcrypt")]
+    Bcrypt {
+        #[serde(default = "default_bcrypt_cost")]
+        cost: u32,
+    },
+    #[cfg(feature = "password_scrypt")]
+    Scrypt {
+        #[serde(default = "default_scrypt_log_n")]
+        log_n: u8,
+        #[serde(default = "default_scrypt_r")]
+        r: u32,
+        #[serde(default = "default_scrypt_p")]
+        p: u32,
+    },
+}
+
+fn default_argon2_memory() -> u32 {
+    Params::DEFAULT_M_COST
+}
+
+fn default_argon2_iterations() -> u32 {
+    Params::DEFAULT_T_COST
+}
+
+fn default_argon2_parallelism() -> u32 {
+    Params::DEFAULT_P_COST
+}
+
+#[cfg(feature = "password_bcrypt")]
+fn default_bcrypt_cost() -> u32 {
+    bcrypt::DEFAULT_COST
+}
+
+#[cfg(feature = "password_scrypt")]
+fn default_scrypt_log_n() -> u8 {
+    scrypt::Params::RECOMMENDED_LOG_N
+}
+
+#[cfg(feature = "password_scrypt")]
+fn default_scrypt_r() -> u32 {
+    scrypt::Params::RECOMMENDED_R
+}
+
+#[cfg(feature = "password_scrypt")]
+fn default_scrypt_p() -> u32 {
+    scrypt::Params::RECOMMENDED_P
+}
+
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        Self::Argon2id {
+            memory: default_argon2_memory(),
+            iterations: default_argon2_iterations(),
+            parallelism: default_argon2_parallelism(),
+        }
+    }
+}
+
+impl PasswordHashing {
+    /// Returns the algorithm and parameters set with `auth.password` in the
+    /// configuration, or the default ones.
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        config
+            .auth
+            .as_ref()
+            .and_then(|auth| auth.password.clone())
+            .unwrap_or_default()
+    }
+
+    /// Hashes a plain text password.
+    ///
+    /// # Errors
+    ///
+    /// When the parameters are not valid, or the password could not be hashed.
+    pub fn hash(&self, pass: &str) -> Result<String> {
+        match self {
+            Self::Argon2id {
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(*memory, *iterations, *parallelism, None)
+                    .map_err(|err| Error::Hash(err.to_string()))?;
+                let arg2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
+                let salt = SaltString::generate(&mut OsRng);
+                Ok(arg2
+                    .hash_password(pass.as_bytes(), &salt)
+                    .map_err(|err| Error::Hash(err.to_string()))?
+                    .to_string())
+            }
+            #[cfg(feature = "password_bcrypt")]
+            Self::Bcrypt { cost } => {
+                bcrypt::hash(pass, *cost).map_err(|err| Error::Hash(err.to_string()))
+            }
+            #[cfg(feature = "password_scrypt")]
+            Self::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(*log_n, *r, *p, scrypt::Params::RECOMMENDED_LEN)
+                    .map_err(|err| Error::Hash(err.to_string()))?;
+                let salt = SaltString::generate(&mut OsRng);
+                Ok(scrypt::Scrypt
+                    .hash_password_customized(pass.as_bytes(), None, None, params, &salt)
+                    .map_err(|err| Error::Hash(err.to_string()))?
+                    .to_string())
+            }
+        }
+    }
+
+    /// Returns `true` when the given hash was not made with this algorithm
+    /// and these parameters.
+    #[must_use]
+    pub fn needs_rehash(&self, hashed_password: &str) -> bool {
+        match self {
+            Self::Argon2id {
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let Ok(hash) = PasswordHash::new(hashed_password) else {
+                    return true;
+                };
+                let Ok(params) = Params::try_from(&hash) else {
+                    return true;
+                };
+                hash.algorithm != argon2::Algorithm::Argon2id.ident()
+                    || hash.version != Some(Version::V0x13.into())
+                    || params.m_cost() != *memory
+                    || params.t_cost() != *iterations
+                    || params.p_cost() != *parallelism
+            }
+            #[cfg(feature = "password_bcrypt")]
+            Self::Bcrypt { cost } => {
+                // `$2b$<cost>$<salt and hash>`
+                let mut parts = hashed_password.split('$').skip(1);
+                !(parts.next().is_some_and(|version| version.starts_with('2'))
+                    && parts.next().and_then(|c| c.parse::<u32>().ok()) == Some(*cost))
+            }
+            #[cfg(feature = "password_scrypt")]
+            Self::Scrypt { log_n, r, p } => {
+                let Ok(hash) = PasswordHash::new(hashed_password) else {
+                    return true;
+                };
+                let Ok(params) = scrypt::Params::try_from(&hash) else {
+                    return true;
+                };
+                hash.algorithm != scrypt::ALG_ID
+                    || params.log_n() != *log_n
+                    || params.r() != *r
+                    || params.p() != *p
+            }
+        }
+    }
+}
+
+impl PasswordHashing {
+    /// Verifies a plain text password against a hashed password, and hashes
+    /// the password again when the hash was not made with this algorithm and
+    /// these parameters.
+    ///
+    /// # Errors
+    ///
+    /// When the password matched but could not be hashed again.
+    ///
+    /// # Example
+    /// ```rust
+    /// use loco_rs::hash::{self, PasswordHashing, Verification};
+    ///
+    /// let policy = PasswordHashing::default();
+    /// let hashed = policy.hash("password").unwrap();
+    /// assert_eq!(
+    ///     policy.verify_and_maybe_rehash("password", &hashed).unwrap(),
+    ///     Verification::Valid
+    /// );
+    /// ```
+    pub fn verify_and_maybe_rehash(
+        &self,
+        pass: &str,
+        hashed_password: &str,
+    ) -> Result<Verification> {
+        if !verify_password(pass, hashed_password) {
+            return Ok(Verification::Invalid);
+        }
+        if self.needs_rehash(hashed_password) {
+            Ok(Verification::Rehashed(self.hash(pass)?))
+        } else {
+            Ok(Verification::Valid)
+        }
+    }
+}
+
+/// Hashes a plain text password with the default algorithm and parameters,
+/// and returns the hashed result. Use [`PasswordHashing::hash`] to follow the
+/// configuration.
 ///
 /// # Errors
 ///
@@ -19,20 +246,12 @@ use rand::{distr::Alphanumeric, rng, Rng};
 /// hash::hash_password("password-to-hash");
 /// ```
 pub fn hash_password(pass: &str) -> Result<String> {
-    let arg2 = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        argon2::Version::V0x13,
-        Params::default(),
-    );
-    let salt = SaltString::generate(&mut OsRng);
-
-    Ok(arg2
-        .hash_password(pass.as_bytes(), &salt)
-        .map_err(|err| Error::Hash(err.to_string()))?
-        .to_string())
+    PasswordHashing::default().hash(pass)
 }
 
-/// Verifies a plain text password against a hashed password.
+/// Verifies a plain text password against a hashed password. Argon2 hashes
+/// are always supported, bcrypt and scrypt hashes with the
+/// `password_bcrypt` and `password_scrypt` features.
 ///
 /// # Errors
 ///
@@ -46,15 +265,45 @@ pub fn hash_password(pass: &str) -> Result<String> {
 /// ```
 #[must_use]
 pub fn verify_password(pass: &str, hashed_password: &str) -> bool {
-    let arg2 = Argon2::new(
-        argon2::Algorithm::Argon2id,
-        Version::V0x13,
-        Params::default(),
-    );
+    if hashed_password.starts_with("$2") {
+        #[cfg(feature = "password_bcrypt")]
+        return bcrypt::verify(pass, hashed_password).unwrap_or(false);
+        #[cfg(not(feature = "password_bcrypt"))]
+        return false;
+    }
+
     let Ok(hash) = PasswordHash::new(hashed_password) else {
         return false;
     };
-    arg2.verify_password(pass.as_bytes(), &hash).is_ok()
+    #[cfg(feature = "password_scrypt")]
+    if hash.algorithm == scrypt::ALG_ID {
+        return scrypt::Scrypt
+            .verify_password(pass.as_bytes(), &hash)
+            .is_ok();
+    }
+    Argon2::default()
+        .verify_password(pass.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// The result of [`PasswordHashing::verify_and_maybe_rehash`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// The password does not match the hash
+    Invalid,
+    /// The password matches the hash
+    Valid,
+    /// The password matches the hash, which was made with another algorithm
+    /// or other parameters. Store this new hash in its place.
+    Rehashed(String),
+}
+
+impl Verification {
+    /// Returns `true` when the password matched.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Self::Invalid)
+    }
 }
 
 /// Generates a random alphanumeric string of the specified length.
@@ -77,11 +326,41 @@ pub fn random_string(length: usize) -> String {
         .collect()
 }
 
+/// Compares two secrets in constant time, so that the time taken does not
+/// reveal how much of them matches.
+///
+/// # Example
+///
+/// ```rust
+/// use loco_rs::hash;
+///
+/// assert!(hash::constant_time_eq("secret", "secret"));
+/// assert!(!hash::constant_time_eq("secret", "secreT"));
+/// assert!(!hash::constant_time_eq("secret", "secrets"));
+/// ```
+#[must_use]
+pub fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
+    fn argon2(memory: u32, iterations: u32) -> PasswordHashing {
+        PasswordHashing::Argon2id {
+            memory,
+            iterations,
+            parallelism: 1,
+        }
+    }
+
     #[test]
     fn can_hah_password() {
         let pass = "password-1234";
@@ -91,6 +370,106 @@ mod tests {
         assert!(verify_password(pass, &hash_pass));
     }
 
+    #[test]
+    fn can_detect_changed_parameters() {
+        let hashed = argon2(1024, 1).hash("password-1234").unwrap();
+        assert!(verify_password("password-1234", &hashed));
+        assert!(!verify_password("password-4321", &hashed));
+
+        assert!(!argon2(1024, 1).needs_rehash(&hashed));
+        assert!(argon2(2048, 1).needs_rehash(&hashed));
+        assert!(argon2(1024, 2).needs_rehash(&hashed));
+        assert!(argon2(1024, 1).needs_rehash("not-a-hash"));
+        assert!(PasswordHashing::default().needs_rehash(&hashed));
+        assert!(!PasswordHashing::default()
+            .needs_rehash(&PasswordHashing::default().hash("password-1234").unwrap()));
+    }
+
+    #[test]
+    fn can_rehash_legacy_hashes() {
+        let legacy = argon2(1024, 1).hash("password-1234").unwrap();
+
+        let policy = argon2(2048, 1);
+
+        assert_eq!(
+            policy
+                .verify_and_maybe_rehash("password-4321", &legacy)
+                .unwrap(),
+            Verification::Invalid
+        );
+        let Verification::Rehashed(upgraded) = policy
+            .verify_and_maybe_rehash("password-1234", &legacy)
+            .unwrap()
+        else {
+            panic!("expected the hash to be upgraded");
+        };
+        assert!(verify_password("password-1234", &upgraded));
+        assert_eq!(
+            policy
+                .verify_and_maybe_rehash("password-1234", &upgraded)
+                .unwrap(),
+            Verification::Valid
+        );
+    }
+
+    #[test]
+    fn can_read_policy_from_config() {
+        let mut config = crate::tests_cfg::config::test_config();
+        assert_eq!(
+            PasswordHashing::from_config(&config),
+            PasswordHashing::default()
+        );
+
+        config.auth = Some(
+            serde_json::from_value(serde_json::json!({
+                "password": {"algorithm": "argon2id", "memory": 1024, "iterations": 1}
+            }))
+            .unwrap(),
+        );
+        assert_eq!(PasswordHashing::from_config(&config), argon2(1024, 1));
+    }
+
+    #[test]
+    fn can_deserialize_policy() {
+        let policy: PasswordHashing =
+            serde_json::from_value(serde_json::json!({"algorithm": "argon2id", "memory": 1024}))
+                .unwrap();
+        assert_eq!(policy, argon2(1024, Params::DEFAULT_T_COST));
+    }
+
+    #[cfg(feature = "password_bcrypt")]
+    #[test]
+    fn can_verify_bcrypt_hashes() {
+        // a `$2a$` hash of `password`, as stored by Rails' `has_secure_password`
+        let rails = "$2a$04$UuTkLRZZ6QofpDOlMz32MuuxEHA43WOemOYHPz6.SjsVsyO1tDU96";
+        assert!(verify_password("password", rails));
+        assert!(!verify_password("passwork", rails));
+
+        let policy = PasswordHashing::Bcrypt { cost: 4 };
+        assert!(!policy.needs_rehash(rails));
+        assert!(PasswordHashing::Bcrypt { cost: 5 }.needs_rehash(rails));
+        assert!(PasswordHashing::default().needs_rehash(rails));
+        assert!(verify_password(
+            "password",
+            &policy.hash("password").unwrap()
+        ));
+    }
+
+    #[cfg(feature = "password_scrypt")]
+    #[test]
+    fn can_verify_scrypt_hashes() {
+        let policy = PasswordHashing::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        };
+        let hashed = policy.hash("password").unwrap();
+        assert!(verify_password("password", &hashed));
+        assert!(!verify_password("passwork", &hashed));
+        assert!(!policy.needs_rehash(&hashed));
+        assert!(PasswordHashing::default().needs_rehash(&hashed));
+    }
+
     #[test]
     fn can_random_string() {
         let random_length = 32;
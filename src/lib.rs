@@ -36,6 +36,8 @@ pub mod hash;
 pub mod logger;
 pub mod mailer;
 pub mod scheduler;
+#[cfg(feature = "session")]
+pub mod session;
 pub mod task;
 #[cfg(feature = "testing")]
 pub mod testing;
@@ -0,0 +1,207 @@
+//! Email attachments, either sent as a file or shown inline in the HTML body.
+//!
+//! Attachment contents are serialized into the mailer queue as base64. Large
+//! files should be uploaded to the application storage first and attached by
+//! their storage path, so that only the path goes through the queue and the
+//! contents are read when the email is sent.
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{storage::Storage, Error, Result};
+
+/// The contents of an [`Attachment`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentContent {
+    /// The file contents, serialized as base64
+    Bytes(#[serde(with = "base64_bytes")] Vec<u8>),
+    /// A path in the application storage, read when the email is sent
+    Storage(PathBuf),
+}
+
+/// A file attached to an email.
+///
+/// ```
+/// use loco_rs::mailer::Attachment;
+///
+/// let invoice = Attachment::storage("invoice.pdf", "application/pdf", "invoices/42.pdf");
+/// let logo = Attachment::bytes("logo.png", "image/png", vec![0u8; 8]).inline("logo");
+/// // referenced from the HTML body as <img src="cid:logo">
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// File name shown to the recipient
+    pub filename: String,
+    /// MIME type, such as `application/pdf`
+    pub content_type: String,
+    /// When set, the attachment is shown inline, and referenced from the HTML
+    /// body as `cid:<content_id>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+    pub content: AttachmentContent,
+}
+
+impl Attachment {
+    /// Creates an attachment with the given contents.
+    #[must_use]
+    pub fn bytes(filename: &str, content_type: &str, content: impl Into<Vec<u8>>) -> Self {
+        Self {
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            content_id: None,
+            content: AttachmentContent::Bytes(content.into()),
+        }
+    }
+
+    /// Creates an attachment read from the given storage path when the email
+    /// is sent.
+    #[must_use]
+    pub fn storage(filename: &str, content_type: &str, path: impl Into<PathBuf>) -> Self {
+        Self {
+            filename: filename.to_string(),
+            content_type: content_type.to_string(),
+            content_id: None,
+            content: AttachmentContent::Storage(path.into()),
+        }
+    }
+
+    /// Shows the attachment inline, with the given content id.
+    #[must_use]
+    pub fn inline(mut self, content_id: &str) -> Self {
+        self.content_id = Some(content_id.to_string());
+        self
+    }
+
+    /// Returns the size of the contents kept in the attachment itself, which
+    /// is `0` for storage attachments.
+    #[must_use]
+    pub fn inline_size(&self) -> usize {
+        match &self.content {
+            AttachmentContent::Bytes(bytes) => bytes.len(),
+            AttachmentContent::Storage(_) => 0,
+        }
+    }
+
+    /// Reads the contents of a storage attachment.
+    ///
+    /// # Errors
+    ///
+    /// When the contents could not be read from the storage.
+    pub async fn load(self, storage: &Storage) -> Result<Self> {
+        match &self.content {
+            AttachmentContent::Bytes(_) => Ok(self),
+            AttachmentContent::Storage(path) => {
+                let content: Vec<u8> = storage.download(Path::new(path)).await?;
+                Ok(Self {
+                    content: AttachmentContent::Bytes(content),
+                    ..self
+                })
+            }
+        }
+    }
+
+    pub(crate) fn to_part(&self) -> Result<lettre::message::SinglePart> {
+        let AttachmentContent::Bytes(content) = &self.content else {
+            return Err(Error::Message(format!(
+                "attachment `{}` was not loaded from the storage",
+                self.filename
+            )));
+        };
+        let content_type = lettre::message::header::ContentType::parse(&self.content_type)
+            .map_err(|err| {
+                Error::Message(format!(
+                    "invalid content type `{}` for attachment `{}`: {err}",
+                    self.content_type, self.filename
+                ))
+            })?;
+        let attachment = self.content_id.as_ref().map_or_else(
+            || lettre::message::Attachment::new(self.filename.clone()),
+            |content_id| lettre::message::Attachment::new_inline(content_id.clone()),
+        );
+        Ok(attachment.body(content.clone(), content_type))
+    }
+}
+
+pub(super) mod base64_bytes {
+    use super::{Deserialize, Deserializer, Engine, Serializer, STANDARD};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::storage::drivers::mem;
+
+    #[test]
+    fn serializes_contents_as_base64() {
+        let attachment = Attachment::bytes("a.txt", "text/plain", "loco").inline("a");
+        let value = serde_json::to_value(&attachment).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "filename": "a.txt",
+                "content_type": "text/plain",
+                "content_id": "a",
+                "content": {"bytes": "bG9jbw=="}
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<Attachment>(value).unwrap(),
+            attachment
+        );
+
+        let stored = Attachment::storage("a.pdf", "application/pdf", "invoices/1.pdf");
+        assert_eq!(
+            serde_json::to_value(&stored).unwrap()["content"],
+            serde_json::json!({"storage": "invoices/1.pdf"})
+        );
+        assert_eq!(stored.inline_size(), 0);
+    }
+
+    #[tokio::test]
+    async fn can_load_from_storage() {
+        let storage = Storage::single(mem::new());
+        storage
+            .upload(Path::new("invoices/1.pdf"), &Bytes::from("%PDF"))
+            .await
+            .unwrap();
+
+        let attachment = Attachment::storage("a.pdf", "application/pdf", "invoices/1.pdf")
+            .load(&storage)
+            .await
+            .unwrap();
+        assert_eq!(
+            attachment.content,
+            AttachmentContent::Bytes(b"%PDF".to_vec())
+        );
+
+        assert!(
+            Attachment::storage("b.pdf", "application/pdf", "missing.pdf")
+                .load(&storage)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_unloaded_or_invalid_attachments() {
+        assert!(Attachment::storage("a.pdf", "application/pdf", "a.pdf")
+            .to_part()
+            .is_err());
+        assert!(Attachment::bytes("a.pdf", "not a type", "x")
+            .to_part()
+            .is_err());
+    }
+}
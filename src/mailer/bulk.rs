@@ -0,0 +1,464 @@
+//! Bulk sending of a template to many recipients, such as a newsletter.
+//!
+//! [`super::Mailer::mail_bulk`] renders the template for each recipient with
+//! its own locals, and enqueues the emails in jobs of `mailer.bulk.chunk_size`
+//! emails. A [`BulkMailerWorker`] sends the emails of its chunk one after
+//! another, reusing a pooled SMTP connection, and waits between them to honor
+//! `mailer.bulk.rate_limit`.
+//!
+//! ```rust,ignore
+//! let recipients = users
+//!     .iter()
+//!     .map(|user| Recipient::new(&user.email, json!({"name": user.name})))
+//!     .collect();
+//! let id = NewsletterMailer::mail_bulk(
+//!     &ctx,
+//!     &newsletter,
+//!     BulkArgs {
+//!         locals: json!({"issue": 42}),
+//!         recipients,
+//!         ..Default::default()
+//!     },
+//! )
+//! .await?;
+//!
+//! // later on
+//! let progress = bulk::progress(&ctx, &id).await?;
+//! ```
+//!
+//! The progress is counted in the cache, and is not available with the
+//! `null` cache.
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{error, warn};
+
+use super::{Attachment, Email};
+use crate::{app::AppContext, bgworker::BackgroundWorker, Error, Result};
+
+/// How long the progress of a bulk send is kept in the cache.
+const PROGRESS_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A recipient of a bulk email.
+#[derive(Debug, Clone, Default)]
+pub struct Recipient {
+    /// Mailbox to `To` header
+    pub to: String,
+    /// Locals of this recipient, merged over the shared [`BulkArgs::locals`]
+    pub locals: Value,
+    /// Renders the localized template files for this recipient, instead of
+    /// [`BulkArgs::locale`]
+    pub locale: Option<String>,
+}
+
+impl Recipient {
+    #[must_use]
+    pub fn new(to: impl Into<String>, locals: Value) -> Self {
+        Self {
+            to: to.into(),
+            locals,
+            locale: None,
+        }
+    }
+}
+
+/// The arguments of [`super::Mailer::mail_bulk`].
+#[derive(Debug, Clone, Default)]
+pub struct BulkArgs {
+    pub from: Option<String>,
+    pub reply_to: Option<String>,
+    /// Locals shared by all the recipients
+    pub locals: Value,
+    /// Attached to every email, prefer [`Attachment::storage`] as the
+    /// attachments are copied into each email of the queue payloads
+    pub attachments: Vec<Attachment>,
+    pub locale: Option<String>,
+    pub recipients: Vec<Recipient>,
+}
+
+/// The payload of a [`BulkMailerWorker`] job.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BulkChunk {
+    /// The id of the bulk send
+    pub id: String,
+    /// The position of the chunk in the bulk send
+    pub index: usize,
+    pub emails: Vec<Email>,
+}
+
+/// The progress of a bulk send.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub total: usize,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+impl Progress {
+    /// Returns `true` when every email was sent or failed.
+    #[must_use]
+    pub const fn is_done(&self) -> bool {
+        self.sent + self.failed >= self.total
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Batch {
+    total: usize,
+    chunks: usize,
+}
+
+fn batch_key(id: &str) -> String {
+    format!("loco:mailer:bulk:{id}")
+}
+
+fn chunk_key(id: &str, index: usize) -> String {
+    format!("loco:mailer:bulk:{id}:{index}")
+}
+
+/// Merges the locals of a recipient over the shared locals.
+pub(super) fn merge_locals(shared: &Value, own: &Value) -> Value {
+    let mut locals = serde_json::Map::new();
+    for value in [shared, own] {
+        match value {
+            Value::Object(map) => locals.extend(map.clone()),
+            Value::Null => {}
+            // rendering reports the invalid locals
+            other => return other.clone(),
+        }
+    }
+    Value::Object(locals)
+}
+
+/// Starts counting the progress of a new bulk send, and returns its id.
+pub(super) async fn start(ctx: &AppContext, total: usize, chunks: usize) -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    if let Err(err) = ctx
+        .cache
+        .insert_with_expiry(&batch_key(&id), &Batch { total, chunks }, PROGRESS_EXPIRY)
+        .await
+    {
+        warn!(
+            err = err.to_string(),
+            bulk = id,
+            "could not record the bulk progress"
+        );
+    }
+    id
+}
+
+/// Returns the progress of a bulk send, or `None` when it is unknown.
+///
+/// # Errors
+///
+/// When the cache could not be read.
+pub async fn progress(ctx: &AppContext, id: &str) -> Result<Option<Progress>> {
+    let Some(batch) = ctx.cache.get::<Batch>(&batch_key(id)).await? else {
+        return Ok(None);
+    };
+    let mut progress = Progress {
+        total: batch.total,
+        ..Default::default()
+    };
+    for index in 0..batch.chunks {
+        if let Some(chunk) = ctx.cache.get::<Progress>(&chunk_key(id, index)).await? {
+            progress.sent += chunk.sent;
+            progress.failed += chunk.failed;
+        }
+    }
+    Ok(Some(progress))
+}
+
+/// Spaces out the emails sent by a process to the configured rate.
+pub(crate) struct Throttle {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(rate_limit: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate_limit.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the turn of the next email.
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            tokio::time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+/// Creates the throttle shared by the bulk jobs of the process when
+/// `mailer.bulk.rate_limit` is configured. Called once, when creating the
+/// context.
+pub(crate) fn register_throttle(ctx: &AppContext) {
+    if let Some(rate_limit) = ctx
+        .config
+        .mailer
+        .as_ref()
+        .and_then(|config| config.bulk.as_ref())
+        .and_then(|bulk| bulk.rate_limit)
+    {
+        ctx.shared_store.insert(Arc::new(Throttle::new(rate_limit)));
+    }
+}
+
+/// Sends the emails of a [`BulkChunk`].
+#[allow(clippy::module_name_repetitions)]
+pub struct BulkMailerWorker {
+    pub ctx: AppContext,
+}
+
+#[async_trait]
+impl BackgroundWorker<BulkChunk> for BulkMailerWorker {
+    fn queue() -> Option<String> {
+        Some("mailer".to_string())
+    }
+
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    /// Sends the emails one after another. A failed email is logged and
+    /// counted, and does not stop the others: retrying the job would send the
+    /// emails already sent again.
+    async fn perform(&self, chunk: BulkChunk) -> Result<()> {
+        let Some(mailer) = &self.ctx.mailer else {
+            let err = Error::Message(
+                "attempting to send email but no email sender configured".to_string(),
+            );
+            error!(err = err.to_string(), "mailer error");
+            return Err(err);
+        };
+        let throttle = self.ctx.shared_store.get::<Arc<Throttle>>();
+
+        let mut progress = Progress {
+            total: chunk.emails.len(),
+            ..Default::default()
+        };
+        for email in chunk.emails {
+            if let Some(throttle) = &throttle {
+                throttle.wait().await;
+            }
+            let to = email.to.clone();
+            let res = match email.load_attachments(&self.ctx.storage).await {
+                Ok(email) => super::deliver(&self.ctx, mailer, &email).await,
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(()) => progress.sent += 1,
+                Err(err) => {
+                    progress.failed += 1;
+                    error!(
+                        err = err.to_string(),
+                        bulk = chunk.id,
+                        to,
+                        "bulk mailer error"
+                    );
+                }
+            }
+
+            if let Err(err) = self
+                .ctx
+                .cache
+                .insert_with_expiry(
+                    &chunk_key(&chunk.id, chunk.index),
+                    &progress,
+                    PROGRESS_EXPIRY,
+                )
+                .await
+            {
+                warn!(
+                    err = err.to_string(),
+                    bulk = chunk.id,
+                    "could not record the bulk progress"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use include_dir::{include_dir, Dir};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        config,
+        mailer::{EmailSender, Mailer, MailerOpts},
+        tests_cfg,
+    };
+
+    static TEST: Dir<'_> = include_dir!("tests/fixtures/email_template/test");
+    static LOCALIZED: Dir<'_> = include_dir!("tests/fixtures/email_template/localized");
+    static LAYOUTS: Dir<'_> = include_dir!("tests/fixtures/email_template/layouts");
+
+    struct Newsletter;
+
+    impl Mailer for Newsletter {
+        fn opts() -> MailerOpts {
+            MailerOpts {
+                from: "News <news@example.com>".to_string(),
+                layouts: Some(&LAYOUTS),
+                ..Default::default()
+            }
+        }
+    }
+
+    async fn context() -> AppContext {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        ctx.mailer = Some(EmailSender::stub());
+        ctx.config.mailer = Some(config::Mailer {
+            bulk: Some(config::BulkMailer {
+                chunk_size: 2,
+                rate_limit: None,
+            }),
+            ..Default::default()
+        });
+        ctx
+    }
+
+    #[tokio::test]
+    async fn can_send_in_chunks() {
+        let ctx = context().await;
+        let recipients = ["jane", "joe", "not an address", "ann", "bob"]
+            .iter()
+            .map(|name| Recipient::new(format!("{name}@example.com"), json!({"name": name})))
+            .collect();
+
+        let id = Newsletter::mail_bulk(
+            &ctx,
+            &TEST,
+            BulkArgs {
+                locals: json!({"verifyToken": "1234", "name": "shared"}),
+                recipients,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let deliveries = ctx.mailer.as_ref().unwrap().deliveries();
+        assert_eq!(deliveries.count, 4);
+        for (message, name) in deliveries
+            .messages
+            .iter()
+            .zip(["jane", "joe", "ann", "bob"])
+        {
+            assert!(message.contains("From: News <news@example.com>"));
+            assert!(message.contains(&format!("To: {name}@example.com")));
+            assert!(message.contains(&format!("Welcome to test: {name},")));
+            assert!(message.contains("/verify/1234"));
+        }
+
+        // the `null` cache keeps no progress
+        let expected = cfg!(feature = "cache_inmem").then_some(Progress {
+            total: 5,
+            sent: 4,
+            failed: 1,
+        });
+        assert_eq!(progress(&ctx, &id).await.unwrap(), expected);
+        assert_eq!(progress(&ctx, "unknown").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn cannot_send_when_an_email_fails_to_render() {
+        let ctx = context().await;
+        let mut recipients = ["jane", "joe", "ann"]
+            .iter()
+            .map(|name| Recipient::new(format!("{name}@example.com"), json!({"name": name})))
+            .collect::<Vec<_>>();
+        recipients.push(Recipient::new("bob@example.com", json!("not an object")));
+
+        let res = Newsletter::mail_bulk(
+            &ctx,
+            &TEST,
+            BulkArgs {
+                locals: json!({"verifyToken": "1234"}),
+                recipients,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(ctx.mailer.as_ref().unwrap().deliveries().count, 0);
+    }
+
+    #[tokio::test]
+    async fn can_send_localized_emails() {
+        let ctx = context().await;
+        Newsletter::mail_bulk(
+            &ctx,
+            &LOCALIZED,
+            BulkArgs {
+                locale: Some("de-DE".to_string()),
+                recipients: vec![
+                    Recipient::new("jane@example.com", json!({"name": "Jane"})),
+                    Recipient {
+                        locale: Some("en".to_string()),
+                        ..Recipient::new("joe@example.com", json!({"name": "Joe"}))
+                    },
+                    Recipient::new("ann@example.com", json!({"name": "Ann"})),
+                ],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let messages = ctx.mailer.as_ref().unwrap().deliveries().messages;
+        assert!(messages[0].contains("Willkommen Jane (de-DE)"));
+        assert!(messages[1].contains("Welcome Joe<footer>"));
+        assert!(messages[2].contains("Willkommen Ann (de-DE)"));
+    }
+
+    #[test]
+    fn can_merge_locals() {
+        assert_eq!(
+            merge_locals(&json!({"a": 1, "b": 1}), &json!({"b": 2})),
+            json!({"a": 1, "b": 2})
+        );
+        assert_eq!(merge_locals(&Value::Null, &Value::Null), json!({}));
+    }
+
+    #[tokio::test]
+    async fn can_throttle() {
+        let mut ctx = context().await;
+        if let Some(config) = ctx.config.mailer.as_mut() {
+            config.bulk = Some(config::BulkMailer {
+                chunk_size: 2,
+                rate_limit: Some(50),
+            });
+        }
+        register_throttle(&ctx);
+
+        let started = Instant::now();
+        Newsletter::mail_bulk(
+            &ctx,
+            &TEST,
+            BulkArgs {
+                locals: json!({"verifyToken": "1234", "name": "Jane"}),
+                recipients: (0..4)
+                    .map(|i| Recipient::new(format!("user{i}@example.com"), json!({})))
+                    .collect(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // 20ms between the emails, across the chunks
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert_eq!(ctx.mailer.as_ref().unwrap().deliveries().count, 4);
+    }
+}
@@ -0,0 +1,747 @@
+//! A development transport which keeps the sent emails on disk instead of
+//! delivering them, along with routes to browse them.
+//!
+//! Each email is stored in its own directory, as the raw `message.eml`, its
+//! `mail.json` metadata and the attachment contents:
+//!
+//! ```text
+//! tmp/mails/
+//! └── 20240101120000123456-1a2b3c4d/
+//!     ├── mail.json
+//!     ├── message.eml
+//!     └── attachments/
+//!         └── 0
+//! ```
+//!
+//! Mount [`routes`] to see them at [`DEFAULT_ROUTE_PREFIX`]. The routes answer
+//! `404` unless `mailer.catcher` is configured. Caught HTML bodies and
+//! attachments are served in a sandbox, so that scripts they contain can't
+//! act on the application.
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path as AxumPath, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use lettre::Message;
+use serde::{Deserialize, Serialize};
+
+use super::{AttachmentContent, Email, MailTransport, DEFAULT_FROM_SENDER};
+use crate::{
+    app::AppContext,
+    config,
+    controller::{middleware::csrf, ErrorDetail, Routes},
+    hash, Error, Result,
+};
+
+/// The path the mail catcher routes are mounted on.
+pub const DEFAULT_ROUTE_PREFIX: &str = "/_loco/mails";
+
+const MAIL_FILE: &str = "mail.json";
+const MESSAGE_FILE: &str = "message.eml";
+const ATTACHMENTS_DIR: &str = "attachments";
+/// The cookie keeping the token which the form deleting the emails must send.
+const CLEAR_TOKEN_COOKIE: &str = "loco_mails_token";
+const CLEAR_TOKEN_LENGTH: usize = 32;
+/// Served with caught content, which runs without the application origin.
+const SANDBOX: &str = "sandbox";
+
+/// The metadata of a caught attachment. Its contents are stored in the
+/// `attachments` directory of the email, named after its index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CaughtAttachment {
+    pub filename: String,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+    pub size: usize,
+}
+
+/// An email kept by the [`MailCatcher`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaughtMail {
+    pub id: String,
+    pub sent_at: chrono::DateTime<chrono::Utc>,
+    pub from: String,
+    pub to: String,
+    pub cc: Option<String>,
+    pub bcc: Option<String>,
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    #[serde(default)]
+    pub attachments: Vec<CaughtAttachment>,
+}
+
+/// A [`MailTransport`] writing every email to a directory.
+#[derive(Debug, Clone)]
+pub struct MailCatcher {
+    path: PathBuf,
+}
+
+impl MailCatcher {
+    /// Creates a catcher storing the emails in the given directory.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Creates a catcher from the `mailer.catcher` configuration.
+    ///
+    /// # Errors
+    ///
+    /// When the mail catcher is not configured.
+    pub fn from_config(config: &config::Config) -> Result<Self> {
+        config
+            .mailer
+            .as_ref()
+            .and_then(|mailer| mailer.catcher.as_ref())
+            .map(|catcher| Self::new(&catcher.path))
+            .ok_or(Error::NotFound)
+    }
+
+    /// Returns the directory of the caught emails.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn mail_dir(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(Error::NotFound);
+        }
+        Ok(self.path.join(id))
+    }
+
+    /// Stores an email and returns its metadata.
+    ///
+    /// # Errors
+    ///
+    /// When the email could not be written, or an attachment is not loaded.
+    pub async fn catch(&self, email: &Email, message: &Message) -> Result<CaughtMail> {
+        let sent_at = chrono::Utc::now();
+        let id = format!(
+            "{}-{}",
+            sent_at.format("%Y%m%d%H%M%S%6f"),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        let dir = self.path.join(&id);
+        tokio::fs::create_dir_all(dir.join(ATTACHMENTS_DIR)).await?;
+
+        let mut attachments = Vec::with_capacity(email.attachments.len());
+        for (index, attachment) in email.attachments.iter().enumerate() {
+            let AttachmentContent::Bytes(content) = &attachment.content else {
+                return Err(Error::Message(format!(
+                    "attachment `{}` was not loaded from the storage",
+                    attachment.filename
+                )));
+            };
+            tokio::fs::write(dir.join(ATTACHMENTS_DIR).join(index.to_string()), content).await?;
+            attachments.push(CaughtAttachment {
+                filename: attachment.filename.clone(),
+                content_type: attachment.content_type.clone(),
+                content_id: attachment.content_id.clone(),
+                size: content.len(),
+            });
+        }
+
+        let mail = CaughtMail {
+            id,
+            sent_at,
+            from: email
+                .from
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FROM_SENDER.to_string()),
+            to: email.to.clone(),
+            cc: email.cc.clone(),
+            bcc: email.bcc.clone(),
+            reply_to: email.reply_to.clone(),
+            subject: email.subject.clone(),
+            text: email.text.clone(),
+            html: email.html.clone(),
+            attachments,
+        };
+        tokio::fs::write(dir.join(MESSAGE_FILE), message.formatted()).await?;
+        tokio::fs::write(dir.join(MAIL_FILE), serde_json::to_vec_pretty(&mail)?).await?;
+        Ok(mail)
+    }
+
+    /// Lists the caught emails, newest first.
+    ///
+    /// # Errors
+    ///
+    /// When the directory could not be read.
+    pub async fn list(&self) -> Result<Vec<CaughtMail>> {
+        let mut entries = match tokio::fs::read_dir(&self.path).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut mails = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(content) = tokio::fs::read(entry.path().join(MAIL_FILE)).await else {
+                continue;
+            };
+            match serde_json::from_slice::<CaughtMail>(&content) {
+                Ok(mail) => mails.push(mail),
+                Err(err) => {
+                    tracing::warn!(err = %err, path = %entry.path().display(), "skipping unreadable caught mail");
+                }
+            }
+        }
+        mails.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(mails)
+    }
+
+    /// Finds a caught email.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::NotFound`] when there is no such email.
+    pub async fn find(&self, id: &str) -> Result<CaughtMail> {
+        let content = tokio::fs::read(self.mail_dir(id)?.join(MAIL_FILE))
+            .await
+            .map_err(|_| Error::NotFound)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Returns the raw message of a caught email.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::NotFound`] when there is no such email.
+    pub async fn message(&self, id: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.mail_dir(id)?.join(MESSAGE_FILE))
+            .await
+            .map_err(|_| Error::NotFound)
+    }
+
+    /// Returns an attachment of a caught email, and its contents.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::NotFound`] when there is no such email or attachment.
+    pub async fn attachment(&self, id: &str, index: usize) -> Result<(CaughtAttachment, Vec<u8>)> {
+        let mail = self.find(id).await?;
+        let attachment = mail
+            .attachments
+            .get(index)
+            .cloned()
+            .ok_or(Error::NotFound)?;
+        let content = tokio::fs::read(
+            self.mail_dir(id)?
+                .join(ATTACHMENTS_DIR)
+                .join(index.to_string()),
+        )
+        .await
+        .map_err(|_| Error::NotFound)?;
+        Ok((attachment, content))
+    }
+
+    /// Deletes all the caught emails.
+    ///
+    /// # Errors
+    ///
+    /// When the directory could not be removed.
+    pub async fn clear(&self) -> Result<()> {
+        match tokio::fs::remove_dir_all(&self.path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl MailTransport for MailCatcher {
+    async fn send(&self, email: &Email, message: &Message) -> Result<()> {
+        let mail = self.catch(email, message).await?;
+        tracing::info!(
+            id = mail.id,
+            to = mail.to,
+            subject = mail.subject,
+            "caught email, see {DEFAULT_ROUTE_PREFIX}/{}",
+            mail.id
+        );
+        Ok(())
+    }
+}
+
+pub(super) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(super) fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2rem; color: #222; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ text-align: left; padding: .4rem .6rem; border-bottom: 1px solid #ddd; vertical-align: top; }}
+iframe {{ width: 100%; height: 60vh; border: 1px solid #ddd; }}
+pre {{ white-space: pre-wrap; background: #f6f6f6; padding: 1rem; }}
+</style>
+</head>
+<body>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ClearParams {
+    token: String,
+}
+
+async fn list(State(ctx): State<AppContext>, jar: CookieJar) -> Result<(CookieJar, Html<String>)> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let mails = catcher.list().await?;
+
+    let token = jar
+        .get(CLEAR_TOKEN_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| token.len() == CLEAR_TOKEN_LENGTH)
+        .unwrap_or_else(|| hash::random_string(CLEAR_TOKEN_LENGTH));
+    let jar = jar.add(
+        Cookie::build((CLEAR_TOKEN_COOKIE, token.clone()))
+            .path(DEFAULT_ROUTE_PREFIX)
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .build(),
+    );
+    // the CSRF middleware checks its own token when it is enabled
+    let csrf_field = csrf::CsrfToken::current().map_or_else(String::new, |csrf_token| {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            csrf::FIELD_NAME,
+            escape(csrf_token.get())
+        )
+    });
+
+    let mut body = format!(
+        r#"<h1>Mails ({})</h1>
+<form method="post" action="{DEFAULT_ROUTE_PREFIX}/clear"><input type="hidden" name="token" value="{}">{csrf_field}<button>Delete all</button></form>
+<table>
+<tr><th>Sent at</th><th>From</th><th>To</th><th>Subject</th></tr>
+"#,
+        mails.len(),
+        escape(&token),
+    );
+    for mail in &mails {
+        let _ = writeln!(
+            body,
+            r#"<tr><td>{}</td><td>{}</td><td>{}</td><td><a href="{DEFAULT_ROUTE_PREFIX}/{}">{}</a></td></tr>"#,
+            mail.sent_at.format("%Y-%m-%d %H:%M:%S"),
+            escape(&mail.from),
+            escape(&mail.to),
+            mail.id,
+            escape(&mail.subject),
+        );
+    }
+    body.push_str("</table>");
+    Ok((jar, page("Mails", &body)))
+}
+
+async fn show(
+    State(ctx): State<AppContext>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Html<String>> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let mail = catcher.find(&id).await?;
+    let base = format!("{DEFAULT_ROUTE_PREFIX}/{}", mail.id);
+
+    let mut body = format!(
+        r#"<p><a href="{DEFAULT_ROUTE_PREFIX}">&larr; All mails</a></p>
+<h1>{}</h1>
+<table>
+"#,
+        escape(&mail.subject)
+    );
+    let headers = [
+        ("From", Some(&mail.from)),
+        ("To", Some(&mail.to)),
+        ("Cc", mail.cc.as_ref()),
+        ("Bcc", mail.bcc.as_ref()),
+        ("Reply-To", mail.reply_to.as_ref()),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            let _ = writeln!(body, "<tr><th>{name}</th><td>{}</td></tr>", escape(value));
+        }
+    }
+    let _ = writeln!(
+        body,
+        "<tr><th>Sent at</th><td>{}</td></tr>",
+        mail.sent_at.to_rfc2822()
+    );
+    if !mail.attachments.is_empty() {
+        body.push_str("<tr><th>Attachments</th><td>");
+        for (index, attachment) in mail.attachments.iter().enumerate() {
+            let _ = write!(
+                body,
+                r#"<a href="{base}/attachments/{index}">{}</a> ({}, {} bytes)<br>"#,
+                escape(&attachment.filename),
+                escape(&attachment.content_type),
+                attachment.size,
+            );
+        }
+        body.push_str("</td></tr>");
+    }
+    let _ = write!(
+        body,
+        r#"</table>
+<p><a href="{base}/html">HTML</a> | <a href="{base}/text">Text</a> | <a href="{base}/eml">Raw message</a></p>
+<iframe sandbox src="{base}/html"></iframe>
+<h2>Text</h2>
+<pre>{}</pre>"#,
+        escape(&mail.text)
+    );
+    Ok(page(&mail.subject, &body))
+}
+
+async fn html(State(ctx): State<AppContext>, AxumPath(id): AxumPath<String>) -> Result<Response> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let mail = catcher.find(&id).await?;
+
+    // show inline images from the attachment routes
+    let mut html = mail.html;
+    for (index, attachment) in mail.attachments.iter().enumerate() {
+        if let Some(content_id) = &attachment.content_id {
+            html = html.replace(
+                &format!("cid:{content_id}"),
+                &format!("{DEFAULT_ROUTE_PREFIX}/{}/attachments/{index}", mail.id),
+            );
+        }
+    }
+    Ok(([(header::CONTENT_SECURITY_POLICY, SANDBOX)], Html(html)).into_response())
+}
+
+async fn text(State(ctx): State<AppContext>, AxumPath(id): AxumPath<String>) -> Result<Response> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let mail = catcher.find(&id).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        mail.text,
+    )
+        .into_response())
+}
+
+async fn eml(State(ctx): State<AppContext>, AxumPath(id): AxumPath<String>) -> Result<Response> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let message = catcher.message(&id).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "message/rfc822".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{id}.eml\""),
+            ),
+        ],
+        message,
+    )
+        .into_response())
+}
+
+async fn attachment(
+    State(ctx): State<AppContext>,
+    AxumPath((id, index)): AxumPath<(String, usize)>,
+) -> Result<Response> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    let (attachment, content) = catcher.attachment(&id, index).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"",
+                    attachment.filename.replace('"', "")
+                ),
+            ),
+            (header::CONTENT_SECURITY_POLICY, SANDBOX.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+async fn clear(
+    State(ctx): State<AppContext>,
+    jar: CookieJar,
+    Form(params): Form<ClearParams>,
+) -> Result<Redirect> {
+    let catcher = MailCatcher::from_config(&ctx.config)?;
+    if !jar
+        .get(CLEAR_TOKEN_COOKIE)
+        .is_some_and(|cookie| hash::constant_time_eq(cookie.value(), &params.token))
+    {
+        return Err(Error::CustomError(
+            StatusCode::FORBIDDEN,
+            ErrorDetail::new("forbidden", "invalid token"),
+        ));
+    }
+    catcher.clear().await?;
+    Ok(Redirect::to(DEFAULT_ROUTE_PREFIX))
+}
+
+/// Returns the routes browsing the caught emails, mounted on
+/// [`DEFAULT_ROUTE_PREFIX`].
+///
+/// ```rust,ignore
+/// fn routes(ctx: &AppContext) -> AppRoutes {
+///     let routes = AppRoutes::with_default_routes().add_route(controllers::auth::routes());
+///     if ctx.environment == Environment::Development {
+///         return routes.add_route(loco_rs::mailer::catcher::routes());
+///     }
+///     routes
+/// }
+/// ```
+#[must_use]
+pub fn routes() -> Routes {
+    Routes::at(DEFAULT_ROUTE_PREFIX)
+        .add("/", get(list))
+        .add("/clear", post(clear))
+        .add("/{id}", get(show))
+        .add("/{id}/html", get(html))
+        .add("/{id}/text", get(text))
+        .add("/{id}/eml", get(eml))
+        .add("/{id}/attachments/{index}", get(attachment))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        mailer::{Attachment, EmailSender},
+        tests_cfg,
+    };
+
+    fn email() -> Email {
+        Email {
+            from: Some("test@framework.com".to_string()),
+            to: "user1@framework.com".to_string(),
+            subject: "Welcome <friend>".to_string(),
+            text: "Welcome".to_string(),
+            html: r#"<img src="cid:logo"> Welcome"#.to_string(),
+            attachments: vec![
+                Attachment::bytes("invoice.pdf", "application/pdf", "%PDF-1.4"),
+                Attachment::bytes("logo.png", "image/png", vec![0x89, b'P', b'N', b'G'])
+                    .inline("logo"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    async fn get_body(app: &Router, uri: &str) -> (u16, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[tokio::test]
+    async fn can_catch_and_list_emails() {
+        let tree = tree_fs::TreeBuilder::default().drop(true).create().unwrap();
+        let catcher = MailCatcher::new(tree.root.join("mails"));
+        assert!(catcher.list().await.unwrap().is_empty());
+
+        let sender = EmailSender::custom(catcher.clone());
+        sender.mail(&email()).await.unwrap();
+        sender
+            .mail(&Email {
+                subject: "Second".to_string(),
+                ..email()
+            })
+            .await
+            .unwrap();
+
+        let mails = catcher.list().await.unwrap();
+        assert_eq!(mails.len(), 2);
+        assert_eq!(mails[0].subject, "Second");
+        assert_eq!(mails[1].subject, "Welcome <friend>");
+        assert_eq!(
+            mails[1].attachments[1],
+            CaughtAttachment {
+                filename: "logo.png".to_string(),
+                content_type: "image/png".to_string(),
+                content_id: Some("logo".to_string()),
+                size: 4,
+            }
+        );
+
+        let message = String::from_utf8(catcher.message(&mails[0].id).await.unwrap()).unwrap();
+        assert!(message.contains("Subject: Second"));
+        let (_, content) = catcher.attachment(&mails[0].id, 0).await.unwrap();
+        assert_eq!(content, b"%PDF-1.4");
+
+        assert!(matches!(catcher.find("../etc").await, Err(Error::NotFound)));
+        assert!(matches!(
+            catcher.attachment(&mails[0].id, 2).await,
+            Err(Error::NotFound)
+        ));
+
+        catcher.clear().await.unwrap();
+        assert!(catcher.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_browse_caught_emails() {
+        let tree = tree_fs::TreeBuilder::default().drop(true).create().unwrap();
+        let mut ctx = tests_cfg::app::get_app_context().await;
+
+        let mut app = Router::new();
+        for handler in routes().handlers {
+            app = app.route(
+                &format!("{}{}", DEFAULT_ROUTE_PREFIX, handler.uri),
+                handler.method,
+            );
+        }
+        assert_eq!(
+            get_body(&app.clone().with_state(ctx.clone()), "/_loco/mails/")
+                .await
+                .0,
+            404
+        );
+
+        ctx.config.mailer = Some(config::Mailer {
+            catcher: Some(config::CatcherMailer {
+                path: tree.root.join("mails"),
+            }),
+            ..Default::default()
+        });
+        let catcher = MailCatcher::from_config(&ctx.config).unwrap();
+        EmailSender::custom(catcher.clone())
+            .mail(&email())
+            .await
+            .unwrap();
+        let id = catcher.list().await.unwrap()[0].id.clone();
+        let app = app.with_state(ctx);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/_loco/mails/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(cookie.contains("SameSite=Strict"));
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let token = cookie.split_once('=').unwrap().1.to_string();
+
+        let (status, body) = get_body(&app, "/_loco/mails/").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("Welcome &lt;friend&gt;"));
+        assert!(!body.contains(csrf::FIELD_NAME));
+        let (_, body) = csrf::CsrfToken::scope("csrf-token", get_body(&app, "/_loco/mails/")).await;
+        assert!(body.contains(r#"name="csrf_token" value="csrf-token""#));
+        assert!(body.contains(&format!("/_loco/mails/{id}")));
+
+        let (status, body) = get_body(&app, &format!("/_loco/mails/{id}")).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("invoice.pdf"));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/_loco/mails/{id}/html"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "sandbox"
+        );
+        let (status, body) = get_body(&app, &format!("/_loco/mails/{id}/html")).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            format!(r#"<img src="/_loco/mails/{id}/attachments/1"> Welcome"#)
+        );
+
+        let (status, body) = get_body(&app, &format!("/_loco/mails/{id}/text")).await;
+        assert_eq!((status, body.as_str()), (200, "Welcome"));
+
+        let (status, body) = get_body(&app, &format!("/_loco/mails/{id}/eml")).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("Content-Type: multipart/mixed"));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/_loco/mails/{id}/attachments/0"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            r#"attachment; filename="invoice.pdf""#
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "sandbox"
+        );
+        let (status, body) = get_body(&app, &format!("/_loco/mails/{id}/attachments/0")).await;
+        assert_eq!((status, body.as_str()), (200, "%PDF-1.4"));
+
+        assert_eq!(get_body(&app, "/_loco/mails/missing").await.0, 404);
+
+        // deleting the emails requires the token of the list page
+        let clear = |cookie: &str, token: &str| {
+            Request::post("/_loco/mails/clear")
+                .header(header::COOKIE, cookie)
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(format!("token={token}")))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(clear("", &token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(clear(&cookie, "forged")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(catcher.list().await.unwrap().len(), 1);
+
+        let response = app.clone().oneshot(clear(&cookie, &token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(catcher.list().await.unwrap().is_empty());
+    }
+}
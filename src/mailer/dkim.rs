@@ -0,0 +1,236 @@
+//! DKIM signing of the outgoing emails, configured under `mailer.dkim`.
+use lettre::message::{
+    dkim::{
+        DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm,
+        DkimSigningKey,
+    },
+    header::HeaderName,
+};
+
+use crate::{config, Error, Result};
+
+/// The signed headers. Relays commonly add headers, but leave these ones
+/// untouched. The multipart `Content-Type` is written by lettre after the
+/// message is signed, so it can't be listed.
+const SIGNED_HEADERS: [&str; 8] = [
+    "From",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "MIME-Version",
+];
+
+/// Reads the private key, and returns the signing configuration. Headers
+/// and body use the `relaxed` canonicalization, which survives the
+/// whitespace changes made by relays.
+///
+/// # Errors
+///
+/// When the private key could not be read or parsed.
+pub(super) fn signing_config(config: &config::DkimMailer) -> Result<DkimConfig> {
+    let private_key = std::fs::read_to_string(&config.private_key_path).map_err(|err| {
+        Error::Message(format!(
+            "could not read the DKIM private key `{}`: {err}",
+            config.private_key_path.display()
+        ))
+    })?;
+    let algorithm = match config.algorithm {
+        config::DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        config::DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let private_key = DkimSigningKey::new(private_key.trim(), algorithm)
+        .map_err(|err| Error::Message(format!("invalid DKIM private key: {err}")))?;
+
+    Ok(DkimConfig::new(
+        config.selector.clone(),
+        config.domain.clone(),
+        private_key,
+        SIGNED_HEADERS
+            .into_iter()
+            .map(HeaderName::new_from_ascii_str)
+            .collect(),
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use lettre::transport::stub::StubTransport;
+    use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs1v15::Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::mailer::{Email, EmailSender, EmailTransport};
+
+    fn fixture(name: &str) -> PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/dkim")
+            .join(name)
+    }
+
+    fn config() -> config::DkimMailer {
+        config::DkimMailer {
+            selector: "loco".to_string(),
+            domain: "example.com".to_string(),
+            private_key_path: fixture("rsa.pem"),
+            algorithm: config::DkimAlgorithm::Rsa,
+        }
+    }
+
+    /// Reduces the whitespace runs to a single space.
+    fn collapse(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c == ' ' || c == '\t' {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    /// Canonicalizes a header, per the `relaxed` canonicalization.
+    fn relaxed(name: &str, value: &str) -> String {
+        let value = collapse(&value.replace("\r\n", ""));
+        format!("{}:{}", name.to_lowercase(), value.trim())
+    }
+
+    /// Verifies the `DKIM-Signature` of a raw message with the public key of
+    /// the fixture.
+    fn verify(raw: &str) -> bool {
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+
+        // unfolded headers, keeping the folding whitespace
+        let mut headers: Vec<(String, String)> = vec![];
+        for line in head.split("\r\n") {
+            if line.starts_with([' ', '\t']) {
+                let last = headers.last_mut().unwrap();
+                last.1.push_str("\r\n");
+                last.1.push_str(line);
+            } else {
+                let (name, value) = line.split_once(':').unwrap();
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+
+        let signature = header("DKIM-Signature").unwrap();
+        let tags = signature
+            .replace("\r\n", "")
+            .split(';')
+            .filter_map(|tag| tag.split_once('='))
+            .map(|(name, value)| {
+                let value = value.chars().filter(|c| !c.is_whitespace());
+                (name.trim().to_string(), value.collect::<String>())
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(tags["a"], "rsa-sha256");
+        assert_eq!(tags["c"], "relaxed/relaxed");
+        assert_eq!(tags["d"], "example.com");
+        assert_eq!(tags["s"], "loco");
+
+        let mut canonical_body = body
+            .split("\r\n")
+            .map(|line| collapse(line).trim_end().to_string())
+            .collect::<Vec<_>>();
+        while canonical_body.last().is_some_and(String::is_empty) {
+            canonical_body.pop();
+        }
+        let canonical_body = canonical_body
+            .iter()
+            .map(|line| format!("{line}\r\n"))
+            .collect::<String>();
+        if tags["bh"] != STANDARD.encode(Sha256::digest(canonical_body)) {
+            return false;
+        }
+
+        let mut signed = String::new();
+        for name in tags["h"].split(':') {
+            if let Some(value) = header(name) {
+                signed.push_str(&relaxed(name, &value));
+                signed.push_str("\r\n");
+            }
+        }
+        let dkim_header = relaxed("DKIM-Signature", &signature);
+        let unsigned = &dkim_header[..dkim_header.rfind("; b=").unwrap() + 4];
+        signed.push_str(unsigned);
+
+        let key =
+            RsaPrivateKey::from_pkcs1_pem(&std::fs::read_to_string(fixture("rsa.pem")).unwrap())
+                .unwrap();
+        RsaPublicKey::from(&key)
+            .verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(signed),
+                &STANDARD.decode(&tags["b"]).unwrap(),
+            )
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn can_sign_emails() {
+        let stub = StubTransport::new_ok();
+        let sender = EmailSender::new(EmailTransport::Test(stub.clone()))
+            .dkim(&config())
+            .unwrap();
+
+        sender
+            .mail(&Email {
+                from: Some("Loco <hello@example.com>".to_string()),
+                to: "Jane <jane@example.com>, joe@example.com".to_string(),
+                cc: Some("audit@example.com".to_string()),
+                subject: "Your invoice is ready".to_string(),
+                text: "Your invoice  is ready  \r\n\r\n".to_string(),
+                html: "<p>Your invoice is ready</p>".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let messages = stub.messages();
+        let raw = &messages[0].1;
+        assert!(raw.contains("DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=loco;"));
+        assert!(verify(raw));
+
+        let tampered = raw.replace("Subject: Your invoice", "Subject: Your bill");
+        assert!(!verify(&tampered));
+    }
+
+    #[test]
+    fn cannot_read_invalid_keys() {
+        let missing = config::DkimMailer {
+            private_key_path: fixture("missing.pem"),
+            ..config()
+        };
+        assert!(signing_config(&missing)
+            .unwrap_err()
+            .to_string()
+            .contains("could not read the DKIM private key"));
+
+        let ed25519 = config::DkimMailer {
+            algorithm: config::DkimAlgorithm::Ed25519,
+            ..config()
+        };
+        assert!(signing_config(&ed25519)
+            .unwrap_err()
+            .to_string()
+            .contains("invalid DKIM private key"));
+    }
+}
@@ -2,8 +2,11 @@
 //! either the SMTP protocol. It includes an asynchronous method `mail` for
 //! sending emails with options like sender, recipient, subject, and content.
 
+use std::sync::Arc;
+
+use async_trait::async_trait;
 use lettre::{
-    message::MultiPart,
+    message::{Mailboxes, MultiPart, SinglePart},
     transport::smtp::{authentication::Credentials, extension::ClientId},
     AsyncTransport, Message, Tokio1Executor, Transport,
 };
@@ -12,6 +15,26 @@ use tracing::error;
 use super::{Email, Result, DEFAULT_FROM_SENDER};
 use crate::{config, errors::Error};
 
+/// Sends emails through a service of your choice. Set
+/// `ctx.mailer = Some(EmailSender::custom(..))` in the `after_context` hook to
+/// use it.
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    /// Sends the given email, along with its message built by
+    /// [`EmailSender::message`]. The attachments are loaded.
+    ///
+    /// # Errors
+    ///
+    /// When the email could not be sent.
+    async fn send(&self, email: &Email, message: &Message) -> Result<()>;
+}
+
+impl std::fmt::Debug for dyn MailTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MailTransport")
+    }
+}
+
 /// An enumeration representing the possible transport methods for sending
 /// emails.
 #[derive(Clone, Debug)]
@@ -20,13 +43,19 @@ pub enum EmailTransport {
     Smtp(lettre::AsyncSmtpTransport<lettre::Tokio1Executor>),
     /// Test/stub transport for testing purposes.
     Test(lettre::transport::stub::StubTransport),
+    /// A [`MailTransport`], such as the HTTP API providers.
+    Custom(Arc<dyn MailTransport>),
 }
 
 /// A structure representing the email sender, encapsulating the chosen
-/// transport method.
+/// transport method. Create it with [`EmailSender::new`] or one of the
+/// transport constructors.
 #[derive(Clone, Debug)]
 pub struct EmailSender {
     pub transport: EmailTransport,
+    /// Signs the messages with DKIM, see [`EmailSender::dkim`]
+    #[cfg(feature = "mailer_dkim")]
+    dkim: Option<Arc<lettre::message::dkim::DkimConfig>>,
 }
 
 #[cfg(feature = "testing")]
@@ -37,6 +66,17 @@ pub struct Deliveries {
 }
 
 impl EmailSender {
+    /// Creates a new `EmailSender` sending emails through the given
+    /// transport.
+    #[must_use]
+    pub const fn new(transport: EmailTransport) -> Self {
+        Self {
+            transport,
+            #[cfg(feature = "mailer_dkim")]
+            dkim: None,
+        }
+    }
+
     /// Creates a new `EmailSender` using the SMTP transport method based on the
     /// provided SMTP configuration.
     ///
@@ -65,16 +105,50 @@ impl EmailSender {
             email_builder = email_builder.hello_name(ClientId::Domain(hello_name.clone()));
         }
 
-        Ok(Self {
-            transport: EmailTransport::Smtp(email_builder.build()),
-        })
+        Ok(Self::new(EmailTransport::Smtp(email_builder.build())))
+    }
+
+    /// Signs the outgoing messages with DKIM.
+    ///
+    /// # Errors
+    ///
+    /// When the private key could not be read or parsed
+    #[cfg(feature = "mailer_dkim")]
+    pub fn dkim(mut self, config: &config::DkimMailer) -> Result<Self> {
+        self.dkim = Some(Arc::new(super::dkim::signing_config(config)?));
+        Ok(self)
+    }
+
+    /// Creates a new `EmailSender` sending emails through the given
+    /// transport.
+    #[must_use]
+    pub fn custom(transport: impl MailTransport + 'static) -> Self {
+        Self::new(EmailTransport::Custom(Arc::new(transport)))
+    }
+
+    /// Creates a new `EmailSender` sending emails through an HTTP API
+    /// provider.
+    ///
+    /// # Errors
+    ///
+    /// when could not initialize the HTTP client
+    #[cfg(feature = "mailer_http")]
+    pub fn http(config: &config::HttpMailer) -> Result<Self> {
+        Ok(Self::custom(super::http::HttpTransport::new(config)?))
+    }
+
+    /// Creates a new `EmailSender` keeping the emails on disk, see
+    /// [`super::catcher`].
+    #[must_use]
+    pub fn catcher(config: &config::CatcherMailer) -> Self {
+        Self::custom(super::catcher::MailCatcher::new(&config.path))
     }
 
     #[must_use]
     pub fn stub() -> Self {
-        Self {
-            transport: EmailTransport::Test(lettre::transport::stub::StubTransport::new_ok()),
-        }
+        Self::new(EmailTransport::Test(
+            lettre::transport::stub::StubTransport::new_ok(),
+        ))
     }
 
     #[cfg(feature = "testing")]
@@ -94,43 +168,101 @@ impl EmailSender {
         Deliveries::default()
     }
 
-    /// Sends an email using the configured transport method.
+    /// Builds the message body: the text and HTML alternatives, the inline
+    /// attachments related to the HTML, and the other attachments.
+    fn content(email: &Email) -> Result<MultiPart> {
+        let (inline, attached): (Vec<_>, Vec<_>) = email
+            .attachments
+            .iter()
+            .partition(|attachment| attachment.content_id.is_some());
+
+        let content = if inline.is_empty() {
+            MultiPart::alternative_plain_html(email.text.clone(), email.html.clone())
+        } else {
+            let mut related = MultiPart::related().singlepart(SinglePart::html(email.html.clone()));
+            for attachment in inline {
+                related = related.singlepart(attachment.to_part()?);
+            }
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(email.text.clone()))
+                .multipart(related)
+        };
+
+        if attached.is_empty() {
+            return Ok(content);
+        }
+        let mut mixed = MultiPart::mixed().multipart(content);
+        for attachment in attached {
+            mixed = mixed.singlepart(attachment.to_part()?);
+        }
+        Ok(mixed)
+    }
+
+    /// Builds the MIME message of an email.
     ///
     /// # Errors
     ///
-    /// When email doesn't send successfully or has an error to build the
-    /// message
-    pub async fn mail(&self, email: &Email) -> Result<()> {
-        let content = MultiPart::alternative_plain_html(email.text.clone(), email.html.clone());
-        let mut builder = Message::builder()
-            .from(
-                email
-                    .from
-                    .clone()
-                    .unwrap_or_else(|| DEFAULT_FROM_SENDER.to_string())
-                    .parse()?,
-            )
-            .to(email.to.parse()?);
+    /// When an address is invalid, or an attachment is not loaded
+    pub fn message(email: &Email) -> Result<Message> {
+        let content = Self::content(email)?;
+        let mut builder = Message::builder().from(
+            email
+                .from
+                .clone()
+                .unwrap_or_else(|| DEFAULT_FROM_SENDER.to_string())
+                .parse()?,
+        );
+
+        // `to`, `cc` and `bcc` accept comma separated lists of addresses. `to`
+        // is empty when only `cc` or `bcc` recipients remain.
+        if !email.to.trim().is_empty() {
+            for to in email.to.parse::<Mailboxes>()? {
+                builder = builder.to(to);
+            }
+        }
 
         if let Some(bcc) = &email.bcc {
-            builder = builder.bcc(bcc.parse()?);
+            for bcc in bcc.parse::<Mailboxes>()? {
+                builder = builder.bcc(bcc);
+            }
         }
 
         if let Some(cc) = &email.cc {
-            builder = builder.cc(cc.parse()?);
+            for cc in cc.parse::<Mailboxes>()? {
+                builder = builder.cc(cc);
+            }
         }
 
         if let Some(reply_to) = &email.reply_to {
             builder = builder.reply_to(reply_to.parse()?);
         }
 
-        let msg = builder
+        if let Some(message_id) = &email.message_id {
+            builder = builder.message_id(Some(format!("<{message_id}>")));
+        }
+
+        Ok(builder
             .subject(email.subject.clone())
             .multipart(content)
             .map_err(|error| {
                 error!(err.msg = %error, err.detail = ?error, "email_building_error");
                 error
-            })?;
+            })?)
+    }
+
+    /// Sends an email using the configured transport method.
+    ///
+    /// # Errors
+    ///
+    /// When email doesn't send successfully or has an error to build the
+    /// message
+    pub async fn mail(&self, email: &Email) -> Result<()> {
+        #[allow(unused_mut)]
+        let mut msg = Self::message(email)?;
+        #[cfg(feature = "mailer_dkim")]
+        if let Some(dkim) = &self.dkim {
+            msg.sign(dkim);
+        }
 
         match &self.transport {
             EmailTransport::Smtp(xp) => {
@@ -140,6 +272,9 @@ impl EmailSender {
                 xp.send(&msg)
                     .map_err(|e| Error::Message(format!("sending email error: {e}")))?;
             }
+            EmailTransport::Custom(xp) => {
+                xp.send(email, &msg).await?;
+            }
         }
         Ok(())
     }
@@ -152,14 +287,13 @@ mod tests {
     use lettre::transport::stub::StubTransport;
 
     use super::*;
+    use crate::mailer::Attachment;
 
     #[tokio::test]
     async fn can_send_email() {
         let stub = StubTransport::new_ok();
 
-        let sender = EmailSender {
-            transport: EmailTransport::Test(stub.clone()),
-        };
+        let sender = EmailSender::new(EmailTransport::Test(stub.clone()));
 
         let html = r"
 ;<html>
@@ -177,6 +311,8 @@ mod tests {
             html: html.to_string(),
             bcc: None,
             cc: None,
+            attachments: vec![],
+            message_id: None,
         };
         assert!(sender.mail(&data).await.is_ok());
 
@@ -187,4 +323,35 @@ mod tests {
             assert_debug_snapshot!(stub.messages());
         });
     }
+
+    #[tokio::test]
+    async fn can_send_email_with_attachments() {
+        let stub = StubTransport::new_ok();
+        let sender = EmailSender::new(EmailTransport::Test(stub.clone()));
+
+        let data = Email {
+            from: Some("test@framework.com".to_string()),
+            to: "user1@framework.com".to_string(),
+            subject: "Invoice".to_string(),
+            text: "Your invoice".to_string(),
+            html: r#"<img src="cid:logo"> Your invoice"#.to_string(),
+            attachments: vec![
+                Attachment::bytes("invoice.pdf", "application/pdf", "%PDF-1.4"),
+                Attachment::bytes("logo.png", "image/png", vec![0x89, b'P', b'N', b'G'])
+                    .inline("logo"),
+            ],
+            ..Default::default()
+        };
+        assert!(sender.mail(&data).await.is_ok());
+
+        let messages = stub.messages();
+        let message = &messages[0].1;
+        assert!(message.contains("Content-Type: multipart/mixed"));
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("Content-Type: multipart/related"));
+        assert!(message.contains("Content-Disposition: attachment; filename=\"invoice.pdf\""));
+        assert!(message.contains("Content-Disposition: inline"));
+        assert!(message.contains("Content-ID: <logo>"));
+        assert!(message.contains("%PDF-1.4"));
+    }
 }
@@ -0,0 +1,535 @@
+//! HTML email helpers: inlining `<style>` rules into `style` attributes, which
+//! many email clients require, and generating the plain text part from the
+//! HTML.
+//!
+//! Rules that cannot be inlined, such as media queries and pseudo classes
+//! like `a:hover`, are kept in a `<style>` element for the clients supporting
+//! them.
+use std::collections::HashMap;
+
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// A `property: value` pair of a CSS rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Declaration {
+    property: String,
+    value: String,
+    important: bool,
+}
+
+/// A CSS rule with a single selector.
+#[derive(Debug)]
+struct Rule {
+    selector: String,
+    declarations: Vec<Declaration>,
+}
+
+/// Splits `input` on `separator`, ignoring separators in quotes and
+/// parentheses, such as in `url(data:image/png;base64,...)`.
+fn split_top_level(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, _) if c == separator && depth == 0 => {
+                parts.push(&input[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+fn parse_declarations(block: &str) -> Vec<Declaration> {
+    split_top_level(block, ';')
+        .into_iter()
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            let property = property.trim().to_lowercase();
+            let mut value = value.trim();
+            let important = value.to_lowercase().ends_with("!important");
+            if important {
+                value = value[..value.len() - "!important".len()].trim_end();
+            }
+            if property.is_empty() || value.is_empty() {
+                return None;
+            }
+            Some(Declaration {
+                property,
+                value: value.to_string(),
+                important,
+            })
+        })
+        .collect()
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Splits a stylesheet into the rules to inline, and the CSS to keep in a
+/// `<style>` element.
+fn parse_stylesheet(css: &str) -> (Vec<Rule>, String) {
+    let css = strip_comments(css);
+    let mut rules = vec![];
+    let mut kept = String::new();
+
+    let mut rest = css.as_str();
+    while let Some(open) = rest.find('{') {
+        let prelude = rest[..open].trim();
+        let mut depth = 0usize;
+        let mut close = rest.len();
+        for (index, c) in rest[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = open + index;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let block = &rest[(open + 1).min(close)..close];
+        rest = rest.get(close + 1..).unwrap_or("");
+
+        if prelude.starts_with('@') {
+            kept.push_str(&format!("{prelude}{{{block}}}\n"));
+            continue;
+        }
+        let declarations = parse_declarations(block);
+        for selector in split_top_level(prelude, ',') {
+            let selector = selector.trim();
+            if selector.is_empty() {
+                continue;
+            }
+            if has_pseudo(selector) || Selector::parse(selector).is_err() {
+                kept.push_str(&format!("{selector}{{{block}}}\n"));
+            } else {
+                rules.push(Rule {
+                    selector: selector.to_string(),
+                    declarations: declarations.clone(),
+                });
+            }
+        }
+    }
+    (rules, kept)
+}
+
+/// Returns `selector` with the content of its attribute selectors removed, so
+/// `a[href^="https://a.b"]` becomes `a[]`.
+fn strip_attributes(selector: &str) -> String {
+    let mut stripped = String::with_capacity(selector.len());
+    let mut depth = 0usize;
+    let mut quote = None;
+    for c in selector.chars() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') if depth > 0 => quote = Some(c),
+            (None, '[') => {
+                depth += 1;
+                if depth == 1 {
+                    stripped.push(c);
+                }
+            }
+            (None, ']') => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    stripped.push(c);
+                }
+            }
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+/// Whether `selector` has pseudo classes or pseudo elements, such as `a:hover`
+/// or `p::before`, which depend on state or generate content and cannot be
+/// written as `style` attributes.
+fn has_pseudo(selector: &str) -> bool {
+    strip_attributes(selector).contains(':')
+}
+
+/// Returns the `(ids, classes and attributes, elements)` specificity of a
+/// selector without pseudo classes.
+fn specificity(selector: &str) -> (usize, usize, usize) {
+    let selector = strip_attributes(selector);
+    let selector = selector.as_str();
+    let ids = selector.matches('#').count();
+    let classes = selector.matches('.').count() + selector.matches('[').count();
+    let elements = selector
+        .split(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .filter(|compound| compound.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .count();
+    (ids, classes, elements)
+}
+
+/// Sets `declarations` in the `declared` list, replacing the earlier values of
+/// the same properties.
+fn apply(declared: &mut Vec<(String, String)>, declarations: &[&Declaration]) {
+    for declaration in declarations {
+        declared.retain(|(property, _)| property != &declaration.property);
+        declared.push((declaration.property.clone(), declaration.value.clone()));
+    }
+}
+
+/// Inlines the `<style>` rules of an HTML document into the `style`
+/// attributes of the matching elements. The `style` attributes already in the
+/// document take precedence over the rules, unless they are `!important`.
+#[must_use]
+pub fn inline_css(html: &str) -> String {
+    let mut document = Html::parse_document(html);
+    let style_selector = Selector::parse("style").expect("valid selector");
+
+    let mut css = String::new();
+    let mut style_ids = vec![];
+    for style in document.select(&style_selector) {
+        css.push_str(&style.text().collect::<String>());
+        css.push('\n');
+        style_ids.push(style.id());
+    }
+    if style_ids.is_empty() {
+        return html.to_string();
+    }
+    let (rules, kept) = parse_stylesheet(&css);
+
+    let mut matched: HashMap<_, Vec<_>> = HashMap::new();
+    for (order, rule) in rules.iter().enumerate() {
+        let Ok(selector) = Selector::parse(&rule.selector) else {
+            continue;
+        };
+        let specificity = specificity(&rule.selector);
+        for element in document.select(&selector) {
+            matched.entry(element.id()).or_default().extend(
+                rule.declarations
+                    .iter()
+                    .map(|declaration| (declaration.important, specificity, order, declaration)),
+            );
+        }
+    }
+
+    for (id, mut declarations) in matched {
+        declarations
+            .sort_by_key(|(important, specificity, order, _)| (*important, *specificity, *order));
+        let Some(mut node) = document.tree.get_mut(id) else {
+            continue;
+        };
+        let Node::Element(element) = node.value() else {
+            continue;
+        };
+        let inline = element
+            .attr("style")
+            .map(parse_declarations)
+            .unwrap_or_default();
+
+        let (important, normal): (Vec<_>, Vec<_>) = declarations
+            .iter()
+            .map(|(_, _, _, declaration)| *declaration)
+            .partition(|declaration| declaration.important);
+        let mut declared = vec![];
+        apply(&mut declared, &normal);
+        apply(&mut declared, &inline.iter().collect::<Vec<_>>());
+        apply(&mut declared, &important);
+
+        let style = declared
+            .iter()
+            .map(|(property, value)| format!("{property}: {value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let key = element
+            .attrs
+            .keys()
+            .find(|name| &*name.local == "style")
+            .cloned()
+            .unwrap_or_else(|| {
+                html5ever::QualName::new(
+                    None,
+                    html5ever::Namespace::from(""),
+                    html5ever::local_name!("style"),
+                )
+            });
+        element.attrs.insert(key, style.as_str().into());
+    }
+
+    // keep the rules which could not be inlined in the first `<style>`
+    for (index, id) in style_ids.into_iter().enumerate() {
+        let Some(mut style) = document.tree.get_mut(id) else {
+            continue;
+        };
+        if index > 0 || kept.is_empty() {
+            style.detach();
+            continue;
+        }
+        while let Some(mut child) = style.first_child() {
+            child.detach();
+        }
+        style.append(Node::Text(scraper::node::Text {
+            text: kept.as_str().into(),
+        }));
+    }
+
+    document.html()
+}
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "div",
+    "footer",
+    "header",
+    "main",
+    "nav",
+    "ol",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+const PARAGRAPH_ELEMENTS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6", "p", "pre"];
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "template", "title"];
+
+fn write_text(element: ElementRef<'_>, out: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => {
+                let mut words = text.split_whitespace().peekable();
+                if text.starts_with(char::is_whitespace) && words.peek().is_some() {
+                    out.push(' ');
+                }
+                while let Some(word) = words.next() {
+                    out.push_str(word);
+                    if words.peek().is_some() {
+                        out.push(' ');
+                    }
+                }
+                if text.ends_with(char::is_whitespace) {
+                    out.push(' ');
+                }
+            }
+            Node::Element(_) => {
+                if let Some(element) = ElementRef::wrap(child) {
+                    write_element(element, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_element(element: ElementRef<'_>, out: &mut String) {
+    let name = element.value().name();
+    if SKIPPED_ELEMENTS.contains(&name) {
+        return;
+    }
+    match name {
+        "br" => out.push('\n'),
+        "hr" => out.push_str("\n\n---\n\n"),
+        "img" => {
+            if let Some(alt) = element.value().attr("alt") {
+                out.push_str(alt);
+            }
+        }
+        "li" => {
+            out.push_str("\n- ");
+            write_text(element, out);
+            out.push('\n');
+        }
+        "td" | "th" => {
+            write_text(element, out);
+            out.push(' ');
+        }
+        "a" => {
+            let start = out.len();
+            write_text(element, out);
+            let label = out[start..].trim().to_string();
+            if let Some(href) = element.value().attr("href") {
+                if !href.starts_with('#') && !href.starts_with("mailto:") && label != href {
+                    if label.is_empty() {
+                        out.push_str(href);
+                    } else {
+                        out.push_str(&format!(" ({href})"));
+                    }
+                }
+            }
+        }
+        _ if PARAGRAPH_ELEMENTS.contains(&name) => {
+            out.push_str("\n\n");
+            write_text(element, out);
+            out.push_str("\n\n");
+        }
+        _ if BLOCK_ELEMENTS.contains(&name) => {
+            out.push('\n');
+            write_text(element, out);
+            out.push('\n');
+        }
+        _ => write_text(element, out),
+    }
+}
+
+/// Generates the plain text version of an HTML email: paragraphs and line
+/// breaks are kept, links are followed by their URL and images are replaced
+/// with their `alt` text.
+#[must_use]
+pub fn to_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut text = String::new();
+    write_element(document.root_element(), &mut text);
+
+    let mut lines: Vec<&str> = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() && lines.last().map_or(true, |last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_stylesheet() {
+        let (rules, kept) = parse_stylesheet(
+            "/* header */ h1, .title { color: red; font-weight: bold !important }
+             a:hover { color: blue }
+             @media (max-width: 600px) { h1 { font-size: 12px } }
+             .logo { background: url(data:image/png;base64,AAAA) }",
+        );
+        assert_eq!(
+            rules
+                .iter()
+                .map(|rule| rule.selector.as_str())
+                .collect::<Vec<_>>(),
+            vec!["h1", ".title", ".logo"]
+        );
+        assert_eq!(
+            rules[0].declarations,
+            vec![
+                Declaration {
+                    property: "color".to_string(),
+                    value: "red".to_string(),
+                    important: false,
+                },
+                Declaration {
+                    property: "font-weight".to_string(),
+                    value: "bold".to_string(),
+                    important: true,
+                },
+            ]
+        );
+        assert_eq!(
+            rules[2].declarations[0].value,
+            "url(data:image/png;base64,AAAA)"
+        );
+        assert_eq!(
+            kept,
+            "a:hover{ color: blue }\n@media (max-width: 600px){ h1 { font-size: 12px } }\n"
+        );
+    }
+
+    #[test]
+    fn can_compute_specificity() {
+        assert_eq!(specificity("p"), (0, 0, 1));
+        assert_eq!(specificity("table td.cell"), (0, 1, 2));
+        assert_eq!(specificity("#main > a[href]"), (1, 1, 1));
+        assert_eq!(specificity(r#"a[href="https://a.b/#top"]"#), (0, 1, 1));
+    }
+
+    #[test]
+    fn keeps_pseudo_selectors_in_style() {
+        let html = r#"<html><head><style>
+            p:first-child { color: red }
+            p::before { content: "> " }
+            a[href^="https:"] { color: green }
+            li:not(.done), li { margin: 0 }
+        </style></head><body>
+            <p>Hello</p>
+            <a href="https://loco.rs">Loco</a>
+            <ul><li>Item</li></ul>
+        </body></html>"#;
+
+        let inlined = inline_css(html);
+        assert!(inlined.contains("<p>Hello</p>"));
+        assert!(inlined.contains(r#"<a href="https://loco.rs" style="color: green">"#));
+        assert!(inlined.contains(r#"<li style="margin: 0">"#));
+        assert!(inlined.contains(
+            "<style>p:first-child{ color: red }\np::before{ content: \"> \" }\nli:not(.done){ margin: 0 }\n</style>"
+        ));
+    }
+
+    #[test]
+    fn can_inline_css() {
+        let html = r#"<html><head><style>
+            p { color: red; margin: 0 }
+            .note { color: green }
+            #intro { color: blue }
+            a:hover { color: black }
+            .strong { font-weight: bold !important }
+        </style></head><body>
+            <p id="intro" class="note">Hello</p>
+            <p class="note strong" style="color: gray; font-weight: normal">World</p>
+            <span>Untouched</span>
+        </body></html>"#;
+
+        let inlined = inline_css(html);
+        assert!(inlined.contains(r#"<p id="intro" class="note" style="margin: 0; color: blue">"#));
+        assert!(inlined.contains(
+            r#"<p class="note strong" style="margin: 0; color: gray; font-weight: bold">"#
+        ));
+        assert!(inlined.contains("<span>Untouched</span>"));
+        assert!(inlined.contains("<style>a:hover{ color: black }\n</style>"));
+    }
+
+    #[test]
+    fn leaves_html_without_styles_as_is() {
+        let html = "<p>Hello</p>";
+        assert_eq!(inline_css(html), html);
+    }
+
+    #[test]
+    fn can_convert_html_to_text() {
+        let html = r#"<html><head><title>Welcome</title><style>p { color: red }</style></head>
+        <body>
+            <h1>Welcome   Jane</h1>
+            <p>Please <a href="https://example.com/verify">verify your account</a>.<br>Thanks!</p>
+            <ul><li>One</li><li>Two</li></ul>
+            <img src="logo.png" alt="Loco">
+            <p><a href="https://example.com">https://example.com</a></p>
+        </body></html>"#;
+
+        assert_eq!(
+            to_text(html),
+            "Welcome Jane\n\nPlease verify your account (https://example.com/verify).\nThanks!\n\n- One\n\n- Two\n\nLoco\n\nhttps://example.com"
+        );
+    }
+}
@@ -0,0 +1,852 @@
+//! Sends emails through the HTTP APIs of email providers, for networks where
+//! outbound SMTP is blocked.
+//!
+//! SendGrid and Postmark receive the email as JSON. Mailgun and Amazon SES
+//! receive the MIME message built by [`super::EmailSender::message`], so the email
+//! arrives as it would through SMTP.
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lettre::{
+    message::{Mailbox, Mailboxes},
+    Message,
+};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::{AttachmentContent, Email, MailTransport, DEFAULT_FROM_SENDER};
+use crate::{config::HttpMailer, hash, Error, Result};
+
+const SENDGRID_ENDPOINT: &str = "https://api.sendgrid.com";
+const POSTMARK_ENDPOINT: &str = "https://api.postmarkapp.com";
+const MAILGUN_ENDPOINT: &str = "https://api.mailgun.net";
+
+/// A failure to send an email through an HTTP API provider.
+#[derive(thiserror::Error, Debug)]
+pub enum HttpMailerError {
+    #[error("{provider} rejected the email with {status}: {body}")]
+    Rejected {
+        provider: &'static str,
+        status: reqwest::StatusCode,
+        body: String,
+    },
+
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+impl HttpMailerError {
+    /// Returns `true` for the failures worth retrying: rate limiting, server
+    /// errors, and connection failures or timeouts.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Rejected { status, .. } => {
+                *status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Self::Request(err) => err.is_connect() || err.is_timeout(),
+        }
+    }
+}
+
+/// A [`MailTransport`] sending emails through an HTTP API provider.
+pub struct HttpTransport {
+    client: reqwest::Client,
+    config: HttpMailer,
+}
+
+impl HttpTransport {
+    /// Creates a new transport for the given provider.
+    ///
+    /// # Errors
+    ///
+    /// When the HTTP client could not be created.
+    pub fn new(config: &HttpMailer) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .user_agent(concat!("loco/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .map_err(Error::wrap)?,
+            config: config.clone(),
+        })
+    }
+
+    fn provider(&self) -> &'static str {
+        match self.config {
+            HttpMailer::Sendgrid { .. } => "sendgrid",
+            HttpMailer::Postmark { .. } => "postmark",
+            HttpMailer::Mailgun { .. } => "mailgun",
+            HttpMailer::Ses { .. } => "ses",
+            HttpMailer::Webhook { .. } => "webhook",
+        }
+    }
+
+    fn request(&self, email: &Email, message: &Message) -> Result<reqwest::RequestBuilder> {
+        Ok(match &self.config {
+            HttpMailer::Sendgrid { api_key, endpoint } => self
+                .client
+                .post(format!(
+                    "{}/v3/mail/send",
+                    endpoint.as_deref().unwrap_or(SENDGRID_ENDPOINT)
+                ))
+                .bearer_auth(api_key)
+                .json(&sendgrid_body(email)?),
+            HttpMailer::Postmark {
+                server_token,
+                message_stream,
+                endpoint,
+            } => self
+                .client
+                .post(format!(
+                    "{}/email",
+                    endpoint.as_deref().unwrap_or(POSTMARK_ENDPOINT)
+                ))
+                .header("X-Postmark-Server-Token", server_token)
+                .header(reqwest::header::ACCEPT, "application/json")
+                .json(&postmark_body(email, message_stream.as_deref())?),
+            HttpMailer::Mailgun {
+                api_key,
+                domain,
+                endpoint,
+            } => {
+                let boundary = hash::random_string(32);
+                self.client
+                    .post(format!(
+                        "{}/v3/{domain}/messages.mime",
+                        endpoint.as_deref().unwrap_or(MAILGUN_ENDPOINT)
+                    ))
+                    .basic_auth("api", Some(api_key))
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={boundary}"),
+                    )
+                    .body(mailgun_body(message, &boundary))
+            }
+            HttpMailer::Ses {
+                region,
+                access_key_id,
+                secret_access_key,
+                session_token,
+                endpoint,
+            } => {
+                let endpoint = endpoint
+                    .clone()
+                    .unwrap_or_else(|| format!("https://email.{region}.amazonaws.com"));
+                let url = reqwest::Url::parse(&format!("{endpoint}/v2/email/outbound-emails"))
+                    .map_err(Error::wrap)?;
+                let body = serde_json::to_vec(&ses_body(message))?;
+                let credentials = Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: session_token.as_deref(),
+                    region,
+                    service: "ses",
+                };
+                let mut request = self.client.post(url.clone());
+                for (name, value) in credentials.sign("POST", &url, &body, Utc::now()) {
+                    request = request.header(name, value);
+                }
+                request.body(body)
+            }
+            HttpMailer::Webhook { url, headers } => {
+                let mut request = self.client.post(url).json(email);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                request
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for HttpTransport {
+    async fn send(&self, email: &Email, message: &Message) -> Result<()> {
+        let response = self
+            .request(email, message)?
+            .send()
+            .await
+            .map_err(|err| Error::wrap(HttpMailerError::from(err)))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(Error::wrap(HttpMailerError::Rejected {
+            provider: self.provider(),
+            status,
+            body,
+        }))
+    }
+}
+
+fn mailboxes(addresses: Option<&str>) -> Result<Vec<Mailbox>> {
+    Ok(addresses
+        .filter(|addresses| !addresses.trim().is_empty())
+        .map(str::parse::<Mailboxes>)
+        .transpose()?
+        .map(|mailboxes| mailboxes.into_iter().collect())
+        .unwrap_or_default())
+}
+
+/// Returns the `to`, `cc` and `bcc` recipients. The providers require a `to`
+/// recipient, so the `cc` recipients take the place of a missing `to`, such
+/// as when all the `to` recipients are suppressed.
+fn recipients_of(email: &Email) -> Result<[Vec<Mailbox>; 3]> {
+    let to = mailboxes(Some(&email.to))?;
+    let cc = mailboxes(email.cc.as_deref())?;
+    let bcc = mailboxes(email.bcc.as_deref())?;
+    Ok(if to.is_empty() {
+        [cc, Vec::new(), bcc]
+    } else {
+        [to, cc, bcc]
+    })
+}
+
+/// The `Message-ID` header, as the SMTP transport sets it.
+fn message_id_header(email: &Email) -> Option<String> {
+    email
+        .message_id
+        .as_ref()
+        .map(|message_id| format!("<{message_id}>"))
+}
+
+fn from(email: &Email) -> Result<Mailbox> {
+    Ok(email
+        .from
+        .as_deref()
+        .unwrap_or(DEFAULT_FROM_SENDER)
+        .parse()?)
+}
+
+fn attachment_content(content: &AttachmentContent) -> Result<String> {
+    match content {
+        AttachmentContent::Bytes(bytes) => Ok(STANDARD.encode(bytes)),
+        AttachmentContent::Storage(path) => Err(Error::Message(format!(
+            "attachment `{}` was not loaded from the storage",
+            path.display()
+        ))),
+    }
+}
+
+fn sendgrid_body(email: &Email) -> Result<Value> {
+    let addresses = |mailboxes: Vec<Mailbox>| -> Vec<Value> {
+        mailboxes
+            .into_iter()
+            .map(|mailbox| match mailbox.name {
+                Some(name) => json!({"email": mailbox.email.to_string(), "name": name}),
+                None => json!({"email": mailbox.email.to_string()}),
+            })
+            .collect()
+    };
+
+    let [to, cc, bcc] = recipients_of(email)?;
+    let personalizations: Vec<Value> = if to.is_empty() {
+        // only bcc recipients: each gets a copy of their own, without seeing
+        // the others
+        addresses(bcc)
+            .into_iter()
+            .map(|recipient| json!({"to": [recipient]}))
+            .collect()
+    } else {
+        let mut personalization = Map::new();
+        personalization.insert("to".to_string(), addresses(to).into());
+        for (field, value) in [("cc", cc), ("bcc", bcc)] {
+            let value = addresses(value);
+            if !value.is_empty() {
+                personalization.insert(field.to_string(), value.into());
+            }
+        }
+        vec![personalization.into()]
+    };
+
+    let mut content = Vec::new();
+    if !email.text.is_empty() {
+        content.push(json!({"type": "text/plain", "value": email.text}));
+    }
+    if !email.html.is_empty() {
+        content.push(json!({"type": "text/html", "value": email.html}));
+    }
+
+    let mut body = json!({
+        "personalizations": personalizations,
+        "from": addresses(vec![from(email)?])[0],
+        "subject": email.subject,
+        "content": content,
+    });
+    if let Some(reply_to) = addresses(mailboxes(email.reply_to.as_deref())?).first() {
+        body["reply_to"] = reply_to.clone();
+    }
+    if let Some(message_id) = message_id_header(email) {
+        body["headers"] = json!({"Message-ID": message_id});
+    }
+    if !email.attachments.is_empty() {
+        body["attachments"] = email
+            .attachments
+            .iter()
+            .map(|attachment| {
+                let mut value = json!({
+                    "content": attachment_content(&attachment.content)?,
+                    "type": attachment.content_type,
+                    "filename": attachment.filename,
+                    "disposition": "attachment",
+                });
+                if let Some(content_id) = &attachment.content_id {
+                    value["disposition"] = "inline".into();
+                    value["content_id"] = content_id.clone().into();
+                }
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into();
+    }
+    Ok(body)
+}
+
+fn postmark_body(email: &Email, message_stream: Option<&str>) -> Result<Value> {
+    let addresses = |mailboxes: Vec<Mailbox>| -> Option<String> {
+        (!mailboxes.is_empty()).then(|| {
+            mailboxes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    };
+
+    let from = from(email)?;
+    let [to, cc, bcc] = recipients_of(email)?;
+    // only bcc recipients: the email is addressed to the sender, so they
+    // don't see each other
+    let to = addresses(to).unwrap_or_else(|| from.to_string());
+    Ok(json!({
+        "From": from.to_string(),
+        "To": to,
+        "Cc": addresses(cc),
+        "Bcc": addresses(bcc),
+        "ReplyTo": addresses(mailboxes(email.reply_to.as_deref())?),
+        "Headers": message_id_header(email)
+            .map(|message_id| vec![json!({"Name": "Message-ID", "Value": message_id})])
+            .unwrap_or_default(),
+        "Subject": email.subject,
+        "TextBody": email.text,
+        "HtmlBody": email.html,
+        "MessageStream": message_stream.unwrap_or("outbound"),
+        "Attachments": email
+            .attachments
+            .iter()
+            .map(|attachment| {
+                Ok(json!({
+                    "Name": attachment.filename,
+                    "Content": attachment_content(&attachment.content)?,
+                    "ContentType": attachment.content_type,
+                    "ContentID": attachment.content_id.as_ref().map(|id| format!("cid:{id}")),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?,
+    }))
+}
+
+fn recipients(message: &Message) -> Vec<String> {
+    message
+        .envelope()
+        .to()
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn mailgun_body(message: &Message, boundary: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    for recipient in recipients(message) {
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"to\"\r\n\r\n{recipient}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"message\"; \
+             filename=\"message.mime\"\r\nContent-Type: message/rfc822\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&message.formatted());
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+fn ses_body(message: &Message) -> Value {
+    json!({
+        "Destination": {"ToAddresses": recipients(message)},
+        "Content": {"Raw": {"Data": STANDARD.encode(message.formatted())}},
+    })
+}
+
+/// Signs AWS requests with [Signature Version 4](https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv.html).
+struct Credentials<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a str,
+    session_token: Option<&'a str>,
+    region: &'a str,
+    service: &'a str,
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl Credentials<'_> {
+    fn signing_key(&self, date: &str) -> Vec<u8> {
+        let key = hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        let key = hmac_sha256(&key, self.region);
+        let key = hmac_sha256(&key, self.service);
+        hmac_sha256(&key, "aws4_request")
+    }
+
+    /// Returns the headers to add to a JSON request to sign it.
+    fn sign(
+        &self,
+        method: &str,
+        url: &reqwest::Url,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        self.sign_with_headers(
+            method,
+            url,
+            &[("content-type", "application/json")],
+            body,
+            now,
+        )
+    }
+
+    fn sign_with_headers(
+        &self,
+        method: &str,
+        url: &reqwest::Url,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let mut signed: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), (*value).to_string()))
+            .collect();
+        signed.push(("host".to_string(), host));
+        signed.push(("x-amz-date".to_string(), amz_date.clone()));
+        if let Some(token) = self.session_token {
+            signed.push(("x-amz-security-token".to_string(), token.to_string()));
+        }
+        signed.sort();
+
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = signed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let mut query: Vec<_> = url.query().unwrap_or_default().split('&').collect();
+        query.sort_unstable();
+        let canonical_request = format!(
+            "{method}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+            url.path(),
+            query.join("&"),
+            hex::encode(Sha256::digest(body))
+        );
+
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signature = hex::encode(hmac_sha256(&self.signing_key(&date), &string_to_sign));
+
+        signed.retain(|(name, _)| name != "host");
+        signed.push((
+            "authorization".to_string(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, \
+                 Signature={signature}",
+                self.access_key_id
+            ),
+        ));
+        signed
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, StatusCode, Uri},
+        Router,
+    };
+    use chrono::TimeZone;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::mailer::{Attachment, EmailSender};
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct Received {
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> &str {
+            self.headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        }
+
+        fn json(&self) -> Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+    }
+
+    /// Starts a server recording the requests it receives, and responding
+    /// with the given status.
+    pub(crate) async fn mock_server(status: StatusCode) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = received.clone();
+        let app = Router::new().fallback(move |uri: Uri, headers: HeaderMap, body: Bytes| {
+            requests
+                .lock()
+                .unwrap()
+                .push(Received { uri, headers, body });
+            async move { (status, "{}") }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}"), received)
+    }
+
+    fn email() -> Email {
+        Email {
+            from: Some("Loco <hello@loco.rs>".to_string()),
+            to: "Jane <jane@example.com>, joe@example.com".to_string(),
+            bcc: Some("audit@example.com".to_string()),
+            subject: "Your invoice".to_string(),
+            text: "Invoice attached".to_string(),
+            html: "<p>Invoice attached</p>".to_string(),
+            attachments: vec![Attachment::bytes(
+                "invoice.pdf",
+                "application/pdf",
+                "%PDF-1.4",
+            )],
+            ..Default::default()
+        }
+    }
+
+    async fn send(config: HttpMailer) -> Result<()> {
+        EmailSender::http(&config).unwrap().mail(&email()).await
+    }
+
+    #[tokio::test]
+    async fn can_send_with_sendgrid() {
+        let (endpoint, received) = mock_server(StatusCode::ACCEPTED).await;
+        send(HttpMailer::Sendgrid {
+            api_key: "SG.key".to_string(),
+            endpoint: Some(endpoint),
+        })
+        .await
+        .unwrap();
+
+        let request = received.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/v3/mail/send");
+        assert_eq!(request.header("authorization"), "Bearer SG.key");
+        let body = request.json();
+        assert_eq!(
+            body["personalizations"][0]["to"],
+            json!([{"email": "jane@example.com", "name": "Jane"}, {"email": "joe@example.com"}])
+        );
+        assert_eq!(
+            body["personalizations"][0]["bcc"],
+            json!([{"email": "audit@example.com"}])
+        );
+        assert!(body["personalizations"][0].get("cc").is_none());
+        assert_eq!(
+            body["from"],
+            json!({"email": "hello@loco.rs", "name": "Loco"})
+        );
+        assert_eq!(body["content"][1]["value"], "<p>Invoice attached</p>");
+        assert_eq!(body["attachments"][0]["content"], "JVBERi0xLjQ=");
+        assert_eq!(body["attachments"][0]["disposition"], "attachment");
+    }
+
+    #[tokio::test]
+    async fn can_send_with_postmark() {
+        let (endpoint, received) = mock_server(StatusCode::OK).await;
+        send(HttpMailer::Postmark {
+            server_token: "token".to_string(),
+            message_stream: None,
+            endpoint: Some(endpoint),
+        })
+        .await
+        .unwrap();
+
+        let request = received.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/email");
+        assert_eq!(request.header("x-postmark-server-token"), "token");
+        let body = request.json();
+        assert_eq!(body["To"], "Jane <jane@example.com>, joe@example.com");
+        assert_eq!(body["Cc"], Value::Null);
+        assert_eq!(body["MessageStream"], "outbound");
+        assert_eq!(body["Attachments"][0]["Name"], "invoice.pdf");
+    }
+
+    #[rstest]
+    #[case::cc(Some("cc@example.com"), json!([{"to": [{"email": "cc@example.com"}], "bcc": [{"email": "audit@example.com"}]}]))]
+    #[case::bcc(None, json!([{"to": [{"email": "audit@example.com"}]}, {"to": [{"email": "ops@example.com"}]}]))]
+    #[tokio::test]
+    async fn can_send_with_sendgrid_without_to(
+        #[case] cc: Option<&str>,
+        #[case] personalizations: Value,
+    ) {
+        let (endpoint, received) = mock_server(StatusCode::ACCEPTED).await;
+        let email = Email {
+            to: String::new(),
+            cc: cc.map(ToString::to_string),
+            bcc: Some(if cc.is_some() {
+                "audit@example.com".to_string()
+            } else {
+                "audit@example.com, ops@example.com".to_string()
+            }),
+            message_id: Some("abc@loco.rs".to_string()),
+            ..email()
+        };
+        let config = HttpMailer::Sendgrid {
+            api_key: "SG.key".to_string(),
+            endpoint: Some(endpoint),
+        };
+        EmailSender::http(&config)
+            .unwrap()
+            .mail(&email)
+            .await
+            .unwrap();
+
+        let body = received.lock().unwrap()[0].json();
+        assert_eq!(body["personalizations"], personalizations);
+        assert_eq!(body["headers"], json!({"Message-ID": "<abc@loco.rs>"}));
+    }
+
+    #[tokio::test]
+    async fn can_send_with_postmark_without_to() {
+        let (endpoint, received) = mock_server(StatusCode::OK).await;
+        let email = Email {
+            to: String::new(),
+            message_id: Some("abc@loco.rs".to_string()),
+            ..email()
+        };
+        let config = HttpMailer::Postmark {
+            server_token: "token".to_string(),
+            message_stream: None,
+            endpoint: Some(endpoint),
+        };
+        EmailSender::http(&config)
+            .unwrap()
+            .mail(&email)
+            .await
+            .unwrap();
+
+        let body = received.lock().unwrap()[0].json();
+        assert_eq!(body["To"], "Loco <hello@loco.rs>");
+        assert_eq!(body["Bcc"], "audit@example.com");
+        assert_eq!(
+            body["Headers"],
+            json!([{"Name": "Message-ID", "Value": "<abc@loco.rs>"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn can_send_with_mailgun() {
+        let (endpoint, received) = mock_server(StatusCode::OK).await;
+        send(HttpMailer::Mailgun {
+            api_key: "key".to_string(),
+            domain: "mg.loco.rs".to_string(),
+            endpoint: Some(endpoint),
+        })
+        .await
+        .unwrap();
+
+        let request = received.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/v3/mg.loco.rs/messages.mime");
+        assert_eq!(
+            request.header("authorization"),
+            format!("Basic {}", STANDARD.encode("api:key"))
+        );
+        assert!(request
+            .header("content-type")
+            .starts_with("multipart/form-data; boundary="));
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.contains("name=\"to\"\r\n\r\naudit@example.com\r\n"));
+        assert!(body.contains("Subject: Your invoice"));
+        assert!(body.contains("filename=\"invoice.pdf\""));
+    }
+
+    #[tokio::test]
+    async fn can_send_with_ses() {
+        let (endpoint, received) = mock_server(StatusCode::OK).await;
+        send(HttpMailer::Ses {
+            region: "eu-west-1".to_string(),
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: Some("session".to_string()),
+            endpoint: Some(endpoint),
+        })
+        .await
+        .unwrap();
+
+        let request = received.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/v2/email/outbound-emails");
+        assert!(request.header("authorization").starts_with(&format!(
+            "AWS4-HMAC-SHA256 Credential=AKID/{}/eu-west-1/ses/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature=",
+            Utc::now().format("%Y%m%d")
+        )));
+        assert_eq!(request.header("x-amz-security-token"), "session");
+        let body = request.json();
+        assert_eq!(
+            body["Destination"]["ToAddresses"],
+            json!(["jane@example.com", "joe@example.com", "audit@example.com"])
+        );
+        let raw = STANDARD
+            .decode(body["Content"]["Raw"]["Data"].as_str().unwrap())
+            .unwrap();
+        assert!(String::from_utf8_lossy(&raw).contains("Subject: Your invoice"));
+    }
+
+    #[tokio::test]
+    async fn can_send_with_webhook() {
+        let (endpoint, received) = mock_server(StatusCode::OK).await;
+        send(HttpMailer::Webhook {
+            url: format!("{endpoint}/relay"),
+            headers: BTreeMap::from([("Authorization".to_string(), "Token abc".to_string())]),
+        })
+        .await
+        .unwrap();
+
+        let request = received.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/relay");
+        assert_eq!(request.header("authorization"), "Token abc");
+        let body: Email = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.subject, "Your invoice");
+        assert_eq!(body.attachments, email().attachments);
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_provider_rejects_the_email() {
+        let (endpoint, _) = mock_server(StatusCode::UNAUTHORIZED).await;
+        let err = send(HttpMailer::Sendgrid {
+            api_key: "SG.key".to_string(),
+            endpoint: Some(endpoint),
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sendgrid rejected the email with 401 Unauthorized: {}"
+        );
+    }
+
+    #[tokio::test]
+    async fn can_tell_transient_failures() {
+        let is_transient = |err: Error| match err {
+            Error::Any(err) => err
+                .downcast_ref::<HttpMailerError>()
+                .unwrap()
+                .is_transient(),
+            err => panic!("unexpected error {err}"),
+        };
+        for (status, transient) in [
+            (StatusCode::TOO_MANY_REQUESTS, true),
+            (StatusCode::SERVICE_UNAVAILABLE, true),
+            (StatusCode::BAD_REQUEST, false),
+            (StatusCode::UNAUTHORIZED, false),
+        ] {
+            let (endpoint, _) = mock_server(status).await;
+            let err = send(HttpMailer::Postmark {
+                server_token: "token".to_string(),
+                message_stream: None,
+                endpoint: Some(endpoint),
+            })
+            .await
+            .unwrap_err();
+            assert_eq!(is_transient(err), transient, "{status}");
+        }
+
+        // nothing listens on the port of a dropped listener
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let err = send(HttpMailer::Webhook {
+            url: endpoint,
+            headers: BTreeMap::new(),
+        })
+        .await
+        .unwrap_err();
+        assert!(is_transient(err));
+    }
+
+    #[test]
+    fn can_sign_aws_requests() {
+        // the example from the AWS Signature Version 4 documentation
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE",
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            session_token: None,
+            region: "us-east-1",
+            service: "iam",
+        };
+        assert_eq!(
+            hex::encode(credentials.signing_key("20150830")),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+
+        let url =
+            reqwest::Url::parse("https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08")
+                .unwrap();
+        let headers = credentials.sign_with_headers(
+            "GET",
+            &url,
+            &[(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )],
+            b"",
+            Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap(),
+        );
+        assert_eq!(
+            headers.last().unwrap().1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+}
@@ -0,0 +1,641 @@
+//! Receives emails into the application, such as replies to support tickets
+//! or comment notifications.
+//!
+//! Emails arrive either through the [`smtp`] listener, or as raw MIME
+//! messages posted by the email provider to the inbound route. Each email is
+//! processed in the background by the first [`Mailbox`] whose pattern matches
+//! one of its recipients:
+//!
+//! ```rust,ignore
+//! struct RepliesMailbox;
+//!
+//! #[async_trait]
+//! impl Mailbox for RepliesMailbox {
+//!     async fn process(&self, ctx: &AppContext, email: &InboundEmail) -> Result<()> {
+//!         // reply-<comment id>@reply.example.com
+//!         let id = email.recipient_matching("reply-*@reply.example.com");
+//!         ...
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl Hooks for App {
+//!     fn register_mailboxes(_ctx: &AppContext, mailboxes: &mut Mailboxes) {
+//!         mailboxes.route("support@example.com", SupportMailbox);
+//!         mailboxes.route("reply-*@reply.example.com", RepliesMailbox);
+//!     }
+//! }
+//! ```
+//!
+//! Mount [`routes`] to accept messages at [`DEFAULT_ROUTE_PREFIX`]. The route
+//! answers `404` unless `mailer.inbound.webhook` is configured, and `401`
+//! without its token.
+pub mod smtp;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    routing::post,
+};
+use mail_parser::{Address, MessageParser, MimeHeaders};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use super::attachment::base64_bytes;
+use crate::{
+    app::{AppContext, Hooks},
+    controller::Routes,
+    hash,
+    prelude::BackgroundWorker,
+    Error, Result,
+};
+
+/// The path the inbound webhook is mounted on.
+pub const DEFAULT_ROUTE_PREFIX: &str = "/_loco/inbound";
+
+/// Headers naming the recipient the email was delivered to, set by most mail
+/// servers when the recipient is only in `Bcc`.
+const DELIVERY_HEADERS: &[&str] = &["Delivered-To", "X-Original-To", "X-Envelope-To"];
+
+/// A file attached to an [`InboundEmail`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InboundAttachment {
+    pub filename: String,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+}
+
+/// A received email.
+#[derive(Debug, Clone, Default)]
+pub struct InboundEmail {
+    /// `Message-ID` header, without the angle brackets
+    pub message_id: Option<String>,
+    /// Address of the `From` header
+    pub from: Option<String>,
+    /// Display name of the `From` header
+    pub from_name: Option<String>,
+    /// Address of the `Reply-To` header
+    pub reply_to: Option<String>,
+    /// Addresses of the `To` header
+    pub to: Vec<String>,
+    /// Addresses of the `Cc` header
+    pub cc: Vec<String>,
+    /// Envelope recipients, as given to the SMTP listener
+    pub recipients: Vec<String>,
+    pub subject: String,
+    /// Plain text body, converted from the HTML when missing
+    pub text: String,
+    /// HTML body, converted from the plain text when missing
+    pub html: String,
+    /// `In-Reply-To` header, without the angle brackets
+    pub in_reply_to: Option<String>,
+    /// `References` header, without the angle brackets
+    pub references: Vec<String>,
+    /// All the headers, in the order of the message
+    pub headers: Vec<(String, String)>,
+    pub attachments: Vec<InboundAttachment>,
+    /// The raw MIME message
+    pub raw: Vec<u8>,
+}
+
+fn addresses(address: Option<&Address<'_>>) -> Vec<String> {
+    address
+        .map(|address| {
+            address
+                .iter()
+                .filter_map(|addr| addr.address())
+                .map(str::to_lowercase)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl InboundEmail {
+    /// Parses a raw MIME message.
+    ///
+    /// # Errors
+    ///
+    /// When the message could not be parsed.
+    pub fn parse(raw: &[u8]) -> Result<Self> {
+        let message = MessageParser::default()
+            .parse(raw)
+            .filter(|message| !message.headers().is_empty())
+            .ok_or_else(|| Error::BadRequest("invalid MIME message".to_string()))?;
+
+        let from = message.from().and_then(Address::first);
+        let headers = message
+            .headers()
+            .iter()
+            .map(|header| {
+                let value = raw
+                    .get(header.offset_start as usize..header.offset_end as usize)
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                (header.name().to_string(), value.trim().to_string())
+            })
+            .collect();
+        let attachments = message
+            .attachments()
+            .map(|part| InboundAttachment {
+                filename: part.attachment_name().unwrap_or("attachment").to_string(),
+                content_type: part.content_type().map_or_else(
+                    || "application/octet-stream".to_string(),
+                    |content_type| match content_type.subtype() {
+                        Some(subtype) => format!("{}/{subtype}", content_type.ctype()),
+                        None => content_type.ctype().to_string(),
+                    },
+                ),
+                content_id: part.content_id().map(ToString::to_string),
+                content: part.contents().to_vec(),
+            })
+            .collect();
+
+        Ok(Self {
+            message_id: message.message_id().map(ToString::to_string),
+            from: from.and_then(|addr| addr.address()).map(str::to_lowercase),
+            from_name: from.and_then(|addr| addr.name()).map(ToString::to_string),
+            reply_to: addresses(message.reply_to()).into_iter().next(),
+            to: addresses(message.to()),
+            cc: addresses(message.cc()),
+            recipients: vec![],
+            subject: message.subject().unwrap_or_default().to_string(),
+            text: message.body_text(0).unwrap_or_default().to_string(),
+            html: message.body_html(0).unwrap_or_default().to_string(),
+            in_reply_to: message.in_reply_to().as_text().map(ToString::to_string),
+            references: message
+                .references()
+                .as_text_list()
+                .map(|references| references.iter().map(ToString::to_string).collect())
+                .unwrap_or_default(),
+            headers,
+            attachments,
+            raw: raw.to_vec(),
+        })
+    }
+
+    /// Returns the first value of a header, ignoring the case of its name.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the addresses the email was sent to: the envelope recipients,
+    /// the `To` and `Cc` addresses and the delivery headers, without
+    /// duplicates.
+    #[must_use]
+    pub fn all_recipients(&self) -> Vec<String> {
+        let delivered = self
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                DELIVERY_HEADERS
+                    .iter()
+                    .any(|header| header.eq_ignore_ascii_case(name))
+            })
+            .map(|(_, value)| {
+                value
+                    .trim_matches(|c: char| c == '<' || c == '>' || c.is_whitespace())
+                    .to_lowercase()
+            });
+
+        let mut recipients: Vec<String> = vec![];
+        for recipient in self
+            .recipients
+            .iter()
+            .chain(&self.to)
+            .chain(&self.cc)
+            .cloned()
+            .chain(delivered)
+        {
+            if !recipient.is_empty() && !recipients.contains(&recipient) {
+                recipients.push(recipient);
+            }
+        }
+        recipients
+    }
+
+    /// Returns the first recipient matching a pattern, where `*` matches any
+    /// characters, such as `reply-*@reply.example.com`.
+    #[must_use]
+    pub fn recipient_matching(&self, pattern: &str) -> Option<String> {
+        self.all_recipients()
+            .into_iter()
+            .find(|recipient| matches_pattern(pattern, recipient))
+    }
+}
+
+/// Matches an address against a pattern where `*` matches any characters,
+/// ignoring case.
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == value[v] {
+            p += 1;
+            v += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Processes the received emails routed to it.
+#[async_trait]
+pub trait Mailbox: Send + Sync {
+    /// Processes an email. An error retries the job, when the queue supports
+    /// retries.
+    async fn process(&self, ctx: &AppContext, email: &InboundEmail) -> Result<()>;
+}
+
+/// The mailboxes of an application, by recipient pattern.
+#[derive(Default)]
+pub struct Mailboxes {
+    routes: Vec<(String, Arc<dyn Mailbox>)>,
+    fallback: Option<Arc<dyn Mailbox>>,
+}
+
+impl std::fmt::Debug for Mailboxes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mailboxes")
+            .field(
+                "routes",
+                &self
+                    .routes
+                    .iter()
+                    .map(|(pattern, _)| pattern)
+                    .collect::<Vec<_>>(),
+            )
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl Mailboxes {
+    /// Collects the mailboxes registered in [`Hooks::register_mailboxes`].
+    #[must_use]
+    pub fn from_hooks<H: Hooks>(ctx: &AppContext) -> Self {
+        let mut mailboxes = Self::default();
+        H::register_mailboxes(ctx, &mut mailboxes);
+        mailboxes
+    }
+
+    /// Returns the mailboxes of the application, kept in the shared store when
+    /// the application boots.
+    ///
+    /// # Errors
+    ///
+    /// When no mailboxes were registered.
+    pub fn from_context(ctx: &AppContext) -> Result<Arc<Self>> {
+        ctx.shared_store
+            .get::<Arc<Self>>()
+            .ok_or_else(|| Error::string("no mailboxes registered for inbound emails"))
+    }
+
+    /// Routes the emails sent to a recipient matching the pattern, where `*`
+    /// matches any characters, to the mailbox. Routes are tried in order.
+    pub fn route(&mut self, pattern: &str, mailbox: impl Mailbox + 'static) {
+        self.routes.push((pattern.to_string(), Arc::new(mailbox)));
+    }
+
+    /// Routes the emails matching no route to the mailbox, instead of dropping
+    /// them.
+    pub fn fallback(&mut self, mailbox: impl Mailbox + 'static) {
+        self.fallback = Some(Arc::new(mailbox));
+    }
+
+    /// Finds the mailbox of an email.
+    #[must_use]
+    pub fn find(&self, email: &InboundEmail) -> Option<Arc<dyn Mailbox>> {
+        let recipients = email.all_recipients();
+        self.routes
+            .iter()
+            .find(|(pattern, _)| {
+                recipients
+                    .iter()
+                    .any(|recipient| matches_pattern(pattern, recipient))
+            })
+            .map(|(_, mailbox)| mailbox.clone())
+            .or_else(|| self.fallback.clone())
+    }
+
+    /// Processes an email with its mailbox. Emails without a mailbox are
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// When the mailbox fails to process the email.
+    pub async fn process(&self, ctx: &AppContext, email: &InboundEmail) -> Result<()> {
+        if let Some(mailbox) = self.find(email) {
+            return mailbox.process(ctx, email).await;
+        }
+        warn!(
+            message_id = ?email.message_id,
+            recipients = email.all_recipients().join(", "),
+            "no mailbox for the inbound email, dropping it"
+        );
+        Ok(())
+    }
+}
+
+/// A received message, waiting to be processed by the [`InboundWorker`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboundMessage {
+    /// The raw MIME message, serialized as base64
+    #[serde(with = "base64_bytes")]
+    pub raw: Vec<u8>,
+    /// Envelope recipients, when known
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+/// Queues a raw MIME message to be processed by its mailbox.
+///
+/// # Errors
+///
+/// When the message could not be parsed or queued.
+pub async fn receive(ctx: &AppContext, raw: Vec<u8>, recipients: Vec<String>) -> Result<()> {
+    InboundEmail::parse(&raw)?;
+    InboundWorker::perform_later(ctx, InboundMessage { raw, recipients }).await
+}
+
+/// Processes the received messages with the registered [`Mailboxes`].
+#[allow(clippy::module_name_repetitions)]
+pub struct InboundWorker {
+    pub ctx: AppContext,
+}
+
+#[async_trait]
+impl BackgroundWorker<InboundMessage> for InboundWorker {
+    fn queue() -> Option<String> {
+        Some("mailer".to_string())
+    }
+
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, message: InboundMessage) -> Result<()> {
+        let res = match (
+            Mailboxes::from_context(&self.ctx),
+            InboundEmail::parse(&message.raw),
+        ) {
+            (Ok(mailboxes), Ok(email)) => {
+                let email = InboundEmail {
+                    recipients: message.recipients,
+                    ..email
+                };
+                mailboxes.process(&self.ctx, &email).await
+            }
+            (Err(err), _) | (_, Err(err)) => Err(err),
+        };
+        if let Err(err) = &res {
+            error!(err = err.to_string(), "inbound mailer error");
+        }
+        res
+    }
+}
+
+async fn webhook(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode> {
+    let config = ctx
+        .config
+        .mailer
+        .as_ref()
+        .and_then(|mailer| mailer.inbound.as_ref())
+        .and_then(|inbound| inbound.webhook.as_ref())
+        .ok_or(Error::NotFound)?;
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !hash::constant_time_eq(&config.token, token) {
+        return Err(Error::Unauthorized("invalid inbound token".to_string()));
+    }
+
+    receive(&ctx, body.to_vec(), vec![]).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Returns the route accepting raw MIME messages, mounted on
+/// [`DEFAULT_ROUTE_PREFIX`].
+///
+/// ```rust,ignore
+/// fn routes(_ctx: &AppContext) -> AppRoutes {
+///     AppRoutes::with_default_routes()
+///         .add_route(controllers::auth::routes())
+///         .add_route(loco_rs::mailer::inbound::routes())
+/// }
+/// ```
+#[must_use]
+pub fn routes() -> Routes {
+    Routes::at(DEFAULT_ROUTE_PREFIX).add("/", post(webhook))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config, tests_cfg};
+
+    pub const MESSAGE: &str = "From: Jane Doe <Jane@Example.com>\r
+To: support@example.com, reply-42@reply.example.com\r
+Cc: team@example.com\r
+Subject: Re: Order #42\r
+Message-ID: <reply-1@example.com>\r
+In-Reply-To: <order-42@example.com>\r
+References: <order-1@example.com> <order-42@example.com>\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"b1\"\r
+\r
+--b1\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+Thanks for the update!\r
+--b1\r
+Content-Type: text/csv; name=\"items.csv\"\r
+Content-Disposition: attachment; filename=\"items.csv\"\r
+Content-Transfer-Encoding: base64\r
+\r
+aWQsbmFtZQ==\r
+--b1--\r
+";
+
+    /// Keeps the subjects of the processed emails.
+    #[derive(Clone, Default)]
+    pub struct Recorder(pub Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Mailbox for Recorder {
+        async fn process(&self, _ctx: &AppContext, email: &InboundEmail) -> Result<()> {
+            self.0.lock().await.push(email.subject.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn can_parse_message() {
+        let email = InboundEmail::parse(MESSAGE.as_bytes()).unwrap();
+        assert_eq!(email.message_id.as_deref(), Some("reply-1@example.com"));
+        assert_eq!(email.from.as_deref(), Some("jane@example.com"));
+        assert_eq!(email.from_name.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            email.to,
+            vec!["support@example.com", "reply-42@reply.example.com"]
+        );
+        assert_eq!(email.cc, vec!["team@example.com"]);
+        assert_eq!(email.subject, "Re: Order #42");
+        assert_eq!(email.text.trim(), "Thanks for the update!");
+        assert_eq!(email.in_reply_to.as_deref(), Some("order-42@example.com"));
+        assert_eq!(
+            email.references,
+            vec!["order-1@example.com", "order-42@example.com"]
+        );
+        assert_eq!(email.header("message-id"), Some("<reply-1@example.com>"));
+        assert_eq!(
+            email.attachments,
+            vec![InboundAttachment {
+                filename: "items.csv".to_string(),
+                content_type: "text/csv".to_string(),
+                content_id: None,
+                content: b"id,name".to_vec(),
+            }]
+        );
+
+        assert!(InboundEmail::parse(b"").is_err());
+    }
+
+    #[rstest::rstest]
+    #[case("support@example.com", "support@example.com", true)]
+    #[case("support@example.com", "Support@Example.com", true)]
+    #[case("reply-*@reply.example.com", "reply-42@reply.example.com", true)]
+    #[case("reply-*@reply.example.com", "reply-@reply.example.com", true)]
+    #[case("reply-*@reply.example.com", "reply-42@example.com", false)]
+    #[case("*@example.com", "support@example.com", true)]
+    #[case("*@example.com", "support@example.com.evil", false)]
+    #[case("*", "anyone@anywhere", true)]
+    fn can_match_patterns(#[case] pattern: &str, #[case] value: &str, #[case] expected: bool) {
+        assert_eq!(matches_pattern(pattern, value), expected);
+    }
+
+    #[tokio::test]
+    async fn can_route_to_mailboxes() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let (support, replies, fallback) = (
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        );
+
+        let mut mailboxes = Mailboxes::default();
+        mailboxes.route("reply-*@reply.example.com", replies.clone());
+        mailboxes.route("support@example.com", support.clone());
+        mailboxes
+            .process(&ctx, &InboundEmail::parse(MESSAGE.as_bytes()).unwrap())
+            .await
+            .unwrap();
+
+        let other = InboundEmail {
+            subject: "Hello".to_string(),
+            recipients: vec!["sales@example.com".to_string()],
+            ..Default::default()
+        };
+        mailboxes.process(&ctx, &other).await.unwrap();
+        mailboxes.fallback(fallback.clone());
+        mailboxes.process(&ctx, &other).await.unwrap();
+
+        assert_eq!(*replies.0.lock().await, vec!["Re: Order #42"]);
+        assert!(support.0.lock().await.is_empty());
+        assert_eq!(*fallback.0.lock().await, vec!["Hello"]);
+
+        let bcc = InboundEmail {
+            headers: vec![(
+                "Delivered-To".to_string(),
+                "<Support@example.com>".to_string(),
+            )],
+            ..Default::default()
+        };
+        assert_eq!(
+            bcc.recipient_matching("support@*"),
+            Some("support@example.com".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_receive_through_the_webhook() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        let recorder = Recorder::default();
+        let mut mailboxes = Mailboxes::default();
+        mailboxes.route("support@example.com", recorder.clone());
+        ctx.shared_store.insert(Arc::new(mailboxes));
+
+        let mut app = Router::new();
+        for handler in routes().handlers {
+            app = app.route(
+                &format!("{}{}", DEFAULT_ROUTE_PREFIX, handler.uri),
+                handler.method,
+            );
+        }
+        let post = |app: Router<AppContext>, ctx: AppContext, token: &str, body: &str| {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/_loco/inbound/")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            async move {
+                app.with_state(ctx)
+                    .oneshot(request)
+                    .await
+                    .unwrap()
+                    .status()
+                    .as_u16()
+            }
+        };
+
+        assert_eq!(post(app.clone(), ctx.clone(), "secret", MESSAGE).await, 404);
+
+        ctx.config.mailer = Some(config::Mailer {
+            inbound: Some(config::InboundMailer {
+                webhook: Some(config::InboundWebhook {
+                    token: "secret".to_string(),
+                }),
+                smtp: None,
+            }),
+            ..Default::default()
+        });
+        assert_eq!(post(app.clone(), ctx.clone(), "wrong", MESSAGE).await, 401);
+        assert_eq!(post(app.clone(), ctx.clone(), "secret", "").await, 400);
+        assert_eq!(post(app, ctx, "secret", MESSAGE).await, 202);
+
+        assert_eq!(*recorder.0.lock().await, vec!["Re: Order #42"]);
+    }
+}
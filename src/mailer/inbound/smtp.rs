@@ -0,0 +1,532 @@
+//! A minimal SMTP listener receiving emails into the [`super::Mailboxes`],
+//! started along with the server when `mailer.inbound.smtp` is configured.
+//!
+//! It accepts plain SMTP only, and is meant to sit behind the MX of a
+//! domain, or a relay terminating TLS. Messages are queued as soon as they
+//! are received, and processed by the [`super::InboundWorker`].
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::Semaphore,
+};
+use tracing::{debug, error, info};
+
+use crate::{app::AppContext, config::InboundSmtp, Error, Result};
+
+/// Longest accepted command line, from RFC 5321.
+const MAX_LINE_LENGTH: u64 = 1000;
+/// Most recipients of a single message, from RFC 5321.
+const MAX_RECIPIENTS: usize = 100;
+/// Pause after a failed accept, so that running out of file descriptors does
+/// not spin the listener.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts SMTP connections and queues the received messages.
+pub struct SmtpListener {
+    listener: TcpListener,
+    config: InboundSmtp,
+    ctx: AppContext,
+}
+
+impl SmtpListener {
+    /// Listens on the configured binding and port.
+    ///
+    /// # Errors
+    ///
+    /// When the address could not be bound.
+    pub async fn bind(ctx: &AppContext, config: &InboundSmtp) -> Result<Self> {
+        let listener = TcpListener::bind((config.binding.as_str(), config.port)).await?;
+        Ok(Self {
+            listener,
+            config: config.clone(),
+            ctx: ctx.clone(),
+        })
+    }
+
+    /// Returns the address the listener is bound to.
+    ///
+    /// # Errors
+    ///
+    /// When the address could not be read.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until the task is dropped, handling each of them
+    /// in its own task, up to `max_sessions` at once.
+    ///
+    /// # Errors
+    ///
+    /// When the address of the listener could not be read.
+    pub async fn run(self) -> Result<()> {
+        info!(
+            address = %self.local_addr()?,
+            "listening for inbound emails over SMTP"
+        );
+        let sessions = Arc::new(Semaphore::new(self.config.max_sessions));
+        loop {
+            let (mut stream, peer) = match self.listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    // such as running out of file descriptors, or a client
+                    // resetting the connection before it was accepted
+                    error!(err = err.to_string(), "could not accept an SMTP connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            let Ok(permit) = sessions.clone().try_acquire_owned() else {
+                debug!(%peer, "too many inbound SMTP sessions, turning the client away");
+                let _ = timeout(self.config.read_timeout, async {
+                    Ok(stream
+                        .write_all(b"421 too many connections, try again later\r\n")
+                        .await?)
+                })
+                .await;
+                continue;
+            };
+            let (ctx, config) = (self.ctx.clone(), self.config.clone());
+            tokio::spawn(async move {
+                if let Err(err) = session(stream, &ctx, &config).await {
+                    debug!(err = err.to_string(), %peer, "inbound SMTP session ended");
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// Extracts the address of a `MAIL FROM:<...>` or `RCPT TO:<...>` argument,
+/// along with its parameters.
+fn path_argument<'a>(argument: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    if !argument
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    {
+        return None;
+    }
+    let rest = argument.get(prefix.len()..)?.trim_start();
+    let rest = rest.strip_prefix('<')?;
+    let (address, parameters) = rest.split_once('>')?;
+    Some((address.trim(), parameters.trim()))
+}
+
+fn accepts_domain(config: &InboundSmtp, address: &str) -> bool {
+    if config.domains.is_empty() {
+        return true;
+    }
+    address.rsplit_once('@').is_some_and(|(_, domain)| {
+        config
+            .domains
+            .iter()
+            .any(|accepted| accepted.eq_ignore_ascii_case(domain))
+    })
+}
+
+/// Fails when the future does not complete within `millis`.
+async fn timeout<T>(millis: u64, future: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(Duration::from_millis(millis), future)
+        .await
+        .map_err(|_| Error::string("SMTP client timed out"))?
+}
+
+/// Reads a line of at most `limit` bytes, returning `false` at the end of the
+/// stream.
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    line: &mut Vec<u8>,
+    limit: u64,
+) -> Result<bool> {
+    line.clear();
+    let read = (&mut *reader).take(limit).read_until(b'\n', line).await?;
+    if read == 0 {
+        return Ok(false);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(Error::string("SMTP line too long"));
+    }
+    Ok(true)
+}
+
+/// Reads the message following `DATA` until the `.` line, returning `None`
+/// when it is larger than `max_size`. Lines are read in chunks of at most
+/// [`MAX_LINE_LENGTH`] bytes, each within `read_timeout`.
+async fn read_data<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    max_size: usize,
+    read_timeout: u64,
+) -> Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+    let mut line = vec![];
+    let mut too_large = false;
+    // whether the chunk starts a line, rather than continuing a long one
+    let mut line_start = true;
+    loop {
+        line.clear();
+        let read = timeout(read_timeout, async {
+            let mut chunk = (&mut *reader).take(MAX_LINE_LENGTH);
+            Ok(chunk.read_until(b'\n', &mut line).await?)
+        });
+        if read.await? == 0 {
+            return Err(Error::string("connection closed while reading data"));
+        }
+        if line_start && (line == b".\r\n" || line == b".\n") {
+            break;
+        }
+        let content = if line_start {
+            line.strip_prefix(b".").unwrap_or(&line)
+        } else {
+            &line
+        };
+        line_start = line.ends_with(b"\n");
+        if data.len() + content.len() > max_size {
+            too_large = true;
+            data.clear();
+        }
+        if !too_large {
+            data.extend_from_slice(content);
+        }
+    }
+    Ok((!too_large).then_some(data))
+}
+
+/// Talks SMTP on a connection.
+async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    ctx: &AppContext,
+    config: &InboundSmtp,
+) -> Result<()> {
+    let hostname = config.hostname.as_deref().unwrap_or(&config.binding);
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let mut sender: Option<String> = None;
+    let mut recipients: Vec<String> = vec![];
+    let mut line = vec![];
+
+    writer
+        .write_all(format!("220 {hostname} ESMTP ready\r\n").as_bytes())
+        .await?;
+    while timeout(
+        config.idle_timeout,
+        read_line(&mut reader, &mut line, MAX_LINE_LENGTH),
+    )
+    .await?
+    {
+        let command = String::from_utf8_lossy(&line);
+        let command = command.trim_end();
+        let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+
+        let reply = match verb.to_uppercase().as_str() {
+            "EHLO" => format!(
+                "250-{hostname}\r\n250-SIZE {}\r\n250-8BITMIME\r\n250 PIPELINING",
+                config.max_size
+            ),
+            "HELO" => format!("250 {hostname}"),
+            "MAIL" => match path_argument(argument, "FROM:") {
+                Some((_, parameters))
+                    if parameters
+                        .split_whitespace()
+                        .filter_map(|parameter| parameter.strip_prefix("SIZE="))
+                        .any(|size| size.parse::<usize>().unwrap_or(0) > config.max_size) =>
+                {
+                    "552 5.3.4 Message size exceeds fixed limit".to_string()
+                }
+                Some((address, _)) => {
+                    sender = Some(address.to_string());
+                    recipients.clear();
+                    "250 2.1.0 OK".to_string()
+                }
+                None => "501 5.5.4 Syntax: MAIL FROM:<address>".to_string(),
+            },
+            "RCPT" => match path_argument(argument, "TO:") {
+                _ if sender.is_none() => "503 5.5.1 Need MAIL command".to_string(),
+                _ if recipients.len() >= MAX_RECIPIENTS => {
+                    "452 4.5.3 Too many recipients".to_string()
+                }
+                Some((address, _)) if accepts_domain(config, address) => {
+                    recipients.push(address.to_lowercase());
+                    "250 2.1.5 OK".to_string()
+                }
+                Some(_) => "550 5.1.1 Mailbox unavailable".to_string(),
+                None => "501 5.5.4 Syntax: RCPT TO:<address>".to_string(),
+            },
+            "DATA" if recipients.is_empty() => "503 5.5.1 Need RCPT command".to_string(),
+            "DATA" => {
+                writer
+                    .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                    .await?;
+                let reply = match read_data(&mut reader, config.max_size, config.read_timeout)
+                    .await?
+                {
+                    None => "552 5.3.4 Message size exceeds fixed limit".to_string(),
+                    Some(data) => {
+                        match super::receive(ctx, data, std::mem::take(&mut recipients)).await {
+                            Ok(()) => "250 2.0.0 OK queued".to_string(),
+                            Err(Error::BadRequest(_)) => "554 5.6.0 Invalid message".to_string(),
+                            Err(err) => {
+                                error!(err = err.to_string(), "could not queue inbound email");
+                                "451 4.3.0 Temporary failure".to_string()
+                            }
+                        }
+                    }
+                };
+                sender = None;
+                recipients.clear();
+                reply
+            }
+            "RSET" => {
+                sender = None;
+                recipients.clear();
+                "250 2.0.0 OK".to_string()
+            }
+            "NOOP" => "250 2.0.0 OK".to_string(),
+            "VRFY" => "252 2.1.5 Cannot verify".to_string(),
+            "QUIT" => {
+                writer.write_all(b"221 2.0.0 Bye\r\n").await?;
+                return Ok(());
+            }
+            _ => "500 5.5.2 Command not recognized".to_string(),
+        };
+        writer.write_all(format!("{reply}\r\n").as_bytes()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::*;
+    use crate::{
+        mailer::inbound::{
+            tests::{Recorder, MESSAGE},
+            Mailboxes,
+        },
+        tests_cfg,
+    };
+
+    fn config() -> InboundSmtp {
+        InboundSmtp {
+            binding: "127.0.0.1".to_string(),
+            port: 0,
+            hostname: Some("mx.example.com".to_string()),
+            max_size: 4096,
+            domains: vec!["example.com".to_string(), "reply.example.com".to_string()],
+            idle_timeout: 1000,
+            read_timeout: 100,
+            max_sessions: 2,
+        }
+    }
+
+    /// Reads a reply, which spans several lines when they continue with `-`.
+    async fn read_reply<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> String {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            reply.push_str(&line);
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                break;
+            }
+        }
+        reply.trim_end().to_string()
+    }
+
+    /// Sends commands over an in-memory connection, returning the replies.
+    async fn talk(ctx: &AppContext, commands: &[&str]) -> Vec<String> {
+        let (client, server) = duplex(64 * 1024);
+        let (ctx, config) = (ctx.clone(), config());
+        let server = tokio::spawn(async move { session(server, &ctx, &config).await });
+
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut reader = BufReader::new(reader);
+        let mut replies = vec![read_reply(&mut reader).await];
+        for command in commands {
+            writer.write_all(command.as_bytes()).await.unwrap();
+            replies.push(read_reply(&mut reader).await);
+        }
+        drop((reader, writer));
+        server.await.unwrap().unwrap();
+        replies
+    }
+
+    #[tokio::test]
+    async fn can_receive_emails() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let recorder = Recorder::default();
+        let mut mailboxes = Mailboxes::default();
+        mailboxes.route("reply-*@reply.example.com", recorder.clone());
+        ctx.shared_store.insert(Arc::new(mailboxes));
+
+        let data = format!("{}.\r\n", MESSAGE.replace("\r\n--b1--", "\r\n..\r\n--b1--"));
+        let replies = talk(
+            &ctx,
+            &[
+                "EHLO client.example.org\r\n",
+                "RCPT TO:<reply-42@reply.example.com>\r\n",
+                "MAIL FROM:<jane@example.org>\r\n",
+                "RCPT TO:<someone@elsewhere.org>\r\n",
+                "RCPT TO:<Reply-42@reply.example.com>\r\n",
+                "DATA\r\n",
+                &data,
+                "QUIT\r\n",
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            replies,
+            vec![
+                "220 mx.example.com ESMTP ready",
+                "250-mx.example.com\r\n250-SIZE 4096\r\n250-8BITMIME\r\n250 PIPELINING",
+                "503 5.5.1 Need MAIL command",
+                "250 2.1.0 OK",
+                "550 5.1.1 Mailbox unavailable",
+                "250 2.1.5 OK",
+                "354 End data with <CR><LF>.<CR><LF>",
+                "250 2.0.0 OK queued",
+                "221 2.0.0 Bye",
+            ]
+        );
+        assert_eq!(*recorder.0.lock().await, vec!["Re: Order #42"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_out_of_order_commands_and_large_messages() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let large = format!("Subject: large\r\n\r\n{}\r\n.\r\n", "a".repeat(5000));
+        let replies = talk(
+            &ctx,
+            &[
+                "HELO client.example.org\r\n",
+                "MAIL FROM:<jane@example.org> SIZE=5000\r\n",
+                "MAIL FROM:<jane@example.org>\r\n",
+                "DATA\r\n",
+                "RCPT TO:<support@example.com>\r\n",
+                "DATA\r\n",
+                &large,
+                "RSET\r\n",
+                "HACK\r\n",
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            replies,
+            vec![
+                "220 mx.example.com ESMTP ready",
+                "250 mx.example.com",
+                "552 5.3.4 Message size exceeds fixed limit",
+                "250 2.1.0 OK",
+                "503 5.5.1 Need RCPT command",
+                "250 2.1.5 OK",
+                "354 End data with <CR><LF>.<CR><LF>",
+                "552 5.3.4 Message size exceeds fixed limit",
+                "250 2.0.0 OK",
+                "500 5.5.2 Command not recognized",
+            ]
+        );
+    }
+
+    #[test]
+    fn can_parse_path_arguments() {
+        assert_eq!(
+            path_argument("FROM:<jane@example.org> SIZE=100", "FROM:"),
+            Some(("jane@example.org", "SIZE=100"))
+        );
+        assert_eq!(
+            path_argument("to: <Jane@Example.org>", "TO:"),
+            Some(("Jane@Example.org", ""))
+        );
+        assert_eq!(path_argument("TOÖ:<jane@example.org>", "TO:"), None);
+        assert_eq!(path_argument("FRÖM", "FROM:"), None);
+        assert_eq!(path_argument("TO:jane@example.org", "TO:"), None);
+    }
+
+    #[tokio::test]
+    async fn can_read_long_data_lines() {
+        let long = format!("{}.\r\n", "a".repeat(1000));
+        let input = format!("Subject: long\r\n\r\n..b\r\n{long}.\r\n");
+        let data = read_data(&mut BufReader::new(input.as_bytes()), 4096, 100)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            format!("Subject: long\r\n\r\n.b\r\n{long}")
+        );
+
+        let large = format!("{}\r\n.\r\n", "a".repeat(5000));
+        let data = read_data(&mut BufReader::new(large.as_bytes()), 4096, 100)
+            .await
+            .unwrap();
+        assert!(data.is_none());
+    }
+
+    #[tokio::test]
+    async fn disconnects_idle_and_slow_clients() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let start = |ctx: &AppContext| {
+            let (client, server) = duplex(64 * 1024);
+            let (ctx, config) = (ctx.clone(), config());
+            let server = tokio::spawn(async move { session(server, &ctx, &config).await });
+            let (reader, writer) = tokio::io::split(client);
+            (BufReader::new(reader), writer, server)
+        };
+
+        let (mut reader, _writer, server) = start(&ctx);
+        read_reply(&mut reader).await;
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "SMTP client timed out");
+
+        let (mut reader, mut writer, server) = start(&ctx);
+        read_reply(&mut reader).await;
+        for command in [
+            "HELO client.example.org\r\n",
+            "MAIL FROM:<jane@example.org>\r\n",
+            "RCPT TO:<support@example.com>\r\n",
+            "DATA\r\n",
+        ] {
+            writer.write_all(command.as_bytes()).await.unwrap();
+            read_reply(&mut reader).await;
+        }
+        writer.write_all(b"Subject: never ending").await.unwrap();
+        let started = std::time::Instant::now();
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "SMTP client timed out");
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn can_limit_concurrent_sessions() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let listener = SmtpListener::bind(&ctx, &config()).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(listener.run());
+
+        let connect = || async move {
+            let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let reply = read_reply(&mut reader).await;
+            (reader, reply)
+        };
+
+        let (first, reply) = connect().await;
+        assert!(reply.starts_with("220 "));
+        let (_second, reply) = connect().await;
+        assert!(reply.starts_with("220 "));
+        let (_, reply) = connect().await;
+        assert!(reply.starts_with("421 "));
+
+        // the session of a disconnected client is released
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (_, reply) = connect().await;
+        assert!(reply.starts_with("220 "));
+    }
+}
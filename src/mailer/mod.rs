@@ -2,21 +2,44 @@
 //! trait and its implementation, `Email` structure, and the `MailerWorker` for
 //! asynchronous email processing.
 
+mod attachment;
+pub mod bulk;
+pub mod catcher;
+#[cfg(feature = "mailer_dkim")]
+mod dkim;
 mod email_sender;
+#[cfg(feature = "mailer_html")]
+pub mod html;
+#[cfg(feature = "mailer_http")]
+mod http;
+#[cfg(feature = "mailer_inbound")]
+pub mod inbound;
+pub mod preview;
 mod template;
+#[cfg(feature = "with-db")]
+pub mod tracking;
+
+use std::collections::{hash_map::Entry, HashMap};
 
 use async_trait::async_trait;
-pub use email_sender::EmailSender;
+pub use attachment::{Attachment, AttachmentContent};
+use bulk::{BulkArgs, BulkChunk, BulkMailerWorker};
+pub use email_sender::{EmailSender, EmailTransport, MailTransport};
+#[cfg(feature = "mailer_http")]
+pub use http::HttpMailerError;
 use include_dir::Dir;
 use serde::{Deserialize, Serialize};
-use tracing::error;
+pub use template::{Content, Renderer, Template};
+use tracing::{error, warn};
 
-use self::template::Template;
 use super::{app::AppContext, Result};
 use crate::prelude::BackgroundWorker;
 
 pub const DEFAULT_FROM_SENDER: &str = "System <system@example.com>";
 
+/// Above this size, attachment contents in the queue payload log a warning.
+const LARGE_ATTACHMENTS_SIZE: usize = 256 * 1024;
+
 /// The arguments struct for specifying email details such as sender, recipient,
 /// reply-to, and locals.
 #[derive(Debug, Clone, Default)]
@@ -27,6 +50,10 @@ pub struct Args {
     pub locals: serde_json::Value,
     pub bcc: Option<String>,
     pub cc: Option<String>,
+    pub attachments: Vec<Attachment>,
+    /// Renders the localized template files, such as `html.de-DE.t`, and is
+    /// available to the templates as `locale`
+    pub locale: Option<String>,
 }
 
 /// The structure representing an email details.
@@ -48,6 +75,28 @@ pub struct Email {
     pub bcc: Option<String>,
     /// CC header to message
     pub cc: Option<String>,
+    /// Attached files and inline images
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// `Message-ID` header without the angle brackets, generated when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+}
+
+impl Email {
+    /// Reads the contents of the attachments kept in the storage.
+    ///
+    /// # Errors
+    ///
+    /// When an attachment could not be read from the storage.
+    pub async fn load_attachments(mut self, storage: &crate::storage::Storage) -> Result<Self> {
+        let mut attachments = Vec::with_capacity(self.attachments.len());
+        for attachment in self.attachments {
+            attachments.push(attachment.load(storage).await?);
+        }
+        self.attachments = attachments;
+        Ok(self)
+    }
 }
 
 /// The options struct for configuring the email sender.
@@ -56,6 +105,13 @@ pub struct Email {
 pub struct MailerOpts {
     pub from: String,
     pub reply_to: Option<String>,
+    /// Shared layouts and partials of the templates, such as
+    /// `include_dir!("src/mailers/layouts")`
+    pub layouts: Option<&'static Dir<'static>>,
+    /// Inlines the `<style>` rules of the HTML templates into `style`
+    /// attributes, for the email clients ignoring `<style>`. Requires the
+    /// `mailer_html` feature, ignored without it.
+    pub inline_css: bool,
 }
 
 /// The `Mailer` trait defines methods for sending emails and processing email
@@ -71,6 +127,29 @@ pub trait Mailer {
         }
     }
 
+    /// Registers additional Tera filters and functions for the templates, such
+    /// as the `t` function of the view engine.
+    fn configure_tera(_tera: &mut tera::Tera) {}
+
+    /// Returns the template of the given directory, with the layouts and
+    /// functions of the mailer.
+    #[must_use]
+    #[allow(clippy::let_and_return)]
+    fn template<'a>(dir: &'a Dir<'a>, locale: Option<&str>) -> Template<'a> {
+        let opts = Self::opts();
+        let template = Template::new(dir)
+            .layouts(opts.layouts)
+            .locale(locale)
+            .configure(Self::configure_tera);
+        #[cfg(feature = "mailer_html")]
+        let template = template.inline_css(opts.inline_css);
+        #[cfg(not(feature = "mailer_html"))]
+        if opts.inline_css {
+            warn!("`inline_css` is set, but loco-rs is built without the `mailer_html` feature");
+        }
+        template
+    }
+
     /// Sends an email using the provided [`AppContext`] and email details.
     async fn mail(ctx: &AppContext, email: &Email) -> Result<()> {
         let opts = Self::opts();
@@ -79,6 +158,15 @@ pub trait Mailer {
         email.from = Some(email.from.unwrap_or_else(|| opts.from.clone()));
         email.reply_to = email.reply_to.or_else(|| opts.reply_to.clone());
 
+        let attachments_size: usize = email.attachments.iter().map(Attachment::inline_size).sum();
+        if attachments_size > LARGE_ATTACHMENTS_SIZE {
+            warn!(
+                size = attachments_size,
+                "large attachments are serialized into the mailer queue, upload them to the \
+                 storage and use `Attachment::storage` instead"
+            );
+        }
+
         MailerWorker::perform_later(ctx, email.clone()).await?;
         Ok(())
     }
@@ -86,7 +174,7 @@ pub trait Mailer {
     /// Renders and sends an email using the provided [`AppContext`], template
     /// directory, and arguments.
     async fn mail_template(ctx: &AppContext, dir: &Dir<'_>, args: Args) -> Result<()> {
-        let content = Template::new(dir).render(&args.locals)?;
+        let content = Self::template(dir, args.locale.as_deref()).render(&args.locals)?;
         Self::mail(
             ctx,
             &Email {
@@ -98,10 +186,111 @@ pub trait Mailer {
                 html: content.html,
                 bcc: args.bcc.clone(),
                 cc: args.cc.clone(),
+                attachments: args.attachments,
+                message_id: None,
             },
         )
         .await
     }
+
+    /// Renders the template for each recipient, with its locals merged over
+    /// the shared locals, and sends the emails in jobs of
+    /// `mailer.bulk.chunk_size` emails. Nothing is sent when an email could
+    /// not be rendered. Returns the id of the bulk send, to
+    /// follow its [`bulk::progress`].
+    async fn mail_bulk(ctx: &AppContext, dir: &Dir<'_>, args: BulkArgs) -> Result<String> {
+        let opts = Self::opts();
+        let chunk_size = ctx
+            .config
+            .mailer
+            .as_ref()
+            .and_then(|config| config.bulk.clone())
+            .unwrap_or_default()
+            .chunk_size
+            .max(1);
+
+        let attachments_size: usize = args.attachments.iter().map(Attachment::inline_size).sum();
+        if attachments_size * chunk_size > LARGE_ATTACHMENTS_SIZE {
+            warn!(
+                size = attachments_size,
+                "attachments are copied into each email of the bulk jobs, upload them to the \
+                 storage and use `Attachment::storage` instead"
+            );
+        }
+
+        // every email is rendered before enqueuing any, so a failed render
+        // sends nothing
+        let mut renderers = HashMap::new();
+        let mut emails = Vec::with_capacity(args.recipients.len());
+        for recipient in &args.recipients {
+            let locale = recipient.locale.clone().or_else(|| args.locale.clone());
+            let renderer = match renderers.entry(locale) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let renderer = Self::template(dir, entry.key().as_deref()).renderer()?;
+                    entry.insert(renderer)
+                }
+            };
+            let content = renderer.render(&bulk::merge_locals(&args.locals, &recipient.locals))?;
+            emails.push(Email {
+                from: Some(args.from.clone().unwrap_or_else(|| opts.from.clone())),
+                to: recipient.to.clone(),
+                reply_to: args.reply_to.clone().or_else(|| opts.reply_to.clone()),
+                subject: content.subject,
+                text: content.text,
+                html: content.html,
+                bcc: None,
+                cc: None,
+                attachments: args.attachments.clone(),
+                message_id: None,
+            });
+        }
+        drop(renderers);
+
+        let total = emails.len();
+        let mut chunks = vec![];
+        let mut emails = emails.into_iter().peekable();
+        while emails.peek().is_some() {
+            chunks.push(emails.by_ref().take(chunk_size).collect::<Vec<_>>());
+        }
+
+        let id = bulk::start(ctx, total, chunks.len()).await;
+        for (index, emails) in chunks.into_iter().enumerate() {
+            BulkMailerWorker::perform_later(
+                ctx,
+                BulkChunk {
+                    id: id.clone(),
+                    index,
+                    emails,
+                },
+            )
+            .await?;
+        }
+        Ok(id)
+    }
+}
+
+/// Sends the email, through the delivery log when `mailer.tracking` is
+/// configured, which asks for the transient failures to be enqueued again.
+#[cfg_attr(not(feature = "with-db"), allow(unused_variables))]
+async fn deliver(ctx: &AppContext, mailer: &EmailSender, email: &Email) -> Result<()> {
+    #[cfg(feature = "with-db")]
+    if let Some(tracking) = ctx
+        .config
+        .mailer
+        .as_ref()
+        .and_then(|config| config.tracking.as_ref())
+    {
+        let attempt = tracking::DeliveryLog::from_context(ctx)
+            .deliver(mailer, email, tracking)
+            .await?;
+        if let tracking::Attempt::Retry { email, delay } = attempt {
+            // the retry is a new job, kept by the queue until it is due
+            MailerWorker::perform_in(ctx, *email, delay).await?;
+        }
+        return Ok(());
+    }
+    mailer.mail(email).await
 }
 
 /// The [`MailerWorker`] struct represents a worker responsible for asynchronous
@@ -126,7 +315,10 @@ impl BackgroundWorker<Email> for MailerWorker {
     /// and email details.
     async fn perform(&self, email: Email) -> crate::Result<()> {
         if let Some(mailer) = &self.ctx.mailer {
-            let res = mailer.mail(&email).await;
+            let res = match email.load_attachments(&self.ctx.storage).await {
+                Ok(email) => deliver(&self.ctx, mailer, &email).await,
+                Err(err) => Err(err),
+            };
             match res {
                 Ok(res) => Ok(res),
                 Err(err) => {
@@ -0,0 +1,322 @@
+//! Renders mailer templates with sample locals, to iterate on them without
+//! sending anything.
+//!
+//! ```rust,ignore
+//! impl MailerPreview for AuthMailer {
+//!     fn name() -> String {
+//!         "auth".to_string()
+//!     }
+//!
+//!     fn previews(ctx: &AppContext) -> Vec<Preview> {
+//!         vec![Preview::new(
+//!             "welcome",
+//!             &welcome,
+//!             json!({"name": "Jane", "verifyToken": "token", "domain": ctx.config.server.full_url()}),
+//!         )]
+//!     }
+//! }
+//! ```
+//!
+//! Register the mailers in [`Hooks::register_mailer_previews`], then run
+//! `cargo loco mailer preview auth/welcome`, or mount [`routes`] to see them
+//! at [`DEFAULT_ROUTE_PREFIX`].
+use std::{collections::BTreeMap, fmt::Write};
+
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use include_dir::Dir;
+
+use super::{
+    catcher::{escape, page},
+    Content, Mailer, Template,
+};
+use crate::{
+    app::{AppContext, Hooks},
+    controller::Routes,
+    environment::Environment,
+    Error, Result,
+};
+
+/// The path the mailer preview routes are mounted on.
+pub const DEFAULT_ROUTE_PREFIX: &str = "/_loco/mailers";
+
+type TemplateFn = fn(&'static Dir<'static>, Option<&str>) -> Template<'static>;
+
+/// A mailer template along with sample locals.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub name: String,
+    pub template: &'static Dir<'static>,
+    pub locals: serde_json::Value,
+    pub locale: Option<String>,
+    /// Builds the template with the layouts and functions of the mailer, set
+    /// when the mailer is registered
+    template_fn: TemplateFn,
+}
+
+impl Preview {
+    #[must_use]
+    pub fn new(name: &str, template: &'static Dir<'static>, locals: serde_json::Value) -> Self {
+        Self {
+            name: name.to_string(),
+            template,
+            locals,
+            locale: None,
+            template_fn: |dir, locale| Template::new(dir).locale(locale),
+        }
+    }
+
+    /// Renders the template files of the given locale.
+    #[must_use]
+    pub fn locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    /// Renders the subject, text and HTML of the template.
+    ///
+    /// # Errors
+    ///
+    /// When the template could not be rendered.
+    pub fn render(&self) -> Result<Content> {
+        (self.template_fn)(self.template, self.locale.as_deref()).render(&self.locals)
+    }
+}
+
+/// Provides sample emails of a mailer.
+pub trait MailerPreview: Mailer {
+    /// The name the previews are listed under, such as `auth`.
+    fn name() -> String;
+
+    /// The sample emails, one for each template of the mailer.
+    fn previews(ctx: &AppContext) -> Vec<Preview>;
+}
+
+/// The mailer previews of an application, by mailer name.
+#[derive(Default, Debug)]
+pub struct MailerPreviews {
+    registry: BTreeMap<String, Vec<Preview>>,
+}
+
+impl MailerPreviews {
+    /// Collects the previews registered in [`Hooks::register_mailer_previews`].
+    #[must_use]
+    pub fn from_hooks<H: Hooks>(ctx: &AppContext) -> Self {
+        let mut previews = Self::default();
+        H::register_mailer_previews(ctx, &mut previews);
+        previews
+    }
+
+    /// Registers the previews of a mailer.
+    pub fn register<M: MailerPreview>(&mut self, ctx: &AppContext) {
+        let previews = M::previews(ctx)
+            .into_iter()
+            .map(|preview| Preview {
+                template_fn: M::template,
+                ..preview
+            })
+            .collect();
+        self.registry.insert(M::name(), previews);
+    }
+
+    /// Lists the preview names, as `<mailer>/<preview>`.
+    #[must_use]
+    pub fn names(&self) -> Vec<String> {
+        self.registry
+            .iter()
+            .flat_map(|(mailer, previews)| {
+                previews
+                    .iter()
+                    .map(move |preview| format!("{mailer}/{}", preview.name))
+            })
+            .collect()
+    }
+
+    /// Finds a preview by its `<mailer>/<preview>` name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Preview> {
+        let (mailer, preview) = name.split_once('/')?;
+        self.registry
+            .get(mailer)?
+            .iter()
+            .find(|candidate| candidate.name == preview)
+    }
+}
+
+fn previews<H: Hooks + 'static>(ctx: &AppContext) -> Result<MailerPreviews> {
+    // previews show sample data and templates, keep them out of production
+    if !matches!(
+        ctx.environment,
+        Environment::Development | Environment::Test
+    ) {
+        return Err(Error::NotFound);
+    }
+    Ok(MailerPreviews::from_hooks::<H>(ctx))
+}
+
+fn render<H: Hooks + 'static>(ctx: &AppContext, name: &str) -> Result<Content> {
+    previews::<H>(ctx)?
+        .get(name)
+        .ok_or(Error::NotFound)?
+        .render()
+}
+
+async fn list<H: Hooks + 'static>(State(ctx): State<AppContext>) -> Result<Html<String>> {
+    let mut body = "<h1>Mailer previews</h1>\n<ul>\n".to_string();
+    for name in previews::<H>(&ctx)?.names() {
+        let _ = writeln!(
+            body,
+            r#"<li><a href="{DEFAULT_ROUTE_PREFIX}/{name}">{}</a></li>"#,
+            escape(&name)
+        );
+    }
+    body.push_str("</ul>");
+    Ok(page("Mailer previews", &body))
+}
+
+async fn show<H: Hooks + 'static>(
+    State(ctx): State<AppContext>,
+    Path((mailer, preview)): Path<(String, String)>,
+) -> Result<Html<String>> {
+    let name = format!("{mailer}/{preview}");
+    let content = render::<H>(&ctx, &name)?;
+    let base = format!("{DEFAULT_ROUTE_PREFIX}/{name}");
+    let body = format!(
+        r#"<p><a href="{DEFAULT_ROUTE_PREFIX}">&larr; All previews</a></p>
+<h1>{}</h1>
+<p><a href="{base}/html">HTML</a> | <a href="{base}/text">Text</a></p>
+<iframe sandbox src="{base}/html"></iframe>
+<h2>Text</h2>
+<pre>{}</pre>"#,
+        escape(&content.subject),
+        escape(&content.text)
+    );
+    Ok(page(&content.subject, &body))
+}
+
+async fn html<H: Hooks + 'static>(
+    State(ctx): State<AppContext>,
+    Path((mailer, preview)): Path<(String, String)>,
+) -> Result<Html<String>> {
+    Ok(Html(
+        render::<H>(&ctx, &format!("{mailer}/{preview}"))?.html,
+    ))
+}
+
+async fn text<H: Hooks + 'static>(
+    State(ctx): State<AppContext>,
+    Path((mailer, preview)): Path<(String, String)>,
+) -> Result<Response> {
+    let content = render::<H>(&ctx, &format!("{mailer}/{preview}"))?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        content.text,
+    )
+        .into_response())
+}
+
+/// Returns the routes rendering the mailer previews, mounted on
+/// [`DEFAULT_ROUTE_PREFIX`]. They answer `404` outside of the development
+/// and test environments.
+///
+/// ```rust,ignore
+/// fn routes(_ctx: &AppContext) -> AppRoutes {
+///     AppRoutes::with_default_routes()
+///         .add_route(controllers::auth::routes())
+///         .add_route(loco_rs::mailer::preview::routes::<Self>())
+/// }
+/// ```
+#[must_use]
+pub fn routes<H: Hooks + 'static>() -> Routes {
+    Routes::at(DEFAULT_ROUTE_PREFIX)
+        .add("/", get(list::<H>))
+        .add("/{mailer}/{preview}", get(show::<H>))
+        .add("/{mailer}/{preview}/html", get(html::<H>))
+        .add("/{mailer}/{preview}/text", get(text::<H>))
+}
+
+#[cfg(all(test, feature = "with-db"))]
+mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::tests_cfg::{self, db::AppHook};
+
+    #[tokio::test]
+    async fn can_render_registered_previews() {
+        let ctx = tests_cfg::app::get_app_context().await;
+        let previews = MailerPreviews::from_hooks::<AppHook>(&ctx);
+
+        assert_eq!(previews.names(), vec!["test/welcome".to_string()]);
+        assert!(previews.get("test/missing").is_none());
+        assert!(previews.get("welcome").is_none());
+
+        let content = previews.get("test/welcome").unwrap().render().unwrap();
+        assert_eq!(content.subject.trim(), "Test Preview <user>");
+        assert!(content.html.contains("/verify/1111-2222-3333-4444"));
+    }
+
+    #[tokio::test]
+    async fn can_serve_previews() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        let mut app = Router::new();
+        for handler in routes::<AppHook>().handlers {
+            app = app.route(
+                &format!("{}{}", DEFAULT_ROUTE_PREFIX, handler.uri),
+                handler.method,
+            );
+        }
+
+        let get = |app: Router, uri: &str| {
+            app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get(app.clone().with_state(ctx.clone()), "/_loco/mailers/")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("/_loco/mailers/test/welcome"));
+
+        let response = get(
+            app.clone().with_state(ctx.clone()),
+            "/_loco/mailers/test/welcome",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Test Preview &lt;user&gt;"));
+
+        let response = get(
+            app.clone().with_state(ctx.clone()),
+            "/_loco/mailers/test/welcome/text",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let response = get(
+            app.clone().with_state(ctx.clone()),
+            "/_loco/mailers/test/missing",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 404);
+
+        ctx.environment = Environment::Production;
+        let response = get(app.with_state(ctx), "/_loco/mailers/test/welcome")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
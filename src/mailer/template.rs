@@ -14,26 +14,51 @@
 //! let content = Template::new("contnt").render(&args);
 //! ```
 
-use include_dir::Dir;
-
-use crate::{errors::Error, tera, Result};
-
-/// The filename for the subject template file.
-const SUBJECT: &str = "subject.t";
-/// The filename for the HTML template file.
-const HTML: &str = "html.t";
-/// The filename for the plain text template file.
-const TEXT: &str = "text.t";
-
-/// Reads an embedded file from the provided directory and returns its content
-/// as a string.
-fn embedded_file(dir: &Dir<'_>, name: &str) -> Result<String> {
-    Ok(String::from_utf8_lossy(
-        dir.get_file(name)
-            .ok_or_else(|| Error::Message(format!("no mailer template file found {name}")))?
-            .contents(),
-    )
-    .to_string())
+use std::path::Path;
+
+use include_dir::{Dir, File};
+
+use crate::{controller::views::tera_builtins, errors::Error, Result};
+
+/// The name of the subject template file.
+const SUBJECT: &str = "subject";
+/// The name of the HTML template file.
+const HTML: &str = "html";
+/// The name of the plain text template file.
+const TEXT: &str = "text";
+/// The extension of template files.
+const EXTENSION: &str = "t";
+
+/// Finds the embedded template file for the given locale, falling back from
+/// `html.de-DE.t` to `html.de.t` and then to `html.t`.
+fn embedded_file<'a>(dir: &'a Dir<'a>, name: &str, locale: Option<&str>) -> Result<&'a File<'a>> {
+    let mut candidates = vec![];
+    if let Some(locale) = locale {
+        candidates.push(format!("{name}.{locale}.{EXTENSION}"));
+        if let Some((language, _)) = locale.split_once('-') {
+            candidates.push(format!("{name}.{language}.{EXTENSION}"));
+        }
+    }
+    candidates.push(format!("{name}.{EXTENSION}"));
+
+    candidates
+        .iter()
+        .find_map(|candidate| dir.get_file(dir.path().join(candidate)))
+        .ok_or_else(|| Error::Message(format!("no mailer template file found {name}.{EXTENSION}")))
+}
+
+/// Collects the files of a directory and its subdirectories.
+fn embedded_files<'a>(dir: &'a Dir<'a>, files: &mut Vec<&'a File<'a>>) {
+    files.extend(dir.files());
+    for dir in dir.dirs() {
+        embedded_files(dir, files);
+    }
+}
+
+const fn no_configure(_tera: &mut tera::Tera) {}
+
+fn contents(file: &File<'_>) -> String {
+    String::from_utf8_lossy(file.contents()).to_string()
 }
 
 /// A structure representing the content of an email, including subject, text,
@@ -50,25 +75,174 @@ pub struct Content {
 pub struct Template<'a> {
     /// The directory containing the embedded template files.
     dir: &'a Dir<'a>,
+    /// Shared layouts and partials, available to `{% extends %}` and
+    /// `{% include %}` by their path in the directory.
+    layouts: Option<&'a Dir<'a>>,
+    /// Selects the localized template files, such as `html.de-DE.t`.
+    locale: Option<String>,
+    /// Registers additional filters and functions.
+    configure: fn(&mut tera::Tera),
+    /// Inlines the `<style>` rules of the HTML into `style` attributes.
+    #[cfg(feature = "mailer_html")]
+    inline_css: bool,
 }
 
 impl<'a> Template<'a> {
     /// Creates a new `Template` instance with the provided directory.
     pub const fn new(dir: &'a Dir<'_>) -> Self {
-        Self { dir }
+        Self {
+            dir,
+            layouts: None,
+            locale: None,
+            configure: no_configure,
+            #[cfg(feature = "mailer_html")]
+            inline_css: false,
+        }
+    }
+
+    /// Makes the layouts and partials of the given directory available to the
+    /// templates.
+    #[must_use]
+    pub fn layouts(mut self, layouts: Option<&'a Dir<'a>>) -> Self {
+        self.layouts = layouts;
+        self
+    }
+
+    /// Renders the template files of the given locale when they exist.
+    #[must_use]
+    pub fn locale(mut self, locale: Option<&str>) -> Self {
+        self.locale = locale.map(ToString::to_string);
+        self
+    }
+
+    /// Registers additional filters and functions before rendering.
+    #[must_use]
+    pub fn configure(mut self, configure: fn(&mut tera::Tera)) -> Self {
+        self.configure = configure;
+        self
+    }
+
+    /// Inlines the `<style>` rules of the rendered HTML into the `style`
+    /// attributes of its elements, as many email clients ignore `<style>`.
+    #[cfg(feature = "mailer_html")]
+    #[must_use]
+    pub fn inline_css(mut self, inline_css: bool) -> Self {
+        self.inline_css = inline_css;
+        self
     }
 
     /// Renders the email content based on the provided locals using the
-    /// embedded templates.
+    /// embedded templates. Without a `text.t` file, the plain text is generated
+    /// from the HTML when the `mailer_html` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// When a template file is missing, or could not be parsed or rendered.
+    pub fn render(&self, locals: &serde_json::Value) -> Result<Content> {
+        self.renderer()?.render(locals)
+    }
+
+    /// Parses the embedded templates once, to render the content of many
+    /// emails, such as with [`super::Mailer::mail_bulk`].
+    ///
+    /// # Errors
+    ///
+    /// When a template file is missing or could not be parsed.
+    pub fn renderer(&self) -> Result<Renderer> {
+        let locale = self.locale.as_deref();
+        let subject = embedded_file(self.dir, SUBJECT, locale)?;
+        // the plain text can be generated from the HTML
+        let text = embedded_file(self.dir, TEXT, locale).ok();
+        let html = embedded_file(self.dir, HTML, locale)?;
+
+        let mut layouts = vec![];
+        if let Some(dir) = self.layouts {
+            embedded_files(dir, &mut layouts);
+        }
+        let mut templates = layouts
+            .iter()
+            .map(|file| {
+                let path = file
+                    .path()
+                    .strip_prefix(self.layouts.map_or(Path::new(""), Dir::path))
+                    .unwrap_or_else(|_| file.path());
+                (path.to_string_lossy().to_string(), contents(file))
+            })
+            .collect::<Vec<_>>();
+        for (name, file) in [(SUBJECT, Some(subject)), (TEXT, text), (HTML, Some(html))] {
+            if let Some(file) = file {
+                templates.push((format!("__mail__/{name}"), contents(file)));
+            }
+        }
+
+        let mut tera = tera::Tera::default();
+        // emails were always rendered without escaping
+        tera.autoescape_on(vec![]);
+        tera_builtins::filters::register_filters(&mut tera);
+        tera_builtins::functions::register_functions(&mut tera);
+        (self.configure)(&mut tera);
+        tera.add_raw_templates(templates)?;
+
+        Ok(Renderer {
+            tera,
+            locale: self.locale.clone(),
+            has_text: text.is_some(),
+            #[cfg(feature = "mailer_html")]
+            inline_css: self.inline_css,
+        })
+    }
+}
+
+/// The parsed templates of a [`Template`], rendering the content of emails.
+#[derive(Debug)]
+pub struct Renderer {
+    tera: tera::Tera,
+    locale: Option<String>,
+    has_text: bool,
+    #[cfg(feature = "mailer_html")]
+    inline_css: bool,
+}
+
+impl Renderer {
+    /// Renders the email content based on the provided locals.
+    ///
+    /// # Errors
+    ///
+    /// When a template could not be rendered.
     pub fn render(&self, locals: &serde_json::Value) -> Result<Content> {
-        let subject_t = embedded_file(self.dir, SUBJECT)?;
-        let text_t = embedded_file(self.dir, TEXT)?;
-        let html_t = embedded_file(self.dir, HTML)?;
+        let mut context = tera::Context::from_serialize(locals)?;
+        if let Some(locale) = &self.locale {
+            if !context.contains_key("locale") {
+                context.insert("locale", locale);
+            }
+        }
 
         // TODO(consider): check+consider offloading to tokio async this work
-        let text = tera::render_string(&text_t, locals)?;
-        let html = tera::render_string(&html_t, locals)?;
-        let subject = tera::render_string(&subject_t, locals)?;
+        let subject = self.tera.render(&format!("__mail__/{SUBJECT}"), &context)?;
+        let html = self.tera.render(&format!("__mail__/{HTML}"), &context)?;
+        let text = if self.has_text {
+            Some(self.tera.render(&format!("__mail__/{TEXT}"), &context)?)
+        } else {
+            None
+        };
+        let text = match text {
+            Some(text) => text,
+            #[cfg(feature = "mailer_html")]
+            None => super::html::to_text(&html),
+            #[cfg(not(feature = "mailer_html"))]
+            None => {
+                return Err(Error::Message(format!(
+                    "no mailer template file found {TEXT}.{EXTENSION}"
+                )))
+            }
+        };
+        #[cfg(feature = "mailer_html")]
+        let html = if self.inline_css {
+            super::html::inline_css(&html)
+        } else {
+            html
+        };
+
         Ok(Content {
             subject,
             text,
@@ -95,4 +269,87 @@ mod tests {
             Template::new(&include_dir!("tests/fixtures/email_template/test")).render(&args)
         );
     }
+
+    static LOCALIZED: Dir<'_> = include_dir!("tests/fixtures/email_template/localized");
+    static LAYOUTS: Dir<'_> = include_dir!("tests/fixtures/email_template/layouts");
+
+    #[rstest::rstest]
+    #[case(None, "Welcome Jane", "Welcome Jane")]
+    #[case(Some("en-US"), "Welcome Jane", "Welcome Jane")]
+    #[case(Some("de"), "Willkommen Jane", "Welcome Jane")]
+    #[case(Some("de-AT"), "Willkommen Jane", "Welcome Jane")]
+    #[case(Some("de-DE"), "Willkommen Jane", "Willkommen Jane (de-DE)")]
+    fn can_render_localized_template(
+        #[case] locale: Option<&str>,
+        #[case] subject: &str,
+        #[case] body: &str,
+    ) {
+        let content = Template::new(&LOCALIZED)
+            .layouts(Some(&LAYOUTS))
+            .locale(locale)
+            .render(&serde_json::json!({"name": "Jane"}))
+            .unwrap();
+        assert_eq!(content.subject.trim(), subject);
+        assert_eq!(
+            content.html.trim(),
+            format!("<html><body>{body}<footer>1,234,567 users</footer></body></html>")
+        );
+    }
+
+    #[test]
+    fn can_configure_tera() {
+        let content = Template::new(&include_dir!("tests/fixtures/email_template/configured"))
+            .configure(|tera| {
+                tera.register_filter("shout", |value: &tera::Value, _: &_| {
+                    Ok(tera::Value::String(
+                        value.as_str().unwrap_or_default().to_uppercase(),
+                    ))
+                })
+            })
+            .render(&serde_json::json!({"name": "Jane"}))
+            .unwrap();
+        assert_eq!(content.subject.trim(), "JANE");
+
+        // layouts are required by the templates extending them
+        assert!(Template::new(&LOCALIZED)
+            .render(&serde_json::json!({"name": "Jane"}))
+            .is_err());
+    }
+
+    #[test]
+    fn can_render_many_emails() {
+        let renderer = Template::new(&LOCALIZED)
+            .layouts(Some(&LAYOUTS))
+            .locale(Some("de-DE"))
+            .renderer()
+            .unwrap();
+        for name in ["Jane", "Joe"] {
+            let content = renderer.render(&serde_json::json!({"name": name})).unwrap();
+            assert_eq!(content.subject.trim(), format!("Willkommen {name}"));
+            assert!(content.html.contains(&format!("Willkommen {name} (de-DE)")));
+        }
+    }
+
+    #[cfg(feature = "mailer_html")]
+    #[test]
+    fn can_inline_css_and_generate_text() {
+        let styled = include_dir!("tests/fixtures/email_template/styled");
+        let args = serde_json::json!({"name": "Jane"});
+
+        let content = Template::new(&styled).render(&args).unwrap();
+        assert!(content.html.contains("<p>Welcome Jane"));
+        assert_eq!(
+            content.text,
+            "Welcome Jane, please verify your account (https://example.com/verify)."
+        );
+
+        let content = Template::new(&styled)
+            .inline_css(true)
+            .render(&args)
+            .unwrap();
+        assert!(content
+            .html
+            .contains(r#"<p style="color: #333333">Welcome Jane"#));
+        assert!(content.html.contains("a:hover{ color: red }"));
+    }
 }
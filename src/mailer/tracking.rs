@@ -0,0 +1,1331 @@
+//! Email delivery tracking and the suppression list.
+//!
+//! With `mailer.tracking` configured, the [`super::MailerWorker`] records
+//! every delivery attempt in the `loco_mail_deliveries` table, skips the
+//! recipients found in the `loco_mail_suppressions` table, and retries
+//! transient failures, such as SMTP `451` replies or HTTP `429` and `5xx`
+//! responses, with an exponential backoff, by enqueuing the email again.
+//!
+//! Email providers report bounces and complaints to the route returned by
+//! [`routes`]. Hard bounces and complaints add their recipient to the
+//! suppression list, so the app stops emailing dead addresses. The route
+//! understands the notifications of Amazon SES (through SNS), Postmark,
+//! SendGrid and Mailgun, and a generic format:
+//!
+//! ```json
+//! {"type": "hard_bounce", "recipient": "jane@example.com", "message_id": "...", "detail": "..."}
+//! ```
+//!
+//! The tables are created on first use when missing.
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use axum::{
+    body::Bytes,
+    extract::{Query as AxumQuery, State},
+    http::{header, HeaderMap, StatusCode},
+    routing::post,
+};
+use chrono::{DateTime, Utc};
+use lettre::message::Mailboxes;
+use sea_orm::{
+    sea_query::{Alias, ColumnDef, Expr, Func, Index, OnConflict, Order, Query, Table},
+    ConnectionTrait, DatabaseConnection, QueryResult,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::{Email, EmailSender, DEFAULT_FROM_SENDER};
+use crate::{app::AppContext, config, controller::Routes, db::LazySchema, hash, Error, Result};
+
+/// The path the bounce notifications route is mounted on.
+pub const DEFAULT_ROUTE_PREFIX: &str = "/_loco/mail/bounces";
+
+const DELIVERIES_TABLE: &str = "loco_mail_deliveries";
+const SUPPRESSIONS_TABLE: &str = "loco_mail_suppressions";
+
+/// The status of a delivery attempt to a recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Accepted by the transport
+    Sent,
+    /// Failed with a transient error, and retried
+    Deferred,
+    /// Failed, and not retried
+    Failed,
+    /// Not sent, as the recipient is in the suppression list
+    Suppressed,
+    /// Reported as bounced by the email provider
+    Bounced,
+    /// Reported as spam by the recipient
+    Complained,
+}
+
+impl DeliveryStatus {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Deferred => "deferred",
+            Self::Failed => "failed",
+            Self::Suppressed => "suppressed",
+            Self::Bounced => "bounced",
+            Self::Complained => "complained",
+        }
+    }
+
+    fn from_str(status: &str) -> Result<Self> {
+        Ok(match status {
+            "sent" => Self::Sent,
+            "deferred" => Self::Deferred,
+            "failed" => Self::Failed,
+            "suppressed" => Self::Suppressed,
+            "bounced" => Self::Bounced,
+            "complained" => Self::Complained,
+            _ => {
+                return Err(Error::string(&format!(
+                    "unknown delivery status `{status}`"
+                )))
+            }
+        })
+    }
+}
+
+/// A recorded delivery attempt, or a bounce notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delivery {
+    /// `Message-ID` of the email, without the angle brackets
+    pub message_id: Option<String>,
+    pub recipient: String,
+    pub status: DeliveryStatus,
+    pub error: Option<String>,
+    /// The attempt number, starting at `1`, or `0` for notifications
+    pub attempt: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why an address is in the suppression list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    /// The address hard bounced
+    Bounce,
+    /// The recipient reported an email as spam
+    Complaint,
+    /// Added by the application
+    Manual,
+}
+
+impl SuppressionReason {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Bounce => "bounce",
+            Self::Complaint => "complaint",
+            Self::Manual => "manual",
+        }
+    }
+
+    fn from_str(reason: &str) -> Self {
+        match reason {
+            "bounce" => Self::Bounce,
+            "complaint" => Self::Complaint,
+            _ => Self::Manual,
+        }
+    }
+}
+
+/// An address no email is sent to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suppression {
+    pub address: String,
+    pub reason: SuppressionReason,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The kind of a [`Notification`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// The address does not exist, and is suppressed
+    HardBounce,
+    /// A temporary failure, such as a full mailbox
+    SoftBounce,
+    /// The recipient reported the email as spam, and is suppressed
+    Complaint,
+}
+
+/// A bounce or complaint reported by an email provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    #[serde(rename = "type")]
+    pub kind: NotificationKind,
+    pub recipient: String,
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+fn text(value: &Value, pointer: &str) -> Option<String> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .map(ToString::to_string)
+}
+
+fn message_id(id: Option<String>) -> Option<String> {
+    id.map(|id| {
+        id.trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .to_string()
+    })
+}
+
+impl Notification {
+    fn new(
+        kind: NotificationKind,
+        recipient: Option<String>,
+        message_id: Option<String>,
+        detail: Option<String>,
+    ) -> Option<Self> {
+        Some(Self {
+            kind,
+            recipient: recipient?.trim().to_lowercase(),
+            message_id: self::message_id(message_id),
+            detail,
+        })
+    }
+
+    /// Reads the notifications of a webhook payload, in any of the supported
+    /// provider formats. Other events, such as deliveries, are ignored.
+    #[must_use]
+    pub fn parse(payload: &Value) -> Vec<Self> {
+        use NotificationKind::{Complaint, HardBounce, SoftBounce};
+
+        if let Some(items) = payload.as_array() {
+            return items.iter().flat_map(Self::parse).collect();
+        }
+
+        // Amazon SNS wraps the SES notification in a `Message` string
+        if let Some(message) = payload
+            .get("Message")
+            .and_then(Value::as_str)
+            .and_then(|message| serde_json::from_str::<Value>(message).ok())
+        {
+            return Self::parse(&message);
+        }
+
+        // Amazon SES
+        if let Some(kind) = payload
+            .get("notificationType")
+            .or_else(|| payload.get("eventType"))
+            .and_then(Value::as_str)
+        {
+            let message_id = text(payload, "/mail/commonHeaders/messageId")
+                .or_else(|| text(payload, "/mail/messageId"));
+            let (kind, recipients) = match kind {
+                "Bounce" if text(payload, "/bounce/bounceType").as_deref() == Some("Permanent") => {
+                    (HardBounce, "/bounce/bouncedRecipients")
+                }
+                "Bounce" => (SoftBounce, "/bounce/bouncedRecipients"),
+                "Complaint" => (Complaint, "/complaint/complainedRecipients"),
+                _ => return vec![],
+            };
+            return payload
+                .pointer(recipients)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|recipient| {
+                    Self::new(
+                        kind,
+                        text(recipient, "/emailAddress"),
+                        message_id.clone(),
+                        text(recipient, "/diagnosticCode"),
+                    )
+                })
+                .collect();
+        }
+
+        // Postmark
+        if let Some(record_type) = payload.get("RecordType").and_then(Value::as_str) {
+            let kind = match record_type {
+                "Bounce" if text(payload, "/Type").as_deref() == Some("HardBounce") => HardBounce,
+                "Bounce" => SoftBounce,
+                "SpamComplaint" => Complaint,
+                _ => return vec![],
+            };
+            return Self::new(
+                kind,
+                text(payload, "/Email"),
+                text(payload, "/MessageID"),
+                text(payload, "/Description"),
+            )
+            .into_iter()
+            .collect();
+        }
+
+        // SendGrid
+        if let Some(event) = payload.get("event").and_then(Value::as_str) {
+            let kind = match event {
+                "bounce" if text(payload, "/type").as_deref() == Some("blocked") => SoftBounce,
+                "bounce" => HardBounce,
+                "spamreport" => Complaint,
+                _ => return vec![],
+            };
+            return Self::new(
+                kind,
+                text(payload, "/email"),
+                text(payload, "/smtp-id"),
+                text(payload, "/reason"),
+            )
+            .into_iter()
+            .collect();
+        }
+
+        // Mailgun
+        if let Some(data) = payload.get("event-data") {
+            let kind = match text(data, "/event").as_deref() {
+                Some("failed") if text(data, "/severity").as_deref() == Some("permanent") => {
+                    HardBounce
+                }
+                Some("failed") => SoftBounce,
+                Some("complained") => Complaint,
+                _ => return vec![],
+            };
+            return Self::new(
+                kind,
+                text(data, "/recipient"),
+                text(data, "/message/headers/message-id"),
+                text(data, "/delivery-status/description"),
+            )
+            .into_iter()
+            .collect();
+        }
+
+        serde_json::from_value::<Self>(payload.clone())
+            .ok()
+            .and_then(|notification| {
+                Self::new(
+                    notification.kind,
+                    Some(notification.recipient),
+                    notification.message_id,
+                    notification.detail,
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Returns `true` for the errors worth retrying: transient SMTP replies,
+/// rate limiting and server errors of the HTTP providers, and connection
+/// failures.
+fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::Smtp(err) => !err.is_permanent() && !err.is_client(),
+        #[cfg(feature = "mailer_http")]
+        Error::Any(err) => err
+            .downcast_ref::<super::HttpMailerError>()
+            .is_some_and(super::HttpMailerError::is_transient),
+        _ => false,
+    }
+}
+
+/// Returns the lowercase addresses of a comma separated list.
+fn addresses(list: &str) -> Result<Vec<String>> {
+    Ok(list
+        .parse::<Mailboxes>()?
+        .into_iter()
+        .map(|mailbox| mailbox.email.to_string().to_lowercase())
+        .collect())
+}
+
+/// Removes the suppressed addresses from a comma separated list.
+fn without(list: &str, suppressed: &[String]) -> Result<String> {
+    Ok(list
+        .parse::<Mailboxes>()?
+        .into_iter()
+        .filter(|mailbox| !suppressed.contains(&mailbox.email.to_string().to_lowercase()))
+        .map(|mailbox| mailbox.to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// The outcome of [`DeliveryLog::deliver`].
+#[derive(Debug)]
+pub enum Attempt {
+    /// The email was sent, or all its recipients are suppressed
+    Done,
+    /// The email failed with a transient error, and should be sent again
+    /// after `delay`. It keeps its `Message-ID`, which the next attempt is
+    /// counted from.
+    Retry { email: Box<Email>, delay: Duration },
+}
+
+/// Records deliveries, and keeps the suppression list.
+pub struct DeliveryLog {
+    db: DatabaseConnection,
+    schema: LazySchema,
+}
+
+impl DeliveryLog {
+    /// Creates a new instance keeping deliveries in the given database.
+    #[must_use]
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self {
+            db,
+            schema: LazySchema::default(),
+        }
+    }
+
+    /// Returns the instance kept in the shared store, creating it on first
+    /// use.
+    #[must_use]
+    pub fn from_context(ctx: &AppContext) -> Arc<Self> {
+        if let Some(log) = ctx.shared_store.get::<Arc<Self>>() {
+            return log;
+        }
+        let log = Arc::new(Self::new(ctx.db.clone()));
+        ctx.shared_store.insert(log.clone());
+        log
+    }
+
+    async fn create_tables(&self) -> Result<()> {
+        self.schema
+            .ensure(&self.db, |backend| {
+                let mut statements = Vec::new();
+                let deliveries = Table::create()
+                    .table(Alias::new(DELIVERIES_TABLE))
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("message_id")).string().null())
+                    .col(ColumnDef::new(Alias::new("recipient")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("status")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("error")).text().null())
+                    .col(ColumnDef::new(Alias::new("attempt")).integer().not_null())
+                    .col(
+                        ColumnDef::new(Alias::new("created_at"))
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned();
+                statements.push(backend.build(&deliveries));
+
+                for column in ["recipient", "message_id"] {
+                    let index = Index::create()
+                        .name(format!("idx_{DELIVERIES_TABLE}_{column}"))
+                        .table(Alias::new(DELIVERIES_TABLE))
+                        .col(Alias::new(column))
+                        .if_not_exists()
+                        .to_owned();
+                    statements.push(backend.build(&index));
+                }
+
+                let suppressions = Table::create()
+                    .table(Alias::new(SUPPRESSIONS_TABLE))
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("address"))
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("reason")).string().not_null())
+                    .col(ColumnDef::new(Alias::new("detail")).text().null())
+                    .col(
+                        ColumnDef::new(Alias::new("created_at"))
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned();
+                statements.push(backend.build(&suppressions));
+                statements
+            })
+            .await
+    }
+
+    /// Records a delivery attempt to a recipient.
+    ///
+    /// # Errors
+    ///
+    /// When the delivery could not be stored.
+    pub async fn record(
+        &self,
+        message_id: Option<&str>,
+        recipient: &str,
+        status: DeliveryStatus,
+        error: Option<&str>,
+        attempt: u32,
+    ) -> Result<()> {
+        self.create_tables().await?;
+
+        let statement = Query::insert()
+            .into_table(Alias::new(DELIVERIES_TABLE))
+            .columns([
+                Alias::new("message_id"),
+                Alias::new("recipient"),
+                Alias::new("status"),
+                Alias::new("error"),
+                Alias::new("attempt"),
+                Alias::new("created_at"),
+            ])
+            .values_panic([
+                message_id.into(),
+                recipient.to_lowercase().into(),
+                status.as_str().into(),
+                error.into(),
+                attempt.into(),
+                Utc::now().timestamp().into(),
+            ])
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+        Ok(())
+    }
+
+    fn delivery_from_row(row: &QueryResult) -> Result<Delivery> {
+        Ok(Delivery {
+            message_id: row.try_get("", "message_id")?,
+            recipient: row.try_get("", "recipient")?,
+            status: DeliveryStatus::from_str(&row.try_get::<String>("", "status")?)?,
+            error: row.try_get("", "error")?,
+            attempt: u32::try_from(row.try_get::<i32>("", "attempt")?).unwrap_or_default(),
+            created_at: DateTime::from_timestamp(row.try_get("", "created_at")?, 0)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Returns the number of the last attempt to send a message, `0` when it
+    /// was never attempted.
+    async fn last_attempt(&self, message_id: &str) -> Result<u32> {
+        self.create_tables().await?;
+
+        let statement = Query::select()
+            .expr_as(
+                Func::max(Expr::col(Alias::new("attempt"))),
+                Alias::new("attempt"),
+            )
+            .from(Alias::new(DELIVERIES_TABLE))
+            .and_where(Expr::col(Alias::new("message_id")).eq(message_id))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        let attempt = match self.db.query_one(backend.build(&statement)).await? {
+            Some(row) => row.try_get::<Option<i32>>("", "attempt")?,
+            None => None,
+        };
+        Ok(attempt.map_or(0, |attempt| u32::try_from(attempt).unwrap_or_default()))
+    }
+
+    /// Returns the deliveries to a recipient, or all the deliveries, newest
+    /// first.
+    ///
+    /// # Errors
+    ///
+    /// When the deliveries could not be read.
+    pub async fn deliveries(&self, recipient: Option<&str>) -> Result<Vec<Delivery>> {
+        self.create_tables().await?;
+
+        let mut statement = Query::select();
+        statement
+            .expr(Expr::cust("*"))
+            .from(Alias::new(DELIVERIES_TABLE))
+            .order_by(Alias::new("id"), Order::Desc);
+        if let Some(recipient) = recipient {
+            statement.and_where(Expr::col(Alias::new("recipient")).eq(recipient.to_lowercase()));
+        }
+        let backend = self.db.get_database_backend();
+        self.db
+            .query_all(backend.build(&statement))
+            .await?
+            .iter()
+            .map(Self::delivery_from_row)
+            .collect()
+    }
+
+    /// Adds an address to the suppression list, or updates its reason.
+    ///
+    /// # Errors
+    ///
+    /// When the suppression could not be stored.
+    pub async fn suppress(
+        &self,
+        address: &str,
+        reason: SuppressionReason,
+        detail: Option<&str>,
+    ) -> Result<()> {
+        self.create_tables().await?;
+
+        let statement = Query::insert()
+            .into_table(Alias::new(SUPPRESSIONS_TABLE))
+            .columns([
+                Alias::new("address"),
+                Alias::new("reason"),
+                Alias::new("detail"),
+                Alias::new("created_at"),
+            ])
+            .values_panic([
+                address.trim().to_lowercase().into(),
+                reason.as_str().into(),
+                detail.into(),
+                Utc::now().timestamp().into(),
+            ])
+            .on_conflict(
+                OnConflict::column(Alias::new("address"))
+                    .update_columns([Alias::new("reason"), Alias::new("detail")])
+                    .to_owned(),
+            )
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+        Ok(())
+    }
+
+    /// Removes an address from the suppression list. Returns `false` when it
+    /// was not suppressed.
+    ///
+    /// # Errors
+    ///
+    /// When the suppression could not be removed.
+    pub async fn unsuppress(&self, address: &str) -> Result<bool> {
+        self.create_tables().await?;
+
+        let statement = Query::delete()
+            .from_table(Alias::new(SUPPRESSIONS_TABLE))
+            .and_where(Expr::col(Alias::new("address")).eq(address.trim().to_lowercase()))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        let result = self.db.execute(backend.build(&statement)).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns the suppressed addresses, newest first.
+    ///
+    /// # Errors
+    ///
+    /// When the suppressions could not be read.
+    pub async fn suppressions(&self) -> Result<Vec<Suppression>> {
+        self.create_tables().await?;
+
+        let statement = Query::select()
+            .expr(Expr::cust("*"))
+            .from(Alias::new(SUPPRESSIONS_TABLE))
+            .order_by(Alias::new("created_at"), Order::Desc)
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db
+            .query_all(backend.build(&statement))
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(Suppression {
+                    address: row.try_get("", "address")?,
+                    reason: SuppressionReason::from_str(&row.try_get::<String>("", "reason")?),
+                    detail: row.try_get("", "detail")?,
+                    created_at: DateTime::from_timestamp(row.try_get("", "created_at")?, 0)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the suppressed addresses among the given ones.
+    ///
+    /// # Errors
+    ///
+    /// When the suppressions could not be read.
+    pub async fn suppressed(&self, addresses: &[String]) -> Result<Vec<String>> {
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+        self.create_tables().await?;
+
+        let statement = Query::select()
+            .column(Alias::new("address"))
+            .from(Alias::new(SUPPRESSIONS_TABLE))
+            .and_where(
+                Expr::col(Alias::new("address"))
+                    .is_in(addresses.iter().map(|address| address.to_lowercase())),
+            )
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db
+            .query_all(backend.build(&statement))
+            .await?
+            .iter()
+            .map(|row| Ok(row.try_get("", "address")?))
+            .collect()
+    }
+
+    /// Records a bounce or complaint, suppressing its recipient unless it is
+    /// a soft bounce.
+    ///
+    /// # Errors
+    ///
+    /// When the notification could not be stored.
+    pub async fn handle(&self, notification: &Notification) -> Result<()> {
+        let (status, reason) = match notification.kind {
+            NotificationKind::HardBounce => {
+                (DeliveryStatus::Bounced, Some(SuppressionReason::Bounce))
+            }
+            NotificationKind::SoftBounce => (DeliveryStatus::Bounced, None),
+            NotificationKind::Complaint => (
+                DeliveryStatus::Complained,
+                Some(SuppressionReason::Complaint),
+            ),
+        };
+        self.record(
+            notification.message_id.as_deref(),
+            &notification.recipient,
+            status,
+            notification.detail.as_deref(),
+            0,
+        )
+        .await?;
+        if let Some(reason) = reason {
+            self.suppress(
+                &notification.recipient,
+                reason,
+                notification.detail.as_deref(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends an email to its recipients which are not suppressed, and records
+    /// the attempt. A transient failure returns [`Attempt::Retry`] until the
+    /// configured retries are exhausted, the caller sends the email again.
+    ///
+    /// # Errors
+    ///
+    /// When the email could not be sent, and is not retried.
+    pub async fn deliver(
+        &self,
+        sender: &EmailSender,
+        email: &Email,
+        config: &config::MailTracking,
+    ) -> Result<Attempt> {
+        let mut email = email.clone();
+        let message_id = match &email.message_id {
+            Some(message_id) => message_id.clone(),
+            None => {
+                let from = email.from.as_deref().unwrap_or(DEFAULT_FROM_SENDER);
+                let domain = from
+                    .rsplit_once('@')
+                    .map_or("localhost", |(_, domain)| domain.trim_end_matches('>'));
+                format!("{}@{domain}", uuid::Uuid::new_v4().simple())
+            }
+        };
+        email.message_id = Some(message_id.clone());
+
+        let mut recipients = addresses(&email.to)?;
+        for list in [&email.cc, &email.bcc].into_iter().flatten() {
+            recipients.extend(addresses(list)?);
+        }
+        let suppressed = self.suppressed(&recipients).await?;
+        if !suppressed.is_empty() {
+            for address in &suppressed {
+                self.record(
+                    Some(&message_id),
+                    address,
+                    DeliveryStatus::Suppressed,
+                    None,
+                    0,
+                )
+                .await?;
+            }
+            recipients.retain(|recipient| !suppressed.contains(recipient));
+            if recipients.is_empty() {
+                info!(
+                    message_id,
+                    "all the recipients are suppressed, skipping the email"
+                );
+                return Ok(Attempt::Done);
+            }
+
+            email.to = without(&email.to, &suppressed)?;
+            email.cc = email
+                .cc
+                .as_deref()
+                .map(|cc| without(cc, &suppressed))
+                .transpose()?
+                .filter(|cc| !cc.is_empty());
+            email.bcc = email
+                .bcc
+                .as_deref()
+                .map(|bcc| without(bcc, &suppressed))
+                .transpose()?
+                .filter(|bcc| !bcc.is_empty());
+        }
+
+        let attempt = self.last_attempt(&message_id).await? + 1;
+        let (status, res) = match sender.mail(&email).await {
+            Ok(()) => (DeliveryStatus::Sent, Ok(())),
+            Err(err) if attempt <= config.retries && is_transient(&err) => {
+                (DeliveryStatus::Deferred, Err(err))
+            }
+            Err(err) => (DeliveryStatus::Failed, Err(err)),
+        };
+        let error = res.as_ref().err().map(ToString::to_string);
+        for recipient in &recipients {
+            self.record(
+                Some(&message_id),
+                recipient,
+                status,
+                error.as_deref(),
+                attempt,
+            )
+            .await?;
+        }
+        if status != DeliveryStatus::Deferred {
+            return res.map(|()| Attempt::Done);
+        }
+
+        let delay = config.backoff.saturating_mul(1 << (attempt - 1).min(16));
+        warn!(
+            message_id,
+            attempt, error, delay, "transient failure sending email, retrying"
+        );
+        Ok(Attempt::Retry {
+            email: Box::new(email),
+            delay: Duration::from_millis(delay),
+        })
+    }
+}
+
+async fn bounces(
+    State(ctx): State<AppContext>,
+    AxumQuery(query): AxumQuery<BTreeMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode> {
+    let token = ctx
+        .config
+        .mailer
+        .as_ref()
+        .and_then(|mailer| mailer.tracking.as_ref())
+        .and_then(|tracking| tracking.webhook_token.as_ref())
+        .ok_or(Error::NotFound)?;
+
+    // providers which cannot set headers send the token in the query
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| query.get("token").map(String::as_str))
+        .unwrap_or_default();
+    if !hash::constant_time_eq(token, given) {
+        return Err(Error::Unauthorized("invalid bounces token".to_string()));
+    }
+
+    // SNS posts JSON as `text/plain`, so the content type is not checked
+    let payload: Value = serde_json::from_slice(&body)
+        .map_err(|err| Error::BadRequest(format!("invalid bounce notification: {err}")))?;
+
+    if payload.get("Type").and_then(Value::as_str) == Some("SubscriptionConfirmation") {
+        warn!(
+            url = text(&payload, "/SubscribeURL"),
+            "confirm the SNS subscription of the bounces route by visiting its URL"
+        );
+        return Ok(StatusCode::OK);
+    }
+
+    let log = DeliveryLog::from_context(&ctx);
+    for notification in Notification::parse(&payload) {
+        log.handle(&notification).await?;
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Returns the route receiving the bounce and complaint notifications of the
+/// email provider, mounted on [`DEFAULT_ROUTE_PREFIX`]. It answers `404`
+/// unless `mailer.tracking.webhook_token` is configured.
+///
+/// ```rust,ignore
+/// fn routes(_ctx: &AppContext) -> AppRoutes {
+///     AppRoutes::with_default_routes()
+///         .add_route(controllers::auth::routes())
+///         .add_route(loco_rs::mailer::tracking::routes())
+/// }
+/// ```
+#[must_use]
+pub fn routes() -> Routes {
+    Routes::at(DEFAULT_ROUTE_PREFIX).add("/", post(bounces))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use lettre::transport::stub::StubTransport;
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{mailer::EmailTransport, tests_cfg};
+
+    async fn log() -> DeliveryLog {
+        DeliveryLog::new(tests_cfg::db::memory_db().await)
+    }
+
+    fn tracking() -> config::MailTracking {
+        config::MailTracking {
+            retries: 2,
+            backoff: 0,
+            webhook_token: Some("secret".to_string()),
+        }
+    }
+
+    fn email() -> Email {
+        Email {
+            from: Some("App <app@example.com>".to_string()),
+            to: "jane@example.com, Bounced@example.com".to_string(),
+            cc: Some("bounced@example.com".to_string()),
+            subject: "Welcome".to_string(),
+            text: "Welcome".to_string(),
+            html: "Welcome".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn can_skip_suppressed_recipients() {
+        let log = log().await;
+        let stub = StubTransport::new_ok();
+        let sender = EmailSender::new(EmailTransport::Test(stub.clone()));
+
+        log.suppress("bounced@example.com", SuppressionReason::Bounce, None)
+            .await
+            .unwrap();
+        log.deliver(&sender, &email(), &tracking()).await.unwrap();
+
+        let messages = stub.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0]
+                .0
+                .to()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["jane@example.com"]
+        );
+        assert!(!messages[0].1.contains("Cc:"));
+
+        let deliveries = log.deliveries(None).await.unwrap();
+        let message_id = deliveries[0].message_id.clone().unwrap();
+        assert!(message_id.ends_with("@example.com"));
+        assert!(messages[0]
+            .1
+            .contains(&format!("Message-ID: <{message_id}>")));
+        assert_eq!(
+            deliveries
+                .iter()
+                .map(|delivery| (
+                    delivery.recipient.as_str(),
+                    delivery.status,
+                    delivery.attempt
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("jane@example.com", DeliveryStatus::Sent, 1),
+                ("bounced@example.com", DeliveryStatus::Suppressed, 0),
+            ]
+        );
+
+        // no email at all when every recipient is suppressed
+        log.deliver(
+            &sender,
+            &Email {
+                to: "bounced@example.com".to_string(),
+                cc: None,
+                ..email()
+            },
+            &tracking(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stub.messages().len(), 1);
+
+        // the remaining `cc` and `bcc` recipients still get the email, without
+        // being moved to `To`
+        log.deliver(
+            &sender,
+            &Email {
+                to: "bounced@example.com".to_string(),
+                cc: Some("jane@example.com".to_string()),
+                bcc: Some("audit@example.com".to_string()),
+                ..email()
+            },
+            &tracking(),
+        )
+        .await
+        .unwrap();
+        log.deliver(
+            &sender,
+            &Email {
+                to: "bounced@example.com".to_string(),
+                cc: None,
+                bcc: Some("audit@example.com".to_string()),
+                ..email()
+            },
+            &tracking(),
+        )
+        .await
+        .unwrap();
+        let messages = stub.messages();
+        assert_eq!(messages.len(), 3);
+        assert!(!messages[1].1.contains("To:"));
+        assert!(messages[1].1.contains("Cc: jane@example.com"));
+        let recipients = |index: usize| {
+            messages[index]
+                .0
+                .to()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(recipients(1), vec!["jane@example.com", "audit@example.com"]);
+        assert_eq!(recipients(2), vec!["audit@example.com"]);
+
+        assert!(log.unsuppress("Bounced@example.com").await.unwrap());
+        assert!(!log.unsuppress("bounced@example.com").await.unwrap());
+        assert!(log.suppressions().await.unwrap().is_empty());
+    }
+
+    /// Answers SMTP with `451` to the first `max_failures` `MAIL` commands.
+    async fn flaky_smtp_server(max_failures: usize) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut failures = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 localhost ready\r\n").await.unwrap();
+                let mut in_data = false;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply = if in_data {
+                        if line != "." {
+                            continue;
+                        }
+                        in_data = false;
+                        "250 queued"
+                    } else {
+                        match line.get(..4).unwrap_or_default() {
+                            "MAIL" if failures < max_failures => {
+                                failures += 1;
+                                "451 4.3.0 try again later"
+                            }
+                            "DATA" => {
+                                in_data = true;
+                                "354 go ahead"
+                            }
+                            "QUIT" => "221 bye",
+                            _ => "250 ok",
+                        }
+                    };
+                    writer
+                        .write_all(format!("{reply}\r\n").as_bytes())
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    fn smtp_sender(port: u16) -> EmailSender {
+        EmailSender::smtp(&config::SmtpMailer {
+            enable: true,
+            host: "127.0.0.1".to_string(),
+            port,
+            secure: false,
+            auth: None,
+            hello_name: None,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn can_retry_transient_failures() {
+        let log = log().await;
+        let email = Email {
+            to: "jane@example.com".to_string(),
+            cc: None,
+            ..email()
+        };
+
+        let sender = smtp_sender(flaky_smtp_server(2).await);
+        let mut email = email;
+        let mut delays = vec![];
+        while let Attempt::Retry {
+            email: retry,
+            delay,
+        } = log.deliver(&sender, &email, &tracking()).await.unwrap()
+        {
+            email = *retry;
+            delays.push(delay);
+        }
+        assert_eq!(delays, vec![Duration::ZERO, Duration::ZERO]);
+        assert_eq!(
+            log.deliveries(None)
+                .await
+                .unwrap()
+                .iter()
+                .map(|delivery| (delivery.status, delivery.attempt))
+                .collect::<Vec<_>>(),
+            vec![
+                (DeliveryStatus::Sent, 3),
+                (DeliveryStatus::Deferred, 2),
+                (DeliveryStatus::Deferred, 1),
+            ]
+        );
+
+        let log = self::log().await;
+        let sender = smtp_sender(flaky_smtp_server(3).await);
+        let email = Email {
+            message_id: None,
+            ..email
+        };
+        let mut attempt = log.deliver(&sender, &email, &tracking()).await;
+        while let Ok(Attempt::Retry { email, .. }) = attempt {
+            attempt = log.deliver(&sender, &email, &tracking()).await;
+        }
+        assert!(attempt.is_err());
+        let deliveries = log.deliveries(None).await.unwrap();
+        assert_eq!(
+            (deliveries[0].status, deliveries[0].attempt),
+            (DeliveryStatus::Failed, 3)
+        );
+        assert!(deliveries[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("try again later"));
+    }
+
+    #[cfg(feature = "mailer_http")]
+    #[tokio::test]
+    async fn can_retry_transient_http_failures() {
+        use crate::mailer::http::tests::mock_server;
+
+        let log = log().await;
+        let email = Email {
+            to: "jane@example.com".to_string(),
+            cc: None,
+            ..email()
+        };
+        let sender = |endpoint| {
+            EmailSender::http(&config::HttpMailer::Sendgrid {
+                api_key: "SG.key".to_string(),
+                endpoint: Some(endpoint),
+            })
+            .unwrap()
+        };
+
+        let (endpoint, _) = mock_server(StatusCode::TOO_MANY_REQUESTS).await;
+        let attempt = log.deliver(&sender(endpoint), &email, &tracking()).await;
+        assert!(matches!(attempt, Ok(Attempt::Retry { .. })));
+
+        let (endpoint, _) = mock_server(StatusCode::BAD_GATEWAY).await;
+        let attempt = log.deliver(&sender(endpoint), &email, &tracking()).await;
+        assert!(matches!(attempt, Ok(Attempt::Retry { .. })));
+
+        let (endpoint, _) = mock_server(StatusCode::FORBIDDEN).await;
+        assert!(log
+            .deliver(&sender(endpoint), &email, &tracking())
+            .await
+            .is_err());
+        assert_eq!(
+            log.deliveries(None)
+                .await
+                .unwrap()
+                .iter()
+                .map(|delivery| delivery.status)
+                .collect::<Vec<_>>(),
+            vec![
+                DeliveryStatus::Failed,
+                DeliveryStatus::Deferred,
+                DeliveryStatus::Deferred,
+            ]
+        );
+    }
+
+    #[test]
+    fn can_parse_notifications() {
+        let ses = json!({
+            "notificationType": "Bounce",
+            "bounce": {
+                "bounceType": "Permanent",
+                "bouncedRecipients": [
+                    {"emailAddress": "Jane@example.com", "diagnosticCode": "550 5.1.1 user unknown"},
+                    {"emailAddress": "john@example.com"}
+                ]
+            },
+            "mail": {"messageId": "ses-id", "commonHeaders": {"messageId": "<abc@example.com>"}}
+        });
+        let sns = json!({"Type": "Notification", "Message": json!({
+            "notificationType": "Complaint",
+            "complaint": {"complainedRecipients": [{"emailAddress": "spam@example.com"}]},
+            "mail": {"messageId": "ses-id"}
+        }).to_string()});
+        let postmark = json!({"RecordType": "Bounce", "Type": "SoftBounce", "Email": "full@example.com", "MessageID": "pm-id", "Description": "Mailbox full"});
+        let sendgrid = json!([
+            {"event": "bounce", "type": "bounce", "email": "gone@example.com", "smtp-id": "<sg@example.com>", "reason": "550 unknown"},
+            {"event": "delivered", "email": "ok@example.com"}
+        ]);
+        let mailgun = json!({"event-data": {"event": "failed", "severity": "permanent", "recipient": "mg@example.com", "message": {"headers": {"message-id": "mg@example.com"}}}});
+        let generic = json!({"type": "complaint", "recipient": "generic@example.com"});
+
+        let parsed = [ses, sns, postmark, sendgrid, mailgun, generic]
+            .iter()
+            .flat_map(Notification::parse)
+            .map(|n| (n.kind, n.recipient, n.message_id))
+            .collect::<Vec<_>>();
+        let id = |id: &str| Some(id.to_string());
+        assert_eq!(
+            parsed,
+            vec![
+                (
+                    NotificationKind::HardBounce,
+                    "jane@example.com".to_string(),
+                    id("abc@example.com")
+                ),
+                (
+                    NotificationKind::HardBounce,
+                    "john@example.com".to_string(),
+                    id("abc@example.com")
+                ),
+                (
+                    NotificationKind::Complaint,
+                    "spam@example.com".to_string(),
+                    id("ses-id")
+                ),
+                (
+                    NotificationKind::SoftBounce,
+                    "full@example.com".to_string(),
+                    id("pm-id")
+                ),
+                (
+                    NotificationKind::HardBounce,
+                    "gone@example.com".to_string(),
+                    id("sg@example.com")
+                ),
+                (
+                    NotificationKind::HardBounce,
+                    "mg@example.com".to_string(),
+                    id("mg@example.com")
+                ),
+                (
+                    NotificationKind::Complaint,
+                    "generic@example.com".to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_receive_bounces() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        ctx.shared_store.insert(Arc::new(log().await));
+
+        let mut app = Router::new();
+        for handler in routes().handlers {
+            app = app.route(
+                &format!("{}{}", DEFAULT_ROUTE_PREFIX, handler.uri),
+                handler.method,
+            );
+        }
+        let send = |app: Router<AppContext>, ctx: AppContext, request: Request<Body>| async move {
+            app.with_state(ctx)
+                .oneshot(request)
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        };
+        let post = |app: Router<AppContext>, ctx: AppContext, uri: &str, body: Value| {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            send(app, ctx, request)
+        };
+        let bounce = json!([
+            {"type": "hard_bounce", "recipient": "gone@example.com", "detail": "550 unknown"},
+            {"type": "soft_bounce", "recipient": "full@example.com"}
+        ]);
+
+        let uri = "/_loco/mail/bounces/?token=secret";
+        assert_eq!(
+            post(app.clone(), ctx.clone(), uri, bounce.clone()).await,
+            404
+        );
+
+        ctx.config.mailer = Some(config::Mailer {
+            tracking: Some(tracking()),
+            ..Default::default()
+        });
+        let wrong = "/_loco/mail/bounces/?token=wrong";
+        assert_eq!(
+            post(app.clone(), ctx.clone(), wrong, bounce.clone()).await,
+            401
+        );
+        assert_eq!(post(app.clone(), ctx.clone(), uri, bounce).await, 200);
+
+        // SNS posts its notifications as `text/plain`
+        let sns = json!({"Type": "Notification", "Message": json!({
+            "notificationType": "Complaint",
+            "complaint": {"complainedRecipients": [{"emailAddress": "spam@example.com"}]}
+        }).to_string()});
+        let text = |body: String| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "text/plain; charset=UTF-8")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        assert_eq!(
+            send(app.clone(), ctx.clone(), text(sns.to_string())).await,
+            200
+        );
+        assert_eq!(send(app, ctx.clone(), text("{".to_string())).await, 400);
+
+        let log = DeliveryLog::from_context(&ctx);
+        let suppressions = log.suppressions().await.unwrap();
+        assert_eq!(suppressions.len(), 2);
+        let complaint = suppressions
+            .iter()
+            .find(|suppression| suppression.address == "spam@example.com")
+            .unwrap();
+        assert_eq!(complaint.reason, SuppressionReason::Complaint);
+        let suppressions = suppressions
+            .into_iter()
+            .filter(|suppression| suppression.address == "gone@example.com")
+            .collect::<Vec<_>>();
+        assert_eq!(suppressions[0].address, "gone@example.com");
+        assert_eq!(suppressions[0].reason, SuppressionReason::Bounce);
+        assert_eq!(suppressions[0].detail.as_deref(), Some("550 unknown"));
+        assert_eq!(
+            log.deliveries(Some("full@example.com")).await.unwrap()[0].status,
+            DeliveryStatus::Bounced
+        );
+    }
+}
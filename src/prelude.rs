@@ -42,7 +42,10 @@ pub use crate::{
     },
     errors::Error,
     mailer,
-    mailer::Mailer,
+    mailer::{
+        preview::{MailerPreview, MailerPreviews},
+        Mailer,
+    },
     task::{self, Task, TaskInfo},
     validation::{self, Validatable},
     validator::Validate,
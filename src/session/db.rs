@@ -0,0 +1,191 @@
+//! A [`SessionStore`] keeping sessions in a database table.
+//!
+//! The table is created on first use when missing, with the columns:
+//!
+//! * `id`: the session id (primary key)
+//! * `data`: the session data, as JSON
+//! * `expires_at`: when the session expires, as a unix timestamp
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use sea_orm::{
+    sea_query::{Alias, ColumnDef, Expr, OnConflict, Query, Table},
+    ConnectionTrait, DatabaseConnection,
+};
+
+use super::SessionStore;
+use crate::{db::LazySchema, Result};
+
+/// Expired sessions are purged at most once in this many seconds.
+const PURGE_INTERVAL: i64 = 3600;
+
+/// Keeps sessions in a database table.
+pub struct DbStore {
+    db: DatabaseConnection,
+    table: String,
+    schema: LazySchema,
+    last_purge: AtomicI64,
+}
+
+impl DbStore {
+    /// Creates a new store keeping sessions in the given table.
+    #[must_use]
+    pub fn new(db: DatabaseConnection, table: &str) -> Self {
+        Self {
+            db,
+            table: table.to_string(),
+            schema: LazySchema::default(),
+            last_purge: AtomicI64::new(0),
+        }
+    }
+
+    fn table(&self) -> Alias {
+        Alias::new(&self.table)
+    }
+
+    async fn create_table(&self) -> Result<()> {
+        self.schema
+            .ensure(&self.db, |backend| {
+                let statement = Table::create()
+                    .table(self.table())
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Alias::new("id"))
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Alias::new("data")).text().not_null())
+                    .col(
+                        ColumnDef::new(Alias::new("expires_at"))
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned();
+                vec![backend.build(&statement)]
+            })
+            .await
+    }
+
+    async fn purge_expired(&self, now: i64) -> Result<()> {
+        let last_purge = self.last_purge.load(Ordering::Relaxed);
+        if now - last_purge < PURGE_INTERVAL
+            || self
+                .last_purge
+                .compare_exchange(last_purge, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return Ok(());
+        }
+
+        let statement = Query::delete()
+            .from_table(self.table())
+            .and_where(Expr::col(Alias::new("expires_at")).lte(now))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for DbStore {
+    async fn load(&self, id: &str) -> Result<Option<String>> {
+        self.create_table().await?;
+
+        let statement = Query::select()
+            .column(Alias::new("data"))
+            .from(self.table())
+            .and_where(Expr::col(Alias::new("id")).eq(id))
+            .and_where(Expr::col(Alias::new("expires_at")).gt(chrono::Utc::now().timestamp()))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        let row = self.db.query_one(backend.build(&statement)).await?;
+        Ok(row
+            .map(|row| row.try_get::<String>("", "data"))
+            .transpose()?)
+    }
+
+    async fn save(&self, id: &str, data: &str, expiry: Duration) -> Result<()> {
+        self.create_table().await?;
+
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = now.saturating_add(i64::try_from(expiry.as_secs()).unwrap_or(i64::MAX));
+        let statement = Query::insert()
+            .into_table(self.table())
+            .columns([
+                Alias::new("id"),
+                Alias::new("data"),
+                Alias::new("expires_at"),
+            ])
+            .values_panic([id.into(), data.into(), expires_at.into()])
+            .on_conflict(
+                OnConflict::column(Alias::new("id"))
+                    .update_columns([Alias::new("data"), Alias::new("expires_at")])
+                    .to_owned(),
+            )
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+
+        self.purge_expired(now).await
+    }
+
+    async fn destroy(&self, id: &str) -> Result<()> {
+        self.create_table().await?;
+
+        let statement = Query::delete()
+            .from_table(self.table())
+            .and_where(Expr::col(Alias::new("id")).eq(id))
+            .to_owned();
+        let backend = self.db.get_database_backend();
+        self.db.execute(backend.build(&statement)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_cfg;
+
+    async fn store() -> DbStore {
+        DbStore::new(tests_cfg::db::memory_db().await, "sessions")
+    }
+
+    #[tokio::test]
+    async fn can_save_load_and_destroy() {
+        let store = store().await;
+        assert!(store.load("abc").await.unwrap().is_none());
+
+        store
+            .save("abc", r#"{"a":1}"#, Duration::from_secs(60))
+            .await
+            .unwrap();
+        store
+            .save("abc", r#"{"a":2}"#, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.load("abc").await.unwrap().as_deref(),
+            Some(r#"{"a":2}"#)
+        );
+
+        store.destroy("abc").await.unwrap();
+        assert!(store.load("abc").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn does_not_load_expired_sessions() {
+        let store = store().await;
+        store
+            .save("abc", "{}", Duration::from_secs(0))
+            .await
+            .unwrap();
+
+        assert!(store.load("abc").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! # Server-side Sessions
+//!
+//! Keeps per-visitor state between requests, for server rendered
+//! applications which sign users in without handing out tokens.
+//!
+//! The [`session`](crate::controller::middleware::session) middleware loads
+//! the session of every request, and saves it once the response is ready.
+//! Handlers read and change it with the [`Session`] extractor:
+//!
+//! ```rust,ignore
+//! use loco_rs::{prelude::*, session::Session};
+//!
+//! async fn login(session: Session, State(ctx): State<AppContext>) -> Result<Response> {
+//!     // ... authenticate the user
+//!     // a new session id prevents session fixation
+//!     session.rotate();
+//!     session.insert("pid", &user.pid)?;
+//!     session.flash("notice", "Welcome back!")?;
+//!     format::redirect("/")
+//! }
+//! ```
+//!
+//! Session data is either kept in the cookie itself, or in a [`SessionStore`]
+//! (the application [`Cache`], or a database table), in which case the cookie
+//! only holds the session id.
+#[cfg(feature = "with-db")]
+pub mod db;
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{cache::Cache, Error, Result};
+
+/// Stores the data of server-side sessions by session id.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Returns the data of the given session.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be read.
+    async fn load(&self, id: &str) -> Result<Option<String>>;
+
+    /// Saves the data of the given session, which expires after the given
+    /// duration.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    async fn save(&self, id: &str, data: &str, expiry: Duration) -> Result<()>;
+
+    /// Removes the given session.
+    ///
+    /// # Errors
+    ///
+    /// When the store could not be written.
+    async fn destroy(&self, id: &str) -> Result<()>;
+}
+
+fn cache_key(id: &str) -> String {
+    format!("session:{id}")
+}
+
+#[async_trait]
+impl SessionStore for Cache {
+    async fn load(&self, id: &str) -> Result<Option<String>> {
+        Ok(self.driver.get(&cache_key(id)).await?)
+    }
+
+    async fn save(&self, id: &str, data: &str, expiry: Duration) -> Result<()> {
+        Ok(self
+            .driver
+            .insert_with_expiry(&cache_key(id), data, expiry)
+            .await?)
+    }
+
+    async fn destroy(&self, id: &str) -> Result<()> {
+        Ok(self.driver.remove(&cache_key(id)).await?)
+    }
+}
+
+/// The persisted content of a session.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Record {
+    #[serde(default)]
+    pub data: Map<String, Value>,
+    /// Flash messages for the next request
+    #[serde(default)]
+    pub flash: Map<String, Value>,
+    /// When the session was created, as a unix timestamp
+    pub created_at: i64,
+    /// When the session was last used, as a unix timestamp
+    pub last_seen: i64,
+}
+
+impl Record {
+    pub fn new(now: i64) -> Self {
+        Self {
+            created_at: now,
+            last_seen: now,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.flash.is_empty()
+    }
+}
+
+/// The session of a request, as tracked by the middleware.
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    /// The id of a session found in the store
+    pub id: Option<String>,
+    pub record: Record,
+    /// Flash messages set by the previous request
+    pub flashes: Map<String, Value>,
+    pub changed: bool,
+    pub rotate: bool,
+}
+
+/// The session of the current request.
+///
+/// Changes are saved by the session middleware once the handler returns.
+/// Cloning a session gives another handle to the same session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    pub(crate) fn new(state: State) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub(crate) fn take_state(&self) -> State {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the session id, when the session is kept in a store and was
+    /// saved before.
+    #[must_use]
+    pub fn id(&self) -> Option<String> {
+        self.lock().id.clone()
+    }
+
+    /// Returns the value of the given key.
+    ///
+    /// # Errors
+    ///
+    /// When the value could not be deserialized into `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        self.lock()
+            .record
+            .data
+            .get(key)
+            .map(|value| serde_json::from_value(value.clone()))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Sets the value of the given key.
+    ///
+    /// # Errors
+    ///
+    /// When the value could not be serialized.
+    pub fn insert<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.lock();
+        state.record.data.insert(key.to_string(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    /// Removes the given key, returning its value.
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.lock();
+        let value = state.record.data.remove(key);
+        state.changed |= value.is_some();
+        value
+    }
+
+    /// Removes all the values of the session.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.record.data.clear();
+        state.changed = true;
+    }
+
+    /// Sets a flash message, which is available to the next request only.
+    ///
+    /// # Errors
+    ///
+    /// When the value could not be serialized.
+    pub fn flash<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.lock();
+        state.record.flash.insert(key.to_string(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    /// Returns the flash messages set by the previous request.
+    #[must_use]
+    pub fn flashes(&self) -> Map<String, Value> {
+        self.lock().flashes.clone()
+    }
+
+    /// Moves the session to a new id, keeping its values.
+    ///
+    /// Call it whenever the privileges of the session change, such as on
+    /// login, so that a session id set by an attacker before the login can't
+    /// be used afterwards.
+    pub fn rotate(&self) {
+        let mut state = self.lock();
+        state.rotate = true;
+        state.changed = true;
+    }
+
+    /// Removes all the values and flash messages of the session, and its
+    /// cookie. Use it on logout.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.record.data.clear();
+        state.record.flash.clear();
+        state.rotate = true;
+        state.changed = true;
+    }
+}
+
+impl<S> FromRequestParts<S> for Session
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        parts
+            .extensions
+            .get::<Self>()
+            .cloned()
+            .ok_or_else(|| Error::string("the session middleware is not enabled"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_get_and_set_values() {
+        let session = Session::new(State::default());
+        assert!(session.get::<String>("pid").unwrap().is_none());
+
+        session.insert("pid", "abc").unwrap();
+        session.insert("visits", &3).unwrap();
+        assert_eq!(
+            session.get::<String>("pid").unwrap().as_deref(),
+            Some("abc")
+        );
+        assert_eq!(session.get::<u32>("visits").unwrap(), Some(3));
+        assert!(session.get::<u32>("pid").is_err());
+
+        assert_eq!(session.remove("visits"), Some(Value::from(3)));
+        assert!(session.remove("visits").is_none());
+        assert!(session.take_state().changed);
+    }
+
+    #[test]
+    fn destroy_clears_values_and_flashes() {
+        let session = Session::new(State::default());
+        session.insert("pid", "abc").unwrap();
+        session.flash("notice", "signed in").unwrap();
+
+        session.destroy();
+
+        let state = session.take_state();
+        assert!(state.record.is_empty());
+        assert!(state.rotate);
+    }
+}
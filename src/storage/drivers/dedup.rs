@@ -0,0 +1,496 @@
+//! # Deduplicating Driver
+//!
+//! A driver wrapper that stores content addressed by its SHA-256 hash, so
+//! identical uploads share a single object in the wrapped [`StoreDriver`].
+//!
+//! The wrapped driver holds:
+//! * `objects/<hash>` - the content.
+//! * `objects/<hash>.refs` - the number of paths referencing the content.
+//! * `refs/<path>` - a small reference from the uploaded path to the content
+//!   hash.
+//!
+//! Content is verified against its hash on download, and objects are deleted
+//! once no path references them. Reference counts are updated under a lock
+//! held by the driver, so a deduplicated store must not be shared by multiple
+//! driver instances.
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use super::{GetResponse, StoreDriver, UploadResponse};
+use crate::storage::{StorageError, StorageResult};
+
+const OBJECTS: &str = "objects";
+const REFS: &str = "refs";
+
+/// Deduplication counters, shared between the driver and the application.
+#[derive(Debug, Default)]
+pub struct DedupStats {
+    uploads: AtomicU64,
+    deduplicated: AtomicU64,
+    bytes_uploaded: AtomicU64,
+    bytes_saved: AtomicU64,
+}
+
+impl DedupStats {
+    /// Number of uploads.
+    #[must_use]
+    pub fn uploads(&self) -> u64 {
+        self.uploads.load(Ordering::Relaxed)
+    }
+
+    /// Number of uploads that reused an existing object.
+    #[must_use]
+    pub fn deduplicated(&self) -> u64 {
+        self.deduplicated.load(Ordering::Relaxed)
+    }
+
+    /// Total size of all uploads.
+    #[must_use]
+    pub fn bytes_uploaded(&self) -> u64 {
+        self.bytes_uploaded.load(Ordering::Relaxed)
+    }
+
+    /// Total size of uploads that were not stored thanks to deduplication.
+    #[must_use]
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_saved.load(Ordering::Relaxed)
+    }
+}
+
+/// A reference from a path to content.
+#[derive(Debug, Serialize, Deserialize)]
+struct Reference {
+    hash: String,
+    size: u64,
+}
+
+/// Wraps a driver and deduplicates all of its content.
+pub struct DedupDriver {
+    inner: Box<dyn StoreDriver>,
+    stats: Arc<DedupStats>,
+    lock: Mutex<()>,
+}
+
+/// Create a new deduplicating driver wrapping the given driver. The driver is
+/// returned as is, so its [`DedupDriver::stats`] can be kept before boxing it
+/// into a [`crate::storage::Storage`].
+///
+/// # Examples
+///```
+/// use loco_rs::storage::{drivers::{dedup, mem}, Storage};
+/// let driver = dedup::new(mem::new());
+/// let stats = driver.stats();
+/// let storage = Storage::single(Box::new(driver));
+/// ```
+#[must_use]
+pub fn new(inner: Box<dyn StoreDriver>) -> DedupDriver {
+    DedupDriver::new(inner)
+}
+
+fn hash(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+fn object_path(hash: &str) -> PathBuf {
+    PathBuf::from(OBJECTS).join(hash)
+}
+
+fn refs_count_path(hash: &str) -> PathBuf {
+    PathBuf::from(OBJECTS).join(format!("{hash}.refs"))
+}
+
+/// Returns the reference location of `path`. Absolute paths are kept under
+/// [`REFS`], since joining them would replace it.
+fn ref_path(path: &Path) -> PathBuf {
+    let relative: PathBuf = path
+        .components()
+        .filter(|component| !matches!(component, Component::RootDir | Component::Prefix(_)))
+        .collect();
+    PathBuf::from(REFS).join(relative)
+}
+
+impl DedupDriver {
+    /// Creates a new deduplicating driver wrapping the given driver.
+    #[must_use]
+    pub fn new(inner: Box<dyn StoreDriver>) -> Self {
+        Self {
+            inner,
+            stats: Arc::new(DedupStats::default()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the deduplication counters of this driver. Keep the returned
+    /// handle to report the counters after the driver is moved into a
+    /// [`crate::storage::Storage`].
+    #[must_use]
+    pub fn stats(&self) -> Arc<DedupStats> {
+        self.stats.clone()
+    }
+
+    async fn reference(&self, path: &Path) -> StorageResult<Option<Reference>> {
+        let ref_path = ref_path(path);
+        if !self.inner.exists(&ref_path).await? {
+            return Ok(None);
+        }
+        let content = self.inner.get(&ref_path).await?.bytes().await?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|err| StorageError::Any(Box::new(err)))
+    }
+
+    async fn write_reference(&self, path: &Path, reference: &Reference) -> StorageResult<()> {
+        let content =
+            serde_json::to_vec(reference).map_err(|err| StorageError::Any(Box::new(err)))?;
+        self.inner
+            .upload(&ref_path(path), &Bytes::from(content))
+            .await?;
+        Ok(())
+    }
+
+    async fn refs_count(&self, hash: &str) -> StorageResult<u64> {
+        let path = refs_count_path(hash);
+        if !self.inner.exists(&path).await? {
+            return Ok(0);
+        }
+        let content = self.inner.get(&path).await?.bytes().await?;
+        String::from_utf8_lossy(&content)
+            .trim()
+            .parse()
+            .map_err(|err| StorageError::Any(Box::new(err)))
+    }
+
+    async fn set_refs_count(&self, hash: &str, count: u64) -> StorageResult<()> {
+        self.inner
+            .upload(&refs_count_path(hash), &Bytes::from(count.to_string()))
+            .await?;
+        Ok(())
+    }
+
+    /// Adds a reference to the given object.
+    async fn incref(&self, hash: &str) -> StorageResult<()> {
+        let count = self.refs_count(hash).await? + 1;
+        self.set_refs_count(hash, count).await
+    }
+
+    /// Removes a reference to the given object, and deletes the object when
+    /// it is no longer referenced.
+    async fn decref(&self, hash: &str) -> StorageResult<()> {
+        let count = self.refs_count(hash).await?.saturating_sub(1);
+        if count == 0 {
+            self.inner.delete(&object_path(hash)).await?;
+            self.inner.delete(&refs_count_path(hash)).await
+        } else {
+            self.set_refs_count(hash, count).await
+        }
+    }
+
+    /// Points `path` to the given reference, releasing the reference the path
+    /// held before.
+    async fn replace_reference(&self, path: &Path, reference: &Reference) -> StorageResult<()> {
+        if let Some(previous) = self.reference(path).await? {
+            self.decref(&previous.hash).await?;
+        }
+        self.write_reference(path, reference).await
+    }
+}
+
+#[async_trait]
+impl StoreDriver for DedupDriver {
+    async fn upload(&self, path: &Path, content: &Bytes) -> StorageResult<UploadResponse> {
+        let hash = hash(content);
+        let size = content.len() as u64;
+        let _guard = self.lock.lock().await;
+
+        self.stats.uploads.fetch_add(1, Ordering::Relaxed);
+        self.stats.bytes_uploaded.fetch_add(size, Ordering::Relaxed);
+
+        if self
+            .reference(path)
+            .await?
+            .is_some_and(|reference| reference.hash == hash)
+        {
+            self.stats.deduplicated.fetch_add(1, Ordering::Relaxed);
+            self.stats.bytes_saved.fetch_add(size, Ordering::Relaxed);
+            return Ok(UploadResponse {
+                e_tag: Some(hash),
+                version: None,
+            });
+        }
+
+        // the object is stored before it is counted, so a failed upload does
+        // not leave a reference to missing content
+        let count = self.refs_count(&hash).await?;
+        if count == 0 {
+            self.inner.upload(&object_path(&hash), content).await?;
+        } else {
+            self.stats.deduplicated.fetch_add(1, Ordering::Relaxed);
+            self.stats.bytes_saved.fetch_add(size, Ordering::Relaxed);
+        }
+        self.set_refs_count(&hash, count + 1).await?;
+        self.replace_reference(
+            path,
+            &Reference {
+                hash: hash.clone(),
+                size,
+            },
+        )
+        .await?;
+
+        Ok(UploadResponse {
+            e_tag: Some(hash),
+            version: None,
+        })
+    }
+
+    async fn get(&self, path: &Path) -> StorageResult<GetResponse> {
+        let reference = self.reference(path).await?.ok_or_else(|| {
+            StorageError::Any(format!("path not found: {}", path.display()).into())
+        })?;
+        let content = self
+            .inner
+            .get(&object_path(&reference.hash))
+            .await?
+            .bytes()
+            .await?;
+
+        let actual = hash(&content);
+        if actual != reference.hash {
+            return Err(StorageError::IntegrityMismatch {
+                path: path.to_path_buf(),
+                expected: reference.hash,
+                actual,
+            });
+        }
+        Ok(GetResponse::from_bytes(content))
+    }
+
+    async fn delete(&self, path: &Path) -> StorageResult<()> {
+        let _guard = self.lock.lock().await;
+        if let Some(reference) = self.reference(path).await? {
+            self.inner.delete(&ref_path(path)).await?;
+            self.decref(&reference.hash).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> StorageResult<()> {
+        let _guard = self.lock.lock().await;
+        if from == to || !self.inner.exists(&ref_path(from)).await? {
+            return self.inner.rename(&ref_path(from), &ref_path(to)).await;
+        }
+        if let Some(previous) = self.reference(to).await? {
+            self.decref(&previous.hash).await?;
+        }
+        self.inner.rename(&ref_path(from), &ref_path(to)).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> StorageResult<()> {
+        let _guard = self.lock.lock().await;
+        let reference = self.reference(from).await?.ok_or_else(|| {
+            StorageError::Any(format!("path not found: {}", from.display()).into())
+        })?;
+        self.incref(&reference.hash).await?;
+        self.replace_reference(to, &reference).await
+    }
+
+    async fn exists(&self, path: &Path) -> StorageResult<bool> {
+        self.inner.exists(&ref_path(path)).await
+    }
+
+    async fn list(&self, prefix: &Path) -> StorageResult<Vec<PathBuf>> {
+        Ok(self
+            .inner
+            .list(&ref_path(prefix))
+            .await?
+            .into_iter()
+            .filter_map(|path| path.strip_prefix(REFS).ok().map(Path::to_path_buf))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::drivers::mem;
+
+    async fn objects(store: &DedupDriver) -> Vec<PathBuf> {
+        store
+            .inner
+            .list(Path::new(OBJECTS))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|path| path.extension().is_none())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn can_deduplicate_uploads() {
+        let store = DedupDriver::new(mem::new());
+        let stats = store.stats();
+        let content = Bytes::from("same pdf");
+
+        store.upload(Path::new("1.pdf"), &content).await.unwrap();
+        store.upload(Path::new("2.pdf"), &content).await.unwrap();
+        store
+            .upload(Path::new("3.pdf"), &Bytes::from("other pdf"))
+            .await
+            .unwrap();
+
+        assert_eq!(objects(&store).await.len(), 2);
+        assert_eq!(stats.uploads(), 3);
+        assert_eq!(stats.deduplicated(), 1);
+        assert_eq!(stats.bytes_saved(), content.len() as u64);
+        assert_eq!(
+            store
+                .get(Path::new("2.pdf"))
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap(),
+            content
+        );
+        assert_eq!(
+            store.list(Path::new("")).await.unwrap(),
+            vec![
+                PathBuf::from("1.pdf"),
+                PathBuf::from("2.pdf"),
+                PathBuf::from("3.pdf")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_release_unreferenced_objects() {
+        let store = DedupDriver::new(mem::new());
+        let content = Bytes::from("same pdf");
+
+        store.upload(Path::new("1.pdf"), &content).await.unwrap();
+        store
+            .copy(Path::new("1.pdf"), Path::new("2.pdf"))
+            .await
+            .unwrap();
+        store
+            .rename(Path::new("2.pdf"), Path::new("3.pdf"))
+            .await
+            .unwrap();
+        assert_eq!(objects(&store).await.len(), 1);
+
+        store.delete(Path::new("1.pdf")).await.unwrap();
+        assert!(!store.exists(Path::new("1.pdf")).await.unwrap());
+        assert_eq!(objects(&store).await.len(), 1);
+
+        // overriding the last reference releases the object
+        store
+            .upload(Path::new("3.pdf"), &Bytes::from("new pdf"))
+            .await
+            .unwrap();
+        assert_eq!(objects(&store).await.len(), 1);
+        store.delete(Path::new("3.pdf")).await.unwrap();
+        assert!(objects(&store).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_store_absolute_paths_under_refs() {
+        let store = DedupDriver::new(mem::new());
+        let content = Bytes::from("same pdf");
+        store
+            .upload(Path::new("/docs/1.pdf"), &content)
+            .await
+            .unwrap();
+
+        assert!(store
+            .inner
+            .exists(Path::new("refs/docs/1.pdf"))
+            .await
+            .unwrap());
+        assert!(store.exists(Path::new("/docs/1.pdf")).await.unwrap());
+        assert_eq!(
+            store
+                .get(Path::new("/docs/1.pdf"))
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap(),
+            content
+        );
+    }
+
+    /// A driver failing every object upload.
+    struct FailingObjects(Box<dyn StoreDriver>);
+
+    #[async_trait]
+    impl StoreDriver for FailingObjects {
+        async fn upload(&self, path: &Path, content: &Bytes) -> StorageResult<UploadResponse> {
+            if path.starts_with(OBJECTS) && path.extension().is_none() {
+                return Err(StorageError::Any("upload failed".into()));
+            }
+            self.0.upload(path, content).await
+        }
+
+        async fn get(&self, path: &Path) -> StorageResult<GetResponse> {
+            self.0.get(path).await
+        }
+
+        async fn delete(&self, path: &Path) -> StorageResult<()> {
+            self.0.delete(path).await
+        }
+
+        async fn rename(&self, from: &Path, to: &Path) -> StorageResult<()> {
+            self.0.rename(from, to).await
+        }
+
+        async fn copy(&self, from: &Path, to: &Path) -> StorageResult<()> {
+            self.0.copy(from, to).await
+        }
+
+        async fn exists(&self, path: &Path) -> StorageResult<bool> {
+            self.0.exists(path).await
+        }
+
+        async fn list(&self, prefix: &Path) -> StorageResult<Vec<PathBuf>> {
+            self.0.list(prefix).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cannot_count_failed_uploads() {
+        let store = DedupDriver::new(Box::new(FailingObjects(mem::new())));
+        let content = Bytes::from("same pdf");
+
+        assert!(store.upload(Path::new("1.pdf"), &content).await.is_err());
+        assert_eq!(store.refs_count(&hash(&content)).await.unwrap(), 0);
+        assert!(!store.exists(Path::new("1.pdf")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn can_verify_integrity_on_download() {
+        let store = DedupDriver::new(mem::new());
+        let content = Bytes::from("same pdf");
+        store.upload(Path::new("1.pdf"), &content).await.unwrap();
+
+        store
+            .inner
+            .upload(&object_path(&hash(&content)), &Bytes::from("corrupted"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store.get(Path::new("1.pdf")).await,
+            Err(StorageError::IntegrityMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,552 @@
+//! # Encrypted Driver
+//!
+//! A driver wrapper that encrypts content before it reaches the wrapped
+//! [`StoreDriver`] and decrypts it on download, so content is encrypted at
+//! rest independently of the storage provider.
+//!
+//! Content is encrypted with envelope encryption: every object is encrypted
+//! with its own random data key using AES-256-GCM, and the data key is
+//! encrypted (wrapped) with a key from the [`Keyring`]. The key id is stored
+//! in the object header, so keys can be rotated by adding a new primary key
+//! while keeping the old keys for decryption, and re-wrapping existing
+//! objects with [`EncryptedDriver::rotate`].
+//!
+//! Objects are bound to their path, so an object copied to another path in
+//! the wrapped storage can't be decrypted there. Rename and copy decrypt the
+//! object and encrypt it again for its new path.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use super::{GetResponse, StoreDriver, UploadResponse};
+use crate::storage::{StorageError, StorageResult};
+
+const MAGIC: &[u8; 4] = b"LENC";
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+// the data key is unique per object, so the body is only bound to the format
+// and to its path
+const BODY_AAD: &[u8] = b"LENC\x01";
+
+/// Keyring configuration.
+///
+/// Example (development):
+/// ```yaml
+/// encryption:
+///   primary: "2024-10"
+///   keys:
+///     "2024-01": "{{ get_env(name=\"STORAGE_KEY_2024_01\") }}"
+///     "2024-10": "{{ get_env(name=\"STORAGE_KEY_2024_10\") }}"
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeyringConfig {
+    /// The key id used to encrypt new content.
+    pub primary: String,
+    /// All the keys by id, as hex encoded 32 bytes (for example, generated
+    /// with `openssl rand -hex 32`). Keys that are not primary are only used
+    /// to decrypt existing content.
+    pub keys: BTreeMap<String, String>,
+}
+
+/// A set of key encryption keys identified by id.
+pub struct Keyring {
+    primary: String,
+    keys: BTreeMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    /// Creates a keyring with the given primary key.
+    ///
+    /// # Errors
+    ///
+    /// When the key id is longer than 255 bytes.
+    pub fn new(id: &str, key: &[u8; KEY_LEN]) -> StorageResult<Self> {
+        let keyring = Self {
+            primary: id.to_string(),
+            keys: BTreeMap::new(),
+        };
+        keyring.with_key(id, key)
+    }
+
+    /// Adds a key that is only used for decryption.
+    ///
+    /// # Errors
+    ///
+    /// When the key id is longer than 255 bytes.
+    pub fn with_key(mut self, id: &str, key: &[u8; KEY_LEN]) -> StorageResult<Self> {
+        if u8::try_from(id.len()).is_err() {
+            return Err(StorageError::Encryption(format!(
+                "key id `{id}` is longer than 255 bytes"
+            )));
+        }
+        self.keys.insert(
+            id.to_string(),
+            Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        );
+        Ok(self)
+    }
+
+    /// Creates a keyring from configuration.
+    ///
+    /// # Errors
+    ///
+    /// When a key is not a hex encoded 32 bytes key, or the primary key is
+    /// missing.
+    pub fn from_config(config: &KeyringConfig) -> StorageResult<Self> {
+        let mut keyring = Self {
+            primary: config.primary.clone(),
+            keys: BTreeMap::new(),
+        };
+        for (id, key) in &config.keys {
+            let key: [u8; KEY_LEN] = hex::decode(key)
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| {
+                    StorageError::Encryption(format!(
+                        "key `{id}` must be a hex encoded 32 bytes key"
+                    ))
+                })?;
+            keyring = keyring.with_key(id, &key)?;
+        }
+
+        if !keyring.keys.contains_key(&keyring.primary) {
+            return Err(StorageError::Encryption(format!(
+                "primary key `{}` is not found in keys",
+                keyring.primary
+            )));
+        }
+        Ok(keyring)
+    }
+
+    /// The key id used to encrypt new content.
+    #[must_use]
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    fn key(&self, id: &str) -> StorageResult<&Aes256Gcm> {
+        self.keys
+            .get(id)
+            .ok_or_else(|| StorageError::Encryption(format!("key `{id}` is not found in keyring")))
+    }
+
+    /// Encrypts the given content of the given path with a new data key
+    /// wrapped by the primary key.
+    ///
+    /// # Errors
+    ///
+    /// When the content could not be encrypted.
+    pub fn encrypt(&self, path: &Path, content: &[u8]) -> StorageResult<Bytes> {
+        let data_key: [u8; KEY_LEN] = rand::random();
+        let header = self.header(&self.primary, &data_key)?;
+
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: content,
+                    aad: &body_aad(path),
+                },
+            )
+            .map_err(|_| StorageError::Encryption("could not encrypt content".to_string()))?;
+
+        let mut out = BytesMut::with_capacity(header.len() + NONCE_LEN + ciphertext.len());
+        out.put_slice(&header);
+        out.put_slice(&nonce);
+        out.put_slice(&ciphertext);
+        Ok(out.freeze())
+    }
+
+    /// Decrypts content that was encrypted for the given path with
+    /// [`Keyring::encrypt`] by any key of the keyring.
+    ///
+    /// # Errors
+    ///
+    /// When the content is not encrypted, the key is not found, or the content
+    /// was tampered with or encrypted for another path.
+    pub fn decrypt(&self, path: &Path, content: &[u8]) -> StorageResult<Bytes> {
+        let envelope = Envelope::parse(content)?;
+        let data_key = self.unwrap_key(&envelope)?;
+
+        let (nonce, ciphertext) = envelope.body.split_at(NONCE_LEN);
+        let content = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &body_aad(path),
+                },
+            )
+            .map_err(|_| StorageError::Encryption("could not decrypt content".to_string()))?;
+        Ok(content.into())
+    }
+
+    /// Re-wraps the data key of the given content with the primary key,
+    /// without re-encrypting the content itself. Returns `None` when the
+    /// content is already wrapped by the primary key.
+    ///
+    /// # Errors
+    ///
+    /// When the content is not encrypted, or the key is not found.
+    pub fn rewrap(&self, content: &[u8]) -> StorageResult<Option<Bytes>> {
+        let envelope = Envelope::parse(content)?;
+        if envelope.key_id == self.primary {
+            return Ok(None);
+        }
+
+        let data_key: [u8; KEY_LEN] = self
+            .unwrap_key(&envelope)?
+            .try_into()
+            .map_err(|_| StorageError::Encryption("invalid data key".to_string()))?;
+        let header = self.header(&self.primary, &data_key)?;
+
+        let mut out = BytesMut::with_capacity(header.len() + envelope.body.len());
+        out.put_slice(&header);
+        out.put_slice(envelope.body);
+        Ok(Some(out.freeze()))
+    }
+
+    /// Builds the object header: magic, version, key id and the wrapped data
+    /// key.
+    fn header(&self, key_id: &str, data_key: &[u8; KEY_LEN]) -> StorageResult<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let wrapped = self
+            .key(key_id)?
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: data_key,
+                    aad: key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| StorageError::Encryption("could not wrap data key".to_string()))?;
+
+        let mut header =
+            Vec::with_capacity(MAGIC.len() + 2 + key_id.len() + NONCE_LEN + WRAPPED_KEY_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        // key id length is validated when the key is added to the keyring
+        #[allow(clippy::cast_possible_truncation)]
+        header.push(key_id.len() as u8);
+        header.extend_from_slice(key_id.as_bytes());
+        header.extend_from_slice(&nonce);
+        header.extend_from_slice(&wrapped);
+        Ok(header)
+    }
+
+    fn unwrap_key(&self, envelope: &Envelope<'_>) -> StorageResult<Vec<u8>> {
+        self.key(envelope.key_id)?
+            .decrypt(
+                Nonce::from_slice(envelope.key_nonce),
+                Payload {
+                    msg: envelope.wrapped_key,
+                    aad: envelope.key_id.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                StorageError::Encryption(format!(
+                    "could not unwrap data key with key `{}`",
+                    envelope.key_id
+                ))
+            })
+    }
+}
+
+fn body_aad(path: &Path) -> Vec<u8> {
+    let path = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    [BODY_AAD, path.as_bytes()].concat()
+}
+
+struct Envelope<'a> {
+    key_id: &'a str,
+    key_nonce: &'a [u8],
+    wrapped_key: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    fn parse(content: &'a [u8]) -> StorageResult<Self> {
+        let invalid = || StorageError::Encryption("content is not encrypted".to_string());
+
+        let prefix_len = MAGIC.len() + 2;
+        if content.len() < prefix_len || &content[..MAGIC.len()] != MAGIC {
+            return Err(invalid());
+        }
+        if content[MAGIC.len()] != VERSION {
+            return Err(StorageError::Encryption(format!(
+                "unsupported encryption version: {}",
+                content[MAGIC.len()]
+            )));
+        }
+
+        let key_id_len = usize::from(content[MAGIC.len() + 1]);
+        let header_len = prefix_len + key_id_len + NONCE_LEN + WRAPPED_KEY_LEN;
+        if content.len() < header_len + NONCE_LEN + TAG_LEN {
+            return Err(invalid());
+        }
+
+        let key_id = std::str::from_utf8(&content[prefix_len..prefix_len + key_id_len])
+            .map_err(|_| invalid())?;
+        let key_nonce_start = prefix_len + key_id_len;
+        let wrapped_key_start = key_nonce_start + NONCE_LEN;
+        Ok(Self {
+            key_id,
+            key_nonce: &content[key_nonce_start..wrapped_key_start],
+            wrapped_key: &content[wrapped_key_start..header_len],
+            body: &content[header_len..],
+        })
+    }
+}
+
+/// Wraps a driver and encrypts all of its content.
+pub struct EncryptedDriver {
+    inner: Box<dyn StoreDriver>,
+    keyring: Arc<Keyring>,
+}
+
+/// Create a new encrypted driver wrapping the given driver.
+///
+/// # Examples
+///```
+/// use loco_rs::storage::drivers::{encrypted::{self, Keyring}, mem};
+/// let keyring = Keyring::new("v1", &[7; 32]).unwrap();
+/// let storage = encrypted::new(mem::new(), keyring);
+/// ```
+#[must_use]
+pub fn new(inner: Box<dyn StoreDriver>, keyring: Keyring) -> Box<dyn StoreDriver> {
+    Box::new(EncryptedDriver::new(inner, Arc::new(keyring)))
+}
+
+impl EncryptedDriver {
+    /// Creates a new encrypted driver wrapping the given driver.
+    #[must_use]
+    pub fn new(inner: Box<dyn StoreDriver>, keyring: Arc<Keyring>) -> Self {
+        Self { inner, keyring }
+    }
+
+    /// Re-wraps the data key of the content at the given path with the primary
+    /// key. Returns `true` when the content was re-written.
+    ///
+    /// # Errors
+    ///
+    /// When the content could not be read, re-wrapped or written.
+    pub async fn rotate(&self, path: &Path) -> StorageResult<bool> {
+        let content = self.inner.get(path).await?.bytes().await?;
+        match self.keyring.rewrap(&content)? {
+            Some(content) => {
+                self.inner.upload(path, &content).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Re-wraps all the content under the given prefix with the primary key.
+    /// Returns the number of re-written objects.
+    ///
+    /// # Errors
+    ///
+    /// When the wrapped driver does not support listing, or an object could
+    /// not be rotated.
+    pub async fn rotate_all(&self, prefix: &Path) -> StorageResult<usize> {
+        let mut rotated = 0;
+        for path in self.inner.list(prefix).await? {
+            if self.rotate(&path).await? {
+                rotated += 1;
+            }
+        }
+        Ok(rotated)
+    }
+}
+
+#[async_trait]
+impl StoreDriver for EncryptedDriver {
+    async fn upload(&self, path: &Path, content: &Bytes) -> StorageResult<UploadResponse> {
+        let content = self.keyring.encrypt(path, content)?;
+        self.inner.upload(path, &content).await
+    }
+
+    async fn get(&self, path: &Path) -> StorageResult<GetResponse> {
+        let content = self.inner.get(path).await?.bytes().await?;
+        Ok(GetResponse::from_bytes(
+            self.keyring.decrypt(path, &content)?,
+        ))
+    }
+
+    async fn delete(&self, path: &Path) -> StorageResult<()> {
+        self.inner.delete(path).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> StorageResult<()> {
+        self.copy(from, to).await?;
+        self.inner.delete(from).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> StorageResult<()> {
+        let content = self.get(from).await?.bytes().await?;
+        self.upload(to, &content).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, path: &Path) -> StorageResult<bool> {
+        self.inner.exists(path).await
+    }
+
+    async fn list(&self, prefix: &Path) -> StorageResult<Vec<PathBuf>> {
+        self.inner.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::drivers::mem;
+
+    fn keyring() -> Keyring {
+        Keyring::new("v1", &[1; KEY_LEN]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn can_encrypt_content_at_rest() {
+        let keyring = Arc::new(keyring());
+        let store = EncryptedDriver::new(mem::new(), keyring.clone());
+
+        let path = PathBuf::from("users").join("1.txt");
+        let content = Bytes::from("secret document");
+        store.upload(&path, &content).await.unwrap();
+
+        let at_rest = store.inner.get(&path).await.unwrap().bytes().await.unwrap();
+        assert!(at_rest.starts_with(MAGIC));
+        assert!(!at_rest
+            .windows(content.len())
+            .any(|window| window == content.as_ref()));
+
+        assert_eq!(
+            store.get(&path).await.unwrap().bytes().await.unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn can_not_decrypt_tampered_content() {
+        let keyring = keyring();
+        let path = Path::new("1.txt");
+        let mut content = keyring.encrypt(path, b"secret document").unwrap().to_vec();
+        let last = content.len() - 1;
+        content[last] ^= 1;
+        assert!(keyring.decrypt(path, &content).is_err());
+
+        assert!(keyring.decrypt(path, b"plain content").is_err());
+    }
+
+    #[test]
+    fn can_not_decrypt_with_unknown_key() {
+        let path = Path::new("1.txt");
+        let content = keyring().encrypt(path, b"secret document").unwrap();
+        let keyring = Keyring::new("v1", &[2; KEY_LEN]).unwrap();
+        assert!(keyring.decrypt(path, &content).is_err());
+        let keyring = Keyring::new("v2", &[1; KEY_LEN]).unwrap();
+        assert!(keyring.decrypt(path, &content).is_err());
+    }
+
+    #[tokio::test]
+    async fn can_rotate_keys() {
+        let inner = mem::new();
+        let path = PathBuf::from("users").join("1.txt");
+        let content = Bytes::from("secret document");
+        let encrypted = keyring().encrypt(&path, &content).unwrap();
+        inner.upload(&path, &encrypted).await.unwrap();
+
+        let keyring = Keyring::new("v2", &[2; KEY_LEN])
+            .unwrap()
+            .with_key("v1", &[1; KEY_LEN])
+            .unwrap();
+        let store = EncryptedDriver::new(inner, Arc::new(keyring));
+
+        // old content is readable before and after the rotation
+        assert_eq!(
+            store.get(&path).await.unwrap().bytes().await.unwrap(),
+            content
+        );
+        assert_eq!(store.rotate_all(Path::new("")).await.unwrap(), 1);
+        assert_eq!(store.rotate_all(Path::new("")).await.unwrap(), 0);
+        assert_eq!(
+            store.get(&path).await.unwrap().bytes().await.unwrap(),
+            content
+        );
+
+        // the old key is no longer needed
+        let at_rest = store.inner.get(&path).await.unwrap().bytes().await.unwrap();
+        let keyring = Keyring::new("v2", &[2; KEY_LEN]).unwrap();
+        assert_eq!(keyring.decrypt(&path, &at_rest).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn can_not_decrypt_content_moved_to_another_path() {
+        let store = EncryptedDriver::new(mem::new(), Arc::new(keyring()));
+        let path = PathBuf::from("users").join("1.txt");
+        let other = PathBuf::from("users").join("2.txt");
+        let content = Bytes::from("secret document");
+        store.upload(&path, &content).await.unwrap();
+
+        // copied in the wrapped storage, bypassing the encrypted driver
+        store.inner.copy(&path, &other).await.unwrap();
+        assert!(store.get(&other).await.is_err());
+
+        // copied and renamed through the encrypted driver
+        store.copy(&path, &other).await.unwrap();
+        assert_eq!(
+            store.get(&other).await.unwrap().bytes().await.unwrap(),
+            content
+        );
+        let renamed = PathBuf::from("archive").join("1.txt");
+        store.rename(&path, &renamed).await.unwrap();
+        assert!(!store.exists(&path).await.unwrap());
+        assert_eq!(
+            store.get(&renamed).await.unwrap().bytes().await.unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn can_load_keyring_from_config() {
+        let config: KeyringConfig = serde_yaml::from_str(&format!(
+            "primary: v2\nkeys:\n  v1: {}\n  v2: {}\n",
+            "01".repeat(32),
+            "02".repeat(32)
+        ))
+        .unwrap();
+        let keyring = Keyring::from_config(&config).unwrap();
+        assert_eq!(keyring.primary(), "v2");
+
+        let config = KeyringConfig {
+            primary: "v3".to_string(),
+            keys: config.keys,
+        };
+        assert!(Keyring::from_config(&config).is_err());
+
+        let config = KeyringConfig {
+            primary: "v1".to_string(),
+            keys: BTreeMap::from([("v1".to_string(), "not-hex".to_string())]),
+        };
+        assert!(Keyring::from_config(&config).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -8,6 +8,9 @@ use opendal::Reader;
 pub mod aws;
 #[cfg(feature = "storage_azure")]
 pub mod azure;
+pub mod dedup;
+#[cfg(feature = "storage_encryption")]
+pub mod encrypted;
 #[cfg(feature = "storage_gcp")]
 pub mod gcp;
 pub mod local;
@@ -15,7 +18,7 @@ pub mod mem;
 pub mod null;
 pub mod opendal_adapter;
 
-use super::StorageResult;
+use super::{StorageError, StorageResult};
 
 #[derive(Debug)]
 pub struct UploadResponse {
@@ -28,12 +31,28 @@ pub struct UploadResponse {
 ///
 /// For example, we can read a specific range of bytes from the stream.
 pub struct GetResponse {
-    stream: Reader,
+    body: Body,
+}
+
+enum Body {
+    Stream(Reader),
+    Bytes(Bytes),
 }
 
 impl GetResponse {
     pub(crate) fn new(stream: Reader) -> Self {
-        Self { stream }
+        Self {
+            body: Body::Stream(stream),
+        }
+    }
+
+    /// Creates a response from content that was already read, for drivers
+    /// that transform the content of another driver.
+    #[must_use]
+    pub fn from_bytes(content: Bytes) -> Self {
+        Self {
+            body: Body::Bytes(content),
+        }
     }
 
     /// Read all content from the stream and return as `Bytes`.
@@ -42,7 +61,10 @@ impl GetResponse {
     ///
     /// Returns a `StorageError` with the reason for the failure.
     pub async fn bytes(&self) -> StorageResult<Bytes> {
-        Ok(self.stream.read(..).await?.to_bytes())
+        match &self.body {
+            Body::Stream(stream) => Ok(stream.read(..).await?.to_bytes()),
+            Body::Bytes(content) => Ok(content.clone()),
+        }
     }
 }
 
@@ -94,4 +116,17 @@ pub trait StoreDriver: Sync + Send {
     /// Returns a `StorageResult` with a boolean indicating the existence of the
     /// content.
     async fn exists(&self, path: &Path) -> StorageResult<bool>;
+
+    /// Lists the paths of all the content found under the given prefix in the
+    /// object store, recursively.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageResult` with the listed paths, or an error when the
+    /// store does not support listing.
+    async fn list(&self, _prefix: &Path) -> StorageResult<Vec<PathBuf>> {
+        Err(StorageError::Any(
+            "list operation is not supported by this store".into(),
+        ))
+    }
 }
@@ -1,9 +1,9 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures_util::SinkExt;
-use opendal::{layers::RetryLayer, Operator};
+use opendal::{layers::RetryLayer, EntryMode, Operator};
 
 use super::{GetResponse, StoreDriver, UploadResponse};
 use crate::storage::{StorageError, StorageResult};
@@ -141,4 +141,23 @@ impl StoreDriver for OpendalAdapter {
         let path = path.display().to_string();
         Ok(self.opendal_impl.exists(&path).await.unwrap_or(false))
     }
+
+    /// Lists the paths of all the content found under the given prefix in the
+    /// object store, recursively.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageResult` with the listed paths.
+    async fn list(&self, prefix: &Path) -> StorageResult<Vec<PathBuf>> {
+        let mut prefix = prefix.display().to_string();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        let entries = self.opendal_impl.list_with(&prefix).recursive(true).await?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.metadata().mode() == EntryMode::FILE)
+            .map(|entry| PathBuf::from(entry.path()))
+            .collect())
+    }
 }
@@ -0,0 +1,265 @@
+//! # Storage Journal
+//!
+//! A journal records secondary operations that failed in the
+//! [`super::strategies::mirror::MirrorStrategy`] and
+//! [`super::strategies::backup::BackupStrategy`], so secondaries that fell out
+//! of sync with the primary can be reported and repaired later with
+//! [`super::sync::replay`] (or `cargo loco storage sync`).
+//!
+//! Two journals come out of the box:
+//! * [`FileJournal`] - durable, keeps every entry as a JSON file in a local
+//!   folder.
+//! * [`MemJournal`] - keeps entries in memory, useful for testing.
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{StorageError, StorageResult};
+
+/// A storage operation that was applied on the primary store.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    Upload { path: PathBuf },
+    Delete { path: PathBuf },
+    Rename { from: PathBuf, to: PathBuf },
+    Copy { from: PathBuf, to: PathBuf },
+}
+
+impl Operation {
+    /// Returns the paths whose state is affected by the operation.
+    #[must_use]
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            Self::Upload { path } | Self::Delete { path } => vec![path.as_path()],
+            Self::Rename { from, to } => vec![from.as_path(), to.as_path()],
+            Self::Copy { to, .. } => vec![to.as_path()],
+        }
+    }
+}
+
+/// A failed secondary operation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Unique entry identifier
+    pub id: String,
+    /// The secondary store name the operation failed on
+    pub store: String,
+    /// The operation that was applied on the primary
+    pub operation: Operation,
+    /// The secondary error
+    pub error: String,
+    /// When the failure happened
+    pub created_at: DateTime<Utc>,
+}
+
+impl JournalEntry {
+    /// Creates a new entry for the given secondary store failure.
+    #[must_use]
+    pub fn new(store: &str, operation: Operation, error: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            store: store.to_string(),
+            operation,
+            error: error.to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Journal: Sync + Send {
+    /// Records a failed secondary operation.
+    ///
+    /// # Errors
+    ///
+    /// When the entry could not be persisted.
+    async fn record(&self, entry: JournalEntry) -> StorageResult<()>;
+
+    /// Returns all the pending entries, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// When the entries could not be read.
+    async fn entries(&self) -> StorageResult<Vec<JournalEntry>>;
+
+    /// Removes an entry once it was replayed.
+    ///
+    /// # Errors
+    ///
+    /// When the entry could not be removed.
+    async fn remove(&self, id: &str) -> StorageResult<()>;
+}
+
+/// In-memory journal.
+#[derive(Default)]
+pub struct MemJournal {
+    entries: Mutex<Vec<JournalEntry>>,
+}
+
+impl MemJournal {
+    /// Creates an empty in-memory journal.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Journal for MemJournal {
+    async fn record(&self, entry: JournalEntry) -> StorageResult<()> {
+        self.entries
+            .lock()
+            .map_err(|err| StorageError::Any(err.to_string().into()))?
+            .push(entry);
+        Ok(())
+    }
+
+    async fn entries(&self) -> StorageResult<Vec<JournalEntry>> {
+        Ok(self
+            .entries
+            .lock()
+            .map_err(|err| StorageError::Any(err.to_string().into()))?
+            .clone())
+    }
+
+    async fn remove(&self, id: &str) -> StorageResult<()> {
+        self.entries
+            .lock()
+            .map_err(|err| StorageError::Any(err.to_string().into()))?
+            .retain(|entry| entry.id != id);
+        Ok(())
+    }
+}
+
+/// Durable journal which keeps each entry as a JSON file in a local folder.
+pub struct FileJournal {
+    folder: PathBuf,
+}
+
+impl FileJournal {
+    /// Creates a journal in the given folder. The folder is created on the
+    /// first recorded entry.
+    #[must_use]
+    pub fn new(folder: impl Into<PathBuf>) -> Self {
+        Self {
+            folder: folder.into(),
+        }
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.folder.join(format!("{id}.json"))
+    }
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    StorageError::Any(Box::new(err))
+}
+
+#[async_trait]
+impl Journal for FileJournal {
+    async fn record(&self, entry: JournalEntry) -> StorageResult<()> {
+        tokio::fs::create_dir_all(&self.folder)
+            .await
+            .map_err(io_error)?;
+        let content =
+            serde_json::to_vec_pretty(&entry).map_err(|err| StorageError::Any(Box::new(err)))?;
+        tokio::fs::write(self.entry_path(&entry.id), content)
+            .await
+            .map_err(io_error)
+    }
+
+    async fn entries(&self) -> StorageResult<Vec<JournalEntry>> {
+        if !self.folder.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        let mut dir = tokio::fs::read_dir(&self.folder).await.map_err(io_error)?;
+        while let Some(file) = dir.next_entry().await.map_err(io_error)? {
+            let path = file.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let content = tokio::fs::read(&path).await.map_err(io_error)?;
+            match serde_json::from_slice::<JournalEntry>(&content) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    tracing::warn!(path = %path.display(), err = %err, "skipping invalid journal entry");
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.created_at);
+        Ok(entries)
+    }
+
+    async fn remove(&self, id: &str) -> StorageResult<()> {
+        match tokio::fs::remove_file(self.entry_path(id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(io_error(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn can_record_and_remove_mem_entries() {
+        let journal = MemJournal::new();
+        let entry = JournalEntry::new(
+            "store_2",
+            Operation::Upload {
+                path: PathBuf::from("1.txt"),
+            },
+            "error",
+        );
+        journal.record(entry.clone()).await.unwrap();
+        assert_eq!(journal.entries().await.unwrap(), vec![entry.clone()]);
+
+        journal.remove(&entry.id).await.unwrap();
+        assert!(journal.entries().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_record_and_remove_file_entries() {
+        let tree = tree_fs::TreeBuilder::default().create().unwrap();
+        let journal = FileJournal::new(tree.root.join("journal"));
+        assert!(journal.entries().await.unwrap().is_empty());
+
+        let first = JournalEntry::new(
+            "store_2",
+            Operation::Rename {
+                from: PathBuf::from("1.txt"),
+                to: PathBuf::from("2.txt"),
+            },
+            "error",
+        );
+        let second = JournalEntry::new(
+            "store_3",
+            Operation::Delete {
+                path: PathBuf::from("3.txt"),
+            },
+            "error",
+        );
+        journal.record(first.clone()).await.unwrap();
+        journal.record(second.clone()).await.unwrap();
+
+        // a new journal instance reads the persisted entries
+        let journal = FileJournal::new(tree.root.join("journal"));
+        assert_eq!(
+            journal.entries().await.unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+
+        journal.remove(&first.id).await.unwrap();
+        journal.remove("not-exists").await.unwrap();
+        assert_eq!(journal.entries().await.unwrap(), vec![second]);
+    }
+}
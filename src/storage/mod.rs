@@ -12,15 +12,24 @@
 //! The selected strategy can be dynamically changed at runtime.
 mod contents;
 pub mod drivers;
+pub mod journal;
 pub mod strategies;
+pub mod sync;
+#[cfg(feature = "storage_image")]
+pub mod variants;
 use std::{
     collections::BTreeMap,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use bytes::Bytes;
 
-use self::drivers::StoreDriver;
+use self::{
+    drivers::StoreDriver,
+    strategies::{backup::BackupStrategy, mirror::MirrorStrategy, single::SingleStrategy},
+};
+use crate::config;
 
 #[derive(thiserror::Error, Debug)]
 #[allow(clippy::module_name_repetitions)]
@@ -34,9 +43,31 @@ pub enum StorageError {
     #[error("Unable to read data from file {}", path.display().to_string())]
     UnableToReadBytes { path: PathBuf },
 
+    #[error(
+        "content integrity check failed for {}: expected {expected}, got {actual}",
+        path.display()
+    )]
+    IntegrityMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
     #[error("secondaries errors")]
     Multi(BTreeMap<String, String>),
 
+    #[cfg(feature = "storage_image")]
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+
+    #[cfg(feature = "storage_image")]
+    #[error("invalid transformation: {0}")]
+    InvalidTransformation(String),
+
+    #[cfg(feature = "storage_encryption")]
+    #[error("encryption error: {0}")]
+    Encryption(String),
+
     #[error(transparent)]
     Any(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
@@ -365,3 +396,280 @@ impl Storage {
             .ok_or(StorageError::StoreNotFound(name.to_string()))
     }
 }
+
+/// Creates the application storage from the `storage` configuration. When the
+/// section is missing, a null storage is returned, which can be replaced in the
+/// `after_context` hook.
+///
+/// # Errors
+///
+/// When a store could not be created, or the strategy refers to an unknown
+/// store.
+pub fn create_storage_provider(config: &config::Config) -> crate::Result<Arc<Storage>> {
+    let storage = match &config.storage {
+        Some(config) => Storage::from_config(config)?,
+        None => Storage::single(drivers::null::new()),
+    };
+    Ok(Arc::new(storage))
+}
+
+impl Storage {
+    /// Creates a storage from configuration.
+    ///
+    /// # Errors
+    ///
+    /// When a store could not be created, or the strategy refers to an unknown
+    /// store.
+    pub fn from_config(config: &config::StorageConfig) -> StorageResult<Self> {
+        let mut stores = BTreeMap::new();
+        for (name, store) in &config.stores {
+            stores.insert(name.clone(), create_store(store)?);
+        }
+
+        let strategy: Box<dyn strategies::StorageStrategy> = match &config.strategy {
+            Some(config::StorageStrategyConfig::Single { store }) => {
+                Box::new(SingleStrategy::new(store))
+            }
+            Some(config::StorageStrategyConfig::Mirror {
+                primary,
+                secondaries,
+                failure_mode,
+                journal,
+            }) => {
+                let strategy =
+                    MirrorStrategy::new(primary, Some(secondaries.clone()), failure_mode.clone());
+                Box::new(match journal {
+                    Some(folder) => {
+                        strategy.with_journal(Arc::new(journal::FileJournal::new(folder)))
+                    }
+                    None => strategy,
+                })
+            }
+            Some(config::StorageStrategyConfig::Backup {
+                primary,
+                secondaries,
+                failure_mode,
+                journal,
+            }) => {
+                let strategy =
+                    BackupStrategy::new(primary, Some(secondaries.clone()), failure_mode.clone());
+                Box::new(match journal {
+                    Some(folder) => {
+                        strategy.with_journal(Arc::new(journal::FileJournal::new(folder)))
+                    }
+                    None => strategy,
+                })
+            }
+            None => match stores.keys().collect::<Vec<_>>().as_slice() {
+                [store] => Box::new(SingleStrategy::new(store)),
+                _ => {
+                    return Err(StorageError::Any(
+                        "a storage strategy is required when more than one store is configured"
+                            .into(),
+                    ))
+                }
+            },
+        };
+
+        let storage = Self::new(stores, strategy);
+        storage.validate_strategy_stores(config.strategy.as_ref())?;
+        Ok(storage)
+    }
+
+    fn validate_strategy_stores(
+        &self,
+        strategy: Option<&config::StorageStrategyConfig>,
+    ) -> StorageResult<()> {
+        let names: Vec<&String> = match strategy {
+            Some(config::StorageStrategyConfig::Single { store }) => vec![store],
+            Some(
+                config::StorageStrategyConfig::Mirror {
+                    primary,
+                    secondaries,
+                    ..
+                }
+                | config::StorageStrategyConfig::Backup {
+                    primary,
+                    secondaries,
+                    ..
+                },
+            ) => std::iter::once(primary).chain(secondaries).collect(),
+            None => vec![],
+        };
+        for name in names {
+            self.as_store_err(name)?;
+        }
+        Ok(())
+    }
+}
+
+fn create_store(config: &config::StoreConfig) -> StorageResult<Box<dyn StoreDriver>> {
+    let store = match &config.driver {
+        config::StoreDriverConfig::Local { prefix } => match prefix {
+            Some(prefix) => drivers::local::new_with_prefix(prefix)?,
+            None => drivers::local::new(),
+        },
+        config::StoreDriverConfig::Mem => drivers::mem::new(),
+        config::StoreDriverConfig::Null => drivers::null::new(),
+        #[cfg(feature = "storage_aws_s3")]
+        config::StoreDriverConfig::S3 {
+            bucket,
+            region,
+            key_id,
+            secret_key,
+            token,
+        } => match (key_id, secret_key) {
+            (Some(key_id), Some(secret_key)) => drivers::aws::with_credentials(
+                bucket,
+                region,
+                drivers::aws::Credential {
+                    key_id: key_id.clone(),
+                    secret_key: secret_key.clone(),
+                    token: token.clone(),
+                },
+            )?,
+            _ => drivers::aws::new(bucket, region)?,
+        },
+        #[cfg(feature = "storage_gcp")]
+        config::StoreDriverConfig::Gcs {
+            bucket,
+            credential_path,
+        } => drivers::gcp::new(bucket, credential_path)?,
+        #[cfg(feature = "storage_azure")]
+        config::StoreDriverConfig::Azure {
+            container,
+            account_name,
+            access_key,
+            endpoint,
+        } => drivers::azure::new(container, account_name, access_key, endpoint)?,
+    };
+
+    #[cfg(feature = "storage_encryption")]
+    let store = match &config.encryption {
+        Some(keyring) => {
+            drivers::encrypted::new(store, drivers::encrypted::Keyring::from_config(keyring)?)
+        }
+        None => store,
+    };
+
+    Ok(if config.dedup {
+        Box::new(drivers::dedup::new(store))
+    } else {
+        store
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage_config(yaml: &str) -> config::StorageConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn can_create_storage_from_config() {
+        let storage = Storage::from_config(&storage_config(
+            r"
+stores:
+  primary:
+    kind: Mem
+  secondary:
+    kind: Mem
+    dedup: true
+strategy:
+  kind: Mirror
+  primary: primary
+  secondaries:
+    - secondary
+  failure_mode: MirrorAll
+",
+        ))
+        .unwrap();
+
+        let path = Path::new("users/1.txt");
+        storage.upload(path, &Bytes::from("data")).await.unwrap();
+        for store in ["primary", "secondary"] {
+            assert!(storage
+                .as_store_err(store)
+                .unwrap()
+                .exists(path)
+                .await
+                .unwrap());
+        }
+        let replication = storage.strategy.replication().unwrap();
+        assert_eq!(replication.secondaries, vec!["secondary".to_string()]);
+        assert!(replication.journal.is_none());
+    }
+
+    #[test]
+    fn can_create_single_store_without_strategy() {
+        let storage = Storage::from_config(&storage_config(
+            r"
+stores:
+  local:
+    kind: Mem
+",
+        ))
+        .unwrap();
+        assert!(storage.as_store("local").is_some());
+        assert!(storage.strategy.replication().is_none());
+    }
+
+    #[test]
+    fn can_parse_backup_failure_mode() {
+        let config = storage_config(
+            r"
+stores:
+  a:
+    kind: Mem
+  b:
+    kind: Local
+strategy:
+  kind: Backup
+  primary: a
+  secondaries: [b]
+  failure_mode:
+    CountFailure: 1
+  journal: tmp/journal
+",
+        );
+        assert!(matches!(
+            config.strategy,
+            Some(config::StorageStrategyConfig::Backup {
+                failure_mode: strategies::backup::FailureMode::CountFailure(1),
+                journal: Some(_),
+                ..
+            })
+        ));
+        assert!(Storage::from_config(&config).is_ok());
+    }
+
+    #[test]
+    fn cannot_create_storage_with_unknown_store() {
+        assert!(Storage::from_config(&storage_config(
+            r"
+stores:
+  a:
+    kind: Mem
+strategy:
+  kind: Mirror
+  primary: a
+  secondaries: [b]
+  failure_mode: MirrorAll
+",
+        ))
+        .is_err());
+
+        assert!(Storage::from_config(&storage_config(
+            r"
+stores:
+  a:
+    kind: Mem
+  b:
+    kind: Mem
+",
+        ))
+        .is_err());
+    }
+}
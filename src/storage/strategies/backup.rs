@@ -21,14 +21,19 @@
 //!
 //! * `download`: Initiates the download of the given path only from primary
 //!   storage.
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeMap, path::Path, sync::Arc};
 
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
-use crate::storage::{strategies::StorageStrategy, Storage, StorageError, StorageResult};
+use crate::storage::{
+    journal::{Journal, Operation},
+    strategies::{self, Replication, StorageStrategy},
+    Storage, StorageError, StorageResult,
+};
 
 /// Enum representing the failure mode for the [`BackupStrategy`].
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FailureMode {
     /// Fail if any secondary storage backend encounters an error.
     BackupAll,
@@ -48,10 +53,22 @@ pub struct BackupStrategy {
     pub primary: String,
     pub secondaries: Option<Vec<String>>,
     pub failure_mode: FailureMode,
+    /// Optional journal recording failed secondary operations.
+    pub journal: Option<Arc<dyn Journal>>,
 }
 
 #[async_trait::async_trait]
 impl StorageStrategy for BackupStrategy {
+    /// Returns the primary, the secondaries and the journal, so failed
+    /// secondary operations can be reconciled.
+    fn replication(&self) -> Option<Replication> {
+        Some(Replication {
+            primary: self.primary.clone(),
+            secondaries: self.secondaries.clone().unwrap_or_default(),
+            journal: self.journal.clone(),
+        })
+    }
+
     /// Uploads content to the primary and, if configured, secondary storage
     /// backends.
     // # Errors
@@ -64,6 +81,9 @@ impl StorageStrategy for BackupStrategy {
             .upload(path, content)
             .await?;
 
+        let operation = Operation::Upload {
+            path: path.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -80,6 +100,8 @@ impl StorageStrategy for BackupStrategy {
             }
         }
 
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -103,6 +125,9 @@ impl StorageStrategy for BackupStrategy {
     async fn delete(&self, storage: &Storage, path: &Path) -> StorageResult<()> {
         storage.as_store_err(&self.primary)?.delete(path).await?;
 
+        let operation = Operation::Delete {
+            path: path.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -119,6 +144,8 @@ impl StorageStrategy for BackupStrategy {
             }
         }
 
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -139,6 +166,10 @@ impl StorageStrategy for BackupStrategy {
             .rename(from, to)
             .await?;
 
+        let operation = Operation::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -155,6 +186,8 @@ impl StorageStrategy for BackupStrategy {
             }
         }
 
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -172,6 +205,10 @@ impl StorageStrategy for BackupStrategy {
     async fn copy(&self, storage: &Storage, from: &Path, to: &Path) -> StorageResult<()> {
         storage.as_store_err(&self.primary)?.copy(from, to).await?;
 
+        let operation = Operation::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -188,6 +225,8 @@ impl StorageStrategy for BackupStrategy {
             }
         }
 
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -204,8 +243,21 @@ impl BackupStrategy {
             primary: primary.to_string(),
             secondaries,
             failure_mode,
+            journal: None,
         }
     }
+
+    /// Records failed secondary operations in the given journal, so they can
+    /// be replayed later.
+    #[must_use]
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    async fn journal_failures(&self, operation: &Operation, errors: &BTreeMap<String, String>) {
+        strategies::journal_failures(self.journal.as_deref(), operation, errors).await;
+    }
 }
 
 impl FailureMode {
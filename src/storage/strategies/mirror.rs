@@ -10,8 +10,9 @@
 //!   given operation. If there is any failure with the primary storage, this
 //!   function returns an error. When
 //!   * [`FailureMode::MirrorAll`] is given - all the secondary storages must
-//!     succeed. If there is one failure in the mirror, the operation continues
-//!     to the rest but returns an error.
+//!     succeed. If there is one failure in the mirror, `upload` and `delete`
+//!     continue to the rest but return an error, while `rename` and `copy`
+//!     stop at the failure, and journal the skipped mirrors.
 //!   * [`FailureMode::AllowMirrorFailure`] is given - the operation does not
 //!     return an error when one or more mirror operations fail.
 //!
@@ -20,14 +21,19 @@
 //!   primary, it looks for the content in the secondary storages. If the
 //!   content is not found in any storage backend (both primary and secondary),
 //!   it returns an error.
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeMap, path::Path, sync::Arc};
 
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
-use crate::storage::{strategies::StorageStrategy, Storage, StorageError, StorageResult};
+use crate::storage::{
+    journal::{Journal, Operation},
+    strategies::{self, Replication, StorageStrategy},
+    Storage, StorageError, StorageResult,
+};
 
 /// Enum representing the failure mode for the [`MirrorStrategy`].
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FailureMode {
     /// Fail if any secondary storage mirror encounters an error.
     MirrorAll,
@@ -36,7 +42,7 @@ pub enum FailureMode {
 }
 
 /// Represents the Mirror Strategy for storage operations.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct MirrorStrategy {
     /// The primary storage backend.
     pub primary: String,
@@ -44,6 +50,19 @@ pub struct MirrorStrategy {
     pub secondaries: Option<Vec<String>>,
     /// The failure mode for handling errors from secondary storage backends.
     pub failure_mode: FailureMode,
+    /// Optional journal recording failed secondary operations.
+    pub journal: Option<Arc<dyn Journal>>,
+}
+
+impl std::fmt::Debug for MirrorStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MirrorStrategy")
+            .field("primary", &self.primary)
+            .field("secondaries", &self.secondaries)
+            .field("failure_mode", &self.failure_mode)
+            .field("journal", &self.journal.is_some())
+            .finish()
+    }
 }
 
 /// Implementation of the [`StorageStrategy`] for the [`MirrorStrategy`].
@@ -54,6 +73,16 @@ pub struct MirrorStrategy {
 #[async_trait::async_trait]
 #[async_trait::async_trait]
 impl StorageStrategy for MirrorStrategy {
+    /// Returns the primary, the secondaries and the journal, so failed
+    /// secondary operations can be reconciled.
+    fn replication(&self) -> Option<Replication> {
+        Some(Replication {
+            primary: self.primary.clone(),
+            secondaries: self.secondaries.clone().unwrap_or_default(),
+            journal: self.journal.clone(),
+        })
+    }
+
     /// Uploads content to the primary and, if configured, secondary storage
     /// mirror.
     ///
@@ -67,6 +96,9 @@ impl StorageStrategy for MirrorStrategy {
             .upload(path, content)
             .await?;
 
+        let operation = Operation::Upload {
+            path: path.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -83,6 +115,8 @@ impl StorageStrategy for MirrorStrategy {
             }
         }
 
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -123,6 +157,9 @@ impl StorageStrategy for MirrorStrategy {
     async fn delete(&self, storage: &Storage, path: &Path) -> StorageResult<()> {
         storage.as_store_err(&self.primary)?.delete(path).await?;
 
+        let operation = Operation::Delete {
+            path: path.to_path_buf(),
+        };
         let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
         if let Some(secondaries) = self.secondaries.as_ref() {
             for secondary_store in secondaries {
@@ -138,6 +175,8 @@ impl StorageStrategy for MirrorStrategy {
                 };
             }
         }
+        self.journal_failures(&operation, &collect_errors).await;
+
         if self.failure_mode.should_fail(&collect_errors) {
             return Err(StorageError::Multi(collect_errors));
         }
@@ -158,9 +197,14 @@ impl StorageStrategy for MirrorStrategy {
             .rename(from, to)
             .await?;
 
+        let operation = Operation::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
+
         if let Some(secondaries) = self.secondaries.as_ref() {
             let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
-            for secondary_store in secondaries {
+            for (index, secondary_store) in secondaries.iter().enumerate() {
                 match storage.as_store_err(secondary_store) {
                     Ok(store) => {
                         if let Err(err) = store.rename(from, to).await {
@@ -173,9 +217,12 @@ impl StorageStrategy for MirrorStrategy {
                 }
 
                 if self.failure_mode.should_fail(&collect_errors) {
+                    self.journal_fail_fast(&operation, &collect_errors, &secondaries[index + 1..])
+                        .await;
                     return Err(StorageError::Multi(collect_errors));
                 }
             }
+            self.journal_failures(&operation, &collect_errors).await;
         }
 
         Ok(())
@@ -189,9 +236,14 @@ impl StorageStrategy for MirrorStrategy {
     async fn copy(&self, storage: &Storage, from: &Path, to: &Path) -> StorageResult<()> {
         storage.as_store_err(&self.primary)?.copy(from, to).await?;
 
+        let operation = Operation::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        };
+
         if let Some(secondaries) = self.secondaries.as_ref() {
             let mut collect_errors: BTreeMap<String, String> = BTreeMap::new();
-            for secondary_store in secondaries {
+            for (index, secondary_store) in secondaries.iter().enumerate() {
                 match storage.as_store_err(secondary_store) {
                     Ok(store) => {
                         if let Err(err) = store.copy(from, to).await {
@@ -204,9 +256,12 @@ impl StorageStrategy for MirrorStrategy {
                 }
 
                 if self.failure_mode.should_fail(&collect_errors) {
+                    self.journal_fail_fast(&operation, &collect_errors, &secondaries[index + 1..])
+                        .await;
                     return Err(StorageError::Multi(collect_errors));
                 }
             }
+            self.journal_failures(&operation, &collect_errors).await;
         }
 
         Ok(())
@@ -221,9 +276,40 @@ impl MirrorStrategy {
             primary: primary.to_string(),
             secondaries,
             failure_mode,
+            journal: None,
         }
     }
 
+    /// Records failed secondary operations in the given journal, so they can
+    /// be replayed later.
+    #[must_use]
+    pub fn with_journal(mut self, journal: Arc<dyn Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    async fn journal_failures(&self, operation: &Operation, errors: &BTreeMap<String, String>) {
+        strategies::journal_failures(self.journal.as_deref(), operation, errors).await;
+    }
+
+    /// Journals the failure which stopped an operation, along with the
+    /// secondaries it skipped, so they are all replayed.
+    async fn journal_fail_fast(
+        &self,
+        operation: &Operation,
+        errors: &BTreeMap<String, String>,
+        skipped: &[String],
+    ) {
+        let mut errors = errors.clone();
+        for store in skipped {
+            errors.insert(
+                store.clone(),
+                "skipped after a failed secondary".to_string(),
+            );
+        }
+        self.journal_failures(operation, &errors).await;
+    }
+
     // Private helper function for downloading from a specific store.
     async fn try_download(
         storage: &Storage,
@@ -585,6 +671,51 @@ mod tests {
         assert!(store_3.exists(new_path.as_path()).await.unwrap());
     }
 
+    #[tokio::test]
+    async fn rename_should_fail_fast_only_with_mirror_all_policy() {
+        let path = PathBuf::from("users").join("1.txt");
+        let new_path = PathBuf::from("users").join("2.txt");
+
+        for (failure_mode, renamed, journaled) in [
+            (FailureMode::MirrorAll, false, vec!["missing", "store_2"]),
+            (FailureMode::AllowMirrorFailure, true, vec!["missing"]),
+        ] {
+            let journal = Arc::new(crate::storage::journal::MemJournal::new());
+            let strategy = MirrorStrategy::new(
+                "store_1",
+                Some(vec!["missing".to_string(), "store_2".to_string()]),
+                failure_mode.clone(),
+            )
+            .with_journal(journal.clone());
+            let storage = Storage::new(
+                BTreeMap::from([
+                    ("store_1".to_string(), drivers::mem::new()),
+                    ("store_2".to_string(), drivers::mem::new()),
+                ]),
+                Box::new(strategy),
+            );
+            let content = Bytes::from("file content");
+            for store in ["store_1", "store_2"] {
+                let store = storage.as_store(store).unwrap();
+                store.upload(&path, &content).await.unwrap();
+            }
+
+            let res = storage.rename(&path, &new_path).await;
+            assert_eq!(res.is_ok(), renamed, "{failure_mode:?}");
+            let store_2 = storage.as_store("store_2").unwrap();
+            assert_eq!(store_2.exists(&new_path).await.unwrap(), renamed);
+
+            let stores: Vec<String> = journal
+                .entries()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.store)
+                .collect();
+            assert_eq!(stores, journaled, "{failure_mode:?}");
+        }
+    }
+
     #[tokio::test]
     async fn copy_should_pass_when_primary_is_ok() {
         let store_1 = drivers::mem::new();
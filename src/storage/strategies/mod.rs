@@ -2,11 +2,25 @@ pub mod backup;
 pub mod mirror;
 pub mod single;
 
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path, sync::Arc};
 
 use bytes::Bytes;
 
-use crate::storage::{Storage, StorageResult};
+use crate::storage::{
+    journal::{Journal, JournalEntry, Operation},
+    Storage, StorageResult,
+};
+
+/// Describes how a strategy replicates a primary store into secondaries.
+#[derive(Clone)]
+pub struct Replication {
+    /// The primary store name
+    pub primary: String,
+    /// The secondary store names
+    pub secondaries: Vec<String>,
+    /// The journal recording failed secondary operations, if configured
+    pub journal: Option<Arc<dyn Journal>>,
+}
 
 #[async_trait::async_trait]
 pub trait StorageStrategy: Sync + Send {
@@ -15,4 +29,30 @@ pub trait StorageStrategy: Sync + Send {
     async fn delete(&self, storage: &Storage, path: &Path) -> StorageResult<()>;
     async fn rename(&self, storage: &Storage, from: &Path, to: &Path) -> StorageResult<()>;
     async fn copy(&self, storage: &Storage, from: &Path, to: &Path) -> StorageResult<()>;
+
+    /// Returns the replication details for strategies that replicate a
+    /// primary store into secondaries.
+    fn replication(&self) -> Option<Replication> {
+        None
+    }
+}
+
+/// Records the given secondary errors in the journal. Journal failures are
+/// logged and never fail the storage operation itself.
+pub(crate) async fn journal_failures(
+    journal: Option<&dyn Journal>,
+    operation: &Operation,
+    errors: &BTreeMap<String, String>,
+) {
+    for (store, error) in errors {
+        tracing::warn!(store, error, operation = ?operation, "secondary storage operation failed");
+        if let Some(journal) = journal {
+            if let Err(err) = journal
+                .record(JournalEntry::new(store, operation.clone(), error))
+                .await
+            {
+                tracing::error!(store, err = %err, operation = ?operation, "could not record storage journal entry");
+            }
+        }
+    }
 }
@@ -0,0 +1,303 @@
+//! # Storage Sync
+//!
+//! Reconciles secondaries of replicating strategies (mirror and backup) with
+//! their primary store.
+//!
+//! * [`replay`] - replays the failed secondary operations recorded in the
+//!   strategy [`super::journal::Journal`].
+//! * [`diff`] - lists the paths that are missing from, or extra in, each
+//!   secondary compared to the primary.
+//! * [`repair`] - reconciles the paths found by [`diff`].
+//!
+//! Reconciling a path is idempotent: when the path exists in the primary it is
+//! copied to the secondary, otherwise it is deleted from the secondary.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use super::{journal::JournalEntry, strategies::Replication, Storage, StorageError, StorageResult};
+
+/// The result of a journal replay.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Entries that were replayed successfully and removed from the journal
+    pub replayed: Vec<JournalEntry>,
+    /// Entries that could not be replayed, with the replay error
+    pub failed: Vec<(JournalEntry, String)>,
+}
+
+/// The difference between the primary and a secondary store.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    /// The secondary store name
+    pub store: String,
+    /// Paths found in the primary but not in the secondary
+    pub missing: Vec<PathBuf>,
+    /// Paths found in the secondary but not in the primary
+    pub extra: Vec<PathBuf>,
+}
+
+impl Diff {
+    /// Returns `true` when the secondary is in sync with the primary.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+fn replication(storage: &Storage) -> StorageResult<Replication> {
+    storage.strategy.replication().ok_or_else(|| {
+        StorageError::Any("storage strategy does not replicate to secondaries".into())
+    })
+}
+
+/// Makes the given path on the secondary store identical to the primary.
+///
+/// # Errors
+///
+/// When the stores are not found or the path could not be copied or deleted.
+pub async fn reconcile(
+    storage: &Storage,
+    primary: &str,
+    secondary: &str,
+    path: &Path,
+) -> StorageResult<()> {
+    let primary = storage.as_store_err(primary)?;
+    let secondary = storage.as_store_err(secondary)?;
+
+    if primary.exists(path).await? {
+        let content = primary.get(path).await?.bytes().await?;
+        secondary.upload(path, &content).await?;
+    } else if secondary.exists(path).await? {
+        secondary.delete(path).await?;
+    }
+    Ok(())
+}
+
+/// Replays all the entries recorded in the strategy journal. Replayed entries
+/// are removed from the journal, failed entries are kept for the next replay.
+///
+/// # Errors
+///
+/// When the strategy does not replicate, has no journal, or the journal could
+/// not be read.
+pub async fn replay(storage: &Storage) -> StorageResult<ReplayReport> {
+    let replication = replication(storage)?;
+    let journal = replication
+        .journal
+        .ok_or_else(|| StorageError::Any("storage strategy has no journal".into()))?;
+
+    let mut report = ReplayReport::default();
+    for entry in journal.entries().await? {
+        let mut result = Ok(());
+        for path in entry.operation.paths() {
+            result = reconcile(storage, &replication.primary, &entry.store, path).await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result {
+            Ok(()) => {
+                journal.remove(&entry.id).await?;
+                report.replayed.push(entry);
+            }
+            Err(err) => report.failed.push((entry, err.to_string())),
+        }
+    }
+    Ok(report)
+}
+
+/// Compares the paths under the given prefix in the primary with each
+/// secondary.
+///
+/// Only the existence of paths is compared, content drift of existing paths is
+/// reported by the journal.
+///
+/// # Errors
+///
+/// When the strategy does not replicate, or a store could not be listed.
+pub async fn diff(storage: &Storage, prefix: &Path) -> StorageResult<Vec<Diff>> {
+    let replication = replication(storage)?;
+    let primary: BTreeSet<PathBuf> = storage
+        .as_store_err(&replication.primary)?
+        .list(prefix)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut diffs = vec![];
+    for store in &replication.secondaries {
+        let secondary: BTreeSet<PathBuf> = storage
+            .as_store_err(store)?
+            .list(prefix)
+            .await?
+            .into_iter()
+            .collect();
+
+        diffs.push(Diff {
+            store: store.clone(),
+            missing: primary.difference(&secondary).cloned().collect(),
+            extra: secondary.difference(&primary).cloned().collect(),
+        });
+    }
+    Ok(diffs)
+}
+
+/// Reconciles all the paths found by [`diff`]. Returns the paths that could
+/// not be repaired by store, with the error.
+///
+/// # Errors
+///
+/// When the strategy does not replicate.
+pub async fn repair(
+    storage: &Storage,
+    diffs: &[Diff],
+) -> StorageResult<BTreeMap<String, Vec<(PathBuf, String)>>> {
+    let replication = replication(storage)?;
+    let mut errors: BTreeMap<String, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for diff in diffs {
+        for path in diff.missing.iter().chain(diff.extra.iter()) {
+            if let Err(err) = reconcile(storage, &replication.primary, &diff.store, path).await {
+                errors
+                    .entry(diff.store.clone())
+                    .or_default()
+                    .push((path.clone(), err.to_string()));
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::storage::{
+        drivers,
+        journal::{Journal, MemJournal},
+        strategies::{
+            backup::{BackupStrategy, FailureMode},
+            StorageStrategy,
+        },
+    };
+
+    fn storage(journal: Arc<MemJournal>) -> Storage {
+        let strategy = Box::new(
+            BackupStrategy::new(
+                "store_1",
+                Some(vec!["store_2".to_string(), "store_3".to_string()]),
+                FailureMode::AllowBackupFailure,
+            )
+            .with_journal(journal),
+        ) as Box<dyn StorageStrategy>;
+
+        Storage::new(
+            BTreeMap::from([
+                ("store_1".to_string(), drivers::mem::new()),
+                ("store_2".to_string(), drivers::mem::new()),
+                // store_3 rejects every operation
+                ("store_3".to_string(), drivers::null::new()),
+            ]),
+            strategy,
+        )
+    }
+
+    #[tokio::test]
+    async fn can_record_failed_secondary_operations() {
+        let journal = Arc::new(MemJournal::new());
+        let storage = storage(journal.clone());
+
+        let path = PathBuf::from("users").join("1.txt");
+        assert!(storage.upload(&path, &Bytes::from("data")).await.is_ok());
+
+        let entries = journal.entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].store, "store_3");
+        assert_eq!(
+            entries[0].operation,
+            crate::storage::journal::Operation::Upload { path }
+        );
+    }
+
+    #[tokio::test]
+    async fn can_replay_journal() {
+        let journal = Arc::new(MemJournal::new());
+        let mut storage = storage(journal.clone());
+
+        let path = PathBuf::from("users").join("1.txt");
+        let renamed = PathBuf::from("users").join("2.txt");
+        storage.upload(&path, &Bytes::from("data")).await.unwrap();
+        storage.rename(&path, &renamed).await.unwrap();
+        assert_eq!(journal.entries().await.unwrap().len(), 2);
+
+        // store_3 is still down, nothing is replayed
+        let report = replay(&storage).await.unwrap();
+        assert!(report.replayed.is_empty());
+        assert_eq!(report.failed.len(), 2);
+        assert_eq!(journal.entries().await.unwrap().len(), 2);
+
+        // store_3 is back
+        storage
+            .stores
+            .insert("store_3".to_string(), drivers::mem::new());
+        let report = replay(&storage).await.unwrap();
+        assert_eq!(report.replayed.len(), 2);
+        assert!(report.failed.is_empty());
+        assert!(journal.entries().await.unwrap().is_empty());
+
+        let store_3 = storage.as_store("store_3").unwrap();
+        assert!(!store_3.exists(&path).await.unwrap());
+        assert!(store_3.exists(&renamed).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn can_diff_and_repair() {
+        let journal = Arc::new(MemJournal::new());
+        let mut storage = storage(journal);
+        storage
+            .stores
+            .insert("store_3".to_string(), drivers::mem::new());
+
+        let path = PathBuf::from("users").join("1.txt");
+        let extra = PathBuf::from("users").join("extra.txt");
+        storage.upload(&path, &Bytes::from("data")).await.unwrap();
+        let store_2 = storage.as_store("store_2").unwrap();
+        store_2.delete(&path).await.unwrap();
+        store_2.upload(&extra, &Bytes::from("data")).await.unwrap();
+
+        let diffs = diff(&storage, Path::new("")).await.unwrap();
+        assert_eq!(
+            diffs,
+            vec![
+                Diff {
+                    store: "store_2".to_string(),
+                    missing: vec![path.clone()],
+                    extra: vec![extra.clone()],
+                },
+                Diff {
+                    store: "store_3".to_string(),
+                    ..Default::default()
+                }
+            ]
+        );
+
+        assert!(repair(&storage, &diffs).await.unwrap().is_empty());
+        assert!(diff(&storage, Path::new(""))
+            .await
+            .unwrap()
+            .iter()
+            .all(Diff::is_empty));
+    }
+
+    #[tokio::test]
+    async fn cannot_sync_single_strategy() {
+        let storage = Storage::single(drivers::mem::new());
+        assert!(replay(&storage).await.is_err());
+        assert!(diff(&storage, Path::new("")).await.is_err());
+    }
+}
@@ -0,0 +1,740 @@
+//! # Image Variants
+//!
+//! This module generates image variants (resize, crop and format conversion)
+//! on demand from an original object kept in [`Storage`]. A generated variant
+//! is cached back into the storage under a deterministic key derived from the
+//! original path and the [`Transformation`], so the work is done once per
+//! variant.
+//!
+//! Variants are served through [`routes`], which only accepts transformation
+//! params signed by a [`Signer`]. This prevents clients from requesting an
+//! unbounded number of variants.
+//!
+//! Everything is implemented in pure Rust, no system libraries (such as
+//! `ImageMagick`) are required.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use loco_rs::storage::variants::{self, Fit, Format, Signer, Transformation};
+//!
+//! let signer = Signer::new("my-secret");
+//! let transformation = Transformation::new()
+//!     .width(200)
+//!     .height(200)
+//!     .fit(Fit::Cover)
+//!     .format(Format::Webp);
+//!
+//! // `/_storage/variants/avatars/1.png?w=200&h=200&fit=cover&fmt=webp&s=...`
+//! let url = signer.url("/_storage/variants", Path::new("avatars/1.png"), &transformation);
+//!
+//! // in `Hooks::routes`
+//! AppRoutes::with_default_routes().add_route(variants::routes(signer));
+//! ```
+use std::{
+    fmt::Write,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    extract::{Path as AxumPath, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension,
+};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::{Storage, StorageError, StorageResult};
+use crate::{app::AppContext, controller::Routes, Error, Result};
+
+/// The default prefix which variant routes are mounted on.
+pub const DEFAULT_ROUTE_PREFIX: &str = "/_storage/variants";
+
+/// The storage folder where generated variants are cached.
+const VARIANTS_FOLDER: &str = "variants";
+
+/// The largest width or height a variant can be resized to.
+pub const MAX_DIMENSION: u32 = 8192;
+
+/// Describes how the image is fitted into the requested width and height.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Resize the image to fit within the given bounds, keeping the aspect
+    /// ratio.
+    #[default]
+    Contain,
+    /// Resize the image to fill the given bounds, keeping the aspect ratio and
+    /// cropping the overflow from the center.
+    Cover,
+    /// Resize the image to the exact given bounds, ignoring the aspect ratio.
+    Exact,
+}
+
+impl Fit {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Contain => "contain",
+            Self::Cover => "cover",
+            Self::Exact => "exact",
+        }
+    }
+}
+
+/// The output format of a variant.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Avif,
+}
+
+impl Format {
+    /// The file extension used for variant keys.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// The mime type used when serving the variant.
+    #[must_use]
+    pub const fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    /// Guess the format from a file extension.
+    #[must_use]
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "gif" => Some(Self::Gif),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    const fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Gif => ImageFormat::Gif,
+            Self::Webp => ImageFormat::WebP,
+            Self::Avif => ImageFormat::Avif,
+        }
+    }
+
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::Gif => Some(Self::Gif),
+            ImageFormat::WebP => Some(Self::Webp),
+            ImageFormat::Avif => Some(Self::Avif),
+            _ => None,
+        }
+    }
+}
+
+/// A set of operations applied to an original image to produce a variant.
+///
+/// When no `format` is given the variant keeps the format of the original.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Transformation {
+    /// Target width in pixels
+    pub width: Option<u32>,
+    /// Target height in pixels
+    pub height: Option<u32>,
+    /// How the image is fitted into the target width and height
+    #[serde(default)]
+    pub fit: Fit,
+    /// Output format
+    pub format: Option<Format>,
+}
+
+impl Transformation {
+    /// Creates an empty transformation, which keeps the original as is.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the target width.
+    #[must_use]
+    pub fn width(mut self, width: u32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Set the target height.
+    #[must_use]
+    pub fn height(mut self, height: u32) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    /// Set the fit mode.
+    #[must_use]
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    /// Set the output format.
+    #[must_use]
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Returns a canonical, stable representation of the transformation.
+    ///
+    /// # Example
+    /// ```
+    /// use loco_rs::storage::variants::{Fit, Format, Transformation};
+    ///
+    /// let transformation = Transformation::new()
+    ///     .width(200)
+    ///     .fit(Fit::Cover)
+    ///     .format(Format::Webp);
+    /// assert_eq!(transformation.canonical(), "w200-cover-webp");
+    /// ```
+    #[must_use]
+    pub fn canonical(&self) -> String {
+        let mut parts = vec![];
+        if let Some(width) = self.width {
+            parts.push(format!("w{width}"));
+        }
+        if let Some(height) = self.height {
+            parts.push(format!("h{height}"));
+        }
+        parts.push(self.fit.as_str().to_string());
+        if let Some(format) = self.format {
+            parts.push(format.extension().to_string());
+        }
+        parts.join("-")
+    }
+
+    /// Returns the output format of the variants of the given original path:
+    /// the requested format, otherwise the one of the original extension.
+    /// `None` when the extension is missing or unknown, and only the content
+    /// of the original tells.
+    #[must_use]
+    pub fn output_format(&self, original: &Path) -> Option<Format> {
+        self.format.or_else(|| {
+            original
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(Format::from_extension)
+        })
+    }
+
+    /// Returns the deterministic storage key of the variant of the given
+    /// original path, in the given output format.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::{Path, PathBuf};
+    /// use loco_rs::storage::variants::{Format, Transformation};
+    ///
+    /// let transformation = Transformation::new().width(200).format(Format::Webp);
+    /// assert_eq!(
+    ///     transformation.variant_key(Path::new("avatars/1.png"), Format::Webp),
+    ///     PathBuf::from("variants/avatars/1.png/w200-contain-webp.webp")
+    /// );
+    /// ```
+    #[must_use]
+    pub fn variant_key(&self, original: &Path, format: Format) -> PathBuf {
+        PathBuf::from(VARIANTS_FOLDER)
+            .join(original.strip_prefix("/").unwrap_or(original))
+            .join(format!("{}.{}", self.canonical(), format.extension()))
+    }
+
+    /// Validates the transformation params.
+    ///
+    /// # Errors
+    ///
+    /// When a dimension is zero or exceeds [`MAX_DIMENSION`]
+    pub fn validate(&self) -> StorageResult<()> {
+        for dimension in [self.width, self.height].into_iter().flatten() {
+            if dimension == 0 || dimension > MAX_DIMENSION {
+                return Err(StorageError::InvalidTransformation(format!(
+                    "dimension must be between 1 and {MAX_DIMENSION}, got {dimension}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the transformation on the given encoded image and returns the
+    /// encoded variant together with its format.
+    ///
+    /// # Errors
+    ///
+    /// When the original could not be decoded, or the variant could not be
+    /// encoded.
+    pub fn apply(&self, original: &[u8]) -> StorageResult<(Bytes, Format)> {
+        self.validate()?;
+
+        let reader = image::ImageReader::new(Cursor::new(original))
+            .with_guessed_format()
+            .map_err(|err| StorageError::Any(Box::new(err)))?;
+        let original_format = reader.format();
+        let image = reader.decode()?;
+
+        let format = self
+            .format
+            .or_else(|| original_format.and_then(Format::from_image_format))
+            .ok_or_else(|| {
+                StorageError::InvalidTransformation("unsupported original format".to_string())
+            })?;
+
+        let image = self.resize(image);
+        // JPEG has no alpha channel
+        let image = if format == Format::Jpeg {
+            DynamicImage::ImageRgb8(image.to_rgb8())
+        } else {
+            image
+        };
+
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, format.image_format())?;
+
+        Ok((Bytes::from(encoded.into_inner()), format))
+    }
+
+    fn resize(&self, image: DynamicImage) -> DynamicImage {
+        let (width, height) = match (self.width, self.height) {
+            (None, None) => return image,
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, u32::MAX),
+            (None, Some(height)) => (u32::MAX, height),
+        };
+
+        match self.fit {
+            Fit::Cover if self.width.is_some() && self.height.is_some() => {
+                image.resize_to_fill(width, height, FilterType::Lanczos3)
+            }
+            Fit::Exact if self.width.is_some() && self.height.is_some() => {
+                image.resize_exact(width, height, FilterType::Lanczos3)
+            }
+            _ => image.resize(width, height, FilterType::Lanczos3),
+        }
+    }
+}
+
+/// Signs and verifies transformation params, so only variants produced by
+/// the application can be requested.
+#[derive(Clone)]
+pub struct Signer {
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signer").finish_non_exhaustive()
+    }
+}
+
+impl Signer {
+    /// Creates a new [`Signer`] with the given secret.
+    #[must_use]
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    fn mac(&self, path: &Path, transformation: &Transformation) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC can take a key of any size");
+        mac.update(path.display().to_string().as_bytes());
+        mac.update(b"?");
+        mac.update(transformation.canonical().as_bytes());
+        mac
+    }
+
+    /// Returns the hex encoded signature of the given path and transformation.
+    #[must_use]
+    pub fn sign(&self, path: &Path, transformation: &Transformation) -> String {
+        hex::encode(self.mac(path, transformation).finalize().into_bytes())
+    }
+
+    /// Verifies the given hex encoded signature in constant time.
+    #[must_use]
+    pub fn verify(&self, path: &Path, transformation: &Transformation, signature: &str) -> bool {
+        hex::decode(signature).is_ok_and(|signature| {
+            self.mac(path, transformation)
+                .verify_slice(&signature)
+                .is_ok()
+        })
+    }
+
+    /// Returns a signed url of the variant, to be served by [`routes`]
+    /// mounted on the given prefix.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::Path;
+    /// use loco_rs::storage::variants::{Signer, Transformation};
+    ///
+    /// let signer = Signer::new("secret");
+    /// let url = signer.url("/_storage/variants", Path::new("avatars/1.png"), &Transformation::new().width(100));
+    /// assert!(url.starts_with("/_storage/variants/avatars/1.png?w=100&fit=contain&s="));
+    /// ```
+    #[must_use]
+    pub fn url(&self, prefix: &str, path: &Path, transformation: &Transformation) -> String {
+        let mut url = format!(
+            "{}/{}?",
+            prefix.trim_end_matches('/'),
+            path.display().to_string().trim_start_matches('/')
+        );
+        if let Some(width) = transformation.width {
+            let _ = write!(url, "w={width}&");
+        }
+        if let Some(height) = transformation.height {
+            let _ = write!(url, "h={height}&");
+        }
+        let _ = write!(url, "fit={}&", transformation.fit.as_str());
+        if let Some(format) = transformation.format {
+            let _ = write!(url, "fmt={}&", format.extension());
+        }
+        let _ = write!(url, "s={}", self.sign(path, transformation));
+        url
+    }
+}
+
+impl Storage {
+    /// Returns the variant of the image stored at the given path.
+    ///
+    /// The variant is read from its deterministic key when it was already
+    /// generated, otherwise it is generated from the original and uploaded
+    /// back to the storage.
+    ///
+    /// # Errors
+    ///
+    /// When the original could not be downloaded or transformed, or the
+    /// variant could not be stored.
+    pub async fn variant(
+        &self,
+        path: &Path,
+        transformation: &Transformation,
+    ) -> StorageResult<(Bytes, Format)> {
+        transformation.validate()?;
+
+        // without a requested format or a known extension, the format of the
+        // variant is the one of the original content
+        let mut original = None;
+        let format = match transformation.output_format(path) {
+            Some(format) => format,
+            None => {
+                let content: Vec<u8> = self.download(path).await?;
+                let format = image::guess_format(&content)
+                    .ok()
+                    .and_then(Format::from_image_format)
+                    .ok_or_else(|| {
+                        StorageError::InvalidTransformation(
+                            "unsupported original format".to_string(),
+                        )
+                    })?;
+                original = Some(content);
+                format
+            }
+        };
+        let key = transformation.variant_key(path, format);
+
+        if let Ok(content) = self.download::<Vec<u8>>(&key).await {
+            return Ok((Bytes::from(content), format));
+        }
+
+        let original: Vec<u8> = match original {
+            Some(original) => original,
+            None => self.download(path).await?,
+        };
+        // the variant is encoded in the format of its key
+        let transformation = transformation.clone().format(format);
+        let (content, format) =
+            tokio::task::spawn_blocking(move || transformation.apply(&original))
+                .await
+                .map_err(|err| StorageError::Any(Box::new(err)))??;
+
+        self.upload(&key, &content).await?;
+        Ok((content, format))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct VariantParams {
+    w: Option<u32>,
+    h: Option<u32>,
+    #[serde(default)]
+    fit: Fit,
+    fmt: Option<String>,
+    s: String,
+}
+
+async fn serve_variant(
+    State(ctx): State<AppContext>,
+    Extension(signer): Extension<Signer>,
+    AxumPath(path): AxumPath<String>,
+    Query(params): Query<VariantParams>,
+) -> Result<Response> {
+    let format = params
+        .fmt
+        .as_deref()
+        .map(|fmt| {
+            Format::from_extension(fmt)
+                .ok_or_else(|| Error::BadRequest(format!("unsupported format: {fmt}")))
+        })
+        .transpose()?;
+
+    let transformation = Transformation {
+        width: params.w,
+        height: params.h,
+        fit: params.fit,
+        format,
+    };
+
+    let path = PathBuf::from(path);
+    if !signer.verify(&path, &transformation, &params.s) {
+        return Err(Error::Unauthorized("invalid signature".to_string()));
+    }
+
+    let (content, format) = ctx
+        .storage
+        .variant(&path, &transformation)
+        .await
+        .map_err(|err| {
+            tracing::debug!(err = %err, path = %path.display(), "could not serve variant");
+            match err {
+                StorageError::InvalidTransformation(msg) => Error::BadRequest(msg),
+                _ => Error::NotFound,
+            }
+        })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.mime()),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+/// Returns the routes serving signed variants, mounted on
+/// [`DEFAULT_ROUTE_PREFIX`].
+#[must_use]
+pub fn routes(signer: Signer) -> Routes {
+    Routes::at(DEFAULT_ROUTE_PREFIX)
+        .add("/{*path}", get(serve_variant))
+        .layer(Extension(signer))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{storage::drivers, tests_cfg};
+
+    fn png(width: u32, height: u32) -> Bytes {
+        let image = RgbaImage::from_pixel(width, height, Rgba([200, 10, 10, 255]));
+        let mut content = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut content, ImageFormat::Png)
+            .unwrap();
+        Bytes::from(content.into_inner())
+    }
+
+    #[test]
+    fn can_build_variant_key() {
+        let transformation = Transformation::new().width(100).height(50).fit(Fit::Cover);
+        assert_eq!(
+            transformation.variant_key(Path::new("/users/1.png"), Format::Png),
+            PathBuf::from("variants/users/1.png/w100-h50-cover.png")
+        );
+        assert_eq!(
+            transformation
+                .clone()
+                .format(Format::Avif)
+                .variant_key(Path::new("users/1.png"), Format::Avif),
+            PathBuf::from("variants/users/1.png/w100-h50-cover-avif.avif")
+        );
+        assert_eq!(
+            transformation.output_format(Path::new("users/1.JPEG")),
+            Some(Format::Jpeg)
+        );
+        assert_eq!(transformation.output_format(Path::new("users/1")), None);
+        assert_eq!(transformation.output_format(Path::new("users/1.bin")), None);
+    }
+
+    #[test]
+    fn can_apply_transformation() {
+        let original = png(40, 20);
+
+        let (content, format) = Transformation::new().width(10).apply(&original).unwrap();
+        assert_eq!(format, Format::Png);
+        assert_eq!(
+            image::load_from_memory(&content).unwrap().dimensions(),
+            (10, 5)
+        );
+
+        let (content, format) = Transformation::new()
+            .width(10)
+            .height(10)
+            .fit(Fit::Cover)
+            .format(Format::Webp)
+            .apply(&original)
+            .unwrap();
+        assert_eq!(format, Format::Webp);
+        let variant = image::load_from_memory(&content).unwrap();
+        assert_eq!(variant.dimensions(), (10, 10));
+
+        let (content, _) = Transformation::new()
+            .width(8)
+            .height(4)
+            .fit(Fit::Exact)
+            .format(Format::Jpeg)
+            .apply(&original)
+            .unwrap();
+        assert_eq!(image::guess_format(&content).unwrap(), ImageFormat::Jpeg);
+    }
+
+    #[test]
+    fn can_reject_invalid_transformation() {
+        let original = png(4, 4);
+        assert!(Transformation::new().width(0).apply(&original).is_err());
+        assert!(Transformation::new()
+            .height(MAX_DIMENSION + 1)
+            .apply(&original)
+            .is_err());
+        assert!(Transformation::new()
+            .width(2)
+            .apply(b"not an image")
+            .is_err());
+    }
+
+    #[test]
+    fn can_sign_and_verify() {
+        let signer = Signer::new("secret");
+        let path = Path::new("users/1.png");
+        let transformation = Transformation::new().width(10);
+        let signature = signer.sign(path, &transformation);
+
+        assert!(signer.verify(path, &transformation, &signature));
+        assert!(!signer.verify(path, &transformation.clone().width(11), &signature));
+        assert!(!signer.verify(Path::new("users/2.png"), &transformation, &signature));
+        assert!(!Signer::new("other").verify(path, &transformation, &signature));
+        assert!(!signer.verify(path, &transformation, "not-hex"));
+    }
+
+    #[tokio::test]
+    async fn can_generate_and_cache_variant() {
+        let storage = Storage::single(drivers::mem::new());
+        let path = Path::new("users/1.png");
+        storage.upload(path, &png(40, 20)).await.unwrap();
+
+        let transformation = Transformation::new().width(20).format(Format::Webp);
+        let (content, format) = storage.variant(path, &transformation).await.unwrap();
+        assert_eq!(format, Format::Webp);
+
+        let key = transformation.variant_key(path, format);
+        let store = storage.as_store("store").unwrap();
+        assert!(store.exists(&key).await.unwrap());
+
+        // served from cache once the original is gone
+        storage.delete(path).await.unwrap();
+        let (cached, _) = storage.variant(path, &transformation).await.unwrap();
+        assert_eq!(content, cached);
+    }
+
+    #[tokio::test]
+    async fn can_cache_variant_of_original_without_extension() {
+        let storage = Storage::single(drivers::mem::new());
+        let path = Path::new("users/1");
+        storage.upload(path, &png(40, 20)).await.unwrap();
+
+        let transformation = Transformation::new().width(20);
+        let (content, format) = storage.variant(path, &transformation).await.unwrap();
+        assert_eq!(format, Format::Png);
+        let store = storage.as_store("store").unwrap();
+        assert!(store
+            .exists(Path::new("variants/users/1/w20-contain.png"))
+            .await
+            .unwrap());
+
+        // served from cache, not generated from the replaced original
+        storage.upload(path, &png(80, 40)).await.unwrap();
+        let (cached, format) = storage.variant(path, &transformation).await.unwrap();
+        assert_eq!(format, Format::Png);
+        assert_eq!(content, cached);
+    }
+
+    #[tokio::test]
+    async fn can_serve_signed_variant() {
+        let signer = Signer::new("secret");
+        let ctx = tests_cfg::app::get_app_context().await;
+        let path = Path::new("users/1.png");
+        ctx.storage.upload(path, &png(40, 20)).await.unwrap();
+
+        let mut app = Router::new();
+        let routes = routes(signer.clone());
+        for handler in routes.handlers {
+            app = app.route(
+                &format!("{}{}", DEFAULT_ROUTE_PREFIX, handler.uri),
+                handler.method,
+            );
+        }
+        let app = app.with_state(ctx);
+
+        let transformation = Transformation::new().width(10).format(Format::Webp);
+        let url = signer.url(DEFAULT_ROUTE_PREFIX, path, &transformation);
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/webp"
+        );
+
+        let tampered = url.replace("w=10", "w=11");
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(&tampered)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -13,6 +13,7 @@ pub fn get_cleanup_user_model() -> &'static Vec<(&'static str, &'static str)> {
                 "PID",
             ),
             (r"password: (.*{60}),", "password: \"PASSWORD\","),
+            (r"\b[A-Za-z0-9]{24}\.[A-Za-z0-9]{48}\b", "REFRESH_TOKEN"),
             (r"([A-Za-z0-9-_]*\.[A-Za-z0-9-_]*\.[A-Za-z0-9-_]*)", "TOKEN"),
         ]
     })
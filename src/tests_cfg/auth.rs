@@ -0,0 +1,62 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::{auth::tokens::TokenStore, Result};
+
+/// An in-memory [`TokenStore`], ignoring expiries.
+#[derive(Default)]
+pub struct MemStore(Mutex<HashMap<String, String>>);
+
+#[async_trait]
+impl TokenStore for MemStore {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        // let concurrent calls interleave between reads and writes
+        tokio::task::yield_now().await;
+        Ok(self.0.lock().unwrap().get(key).cloned())
+    }
+
+    async fn insert(&self, key: &str, value: &str, _expiry: Duration) -> Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &str,
+        value: &str,
+        _expiry: Duration,
+    ) -> Result<bool> {
+        let mut map = self.0.lock().unwrap();
+        if map.get(key).map(String::as_str) != Some(expected) {
+            return Ok(false);
+        }
+        map.insert(key.to_string(), value.to_string());
+        Ok(true)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.0.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// The directory of the JWT key fixtures.
+#[must_use]
+pub fn jwt_fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/jwt")
+}
+
+/// Returns the content of the given JWT key fixture.
+///
+/// # Panics
+///
+/// When the fixture could not be read.
+#[must_use]
+pub fn jwt_fixture(name: &str) -> Vec<u8> {
+    std::fs::read(jwt_fixtures_dir().join(name)).unwrap()
+}
@@ -28,6 +28,7 @@ pub fn test_config() -> Config {
         },
         #[cfg(feature = "with-db")]
         database: get_database_config(),
+        storage: None,
         queue: None,
         auth: None,
         workers: config::Workers {
@@ -11,6 +11,7 @@ use crate::{
     config::Config,
     controller::AppRoutes,
     environment::Environment,
+    mailer::preview::MailerPreviews,
     task::Tasks,
     Result,
 };
@@ -59,6 +60,17 @@ pub async fn dummy_connection() -> sea_orm::DatabaseConnection {
     sea_orm::Database::connect(opt).await.unwrap()
 }
 
+/// Creates an empty in-memory database.
+///
+/// # Panics
+/// When the database could not be opened
+pub async fn memory_db() -> sea_orm::DatabaseConnection {
+    let mut opt = sea_orm::ConnectOptions::new("sqlite::memory:");
+    // every connection to an in-memory database sees a different database
+    opt.max_connections(1);
+    sea_orm::Database::connect(opt).await.unwrap()
+}
+
 pub mod test_db {
     use std::fmt;
 
@@ -149,6 +161,10 @@ impl Hooks for AppHook {
         tasks.register(super::task::ParseArgs);
     }
 
+    fn register_mailer_previews(ctx: &AppContext, previews: &mut MailerPreviews) {
+        previews.register::<super::mailer::TestMailer>(ctx);
+    }
+
     async fn truncate(_ctx: &AppContext) -> Result<()> {
         Ok(())
     }
@@ -0,0 +1,27 @@
+use include_dir::{include_dir, Dir};
+
+use crate::prelude::*;
+
+static TEST_TEMPLATE: Dir<'_> = include_dir!("tests/fixtures/email_template/test");
+
+#[derive(Debug)]
+pub struct TestMailer;
+
+impl Mailer for TestMailer {}
+
+impl MailerPreview for TestMailer {
+    fn name() -> String {
+        "test".to_string()
+    }
+
+    fn previews(_ctx: &AppContext) -> Vec<mailer::preview::Preview> {
+        vec![mailer::preview::Preview::new(
+            "welcome",
+            &TEST_TEMPLATE,
+            serde_json::json!({
+                "verifyToken": "1111-2222-3333-4444",
+                "name": "Preview <user>",
+            }),
+        )]
+    }
+}
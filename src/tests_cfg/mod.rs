@@ -1,8 +1,11 @@
 pub mod app;
+#[cfg(all(test, feature = "auth_jwt"))]
+pub mod auth;
 pub mod config;
 pub mod controllers;
 #[cfg(feature = "with-db")]
 pub mod db;
+pub mod mailer;
 #[cfg(test)]
 pub mod postgres;
 #[cfg(any(feature = "bg_pg", feature = "bg_sqlt"))]
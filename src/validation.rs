@@ -72,6 +72,348 @@ pub fn is_valid_email(email: &str) -> Result<(), ValidationError> {
     }
 }
 
+/// Common passwords from public breach lists, rejected by [`PasswordStrength`].
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "123456789",
+    "12345678",
+    "12345",
+    "1234567",
+    "1234567890",
+    "123123",
+    "1234",
+    "111111",
+    "000000",
+    "password",
+    "password1",
+    "password123",
+    "passw0rd",
+    "p@ssw0rd",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "qwerty1",
+    "asdfghjkl",
+    "abc123",
+    "abcd1234",
+    "a1b2c3d4",
+    "iloveyou",
+    "admin",
+    "admin123",
+    "administrator",
+    "root",
+    "toor",
+    "welcome",
+    "welcome1",
+    "letmein",
+    "login",
+    "master",
+    "monkey",
+    "dragon",
+    "sunshine",
+    "princess",
+    "football",
+    "baseball",
+    "shadow",
+    "superman",
+    "batman",
+    "trustno1",
+    "starwars",
+    "whatever",
+    "freedom",
+    "hello",
+    "hello123",
+    "secret",
+    "changeme",
+    "default",
+    "guest",
+    "test",
+    "test123",
+    "testing",
+    "654321",
+    "666666",
+    "696969",
+    "7777777",
+    "888888",
+    "987654321",
+    "121212",
+    "112233",
+    "123321",
+    "11111111",
+    "00000000",
+    "1q2w3e4r",
+    "1q2w3e4r5t",
+    "zaq12wsx",
+    "1qaz2wsx",
+    "qazwsx",
+    "michael",
+    "jennifer",
+    "jordan",
+    "hunter",
+    "charlie",
+    "daniel",
+    "thomas",
+    "jessica",
+    "ashley",
+    "computer",
+    "internet",
+    "mustang",
+    "access",
+    "flower",
+    "pokemon",
+    "lovely",
+    "loveme",
+    "killer",
+    "soccer",
+    "hockey",
+    "summer",
+    "winter",
+    "google",
+    "samsung",
+    "apple",
+    "zxcvbnm",
+    "zxcvbn",
+    "qweasd",
+    "qwe123",
+    "asdf1234",
+    "aaaaaa",
+    "abcdef",
+    "abcdefg",
+    "abcdefgh",
+    "12341234",
+];
+
+/// Checks the strength of passwords: their length, that they are not common
+/// passwords and, optionally, the character classes they use.
+///
+/// ```
+/// use loco_rs::validation::PasswordStrength;
+///
+/// let strength = PasswordStrength::new()
+///     .min_length(10)
+///     .deny(["acme", "acme-corp"]);
+///
+/// assert!(strength.check("correct horse battery staple").is_ok());
+/// assert!(strength.check("password123").is_err());
+/// assert!(strength.check("acme-corp").is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PasswordStrength {
+    min_length: usize,
+    max_length: usize,
+    min_classes: usize,
+    denied: Vec<String>,
+}
+
+impl Default for PasswordStrength {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 256,
+            min_classes: 1,
+            denied: Vec::new(),
+        }
+    }
+}
+
+impl PasswordStrength {
+    /// Creates a checker requiring at least 8 characters, and rejecting
+    /// common passwords.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum number of characters.
+    #[must_use]
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Sets the maximum number of characters.
+    #[must_use]
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Sets how many of lowercase letters, uppercase letters, digits and
+    /// symbols a password must contain.
+    #[must_use]
+    pub fn min_classes(mut self, min_classes: usize) -> Self {
+        self.min_classes = min_classes;
+        self
+    }
+
+    /// Rejects the given passwords too, case insensitively. Use it for a
+    /// larger breach list, or for words such as the application name.
+    #[must_use]
+    pub fn deny<I, S>(mut self, passwords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.denied
+            .extend(passwords.into_iter().map(|p| p.as_ref().to_lowercase()));
+        self
+    }
+
+    /// Checks the given password.
+    ///
+    /// # Errors
+    ///
+    /// When the password is too short, too long, common, or does not use
+    /// enough character classes.
+    pub fn check(&self, password: &str) -> Result<(), ValidationError> {
+        let error = |code: &'static str, message: String| {
+            Err(ValidationError::new(code).with_message(message.into()))
+        };
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            return error(
+                "password_too_short",
+                format!(
+                    "Password must be at least {} characters long.",
+                    self.min_length
+                ),
+            );
+        }
+        if length > self.max_length {
+            return error(
+                "password_too_long",
+                format!(
+                    "Password must be at most {} characters long.",
+                    self.max_length
+                ),
+            );
+        }
+
+        let lowercase = password.to_lowercase();
+        let mut chars = password.chars();
+        let repeated = chars.next().is_some_and(|first| chars.all(|c| c == first));
+        if repeated
+            || COMMON_PASSWORDS.contains(&lowercase.as_str())
+            || self.denied.contains(&lowercase)
+        {
+            return error("password_too_common", "Password is too common.".to_string());
+        }
+
+        let classes = [
+            password.chars().any(char::is_lowercase),
+            password.chars().any(char::is_uppercase),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .iter()
+        .filter(|class| **class)
+        .count();
+        if classes < self.min_classes {
+            return error(
+                "password_too_simple",
+                format!(
+                    "Password must contain {} of lowercase letters, uppercase letters, digits and symbols.",
+                    self.min_classes
+                ),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Validates a password with the default [`PasswordStrength`], for use with
+/// `#[validate(custom(function = "loco_rs::validation::is_strong_password"))]`.
+///
+/// # Errors
+///
+/// When the password is shorter than 8 characters, or is a common password.
+pub fn is_strong_password(password: &str) -> Result<(), ValidationError> {
+    PasswordStrength::default().check(password)
+}
+
+/// The Pwned Passwords range API of Have I Been Pwned.
+#[cfg(feature = "password_pwned")]
+pub const PWNED_PASSWORDS_URL: &str = "https://api.pwnedpasswords.com/range/";
+
+/// Checks passwords against the hundreds of millions of passwords exposed in
+/// data breaches, with the k-anonymity range API of Have I Been Pwned: only
+/// the first 5 characters of the SHA-1 hash of a password are sent, and the
+/// match is made locally against the suffixes of all the breached hashes
+/// sharing them.
+///
+/// ```rust,ignore
+/// use loco_rs::validation::PwnedPasswords;
+///
+/// if PwnedPasswords::new().breach_count(&params.password).await? > 0 {
+///     return bad_request("this password was exposed in a data breach");
+/// }
+/// ```
+#[cfg(feature = "password_pwned")]
+#[derive(Debug, Clone)]
+pub struct PwnedPasswords {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "password_pwned")]
+impl Default for PwnedPasswords {
+    fn default() -> Self {
+        Self::with_url(PWNED_PASSWORDS_URL)
+    }
+}
+
+#[cfg(feature = "password_pwned")]
+impl PwnedPasswords {
+    /// Creates a checker using [`PWNED_PASSWORDS_URL`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a checker using another range API, such as a self-hosted
+    /// mirror. The hash prefix is appended to the given URL.
+    #[must_use]
+    pub fn with_url(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    /// Returns how many times the given password appears in data breaches,
+    /// `0` when it was never exposed.
+    ///
+    /// # Errors
+    ///
+    /// When the range API could not be reached.
+    pub async fn breach_count(&self, password: &str) -> crate::Result<u64> {
+        use sha1::{Digest, Sha1};
+
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        let range = self
+            .client
+            .get(format!("{}{prefix}", self.url))
+            // padded responses don't reveal the prefix by their size
+            .header("Add-Padding", "true")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(crate::Error::wrap)?
+            .text()
+            .await
+            .map_err(crate::Error::wrap)?;
+
+        Ok(range
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(suffix))
+            .and_then(|(_, count)| count.parse().ok())
+            .unwrap_or(0))
+    }
+}
+
 ///
 /// <DbErr conversion hack>
 ///
@@ -173,6 +515,70 @@ mod tests {
         assert_eq!(is_valid_email(test_name).is_ok(), expected);
     }
 
+    #[rstest]
+    #[case("correct horse battery staple", None)]
+    #[case("short", Some("password_too_short"))]
+    #[case("Password123", Some("password_too_common"))]
+    #[case("zzzzzzzzzz", Some("password_too_common"))]
+    fn can_validate_password_strength(#[case] password: &str, #[case] code: Option<&str>) {
+        assert_eq!(
+            is_strong_password(password)
+                .err()
+                .map(|e| e.code.to_string()),
+            code.map(ToString::to_string)
+        );
+    }
+
+    #[cfg(feature = "password_pwned")]
+    #[tokio::test]
+    async fn can_check_breached_passwords() {
+        use axum::{extract::Path, routing::get, Router};
+
+        // `password` hashes to 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let app = Router::new().route(
+            "/range/{prefix}",
+            get(|Path(prefix): Path<String>| async move {
+                if prefix == "5BAA6" {
+                    "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n\
+                     1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n\
+                     FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:0"
+                } else {
+                    "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:0"
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/range/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let pwned = PwnedPasswords::with_url(&url);
+        assert_eq!(pwned.breach_count("password").await.unwrap(), 9_659_365);
+        assert_eq!(pwned.breach_count("password-1234").await.unwrap(), 0);
+    }
+
+    #[test]
+    fn can_configure_password_strength() {
+        let strength = PasswordStrength::new()
+            .min_length(4)
+            .max_length(12)
+            .min_classes(3)
+            .deny(["Loco"]);
+
+        assert!(strength.check("Loco-rs-1").is_ok());
+        assert_eq!(
+            strength.check("loco").unwrap_err().code,
+            "password_too_common"
+        );
+        assert_eq!(
+            strength.check("loco-rs").unwrap_err().code,
+            "password_too_simple"
+        );
+        assert_eq!(
+            strength.check("Loco-rs-framework-1").unwrap_err().code,
+            "password_too_long"
+        );
+    }
+
     #[cfg(feature = "with-db")]
     #[rstest]
     #[case("foo")]
@@ -0,0 +1 @@
+<html><body>{% block content %}{% endblock content %}{% include "partials/footer.t" %}</body></html>
@@ -0,0 +1 @@
+<footer>{{ 1234567 | number_with_delimiter }} users</footer>
\ No newline at end of file
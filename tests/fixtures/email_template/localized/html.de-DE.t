@@ -0,0 +1 @@
+{% extends "base.html.t" %}{% block content %}Willkommen {{ name }} ({{ locale }}){% endblock content %}
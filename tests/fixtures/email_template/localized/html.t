@@ -0,0 +1 @@
+{% extends "base.html.t" %}{% block content %}Welcome {{ name }}{% endblock content %}
@@ -0,0 +1,11 @@
+<html>
+<head>
+  <style>
+    p { color: #333333 }
+    a:hover { color: red }
+  </style>
+</head>
+<body>
+  <p>Welcome {{ name }}, please <a href="https://example.com/verify">verify your account</a>.</p>
+</body>
+</html>